@@ -29,8 +29,47 @@ mod dummy_token_pool {
             (component, pool_tokens, pool_address)
         }
 
+        // A pool only this component can contribute to, like a real pool, so the component acts as the pool's adapter
+        pub fn instantiate_managed_token_pool(
+            address: ResourceAddress,
+            initial_tokens: Bucket,
+        ) -> (Global<TokenPool>, Bucket, ComponentAddress) {
+            let (address_reservation, component_address) =
+                Runtime::allocate_component_address(TokenPool::blueprint_id());
+
+            let pool = Blueprint::<OneResourcePool>::instantiate(
+                OwnerRole::None,
+                rule!(require(global_caller(component_address))),
+                address,
+                None,
+            );
+            let pool_address = pool.address();
+
+            let component = Self { pool }
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .with_address(address_reservation)
+                .globalize();
+
+            let pool_tokens = component.contribute(initial_tokens);
+
+            (component, pool_tokens, pool_address)
+        }
+
         pub fn protected_deposit(&mut self, tokens: Bucket) {
             self.pool.protected_deposit(tokens);
         }
+
+        pub fn contribute(&mut self, tokens: Bucket) -> Bucket {
+            self.pool.contribute(tokens)
+        }
+
+        pub fn redeem(&mut self, pool_units: Bucket) -> Bucket {
+            self.pool.redeem(pool_units)
+        }
+
+        pub fn get_redemption_value(&self, amount: Decimal) -> Decimal {
+            self.pool.get_redemption_value(amount)
+        }
     }
 }
@@ -43,7 +43,231 @@ pub struct EventChangeCollateral {
     pub new_usd_price: Option<Decimal>,
 }
 
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventMcrSurcharge {
+    pub address: ResourceAddress,
+    pub surcharge: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventShareEnforcement {
+    pub address: ResourceAddress,
+    pub enforced: bool,
+    pub share: Decimal,
+}
+
 #[derive(ScryptoSbor, ScryptoEvent, Clone)]
 pub struct EventChangePeg {
     pub internal_price: Decimal,
-}
\ No newline at end of file
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventShareWarning {
+    pub warning: ShareWarning,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventWithdrawalQueued {
+    pub claim_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub amount: Decimal,
+    pub claimable_from: Instant,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventDonation {
+    pub receipt_id: NonFungibleLocalId,
+    pub amount: Decimal,
+    pub donated_stab: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventWithdrawalClaimed {
+    pub claim_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventKeeperCompensation {
+    pub receipt_id: NonFungibleLocalId,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventAutoDeleverage {
+    pub cdp_id: NonFungibleLocalId,
+    pub stab_repaid: Decimal,
+    pub collateral_sold: Decimal,
+    pub bounty: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventYieldHarvested {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral_harvested: Decimal,
+    pub bounty: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventBufferApplied {
+    pub cdp_id: NonFungibleLocalId,
+    pub amount: Decimal,
+    pub bounty: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventCdpFrozen {
+    pub cdp_id: NonFungibleLocalId,
+    pub until: Instant,
+    pub reason: String,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventCdpUnfrozen {
+    pub cdp_id: NonFungibleLocalId,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventBorrowRewardsClaimed {
+    pub cdp_id: NonFungibleLocalId,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventCloseFee {
+    pub cdp_id: NonFungibleLocalId,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventStabilityReserveUsed {
+    pub receipt_id: NonFungibleLocalId,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventLeftoverSwept {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventLeftoverReimbursed {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventLeftoverReturned {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub account: ComponentAddress,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventSystemState {
+    pub circulating_stab: Decimal,
+    pub internal_price: Decimal,
+    pub interest_rate: Decimal,
+    pub marked_cdps: u64,
+    pub collaterals: Vec<CollateralUtilization>,
+}
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventPegAlert {
+    pub market_price: Decimal,
+    pub internal_price: Decimal,
+    pub deviation: Decimal,
+    pub deviating_since: Instant,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventPegRecovered {
+    pub market_price: Decimal,
+    pub internal_price: Decimal,
+    pub deviation: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventEqualization {
+    pub amount: Decimal,
+    pub negative_since: Instant,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventPriceOverride {
+    pub collateral: ResourceAddress,
+    pub price: Decimal,
+    pub expires_at: Instant,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventPriceOverrideExpired {
+    pub collateral: ResourceAddress,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventRebrand {
+    pub branding: Branding,
+    pub resources: Vec<ResourceAddress>,
+    pub components: Vec<ComponentAddress>,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventRegistryUpdate {
+    pub name: String,
+    pub address: Option<GlobalAddress>,
+    pub version: u64,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventTimelockQueued {
+    pub id: u64,
+    pub component: ComponentAddress,
+    pub method: String,
+    pub executable_from: Instant,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventTimelockUpdate {
+    pub id: u64,
+    pub status: TimelockStatus,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventClaimFiled {
+    pub id: u64,
+    pub cdp_id: NonFungibleLocalId,
+    pub liquidation_receipt_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventClaimUpdate {
+    pub id: u64,
+    pub status: ClaimStatus,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventCustodyChange {
+    pub change: CustodyChange,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventRateMode {
+    pub mode: RateMode,
+    pub expired: bool,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone)]
+pub struct EventIncentivesDistributed {
+    pub collateral: ResourceAddress,
+    pub staking_component: ComponentAddress,
+    pub resource: ResourceAddress,
+    pub amount: Decimal,
+}
@@ -3,6 +3,7 @@
 //! This blueprint allows users to borrow STAB tokens from the Stabilis component. The user must pay back the borrowed amount plus interest in the same transaction.
 //! This works by the user receiving a transient token loan receipt, that only the FlashLoan component can burn. They have to pay this back in the same transaction to make the transaction succeed.
 
+use crate::shared_structs::*;
 use crate::stabilis_component::stabilis_component::*;
 use scrypto::prelude::*;
 
@@ -14,8 +15,21 @@ pub struct LoanReceipt {
     pub interest: Decimal,
 }
 
+/// The current terms of the flash loans
+#[derive(ScryptoSbor, Clone)]
+pub struct FlashTerms {
+    /// whether flash loans are possible now
+    pub enabled: bool,
+    /// interest paid on a flash loan (0.05 is 5% interest)
+    pub interest: Decimal,
+    /// resource address of the (transient) loan receipts
+    pub loan_receipt_address: ResourceAddress,
+    /// total amount of STAB loaned through flash loans
+    pub amount_loaned: Decimal,
+}
+
 #[blueprint]
-#[types(Decimal)]
+#[types(Decimal, ResourceAddress, Vault)]
 mod flash_loans {
     enable_method_auth! {
         methods {
@@ -23,6 +37,11 @@ mod flash_loans {
             settings => restrict_to: [OWNER];
             pay_back => restrict_to: [OWNER];
             retrieve_interest => restrict_to: [OWNER];
+            take_interest => restrict_to: [OWNER];
+            get_flash_terms => PUBLIC;
+            get_collected_interest => PUBLIC;
+            deposit_airdrop => PUBLIC;
+            rescue_tokens => restrict_to: [OWNER];
         }
     }
 
@@ -43,6 +62,8 @@ mod flash_loans {
         enabled: bool,
         /// The amount of STAB tokens loaned
         amount_loaned: Decimal,
+        /// Vaults for tokens airdropped to this component (resources not managed by the protocol), until rescued by the owner
+        airdrop_vaults: KeyValueStore<ResourceAddress, Vault>,
     }
 
     impl FlashLoans {
@@ -110,6 +131,7 @@ mod flash_loans {
                 loan_receipt_counter: 0,
                 enabled: true,
                 amount_loaned: dec!(0),
+                airdrop_vaults: FlashLoansKeyValueStore::new_with_registered_type(),
             }
             .instantiate()
             .prepare_to_globalize(OwnerRole::Fixed(rule!(require(controller_address))))
@@ -223,9 +245,61 @@ mod flash_loans {
             payment
         }
 
+        /// Get the current terms of the flash loans
+        pub fn get_flash_terms(&self) -> FlashTerms {
+            FlashTerms {
+                enabled: self.enabled,
+                interest: self.interest,
+                loan_receipt_address: self.loan_receipt_manager.address(),
+                amount_loaned: self.amount_loaned,
+            }
+        }
+
         /// Method called to empty the interest vault
         pub fn retrieve_interest(&mut self) -> Bucket {
             self.interest_vault.as_mut().unwrap().take_all()
         }
+
+        /// Method called to take part of the interest vault
+        pub fn take_interest(&mut self, amount: Decimal) -> Bucket {
+            self.interest_vault.as_mut().unwrap().take(amount)
+        }
+
+        /// Get the amount of STAB in the interest vault
+        pub fn get_collected_interest(&self) -> Decimal {
+            self.interest_vault
+                .as_ref()
+                .map_or(dec!(0), |vault| vault.amount())
+        }
+
+        /// Receive tokens airdropped to this component, kept until the owner rescues them
+        pub fn deposit_airdrop(&mut self, tokens: Bucket) {
+            assert!(
+                !self
+                    .protocol_resources()
+                    .contains(&tokens.resource_address()),
+                "Protocol-managed resources can't be airdropped."
+            );
+            put_in_resource_vaults(&self.airdrop_vaults, tokens);
+        }
+
+        /// Rescue airdropped tokens of a resource that isn't managed by the protocol (STAB, the controller badge and the loan receipts)
+        pub fn rescue_tokens(&mut self, resource: ResourceAddress, amount: Decimal) -> Bucket {
+            take_from_resource_vaults(
+                &self.airdrop_vaults,
+                resource,
+                amount,
+                &self.protocol_resources(),
+            )
+        }
+
+        /// Get the resources managed by the protocol, which can't be airdropped or rescued
+        fn protocol_resources(&self) -> Vec<ResourceAddress> {
+            vec![
+                self.stabilis.get_resource_addresses().stab,
+                self.badge_vault.resource_address(),
+                self.loan_receipt_manager.address(),
+            ]
+        }
     }
 }
@@ -0,0 +1,343 @@
+//! # Insurance Claims Blueprint
+//!
+//! Borrowers whose loan was liquidated on a bad oracle tick (a collateral price that reverted shortly after the liquidation) can claim compensation from the insurance fund of the Stabilis component.
+//!
+//! The life cycle of a claim:
+//! - The borrower files a claim presenting their loan receipt and referencing the liquidation receipt of the liquidation: `file_claim`
+//!     - the claim needs to be filed within the revert window after the liquidation, and be worth at least the minimum claim value
+//!     - the collateral price needs to have reverted: at filing, it needs to be at least the minimum price revert above the price at liquidation (recorded in the liquidation receipt)
+//!     - the collateral prices at liquidation and at filing are recorded, as evidence for the post-mortem
+//! - The owner (ideally a DAO, after a post-mortem) approves the claim, possibly for a lower amount: `approve_claim`, or rejects it: `reject_claim`
+//! - The borrower collects the approved amount, taken from the insurance fund of the liquidated collateral: `collect_payout`
+//!
+//! The component holds the insurance badge of the Stabilis component (see `mint_insurance_badge`), required by its insurance manager role, which only authorizes payouts of approved claims.
+//! Controller badges don't authorize insurance payouts, the insurance manager role is locked, and only a single insurance badge can ever be minted.
+//! So once the badge is deposited here, the insurance funds can't be spent as revenue.
+
+use crate::events::*;
+use crate::shared_structs::*;
+use crate::stabilis_component::stabilis_component::*;
+use scrypto::prelude::*;
+
+/// A claim for compensation of a wrongful liquidation
+#[derive(ScryptoSbor, Clone)]
+pub struct InsuranceClaim {
+    /// id of the liquidated loan / CDP
+    pub cdp_id: NonFungibleLocalId,
+    /// id of the liquidation receipt of the liquidation
+    pub liquidation_receipt_id: NonFungibleLocalId,
+    /// collateral the claim is paid in (the collateral of the liquidated loan)
+    pub collateral: ResourceAddress,
+    /// amount of collateral claimed
+    pub amount: Decimal,
+    /// amount of collateral approved by the owner
+    pub approved_amount: Decimal,
+    /// price of the (parent) collateral at the liquidation
+    pub price_at_liquidation: Decimal,
+    /// price of the (parent) collateral when the claim was filed
+    pub price_at_filing: Decimal,
+    /// time the claim was filed
+    pub filed_at: Instant,
+    /// status of the claim
+    pub status: ClaimStatus,
+}
+
+#[blueprint]
+#[types(u64, NonFungibleLocalId, InsuranceClaim)]
+#[events(EventClaimFiled, EventClaimUpdate)]
+mod insurance_claims {
+    enable_method_auth! {
+        methods {
+            get_claim => PUBLIC;
+            get_claim_by_liquidation => PUBLIC;
+            file_claim => PUBLIC;
+            collect_payout => PUBLIC;
+            deposit_badge => PUBLIC;
+            approve_claim => restrict_to: [OWNER];
+            reject_claim => restrict_to: [OWNER];
+            set_revert_window => restrict_to: [OWNER];
+            set_min_claim_value => restrict_to: [OWNER];
+            set_min_price_revert => restrict_to: [OWNER];
+        }
+    }
+
+    struct InsuranceClaims {
+        /// The global instance of the Stabilis component
+        stabilis: Global<Stabilis>,
+        /// The insurance badge of the Stabilis component, required by its insurance manager role
+        badge_vault: Vault,
+        /// The resource manager for the CDP receipts
+        cdp_receipt_manager: ResourceManager,
+        /// The resource manager for the liquidation receipts
+        liquidation_receipt_manager: ResourceManager,
+        /// KVS storing all claims, by id
+        claims: KeyValueStore<u64, InsuranceClaim>,
+        /// KVS storing the claim id filed for each liquidation receipt, so a liquidation can only be claimed once
+        claims_by_liquidation: KeyValueStore<NonFungibleLocalId, u64>,
+        /// Counter for the claim ids
+        claim_counter: u64,
+        /// Minutes after a liquidation within which a claim can be filed
+        revert_window: i64,
+        /// Minimum value of a claim (amount times the collateral price)
+        min_claim_value: Decimal,
+        /// Minimum relative rise of the collateral price since the liquidation for a claim to be filed (0.05 = 5%)
+        min_price_revert: Decimal,
+    }
+
+    impl InsuranceClaims {
+        /// Instantiates the InsuranceClaims component
+        ///
+        /// # Input
+        /// - `owner_role`: The owner role of the component, allowed to approve and reject claims (ideally a DAO)
+        /// - `stabilis_address`: The address of the Stabilis component
+        /// - `badge_address`: The address of the insurance badge of the Stabilis component
+        /// - `dapp_def_address`: The address of the dapp definition account
+        ///
+        /// # Output
+        /// - The global instance of the InsuranceClaims component
+        pub fn instantiate(
+            owner_role: OwnerRole,
+            stabilis_address: ComponentAddress,
+            badge_address: ResourceAddress,
+            dapp_def_address: GlobalAddress,
+        ) -> Global<InsuranceClaims> {
+            let stabilis: Global<Stabilis> = Global::from(stabilis_address);
+            let addresses: ResourceAddresses = stabilis.get_resource_addresses();
+            assert!(
+                badge_address == addresses.insurance_badge,
+                "Badge needs to be the insurance badge of the Stabilis component."
+            );
+
+            Self {
+                stabilis,
+                badge_vault: Vault::new(badge_address),
+                cdp_receipt_manager: ResourceManager::from_address(addresses.cdp_receipt),
+                liquidation_receipt_manager: ResourceManager::from_address(
+                    addresses.liquidation_receipt,
+                ),
+                claims: InsuranceClaimsKeyValueStore::new_with_registered_type(),
+                claims_by_liquidation: InsuranceClaimsKeyValueStore::new_with_registered_type(),
+                claim_counter: 0,
+                revert_window: 60,
+                min_claim_value: dec!(100),
+                min_price_revert: dec!("0.05"),
+            }
+            .instantiate()
+            .prepare_to_globalize(owner_role)
+            .metadata(metadata! {
+                init {
+                    "name" => "STAB Insurance Claims".to_string(), updatable;
+                    "description" => "A component handling insurance claims of wrongly liquidated STAB loans".to_string(), updatable;
+                    "info_url" => Url::of("https://ilikeitstable.com"), updatable;
+                    "dapp_definition" => dapp_def_address, updatable;
+                }
+            })
+            .globalize()
+        }
+
+        /// Get a claim
+        pub fn get_claim(&self, id: u64) -> Option<InsuranceClaim> {
+            self.claims.get(&id).map(|claim| claim.clone())
+        }
+
+        /// Get the id of the claim filed for a liquidation receipt, if any
+        pub fn get_claim_by_liquidation(
+            &self,
+            liquidation_receipt_id: NonFungibleLocalId,
+        ) -> Option<u64> {
+            self.claims_by_liquidation
+                .get(&liquidation_receipt_id)
+                .map(|id| *id)
+        }
+
+        /// Deposit insurance badges of the Stabilis component
+        pub fn deposit_badge(&mut self, badge: Bucket) {
+            self.badge_vault.put(badge);
+        }
+
+        /// File a claim for a wrongful liquidation
+        ///
+        /// # Input
+        /// - `receipt_proof`: Proof of the receipt of the liquidated loan
+        /// - `liquidation_receipt_id`: The id of the liquidation receipt of the liquidation
+        /// - `amount`: The amount of collateral claimed
+        ///
+        /// # Output
+        /// - The id of the claim
+        ///
+        /// # Logic
+        /// - Check the liquidation receipt belongs to the liquidation of the presented loan, and hasn't been claimed for yet
+        /// - Check the claim is filed within the revert window and is worth at least the minimum claim value
+        /// - Check the collateral price reverted: the current price needs to be at least the minimum price revert above the price at liquidation
+        /// - Store the claim with the collateral prices at liquidation and now
+        pub fn file_claim(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            liquidation_receipt_id: NonFungibleLocalId,
+            amount: Decimal,
+        ) -> u64 {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let cdp_id: NonFungibleLocalId = receipt.local_id().clone();
+            let cdp: Cdp = receipt.data();
+
+            let liquidation: LiquidationReceipt = self
+                .liquidation_receipt_manager
+                .get_non_fungible_data(&liquidation_receipt_id);
+
+            assert!(
+                liquidation.cdp_liquidated == cdp_id,
+                "Liquidation receipt doesn't belong to this loan."
+            );
+            assert!(
+                self.claims_by_liquidation
+                    .get(&liquidation_receipt_id)
+                    .is_none(),
+                "Liquidation already claimed."
+            );
+            assert!(
+                Clock::current_time_is_strictly_before(
+                    liquidation
+                        .date_liquidated
+                        .add_minutes(self.revert_window)
+                        .unwrap(),
+                    TimePrecision::Second
+                ),
+                "Revert window has passed."
+            );
+
+            let price_at_filing: Decimal = self.stabilis.get_collateral_price(cdp.parent_address);
+            assert!(
+                amount * price_at_filing >= self.min_claim_value,
+                "Claim below the minimum claim value."
+            );
+            assert!(
+                price_at_filing >= liquidation.collateral_price * (dec!(1) + self.min_price_revert),
+                "Collateral price hasn't reverted since the liquidation."
+            );
+
+            self.claim_counter += 1;
+            self.claims.insert(
+                self.claim_counter,
+                InsuranceClaim {
+                    cdp_id: cdp_id.clone(),
+                    liquidation_receipt_id: liquidation_receipt_id.clone(),
+                    collateral: liquidation.collateral,
+                    amount,
+                    approved_amount: dec!(0),
+                    price_at_liquidation: liquidation.collateral_price,
+                    price_at_filing,
+                    filed_at: Clock::current_time_rounded_to_seconds(),
+                    status: ClaimStatus::Filed,
+                },
+            );
+            self.claims_by_liquidation
+                .insert(liquidation_receipt_id.clone(), self.claim_counter);
+
+            Runtime::emit_event(EventClaimFiled {
+                id: self.claim_counter,
+                cdp_id,
+                liquidation_receipt_id,
+                collateral: liquidation.collateral,
+                amount,
+            });
+
+            self.claim_counter
+        }
+
+        /// Approve a filed claim, for at most the claimed amount
+        pub fn approve_claim(&mut self, id: u64, approved_amount: Decimal) {
+            let mut claim = self.claims.get_mut(&id).expect("Claim not found.");
+            assert!(claim.status == ClaimStatus::Filed, "Claim not filed.");
+            assert!(
+                approved_amount > dec!(0) && approved_amount <= claim.amount,
+                "Approved amount needs to be positive and at most the claimed amount."
+            );
+
+            claim.status = ClaimStatus::Approved;
+            claim.approved_amount = approved_amount;
+
+            Runtime::emit_event(EventClaimUpdate {
+                id,
+                status: ClaimStatus::Approved,
+                amount: approved_amount,
+            });
+        }
+
+        /// Reject a filed claim
+        pub fn reject_claim(&mut self, id: u64) {
+            let mut claim = self.claims.get_mut(&id).expect("Claim not found.");
+            assert!(claim.status == ClaimStatus::Filed, "Claim not filed.");
+
+            claim.status = ClaimStatus::Rejected;
+
+            Runtime::emit_event(EventClaimUpdate {
+                id,
+                status: ClaimStatus::Rejected,
+                amount: dec!(0),
+            });
+        }
+
+        /// Collect the payout of an approved claim from the insurance fund of the liquidated collateral
+        pub fn collect_payout(&mut self, receipt_proof: NonFungibleProof, id: u64) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let cdp_id: NonFungibleLocalId = receipt_proof.non_fungible::<Cdp>().local_id().clone();
+
+            let (collateral, amount): (ResourceAddress, Decimal) = {
+                let mut claim = self.claims.get_mut(&id).expect("Claim not found.");
+                assert!(claim.cdp_id == cdp_id, "Claim doesn't belong to this loan.");
+                assert!(claim.status == ClaimStatus::Approved, "Claim not approved.");
+                claim.status = ClaimStatus::Paid;
+                (claim.collateral, claim.approved_amount)
+            };
+
+            assert!(
+                self.stabilis.get_treasury_balances(collateral).1 >= amount,
+                "Insurance fund too small to pay this claim."
+            );
+
+            let payout: Bucket = self.badge_vault.authorize_with_all(|| {
+                self.stabilis
+                    .empty_insurance_fund(amount, collateral, false)
+            });
+
+            Runtime::emit_event(EventClaimUpdate {
+                id,
+                status: ClaimStatus::Paid,
+                amount: payout.amount(),
+            });
+
+            payout
+        }
+
+        /// Set the minutes after a liquidation within which a claim can be filed
+        pub fn set_revert_window(&mut self, revert_window: i64) {
+            assert!(revert_window >= 0, "Revert window can't be negative.");
+            self.revert_window = revert_window;
+        }
+
+        /// Set the minimum value of a claim (amount times the collateral price)
+        pub fn set_min_claim_value(&mut self, min_claim_value: Decimal) {
+            assert!(
+                min_claim_value >= dec!(0),
+                "Minimum claim value can't be negative."
+            );
+            self.min_claim_value = min_claim_value;
+        }
+
+        /// Set the minimum relative rise of the collateral price since the liquidation for a claim to be filed (0.05 = 5%)
+        pub fn set_min_price_revert(&mut self, min_price_revert: Decimal) {
+            assert!(
+                min_price_revert >= dec!(0),
+                "Minimum price revert can't be negative."
+            );
+            self.min_price_revert = min_price_revert;
+        }
+    }
+}
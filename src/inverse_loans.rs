@@ -0,0 +1,470 @@
+//! # Inverse Loans Blueprint
+//!
+//! This blueprint allows STAB holders to borrow collateral (such as XRD) against their STAB tokens, the inverse of a Stabilis loan.
+//! The lent collateral is supplied by the owner, typically from the Stabilis component's treasury, which earns a borrow fee on every loan.
+//!
+//! To borrow, users deposit STAB and receive the requested collateral and a loan receipt in return.
+//! The value of the borrowed collateral can't exceed the value of the STAB (at internal price) times the market's loan-to-value ratio (LTV).
+//! A loan is paid off by returning the borrowed collateral plus the borrow fee, which returns the STAB.
+//!
+//! If the value of the borrowed collateral rises above the value of the STAB times the liquidation LTV, anyone can liquidate the loan.
+//! Liquidating a loan means paying back the borrowed collateral, in return for STAB worth the repaid collateral plus a liquidation bonus.
+//! The borrower can then retrieve the STAB that is left.
+//! Unlike Stabilis loans, loans don't need to be marked first, as a liquidator chooses the loan to liquidate and there's no delay to save it.
+//!
+//! This is why the blueprint has its own liquidation path, instead of reusing the CDP / marker machinery of the Stabilis component:
+//! - The Stabilis machinery liquidates loans that minted STAB, in order of their collateral ratio (the AvlTree), burning the repaid STAB. Inverse loans don't mint anything, they lend out supplied collateral, so there's no debt to burn and no ordering to keep.
+//! - Markers, their delay and the liquidation receipts protect borrowers and liquidators against the system-wide risk of undercollateralized STAB. An inverse loan's risk stays within its market (the supplied collateral), and a liquidator repays the exact debt, so none of that is needed.
+//! - Stabilis loans are tracked in the Stabilis component's collateral totals and share caps, which inverse loans would distort.
+//!
+//! Collateral prices and the internal price of STAB are read from the Stabilis component, so they are updated through the Proxy component.
+
+use crate::stabilis_component::stabilis_component::*;
+use scrypto::prelude::*;
+
+/// Data struct of an inverse loan receipt, gained when borrowing collateral against STAB
+#[derive(ScryptoSbor, NonFungibleData, Clone)]
+pub struct InverseLoan {
+    /// collateral borrowed
+    pub collateral: ResourceAddress,
+    /// amount of STAB deposited
+    #[mutable]
+    pub stab_amount: Decimal,
+    /// amount of collateral to pay back (borrowed amount plus the borrow fee)
+    #[mutable]
+    pub debt: Decimal,
+    /// status of the loan
+    #[mutable]
+    pub status: InverseLoanStatus,
+}
+
+/// Status of an inverse loan
+#[derive(ScryptoSbor, PartialEq, Clone)]
+pub enum InverseLoanStatus {
+    Healthy,
+    Liquidated,
+}
+
+/// Market of a collateral that can be borrowed
+#[derive(ScryptoSbor)]
+pub struct InverseMarket {
+    /// vault holding the collateral available to borrow
+    pub vault: Vault,
+    /// maximum value of borrowed collateral / value of deposited STAB when borrowing
+    pub ltv: Decimal,
+    /// value of borrowed collateral / value of deposited STAB from which a loan can be liquidated
+    pub liquidation_ltv: Decimal,
+    /// fee added to the debt when borrowing (0.01 = 1%)
+    pub borrow_fee: Decimal,
+    /// total debt of all loans in this market
+    pub total_debt: Decimal,
+    /// whether new loans can be opened in this market
+    pub accepted: bool,
+}
+
+#[blueprint]
+#[types(ResourceAddress, InverseMarket)]
+mod inverse_loans {
+    enable_method_auth! {
+        methods {
+            borrow => PUBLIC;
+            repay => PUBLIC;
+            liquidate => PUBLIC;
+            retrieve_leftover_stab => PUBLIC;
+            get_loan_ltv => PUBLIC;
+            get_available => PUBLIC;
+            add_market => restrict_to: [OWNER];
+            edit_market => restrict_to: [OWNER];
+            supply => restrict_to: [OWNER];
+            withdraw_supply => restrict_to: [OWNER];
+            set_liquidation_bonus => restrict_to: [OWNER];
+        }
+    }
+
+    struct InverseLoans {
+        /// The global instance of the Stabilis component, used for prices
+        stabilis: Global<Stabilis>,
+        /// The vault holding the deposited STAB
+        stab_vault: Vault,
+        /// KVS storing the markets of all collaterals that can be borrowed
+        markets: KeyValueStore<ResourceAddress, InverseMarket>,
+        /// The resource manager for the loan receipts
+        loan_receipt_manager: ResourceManager,
+        /// Counter for the loan receipts
+        loan_counter: u64,
+        /// The bonus a liquidator receives (0.05 = 5% more STAB value than the repaid collateral value)
+        liquidation_bonus: Decimal,
+    }
+
+    impl InverseLoans {
+        /// Instantiates the InverseLoans component
+        ///
+        /// # Input
+        /// - `owner_role`: The owner role of the component
+        /// - `stabilis_address`: The address of the Stabilis component
+        /// - `stab_address`: The resource address of the STAB token
+        /// - `dapp_def_address`: The address of the dapp definition account
+        ///
+        /// # Output
+        /// - The global instance of the InverseLoans component
+        ///
+        /// # Logic
+        /// - Creates a ResourceManager for the loan receipts
+        /// - Instantiates the InverseLoans component
+        pub fn instantiate(
+            owner_role: OwnerRole,
+            stabilis_address: ComponentAddress,
+            stab_address: ResourceAddress,
+            dapp_def_address: GlobalAddress,
+        ) -> Global<InverseLoans> {
+            let (address_reservation, component_address) =
+                Runtime::allocate_component_address(InverseLoans::blueprint_id());
+
+            let loan_receipt_manager: ResourceManager =
+                ResourceBuilder::new_integer_non_fungible::<InverseLoan>(owner_role.clone())
+                    .metadata(metadata!(
+                        init {
+                            "name" => "Stabilis Inverse Loan Receipt", locked;
+                            "symbol" => "stabINV", locked;
+                            "description" => "A receipt for your collateral loan against STAB", locked;
+                            "info_url" => "https://ilikeitstable.com", updatable;
+                        }
+                    ))
+                    .non_fungible_data_update_roles(non_fungible_data_update_roles!(
+                        non_fungible_data_updater => rule!(require(global_caller(component_address)));
+                        non_fungible_data_updater_updater => rule!(deny_all);
+                    ))
+                    .mint_roles(mint_roles!(
+                        minter => rule!(require(global_caller(component_address)));
+                        minter_updater => rule!(deny_all);
+                    ))
+                    .burn_roles(burn_roles!(
+                        burner => rule!(require(global_caller(component_address)));
+                        burner_updater => rule!(deny_all);
+                    ))
+                    .create_with_no_initial_supply();
+
+            Self {
+                stabilis: Global::from(stabilis_address),
+                stab_vault: Vault::new(stab_address),
+                markets: InverseLoansKeyValueStore::new_with_registered_type(),
+                loan_receipt_manager,
+                loan_counter: 0,
+                liquidation_bonus: dec!("0.05"),
+            }
+            .instantiate()
+            .prepare_to_globalize(owner_role)
+            .with_address(address_reservation)
+            .metadata(metadata! {
+                init {
+                    "name" => "STAB Inverse Loans".to_string(), updatable;
+                    "description" => "A component for borrowing collateral against STAB tokens".to_string(), updatable;
+                    "info_url" => Url::of("https://ilikeitstable.com"), updatable;
+                    "dapp_definition" => dapp_def_address, updatable;
+                }
+            })
+            .globalize()
+        }
+
+        /// Borrow collateral against STAB
+        ///
+        /// # Input
+        /// - `stab`: The STAB to deposit
+        /// - `collateral`: The collateral to borrow
+        /// - `amount`: The amount of collateral to borrow
+        ///
+        /// # Output
+        /// - The borrowed collateral in a `Bucket`
+        /// - The loan receipt in a `Bucket`
+        ///
+        /// # Logic
+        /// - Check if the market is accepted and the STAB is valid
+        /// - Check if the value of the borrowed collateral is within the LTV
+        /// - Add the borrow fee to the debt
+        /// - Mint the loan receipt
+        /// - Return the collateral and the loan receipt
+        pub fn borrow(
+            &mut self,
+            stab: Bucket,
+            collateral: ResourceAddress,
+            amount: Decimal,
+        ) -> (Bucket, Bucket) {
+            assert!(
+                stab.resource_address() == self.stab_vault.resource_address(),
+                "Invalid STAB deposit."
+            );
+            assert!(amount > dec!(0), "Can't borrow nothing.");
+
+            let collateral_price: Decimal = self.stabilis.get_collateral_price(collateral);
+            let stab_price: Decimal = self.stabilis.return_internal_price();
+
+            let mut market = self
+                .markets
+                .get_mut(&collateral)
+                .expect("Market not found.");
+            assert!(
+                market.accepted,
+                "This collateral can't be borrowed right now."
+            );
+            assert!(
+                amount * collateral_price <= stab.amount() * stab_price * market.ltv,
+                "STAB value too low."
+            );
+
+            let debt: Decimal = amount * (dec!(1) + market.borrow_fee);
+            market.total_debt += debt;
+            let borrowed: Bucket = market.vault.take(amount);
+
+            self.loan_counter += 1;
+            let loan_receipt: Bucket = self.loan_receipt_manager.mint_non_fungible(
+                &NonFungibleLocalId::integer(self.loan_counter),
+                InverseLoan {
+                    collateral,
+                    stab_amount: stab.amount(),
+                    debt,
+                    status: InverseLoanStatus::Healthy,
+                },
+            );
+
+            self.stab_vault.put(stab);
+
+            (borrowed, loan_receipt)
+        }
+
+        /// Pay back a loan
+        ///
+        /// # Input
+        /// - `loan_receipt`: The loan receipt
+        /// - `repayment`: The collateral to pay back
+        ///
+        /// # Output
+        /// - The deposited STAB in a `Bucket`
+        /// - The leftover repayment in a `Bucket`
+        ///
+        /// # Logic
+        /// - Check if the receipt and repayment are valid
+        /// - Put the debt (including the borrow fee) in the market's vault
+        /// - Burn the loan receipt
+        /// - Return the STAB and leftover repayment
+        pub fn repay(&mut self, loan_receipt: Bucket, mut repayment: Bucket) -> (Bucket, Bucket) {
+            assert!(
+                loan_receipt.resource_address() == self.loan_receipt_manager.address(),
+                "Invalid loan receipt."
+            );
+
+            let loan: InverseLoan = loan_receipt.as_non_fungible().non_fungible().data();
+
+            assert!(
+                loan.status == InverseLoanStatus::Healthy,
+                "Loan not healthy, retrieve leftover STAB instead."
+            );
+            assert!(
+                repayment.resource_address() == loan.collateral,
+                "Invalid repayment."
+            );
+            assert!(
+                repayment.amount() >= loan.debt,
+                "Not enough collateral paid back."
+            );
+
+            let mut market = self.markets.get_mut(&loan.collateral).unwrap();
+            market.vault.put(repayment.take(loan.debt));
+            market.total_debt -= loan.debt;
+
+            loan_receipt.burn();
+
+            (self.stab_vault.take(loan.stab_amount), repayment)
+        }
+
+        /// Liquidate a loan whose LTV is above the liquidation LTV
+        ///
+        /// # Input
+        /// - `loan_id`: The id of the loan to liquidate
+        /// - `repayment`: The collateral to pay back
+        ///
+        /// # Output
+        /// - The STAB received for liquidating in a `Bucket`
+        /// - The leftover repayment in a `Bucket`
+        ///
+        /// # Logic
+        /// - Check if the loan can be liquidated
+        /// - Put the debt in the market's vault
+        /// - Take STAB worth the debt plus the liquidation bonus (or all STAB if not enough)
+        /// - Update the loan receipt, so the borrower can retrieve the leftover STAB
+        pub fn liquidate(
+            &mut self,
+            loan_id: NonFungibleLocalId,
+            mut repayment: Bucket,
+        ) -> (Bucket, Bucket) {
+            let loan: InverseLoan = self.loan_receipt_manager.get_non_fungible_data(&loan_id);
+
+            assert!(
+                loan.status == InverseLoanStatus::Healthy,
+                "Loan not healthy."
+            );
+            assert!(
+                repayment.resource_address() == loan.collateral,
+                "Invalid repayment."
+            );
+            assert!(
+                repayment.amount() >= loan.debt,
+                "Not enough collateral paid back."
+            );
+
+            let liquidation_ltv: Decimal =
+                self.markets.get(&loan.collateral).unwrap().liquidation_ltv;
+            assert!(
+                self.get_loan_ltv(loan_id.clone()) > liquidation_ltv,
+                "Loan can't be liquidated."
+            );
+
+            let collateral_price: Decimal = self.stabilis.get_collateral_price(loan.collateral);
+            let stab_price: Decimal = self.stabilis.return_internal_price();
+
+            let stab_owed: Decimal =
+                loan.debt * collateral_price * (dec!(1) + self.liquidation_bonus) / stab_price;
+            let stab_to_liquidator: Decimal = stab_owed.min(loan.stab_amount);
+
+            let mut market = self.markets.get_mut(&loan.collateral).unwrap();
+            market.vault.put(repayment.take(loan.debt));
+            market.total_debt -= loan.debt;
+
+            self.loan_receipt_manager.update_non_fungible_data(
+                &loan_id,
+                "stab_amount",
+                loan.stab_amount - stab_to_liquidator,
+            );
+            self.loan_receipt_manager
+                .update_non_fungible_data(&loan_id, "debt", dec!(0));
+            self.loan_receipt_manager.update_non_fungible_data(
+                &loan_id,
+                "status",
+                InverseLoanStatus::Liquidated,
+            );
+
+            (self.stab_vault.take(stab_to_liquidator), repayment)
+        }
+
+        /// Retrieve the STAB left after a loan was liquidated
+        pub fn retrieve_leftover_stab(&mut self, loan_receipt: Bucket) -> Bucket {
+            assert!(
+                loan_receipt.resource_address() == self.loan_receipt_manager.address(),
+                "Invalid loan receipt."
+            );
+
+            let loan: InverseLoan = loan_receipt.as_non_fungible().non_fungible().data();
+
+            assert!(
+                loan.status == InverseLoanStatus::Liquidated,
+                "Loan not liquidated."
+            );
+
+            loan_receipt.burn();
+
+            self.stab_vault.take(loan.stab_amount)
+        }
+
+        /// Get the current LTV of a loan (value of the debt / value of the deposited STAB)
+        pub fn get_loan_ltv(&self, loan_id: NonFungibleLocalId) -> Decimal {
+            let loan: InverseLoan = self.loan_receipt_manager.get_non_fungible_data(&loan_id);
+
+            let collateral_price: Decimal = self.stabilis.get_collateral_price(loan.collateral);
+            let stab_price: Decimal = self.stabilis.return_internal_price();
+
+            match loan.stab_amount > dec!(0) {
+                true => loan.debt * collateral_price / (loan.stab_amount * stab_price),
+                false => dec!(0),
+            }
+        }
+
+        /// Get the amount of a collateral available to borrow
+        pub fn get_available(&self, collateral: ResourceAddress) -> Decimal {
+            self.markets
+                .get(&collateral)
+                .map(|market| market.vault.amount())
+                .unwrap_or(dec!(0))
+        }
+
+        /// Add a market for a collateral that can be borrowed (the collateral needs to be accepted by the Stabilis component, for its price)
+        pub fn add_market(
+            &mut self,
+            collateral: ResourceAddress,
+            ltv: Decimal,
+            liquidation_ltv: Decimal,
+            borrow_fee: Decimal,
+        ) {
+            assert!(
+                self.markets.get(&collateral).is_none(),
+                "Market already exists."
+            );
+            assert!(
+                ltv > dec!(0) && liquidation_ltv <= dec!(1),
+                "LTVs need to be between 0 and 1."
+            );
+            assert!(
+                ltv < liquidation_ltv,
+                "LTV needs to be below liquidation LTV."
+            );
+
+            self.markets.insert(
+                collateral,
+                InverseMarket {
+                    vault: Vault::new(collateral),
+                    ltv,
+                    liquidation_ltv,
+                    borrow_fee,
+                    total_debt: dec!(0),
+                    accepted: true,
+                },
+            );
+        }
+
+        /// Edit a market's parameters
+        pub fn edit_market(
+            &mut self,
+            collateral: ResourceAddress,
+            ltv: Decimal,
+            liquidation_ltv: Decimal,
+            borrow_fee: Decimal,
+            accepted: bool,
+        ) {
+            assert!(
+                ltv > dec!(0) && liquidation_ltv <= dec!(1),
+                "LTVs need to be between 0 and 1."
+            );
+            assert!(
+                ltv < liquidation_ltv,
+                "LTV needs to be below liquidation LTV."
+            );
+
+            let mut market = self.markets.get_mut(&collateral).unwrap();
+            market.ltv = ltv;
+            market.liquidation_ltv = liquidation_ltv;
+            market.borrow_fee = borrow_fee;
+            market.accepted = accepted;
+        }
+
+        /// Supply collateral that can be borrowed
+        pub fn supply(&mut self, collateral: Bucket) {
+            self.markets
+                .get_mut(&collateral.resource_address())
+                .expect("Market not found.")
+                .vault
+                .put(collateral);
+        }
+
+        /// Withdraw collateral that isn't borrowed (including earned borrow fees)
+        pub fn withdraw_supply(&mut self, collateral: ResourceAddress, amount: Decimal) -> Bucket {
+            self.markets
+                .get_mut(&collateral)
+                .unwrap()
+                .vault
+                .take(amount)
+        }
+
+        /// Set the liquidation bonus
+        pub fn set_liquidation_bonus(&mut self, liquidation_bonus: Decimal) {
+            self.liquidation_bonus = liquidation_bonus;
+        }
+    }
+}
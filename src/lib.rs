@@ -14,13 +14,23 @@
 //! - `flash_loans`: The flash loans component, which allows users to borrow STAB tokens from the Stabilis component.
 //! - `stabilis_liquidity_pool`: The liquidity pool component, which is a STAB/XRD liquidity pool native to the Stabilis protocol. It is used to determine the price of STAB tokens.
 //! - `oracle`: A component that aggregates oracle data and casts it into a form the Proxy Component is able to process.
+//! - `inverse_loans`: A component that allows STAB holders to borrow collateral (supplied from the treasury) against their STAB.
+//! - `registry`: A component recording the current addresses and versions of the other components, so they can be resolved by name.
+//! - `timelock`: A component queueing owner actions, executable after a timelock and vetoable by a guardian. It is the recommended owner of the Proxy component.
+//! - `savings`: A component wrapping STAB into stSTAB, a yield-bearing version of STAB whose redemption value grows as savings accrue.
+//! - `insurance_claims`: A component handling claims of borrowers wrongly liquidated on a bad oracle price, paid from the insurance fund after owner approval.
 //!
 //! More information on each component can be found in their respective modules.
 
+pub mod events;
 pub mod flash_loans;
+pub mod insurance_claims;
+pub mod inverse_loans;
+pub mod oracle;
 pub mod proxy;
+pub mod registry;
+pub mod savings;
 pub mod shared_structs;
 pub mod stabilis_component;
 pub mod stabilis_liquidity_pool;
-pub mod oracle;
-pub mod events;
\ No newline at end of file
+pub mod timelock;
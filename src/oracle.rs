@@ -1,5 +1,8 @@
 //! # Oracle Blueprint
 //! Component aggregating Oracle data and processes it into data usable by the Proxy Component.
+//!
+//! Signed price messages are verified by the adapter of the oracle provider they come from (Morpher by default).
+//! Each market can be fed by multiple providers, whose latest fresh prices are aggregated into a single price (their median).
 
 use scrypto::prelude::*;
 
@@ -11,31 +14,106 @@ pub struct PriceMessage {
     pub created_at: u64,
 }
 
+/// Adapter verifying the signed price messages of an oracle provider
+#[derive(ScryptoSbor, Clone)]
+pub struct OracleAdapter {
+    /// component verifying the messages
+    pub component: ComponentAddress,
+    /// method of the component taking a message and signature, returning the verified `PriceMessage`
+    pub verify_method: String,
+}
+
+/// Latest verified price of a market from a single provider
+#[derive(ScryptoSbor, Clone)]
+pub struct ProviderPrice {
+    /// price of the market
+    pub price: Decimal,
+    /// nonce of the message, later messages of the provider need a higher nonce
+    pub nonce: u64,
+    /// creation time of the message (seconds since unix epoch)
+    pub created_at: u64,
+}
+
+/// Latest accepted signed price message of a market, kept for replay protection and auditing
+#[derive(ScryptoSbor, Clone)]
+pub struct MarketUpdate {
+    /// provider the message came from
+    pub provider: String,
+    /// nonce of the message, later messages need a higher nonce
+    pub nonce: u64,
+    /// creation time of the message (seconds since unix epoch)
+    pub created_at: u64,
+    /// hash of the signed message
+    pub message_hash: Hash,
+    /// time the message was accepted
+    pub updated_at: Instant,
+}
+
+/// Volatility of a market over its latest prices, all values are relative (0.05 = 5%)
+#[derive(ScryptoSbor, Clone)]
+pub struct PriceVolatility {
+    /// number of prices used
+    pub samples: u64,
+    /// standard deviation of the price changes between consecutive prices
+    pub std_dev: Decimal,
+    /// largest drop from a previous high
+    pub max_drawdown: Decimal,
+}
+
+/// Name of the default oracle provider
+pub const MORPHER_PROVIDER: &str = "morpher";
+
 #[blueprint]
+#[types(
+    String,
+    Vec<Decimal>,
+    MarketUpdate,
+    HashSet<String>,
+    HashMap<String, ProviderPrice>
+)]
 mod oracle {
     enable_method_auth! {
         methods {
             get_prices => PUBLIC;
             set_price => PUBLIC;
+            set_sandbox_price => PUBLIC;
+            get_price_history => PUBLIC;
+            get_volatility => PUBLIC;
+            get_xrd_quoted_pairs => PUBLIC;
+            get_last_update => PUBLIC;
+            get_adapters => PUBLIC;
+            get_market_providers => PUBLIC;
+            get_provider_prices => PUBLIC;
+            set_provider_price => PUBLIC;
             add_pair => restrict_to: [OWNER];
+            set_adapter => restrict_to: [OWNER];
+            set_market_providers => restrict_to: [OWNER];
+            set_max_provider_age => restrict_to: [OWNER];
+            set_xrd_quoted => restrict_to: [OWNER];
+            set_history_length => restrict_to: [OWNER];
         }
     }
 
-    extern_blueprint! {
-        //"package_sim1pkgxxxxxxxxxpackgexxxxxxxxx000726633226xxxxxxxxxlk8hc9", //simulator package, uncomment to run tests
-        //"package_tdx_2_1phdppf684w8r4za9pwgafzc0zpmsvt7xlmyx8r7kzq2dlgns9k5war", //stokenet morpher package
-        "package_rdx1p5xvvessslnpnfam9weyzldlxr7q06gen2t3d3waa0x760g7jwxhkd", //mainnet morpher package
-        MorpherOracle {
-            fn check_price_input(&self, message: String, signature: String) -> PriceMessage;
-        }
-
-        // oracle address for stokenet: component_tdx_2_1cryq46xl9jxej3v8dr6q0lpnzhs4knlg6x2en6gynahghxztdvdp74
-        // oracle address for mainnet: component_rdx1cp07hrz378zfugcf6h8f9usct4zqx7rdgjhxjwphkzxyv9h7l2q04s
-    }
-
     struct Oracle {
         prices: Vec<(ResourceAddress, Decimal, u64, String)>,
-        oracle_address: ComponentAddress,
+        /// verification adapters per oracle provider
+        adapters: HashMap<String, OracleAdapter>,
+        /// providers whose prices are aggregated, per market
+        market_providers: KeyValueStore<String, HashSet<String>>,
+        /// latest verified price per provider, per market
+        provider_prices: KeyValueStore<String, HashMap<String, ProviderPrice>>,
+        /// maximum age of a provider's price compared to the newest price of its market to be aggregated (seconds)
+        max_provider_age: u64,
+        /// latest prices per market (oldest first)
+        price_history: KeyValueStore<String, Vec<Decimal>>,
+        /// number of prices kept per market
+        history_length: u64,
+        /// whether this is a sandbox oracle, on which prices can be set without signatures (set at instantiation, can't be changed)
+        sandbox: bool,
+        /// markets whose prices are denominated in XRD instead of USD (converted by the Proxy)
+        xrd_quoted: HashSet<String>,
+        /// latest accepted signed price message per market
+        last_updates: KeyValueStore<String, MarketUpdate>,
     }
 
     impl Oracle {
@@ -43,6 +121,24 @@ mod oracle {
             owner_role: OwnerRole,
             oracle_address: ComponentAddress,
             dapp_def_address: GlobalAddress,
+        ) -> Global<Oracle> {
+            Self::instantiate_internal(owner_role, oracle_address, dapp_def_address, false)
+        }
+
+        /// Instantiates a sandbox oracle, on which anyone can set prices without signatures (for staging environments of integrators)
+        pub fn instantiate_sandbox_oracle(
+            owner_role: OwnerRole,
+            oracle_address: ComponentAddress,
+            dapp_def_address: GlobalAddress,
+        ) -> Global<Oracle> {
+            Self::instantiate_internal(owner_role, oracle_address, dapp_def_address, true)
+        }
+
+        fn instantiate_internal(
+            owner_role: OwnerRole,
+            oracle_address: ComponentAddress,
+            dapp_def_address: GlobalAddress,
+            sandbox: bool,
         ) -> Global<Oracle> {
             let prices: Vec<(ResourceAddress, Decimal, u64, String)> = vec![(
                 XRD,
@@ -51,9 +147,39 @@ mod oracle {
                 "GATEIO:XRD_USDT".to_string(),
             )];
 
+            let price_history: KeyValueStore<String, Vec<Decimal>> =
+                OracleKeyValueStore::new_with_registered_type();
+            price_history.insert("GATEIO:XRD_USDT".to_string(), vec![dec!("0.015")]);
+
+            // morpher oracle address for stokenet: component_tdx_2_1cryq46xl9jxej3v8dr6q0lpnzhs4knlg6x2en6gynahghxztdvdp74
+            // morpher oracle address for mainnet: component_rdx1cp07hrz378zfugcf6h8f9usct4zqx7rdgjhxjwphkzxyv9h7l2q04s
+            let mut adapters: HashMap<String, OracleAdapter> = HashMap::new();
+            adapters.insert(
+                MORPHER_PROVIDER.to_string(),
+                OracleAdapter {
+                    component: oracle_address,
+                    verify_method: "check_price_input".to_string(),
+                },
+            );
+
+            let market_providers: KeyValueStore<String, HashSet<String>> =
+                OracleKeyValueStore::new_with_registered_type();
+            market_providers.insert(
+                "GATEIO:XRD_USDT".to_string(),
+                HashSet::from([MORPHER_PROVIDER.to_string()]),
+            );
+
             Self {
                 prices,
-                oracle_address,
+                adapters,
+                market_providers,
+                provider_prices: OracleKeyValueStore::new_with_registered_type(),
+                max_provider_age: 300,
+                price_history,
+                history_length: 50,
+                sandbox,
+                xrd_quoted: HashSet::new(),
+                last_updates: OracleKeyValueStore::new_with_registered_type(),
             }
             .instantiate()
             .prepare_to_globalize(owner_role)
@@ -73,17 +199,249 @@ mod oracle {
         }
 
         //manual price setting, not necessary after religant is available and part in get_prices can be uncommented
+        //sets a price through the morpher provider, see `set_provider_price`
         pub fn set_price(&mut self, message: String, signature: String) {
-            let morpher_oracle = Global::<MorpherOracle>::from(self.oracle_address);
-            let price_message = morpher_oracle.check_price_input(message, signature);
+            self.set_provider_price(MORPHER_PROVIDER.to_string(), message, signature);
+        }
+
+        /// Set a price through a signed message of an oracle provider
+        ///
+        /// # Input
+        /// - `provider`: The name of the provider the message comes from
+        /// - `message`: The signed price message
+        /// - `signature`: The signature of the message
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Verify the message with the provider's adapter
+        /// - Check the provider feeds the market, and the message is newer (higher nonce and creation time) than the provider's last message
+        /// - Store the provider's price and aggregate the fresh prices of all providers of the market into the market price
+        pub fn set_provider_price(&mut self, provider: String, message: String, signature: String) {
+            let message_hash: Hash = hash(message.as_bytes());
+            let adapter: OracleAdapter = self
+                .adapters
+                .get(&provider)
+                .expect("Unknown oracle provider.")
+                .clone();
+            let price_message: PriceMessage = Global::<AnyComponent>::from(adapter.component)
+                .call(&adapter.verify_method, &(message, signature));
+            let market_id: String = price_message.market_id.clone();
+
+            assert!(
+                self.get_market_providers(market_id.clone())
+                    .contains(&provider),
+                "Provider doesn't feed this market."
+            );
+
+            let mut provider_prices: HashMap<String, ProviderPrice> =
+                self.get_provider_prices(market_id.clone());
+            if let Some(last_price) = provider_prices.get(&provider) {
+                assert!(
+                    price_message.nonce > last_price.nonce,
+                    "Nonce already used or out of order."
+                );
+                assert!(
+                    price_message.created_at > last_price.created_at,
+                    "Price is too old"
+                );
+            }
+            provider_prices.insert(
+                provider.clone(),
+                ProviderPrice {
+                    price: price_message.price,
+                    nonce: price_message.nonce,
+                    created_at: price_message.created_at,
+                },
+            );
+            self.provider_prices
+                .insert(market_id.clone(), provider_prices);
+
+            let (price, created_at): (Decimal, u64) = self.aggregate_price(market_id.clone());
+
+            let mut updated: bool = false;
 
             for prices in self.prices.iter_mut() {
-                if prices.3 == price_message.market_id {
-                    assert!(price_message.created_at > prices.2, "Price is too old");
-                    prices.1 = price_message.price;
-                    prices.2 = price_message.created_at;
+                if prices.3 == market_id {
+                    prices.1 = price;
+                    prices.2 = created_at;
+                    updated = true;
                 }
             }
+
+            if updated {
+                self.last_updates.insert(
+                    market_id.clone(),
+                    MarketUpdate {
+                        provider,
+                        nonce: price_message.nonce,
+                        created_at: price_message.created_at,
+                        message_hash,
+                        updated_at: Clock::current_time_rounded_to_seconds(),
+                    },
+                );
+                self.record_price(market_id, price);
+            }
+        }
+
+        /// Get the verification adapters of the oracle providers
+        pub fn get_adapters(&self) -> HashMap<String, OracleAdapter> {
+            self.adapters.clone()
+        }
+
+        /// Get the providers whose prices are aggregated for a market
+        pub fn get_market_providers(&self, market_id: String) -> HashSet<String> {
+            self.market_providers
+                .get(&market_id)
+                .map(|providers| providers.clone())
+                .unwrap_or_default()
+        }
+
+        /// Get the latest verified price of each provider of a market
+        pub fn get_provider_prices(&self, market_id: String) -> HashMap<String, ProviderPrice> {
+            self.provider_prices
+                .get(&market_id)
+                .map(|prices| prices.clone())
+                .unwrap_or_default()
+        }
+
+        /// Set (or remove) the verification adapter of an oracle provider
+        pub fn set_adapter(&mut self, provider: String, adapter: Option<OracleAdapter>) {
+            match adapter {
+                Some(adapter) => {
+                    self.adapters.insert(provider, adapter);
+                }
+                None => {
+                    self.adapters.remove(&provider);
+                }
+            }
+        }
+
+        /// Set the providers whose prices are aggregated for a market
+        pub fn set_market_providers(&mut self, market_id: String, providers: Vec<String>) {
+            assert!(
+                !providers.is_empty(),
+                "A market needs at least one provider."
+            );
+            for provider in providers.iter() {
+                assert!(
+                    self.adapters.contains_key(provider),
+                    "Unknown oracle provider."
+                );
+            }
+            self.market_providers
+                .insert(market_id, providers.into_iter().collect());
+        }
+
+        /// Set the maximum age of a provider's price compared to the newest price of its market to be aggregated (seconds)
+        pub fn set_max_provider_age(&mut self, max_provider_age: u64) {
+            self.max_provider_age = max_provider_age;
+        }
+
+        /// Get the latest accepted signed price message of a market
+        pub fn get_last_update(&self, market_id: String) -> Option<MarketUpdate> {
+            self.last_updates
+                .get(&market_id)
+                .map(|update| update.clone())
+        }
+
+        /// Set a price without a signature, only possible on a sandbox oracle
+        pub fn set_sandbox_price(&mut self, market_id: String, price: Decimal) {
+            assert!(self.sandbox, "Only possible on a sandbox oracle.");
+
+            let now: u64 = Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch as u64;
+            let mut updated: bool = false;
+
+            for prices in self.prices.iter_mut() {
+                if prices.3 == market_id {
+                    prices.1 = price;
+                    prices.2 = now;
+                    updated = true;
+                }
+            }
+
+            if updated {
+                self.record_price(market_id, price);
+            }
+        }
+
+        /// Get the latest prices of a market (oldest first)
+        pub fn get_price_history(&self, market_id: String) -> Vec<Decimal> {
+            self.price_history
+                .get(&market_id)
+                .map(|history| history.clone())
+                .unwrap_or_default()
+        }
+
+        /// Get the volatility of a market over its latest `window` prices
+        ///   - the standard deviation is calculated over the relative changes between consecutive prices
+        ///   - the max drawdown is the largest relative drop from a previous high within the window
+        pub fn get_volatility(&self, market_id: String, window: u64) -> PriceVolatility {
+            let history: Vec<Decimal> = self.get_price_history(market_id);
+            let start: usize = history.len().saturating_sub(window as usize);
+            let prices: &[Decimal] = &history[start..];
+
+            let changes: Vec<Decimal> = prices
+                .windows(2)
+                .filter(|pair| pair[0] > dec!(0))
+                .map(|pair| pair[1] / pair[0] - dec!(1))
+                .collect();
+
+            let std_dev: Decimal = match changes.is_empty() {
+                true => dec!(0),
+                false => {
+                    let count: Decimal = Decimal::from(changes.len() as u64);
+                    let mean: Decimal = changes
+                        .iter()
+                        .fold(dec!(0), |total, change| total + *change)
+                        / count;
+                    let variance: Decimal = changes.iter().fold(dec!(0), |total, change| {
+                        total + (*change - mean) * (*change - mean)
+                    }) / count;
+                    variance.checked_sqrt().unwrap()
+                }
+            };
+
+            let mut max_drawdown: Decimal = dec!(0);
+            let mut high: Decimal = dec!(0);
+            for price in prices {
+                if *price > high {
+                    high = *price;
+                } else if high > dec!(0) && dec!(1) - *price / high > max_drawdown {
+                    max_drawdown = dec!(1) - *price / high;
+                }
+            }
+
+            PriceVolatility {
+                samples: prices.len() as u64,
+                std_dev,
+                max_drawdown,
+            }
+        }
+
+        /// Get the markets whose prices are denominated in XRD
+        pub fn get_xrd_quoted_pairs(&self) -> Vec<String> {
+            self.xrd_quoted.iter().cloned().collect()
+        }
+
+        /// Mark a market as denominated in XRD (or in USD again), for collaterals that only have reliable XRD-pair feeds
+        pub fn set_xrd_quoted(&mut self, market_id: String, xrd_quoted: bool) {
+            assert!(
+                market_id != "GATEIO:XRD_USDT",
+                "The XRD/USD market can't be XRD-quoted."
+            );
+            if xrd_quoted {
+                self.xrd_quoted.insert(market_id);
+            } else {
+                self.xrd_quoted.remove(&market_id);
+            }
+        }
+
+        /// Set the number of prices kept per market
+        pub fn set_history_length(&mut self, history_length: u64) {
+            assert!(history_length > 0, "History length needs to be positive.");
+            self.history_length = history_length;
         }
 
         pub fn add_pair(
@@ -96,8 +454,61 @@ mod oracle {
                 resource_address,
                 starting_price,
                 Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch as u64,
-                market_id,
+                market_id.clone(),
             ));
+            if self.market_providers.get(&market_id).is_none() {
+                self.market_providers.insert(
+                    market_id.clone(),
+                    HashSet::from([MORPHER_PROVIDER.to_string()]),
+                );
+            }
+            self.record_price(market_id, starting_price);
+        }
+
+        /// Aggregate the prices of a market's providers into its price and creation time
+        ///   - only prices of current providers, at most `max_provider_age` older than the newest price, are used
+        ///   - the price is the median of those prices, the creation time is that of the newest price
+        fn aggregate_price(&self, market_id: String) -> (Decimal, u64) {
+            let providers: HashSet<String> = self.get_market_providers(market_id.clone());
+            let provider_prices: Vec<ProviderPrice> = self
+                .get_provider_prices(market_id)
+                .into_iter()
+                .filter(|(provider, _)| {
+                    providers.contains(provider) && self.adapters.contains_key(provider)
+                })
+                .map(|(_, price)| price)
+                .collect();
+
+            let newest: u64 = provider_prices
+                .iter()
+                .map(|price| price.created_at)
+                .max()
+                .expect("No provider prices.");
+            let mut prices: Vec<Decimal> = provider_prices
+                .iter()
+                .filter(|price| price.created_at + self.max_provider_age >= newest)
+                .map(|price| price.price)
+                .collect();
+            prices.sort();
+
+            let middle: usize = prices.len() / 2;
+            let median: Decimal = match prices.len() % 2 {
+                0 => (prices[middle - 1] + prices[middle]) / dec!(2),
+                _ => prices[middle],
+            };
+
+            (median, newest)
+        }
+
+        /// Add a price to the history of a market, removing the oldest prices if the history is full
+        fn record_price(&mut self, market_id: String, price: Decimal) {
+            let mut history: Vec<Decimal> = self.get_price_history(market_id.clone());
+            history.push(price);
+
+            let excess: usize = history.len().saturating_sub(self.history_length as usize);
+            history.drain(..excess);
+
+            self.price_history.insert(market_id, history);
         }
     }
 }
@@ -14,10 +14,18 @@
 //! Methods used to call other components only are explained in their respective modules.
 //! Sometimes, a proof is checked within this component, as they cannot be passed along to other components. The ID for this proof is then passed along, for the other component to check the proofs data.
 
+use crate::events::*;
 use crate::flash_loans::flash_loans::*;
+use crate::flash_loans::FlashTerms;
 use crate::oracle::oracle::*;
+use crate::savings::savings::*;
 use crate::shared_structs::*;
 use crate::stabilis_component::stabilis_component::*;
+use crate::stabilis_component::{
+    BorrowRewardsInfo, CdpFreeze, DeleverageSettings, ForceMintCap, ForceMintLimit,
+    IncentiveRouteInfo, ProtocolParameters, ShareEnforcementStatus, SweptLeftover,
+    WithdrawalWindow,
+};
 use crate::stabilis_liquidity_pool::stabilis_liquidity_pool::*;
 use scrypto::prelude::*;
 use scrypto_math::*;
@@ -31,49 +39,185 @@ use scrypto_math::*;
     u64,
     CdpUpdate,
     Instant,
-    NonFungibleLocalId
+    NonFungibleLocalId,
+    Vault,
+    RateCheckpoint,
+    CustodyChange
+)]
+#[events(
+    EventSystemState,
+    EventPriceOverride,
+    EventPriceOverrideExpired,
+    EventEqualization,
+    EventRebrand,
+    EventPegAlert,
+    EventPegRecovered,
+    EventCustodyChange,
+    EventRateMode
 )]
 mod proxy {
     enable_method_auth! {
         methods {
             open_cdp => PUBLIC;
+            open_cdps => PUBLIC;
+            open_cdp_from_buckets => PUBLIC;
             borrow_more => PUBLIC;
             top_up_cdp => PUBLIC;
+            deposit_to_buffer => PUBLIC;
+            withdraw_from_buffer => PUBLIC;
+            apply_buffer => PUBLIC;
+            set_auto_deleverage => PUBLIC;
+            get_auto_deleverage => PUBLIC;
+            set_refund_account => PUBLIC;
+            get_refund_account => PUBLIC;
+            auto_deleverage => PUBLIC;
+            set_force_mint_cap => PUBLIC;
+            get_force_mint_cap => PUBLIC;
+            set_yield_harvest => PUBLIC;
+            get_yield_harvest => PUBLIC;
+            harvest_yield => PUBLIC;
+            get_buffer => PUBLIC;
+            get_cdp_freeze => PUBLIC;
+            get_cdp_stats => PUBLIC;
+            get_marker => PUBLIC;
+            get_markers_for_cdp => PUBLIC;
+            get_collateral_activity => PUBLIC;
+            claim_borrow_rewards => PUBLIC;
+            get_borrow_rewards => PUBLIC;
+            get_pending_borrow_rewards => PUBLIC;
+            get_incentive_route => PUBLIC;
+            distribute_incentives => PUBLIC;
+            set_incentive_route => restrict_to: [OWNER];
+            get_risk_parameter_changes => PUBLIC;
+            set_cdp_label => PUBLIC;
+            protect_cdp => PUBLIC;
             remove_collateral => PUBLIC;
+            move_collateral => PUBLIC;
             close_cdp => PUBLIC;
+            close_dust_cdp => PUBLIC;
             partial_close_cdp => PUBLIC;
+            migrate_cdp => PUBLIC;
             retrieve_leftover_collateral => PUBLIC;
+            retrieve_leftovers => PUBLIC;
+            reimburse_swept_leftover => PUBLIC;
+            get_swept_leftover => PUBLIC;
+            claim_withdrawal => PUBLIC;
+            get_withdrawal_window => PUBLIC;
+            get_queued_withdrawals => PUBLIC;
+            get_mark_rate => PUBLIC;
+            get_mcr_surcharge => PUBLIC;
+            get_share_enforcement => PUBLIC;
+            get_cr_histogram => PUBLIC;
+            get_global_cr => PUBLIC;
+            get_supply_breakdown => PUBLIC;
+            export_cdps => PUBLIC;
             mark_for_liquidation => PUBLIC;
             liquidate_position_with_marker => PUBLIC;
+            liquidate_and_burn_marker => PUBLIC;
             liquidate_position_without_marker => PUBLIC;
+            liquidate_position_with_marker_split => PUBLIC;
+            liquidate_and_burn_marker_split => PUBLIC;
+            liquidate_position_without_marker_split => PUBLIC;
             update => PUBLIC;
             get_internal_price => PUBLIC;
             flash_borrow => PUBLIC;
             flash_pay_back => PUBLIC;
+            get_flash_terms => PUBLIC;
+            preview_interest_update => PUBLIC;
+            get_controller_state => PUBLIC;
+            get_rate_mode => PUBLIC;
+            get_average_interest_rates => PUBLIC;
+            get_peg_alarm => PUBLIC;
+            get_lp_collateral => PUBLIC;
+            get_lp_price => PUBLIC;
             burn_marker => PUBLIC;
+            burn_markers => PUBLIC;
             burn_loan_receipt => PUBLIC;
             force_mint => PUBLIC;
             force_liquidate => PUBLIC;
             receive_badges => PUBLIC;
+            quote_open => PUBLIC;
+            quote_close => PUBLIC;
+            protocol_info => PUBLIC;
+            get_liquidation_receipts => PUBLIC;
+            redeem_liquidation_receipt => PUBLIC;
+            deposit_keeper_rewards => PUBLIC;
+            get_keeper_rewards => PUBLIC;
+            get_stability_reserve => PUBLIC;
+            donate_and_burn => PUBLIC;
+            get_donated_stab => PUBLIC;
+            get_resource_addresses => PUBLIC;
+            get_cdp_projection => PUBLIC;
+            get_borrow_cost => PUBLIC;
+            get_treasury_balances => PUBLIC;
+            get_price_override => PUBLIC;
+            get_unconfirmed_price => PUBLIC;
+            get_share_warnings => PUBLIC;
+            execute_signed_admin_action => PUBLIC;
+            set_admin_signer => restrict_to: [OWNER];
             change_collateral_price => restrict_to: [OWNER];
+            override_price => restrict_to: [OWNER];
             set_max_vector_length => restrict_to: [OWNER];
             set_price_error => restrict_to: [OWNER];
             set_minmax_interest => restrict_to: [OWNER];
             set_update_delays => restrict_to: [OWNER];
+            set_interest_rate_windows => restrict_to: [OWNER];
+            set_rate_mode => restrict_to: [OWNER];
             set_ks => restrict_to: [OWNER];
             set_allowed_deviation => restrict_to: [OWNER];
+            set_max_price_range => restrict_to: [OWNER];
+            set_max_price_drift => restrict_to: [OWNER];
             add_collateral => restrict_to: [OWNER];
+            remove_collateral_tracking => restrict_to: [OWNER];
             add_pool_collateral => restrict_to: [OWNER];
+            add_adapter_pool_collateral => restrict_to: [OWNER];
+            add_lp_collateral => restrict_to: [OWNER];
+            set_lp_haircut => restrict_to: [OWNER];
             change_internal_price => restrict_to: [OWNER];
             set_oracle => restrict_to: [OWNER];
+            set_confirmation_oracle => restrict_to: [OWNER];
             send_badges => restrict_to: [OWNER];
+            set_badge_receiver => restrict_to: [OWNER];
+            mint_insurance_badge => restrict_to: [OWNER];
+            get_badge_receivers => PUBLIC;
+            get_custody_log => PUBLIC;
             flash_retrieve_interest => restrict_to: [OWNER];
+            rescue_flash_loans_tokens => restrict_to: [OWNER];
+            rescue_pool_tokens => restrict_to: [OWNER];
+            set_equalization => restrict_to: [OWNER];
+            set_peg_alarm => restrict_to: [OWNER];
             set_force_mint_liq_percentage => restrict_to: [OWNER];
             set_number_of_prices_cached => restrict_to: [OWNER];
             add_pair_to_oracle => restrict_to: [OWNER];
             set_reward_per_second => restrict_to: [OWNER];
             put_reward_in_vault => PUBLIC;
+            add_reward_resource => restrict_to: [OWNER];
+            set_reward_resource => restrict_to: [OWNER];
+            take_rewards => restrict_to: [OWNER];
+            rescue_tokens => restrict_to: [OWNER];
             add_claimed_website => restrict_to: [OWNER];
+            rebrand => restrict_to: [OWNER];
+            sweep_unclaimed_leftover => restrict_to: [OWNER];
+            set_collateral_display => restrict_to: [OWNER];
+            get_collateral_displays => PUBLIC;
+            get_hooks => PUBLIC;
+            get_liquidity_sources => PUBLIC;
+            get_best_quote => PUBLIC;
+            swap_with_best_source => PUBLIC;
+            get_xrd_pool_adapters => PUBLIC;
+            quote_route => PUBLIC;
+            swap_route => PUBLIC;
+            flash_liquidate => PUBLIC;
+            validate_collateral_listing => PUBLIC;
+            add_liquidity_source => restrict_to: [OWNER];
+            remove_liquidity_source => restrict_to: [OWNER];
+            set_xrd_pool_adapter => restrict_to: [OWNER];
+            set_liquidity_source_cap => restrict_to: [OWNER];
+            add_hook => restrict_to: [OWNER];
+            remove_hook => restrict_to: [OWNER];
+            set_hook_enabled => restrict_to: [OWNER];
+            set_max_hook_weight => restrict_to: [OWNER];
+            set_xrd_quoted_pricing => restrict_to: [OWNER];
         }
     }
 
@@ -82,12 +226,36 @@ mod proxy {
         badge_vault: FungibleVault,
         /// The global instance of the StabilisPool component
         stab_pool: Global<StabilisPool>,
+        /// The address of the STAB token
+        stab_address: ResourceAddress,
+        /// Whitelisted external DEX adapter components, used next to the StabilisPool for swaps
+        liquidity_sources: HashMap<ComponentAddress, LiquiditySource>,
+        /// External XRD/collateral pool adapter components, used to route swaps between STAB and collateral through XRD
+        xrd_pool_adapters: HashMap<ResourceAddress, ComponentAddress>,
         /// The global instance of the Stabilis component
         stabilis: Global<Stabilis>,
         /// The global instance of the oracle component
         oracle: Global<AnyComponent>,
         /// The name of the method to call on the oracle component
         oracle_method_name: String,
+        /// The oracle component used to confirm large price drops
+        confirmation_oracle: Option<Global<AnyComponent>>,
+        /// The name of the method to call on the confirmation oracle component
+        confirmation_oracle_method_name: String,
+        /// The maximum price drop (0.1 = 10%) that can be applied without confirmation by the confirmation oracle
+        max_unconfirmed_price_drop: Decimal,
+        /// The latest collateral prices sent to the Stabilis component
+        collateral_prices: HashMap<ResourceAddress, Decimal>,
+        /// Oracle prices of large drops awaiting confirmation, used for withdrawal and borrow checks until confirmed or recovered
+        unconfirmed_prices: HashMap<ResourceAddress, Decimal>,
+        /// Emergency collateral price overrides, taking precedence over the oracle until they expire
+        price_overrides: HashMap<ResourceAddress, (Decimal, Instant)>,
+        /// Display metadata of the collaterals (and pool units), for frontends
+        collateral_displays: HashMap<ResourceAddress, CollateralDisplay>,
+        /// Whitelisted extension components called after loan lifecycle events
+        hooks: HashMap<ComponentAddress, CdpHook>,
+        /// The maximum total weight of the hooks called for a single event
+        max_hook_weight: u64,
         /// The global instance of the flash loans component
         flash_loans: Global<FlashLoans>,
         /// The delay between updates (minutes)
@@ -100,6 +268,10 @@ mod proxy {
         cdp_marker_manager: ResourceManager,
         /// The price of the XRD token
         xrd_price: Decimal,
+        /// The method of the oracle component returning the markets denominated in XRD (None if the oracle has none)
+        xrd_quoted_method: Option<String>,
+        /// The maximum age (seconds) of the XRD price for XRD-denominated prices to be converted and applied
+        max_xrd_price_age: u64,
         /// The collaterals accepted by the Stabilis component
         accepted_collaterals: HashMap<ResourceAddress, u64>,
         /// The percentage of the collateral to supply when force minting
@@ -110,12 +282,36 @@ mod proxy {
         parameters: InterestParameters,
         /// Data about STAB's price
         stab_price_data: StabPriceData,
-        /// Reward vault for updating the prices
-        reward_vault: Vault,
+        /// Whether the PID controller sets the interest rate, or the DAO fixed it for a period
+        rate_mode: RateMode,
+        /// History of the interest rate, for time-weighted averages
+        interest_rate_history: InterestRateHistory,
+        /// Parameters for distributing flash loan income to STAB savers during negative interest rates
+        equalization: EqualizationParameters,
+        /// Peg deviation alarm, emitting events when the market price deviates from the internal price for too long
+        peg_alarm: PegAlarm,
+        /// The STAB/XRD LP token as collateral, priced from the StabilisPool's reserves instead of the oracle
+        lp_collateral: LpCollateral,
+        /// Reward vaults for updating the prices, per reward resource
+        reward_vaults: KeyValueStore<ResourceAddress, Vault>,
+        /// The resource currently used to reward updating the prices
+        reward_address: ResourceAddress,
         /// The reward per second for updating the prices
         reward_per_second: Decimal,
         /// The dapp definition account
         dapp_def_account: Global<Account>,
+        /// The public key allowed to sign admin actions that can then be relayed by anyone
+        admin_signer: Option<Bls12381G1PublicKey>,
+        /// The nonce the next signed admin action needs to use
+        admin_action_nonce: u64,
+        /// Components allowed to receive controller badges through `send_badges`
+        badge_receivers: HashSet<ComponentAddress>,
+        /// Append-only log of controller badge movements and oracle changes
+        custody_log: KeyValueStore<u64, CustodyChange>,
+        /// The id of the next custody log entry
+        custody_log_counter: u64,
+        /// Whether a guarded method (price updates, liquidations) is executing, so components called mid-flight can't re-enter one
+        entered: bool,
     }
 
     impl Proxy {
@@ -129,6 +325,7 @@ mod proxy {
         /// - `cdp_marker_address`: The resource address of the CDP markers (created by the Stabilis component)
         /// - `oracle_address`: The address of the oracle component
         /// - `stabilis_address`: The address of the Stabilis component
+        /// - `config`: The genesis configuration (interest rate parameters, price update settings and metadata urls)
         ///
         /// # Output
         /// - The global instance of the Proxy component
@@ -136,6 +333,7 @@ mod proxy {
         /// - The optional bucket for the leftover LP tokens (STAB/XRD, generated by the StabilisPool component)
         ///
         /// # Logic
+        /// - Validates the genesis configuration
         /// - Instantiates the StabilisPool component
         ///     - Adds liquidity to the STAB/XRD pool
         /// - Gets the internal price of the STAB token
@@ -151,7 +349,10 @@ mod proxy {
             cdp_marker_address: ResourceAddress,
             stabilis_address: ComponentAddress,
             reward_address: ResourceAddress,
+            config: GenesisConfig,
         ) -> (Global<Proxy>, Bucket, Option<Bucket>) {
+            config.validate();
+
             let (address_reservation, component_address) =
                 Runtime::allocate_component_address(Proxy::blueprint_id());
 
@@ -162,32 +363,60 @@ mod proxy {
             let stabilis: Global<Stabilis> = Global::from(stabilis_address);
 
             let controller_address: ResourceAddress = controller_badge.resource_address();
+            let stab_address: ResourceAddress = stab_bucket.resource_address();
 
             let stab_pool: Global<StabilisPool> = StabilisPool::new(
                 OwnerRole::Fixed(rule!(require(controller_address))),
                 stab_bucket.resource_address(),
                 xrd_bucket.resource_address(),
                 dec!(0.001),
+                config.min_pool_liquidity,
                 dapp_def_address,
             );
 
             let (lp_tokens, optional_return_bucket): (Bucket, Option<Bucket>) =
                 stab_pool.add_liquidity(stab_bucket, xrd_bucket);
+            assert!(
+                stab_pool.get_invariant().checked_sqrt().unwrap() >= config.min_pool_liquidity,
+                "Genesis liquidity needs to be at least the minimum pool liquidity."
+            );
 
             let internal_price: Decimal =
                 controller_badge.authorize_with_all(|| stabilis.return_internal_price());
 
+            let rate_checkpoints: KeyValueStore<u64, RateCheckpoint> =
+                ProxyKeyValueStore::new_with_registered_type();
+            rate_checkpoints.insert(
+                0,
+                RateCheckpoint {
+                    timestamp: Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch,
+                    cumulative: dec!(0),
+                },
+            );
+
+            let reward_vaults: KeyValueStore<ResourceAddress, Vault> =
+                ProxyKeyValueStore::new_with_registered_type();
+            reward_vaults.insert(reward_address, Vault::new(reward_address));
+
             let mut accepted_collaterals: HashMap<ResourceAddress, u64> = HashMap::new();
             accepted_collaterals.insert(
                 XRD,
                 Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch as u64,
             );
 
-            let own_oracle_address: ComponentAddress = Oracle::instantiate_oracle(
-                owner_role.clone(),
-                morpher_oracle_address,
-                dapp_def_address,
-            )
+            // a sandbox Stabilis component gets a sandbox oracle, so prices can be set freely in staging environments
+            let own_oracle_address: ComponentAddress = match stabilis.is_sandbox() {
+                true => Oracle::instantiate_sandbox_oracle(
+                    owner_role.clone(),
+                    morpher_oracle_address,
+                    dapp_def_address,
+                ),
+                false => Oracle::instantiate_oracle(
+                    owner_role.clone(),
+                    morpher_oracle_address,
+                    dapp_def_address,
+                ),
+            }
             .address();
 
             let flash_loans = FlashLoans::instantiate(
@@ -200,17 +429,15 @@ mod proxy {
             dapp_def_account.set_metadata("name", "STAB Protocol".to_string());
             dapp_def_account
                 .set_metadata("description", "Bringing stable assets to Radix".to_string());
-            dapp_def_account.set_metadata("info_url", Url::of("https://ilikeitstable.com"));
-            dapp_def_account.set_metadata(
-                "icon_url",
-                Url::of("https://ilikeitstable.com/images/stablogo.png"),
-            );
+            dapp_def_account.set_metadata("info_url", Url::of(config.info_url.clone()));
+            dapp_def_account.set_metadata("icon_url", Url::of(config.stab_icon_url.clone()));
             dapp_def_account.set_metadata(
                 "claimed_websites",
-                vec![
-                    Url::of("https://ilikeitstable.com"),
-                    Url::of("https://beta.ilikeitstable.com"),
-                ],
+                config
+                    .claimed_websites
+                    .iter()
+                    .map(|website| Url::of(website.clone()))
+                    .collect::<Vec<Url>>(),
             );
             dapp_def_account.set_metadata(
                 "claimed_entities",
@@ -228,38 +455,93 @@ mod proxy {
                 flash_loans,
                 badge_vault: FungibleVault::with_bucket(controller_badge.as_fungible()),
                 stab_pool,
+                stab_address,
+                liquidity_sources: HashMap::new(),
+                xrd_pool_adapters: HashMap::new(),
                 stabilis,
                 oracle: Global::from(own_oracle_address),
                 oracle_method_name: "get_prices".to_string(),
-                update_delay: 1,
-                number_of_cached_prices: 50,
+                confirmation_oracle: None,
+                confirmation_oracle_method_name: "get_prices".to_string(),
+                max_unconfirmed_price_drop: dec!("0.1"),
+                collateral_prices: HashMap::new(),
+                unconfirmed_prices: HashMap::new(),
+                price_overrides: HashMap::new(),
+                collateral_displays: HashMap::new(),
+                hooks: HashMap::new(),
+                max_hook_weight: 10,
+                update_delay: config.update_delay,
+                number_of_cached_prices: config.number_of_cached_prices,
                 cdp_receipt_manager: ResourceManager::from_address(cdp_receipt_address),
                 cdp_marker_manager: ResourceManager::from_address(cdp_marker_address),
                 xrd_price: dec!("0.041"),
+                xrd_quoted_method: None,
+                max_xrd_price_age: 3600,
                 accepted_collaterals,
                 percentage_to_supply: dec!("1.05"),
                 percentage_to_take: dec!("0.95"),
                 stab_price_data: StabPriceData {
                     latest_stab_price_errors: ProxyKeyValueStore::new_with_registered_type(),
+                    latest_stab_price_error_times: ProxyKeyValueStore::new_with_registered_type(),
                     latest_stab_price_errors_total: dec!(0),
                     last_update: Clock::current_time_rounded_to_seconds(),
                     last_changed_price: 0,
                     internal_price,
                     full_cache: false,
                     interest_rate: dec!(1),
+                    price_drift_remainder: PreciseDecimal::ONE,
+                    last_rate_delta: dec!(0),
+                    last_rate_clamped: false,
+                },
+                rate_mode: RateMode::Pid,
+                equalization: EqualizationParameters {
+                    savings: None,
+                    share: dec!(0),
+                    delay: 1440,
+                    negative_since: None,
+                    last_distribution: Clock::current_time_rounded_to_seconds(),
+                },
+                peg_alarm: PegAlarm {
+                    alert_threshold: dec!("0.02"),
+                    recovery_threshold: dec!("0.01"),
+                    duration: 60,
+                    deviating_since: None,
+                    alerted: false,
+                },
+                lp_collateral: LpCollateral {
+                    address: lp_tokens.resource_address(),
+                    haircut: dec!("0.1"),
+                },
+                interest_rate_history: InterestRateHistory {
+                    checkpoints: rate_checkpoints,
+                    checkpoint_counter: 1,
+                    cumulative: dec!(0),
+                    last_accumulation: Clock::current_time_rounded_to_seconds(),
+                    checkpoint_interval: 3600,
+                    max_checkpoints: 744,
+                    windows: vec![86400, 604800, 2592000],
                 },
                 parameters: InterestParameters {
-                    kp: dec!("0.00000000076517857"),
-                    ki: dec!("0.00000000076517857"),
-                    max_interest_rate: dec!("1.0000007715"),
-                    min_interest_rate: dec!("0.9999992287"),
-                    allowed_deviation: dec!("0.005"),
+                    kp: config.kp,
+                    ki: config.ki,
+                    max_interest_rate: config.max_interest_rate,
+                    min_interest_rate: config.min_interest_rate,
+                    allowed_deviation: config.allowed_deviation,
                     price_error_offset: dec!(1),
                     max_price_error: dec!(0.5),
+                    max_price_range: dec!("0.05"),
+                    max_price_drift: dec!("0.01"),
                 },
-                reward_vault: Vault::new(reward_address),
-                reward_per_second: dec!("0.02"),
+                reward_vaults,
+                reward_address,
+                reward_per_second: config.reward_per_second,
                 dapp_def_account,
+                admin_signer: None,
+                admin_action_nonce: 0,
+                badge_receivers: HashSet::new(),
+                custody_log: ProxyKeyValueStore::new_with_registered_type(),
+                custody_log_counter: 0,
+                entered: false,
             }
             .instantiate()
             .prepare_to_globalize(owner_role)
@@ -268,7 +550,7 @@ mod proxy {
                 init {
                     "name" => "STAB Protocol Proxy".to_string(), updatable;
                     "description" => "A proxy component for the STAB Protocol".to_string(), updatable;
-                    "info_url" => Url::of("https://ilikeitstable.com"), updatable;
+                    "info_url" => Url::of(config.info_url), updatable;
                     "dapp_definition" => dapp_def_address, updatable;
                 }
             })
@@ -287,16 +569,29 @@ mod proxy {
         ///
         /// # Logic
         /// - Updates the collateral prices
+        /// - Updates the price of the STAB/XRD LP token, if it is accepted as collateral
         /// - Checks if the internal price needs to be updated
         /// - Updates the internal price if needed
+        /// - Distributes flash loan income to STAB savers, if the interest rate has been negative long enough
+        /// - Emits the state of the system
         pub fn update(&mut self) -> Option<Bucket> {
+            self.enter();
             self.update_internal_price();
-            self.update_collateral_prices()
+            self.distribute_equalization();
+            let reward: Option<Bucket> = self.update_collateral_prices();
+            self.update_lp_collateral_price();
+            self.emit_system_state();
+            self.exit();
+            reward
         }
 
         /// Receives controller badges
         pub fn receive_badges(&mut self, badge_bucket: Bucket) {
+            let amount: Decimal = badge_bucket.amount();
             self.badge_vault.put(badge_bucket.as_fungible());
+            if amount > dec!(0) {
+                self.record_custody_change(CustodyAction::ReceiveBadges, None, amount);
+            }
         }
 
         //==================================================================
@@ -314,6 +609,20 @@ mod proxy {
             self.parameters.allowed_deviation = allowed_deviation;
         }
 
+        /// Sets the maximum relative range ((high - low) / low) the pool price can move between interest rate updates, before the price sample is ignored
+        pub fn set_max_price_range(&mut self, max_price_range: Decimal) {
+            self.parameters.max_price_range = max_price_range;
+        }
+
+        /// Sets the maximum relative change of the internal price per update, drift above it is carried to the next updates
+        pub fn set_max_price_drift(&mut self, max_price_drift: Decimal) {
+            assert!(
+                max_price_drift > dec!(0),
+                "Max price drift needs to be positive."
+            );
+            self.parameters.max_price_drift = max_price_drift;
+        }
+
         /// Sets the number of prices to cache for the interest rate calculation
         pub fn set_number_of_prices_cached(&mut self, new_number: u64) {
             self.number_of_cached_prices = new_number;
@@ -343,33 +652,245 @@ mod proxy {
             self.update_delay = update_delay;
         }
 
+        /// Sets the windows (seconds) of the time-weighted average interest rates
+        ///   - windows can't exceed the stored history (by default 31 days of hourly checkpoints)
+        pub fn set_interest_rate_windows(&mut self, windows: Vec<i64>) {
+            let max_window: i64 = self.interest_rate_history.checkpoint_interval
+                * self.interest_rate_history.max_checkpoints as i64;
+            for window in windows.iter() {
+                assert!(
+                    *window > 0 && *window <= max_window,
+                    "Window exceeds the stored interest rate history."
+                );
+            }
+            self.interest_rate_history.windows = windows;
+        }
+
+        /// Sets the interest rate mode: the PID controller, or a fixed rate until a given time (e.g. during an oracle migration)
+        ///   - a fixed rate needs to be within the min/max interest rates, and is applied from the next internal price update on
+        ///   - once the fixed period has passed, the mode automatically reverts to the PID controller, which continues from the fixed rate
+        pub fn set_rate_mode(&mut self, mode: RateMode) {
+            if let RateMode::Fixed(rate, until) = &mode {
+                assert!(
+                    *rate >= self.parameters.min_interest_rate
+                        && *rate <= self.parameters.max_interest_rate,
+                    "Fixed rate needs to be within the min/max interest rates."
+                );
+                assert!(
+                    Clock::current_time_is_strictly_before(*until, TimePrecision::Second),
+                    "Fixed rate needs to end in the future."
+                );
+            }
+            self.rate_mode = mode.clone();
+            Runtime::emit_event(EventRateMode {
+                mode,
+                expired: false,
+            });
+        }
+
+        pub fn get_rate_mode(&self) -> RateMode {
+            self.rate_mode.clone()
+        }
+
+        /// Reverts a fixed interest rate to the PID controller once its period has passed
+        fn check_rate_mode(&mut self) {
+            if let RateMode::Fixed(_rate, until) = self.rate_mode {
+                if Clock::current_time_is_strictly_after(until, TimePrecision::Second) {
+                    self.rate_mode = RateMode::Pid;
+                    Runtime::emit_event(EventRateMode {
+                        mode: RateMode::Pid,
+                        expired: true,
+                    });
+                }
+            }
+        }
+
+        /// Gets the fixed interest rate, if one is set and its period hasn't passed yet
+        fn active_fixed_rate(&self) -> Option<Decimal> {
+            match self.rate_mode {
+                RateMode::Fixed(rate, until)
+                    if Clock::current_time_is_at_or_before(until, TimePrecision::Second) =>
+                {
+                    Some(rate)
+                }
+                _ => None,
+            }
+        }
+
         /// Sets the k values for the interest rate calculation
         pub fn set_ks(&mut self, new_ki: Decimal, new_kp: Decimal) {
             self.parameters.ki = new_ki;
             self.parameters.kp = new_kp;
         }
 
-        /// Sets the oracle component and method to call
-        pub fn set_oracle(&mut self, oracle_address: ComponentAddress, method_name: String) {
+        /// Sets the oracle component and method to call, and the method returning its markets denominated in XRD (None if the new oracle has none)
+        ///   - the XRD-denominated markets are set together with the oracle, so a new oracle is never called with a method of the old one
+        pub fn set_oracle(
+            &mut self,
+            oracle_address: ComponentAddress,
+            method_name: String,
+            xrd_quoted_method: Option<String>,
+        ) {
+            self.record_custody_change(
+                CustodyAction::SetOracle {
+                    method_name: method_name.clone(),
+                },
+                Some(oracle_address),
+                dec!(0),
+            );
             self.oracle = Global::from(oracle_address);
             self.oracle_method_name = method_name;
+            self.xrd_quoted_method = xrd_quoted_method;
+        }
+
+        /// Sets the oracle method returning the markets denominated in XRD (None if the oracle has none), and the maximum age (seconds) of the XRD price to convert them with
+        pub fn set_xrd_quoted_pricing(
+            &mut self,
+            method_name: Option<String>,
+            max_xrd_price_age: u64,
+        ) {
+            self.xrd_quoted_method = method_name;
+            self.max_xrd_price_age = max_xrd_price_age;
+        }
+
+        /// Sets the oracle component used to confirm large price drops, the method to call on it, and the maximum price drop that needs no confirmation
+        ///   - without a confirmation oracle, all price drops are applied immediately
+        pub fn set_confirmation_oracle(
+            &mut self,
+            oracle_address: Option<ComponentAddress>,
+            method_name: String,
+            max_unconfirmed_price_drop: Decimal,
+        ) {
+            self.record_custody_change(
+                CustodyAction::SetConfirmationOracle {
+                    method_name: method_name.clone(),
+                },
+                oracle_address,
+                dec!(0),
+            );
+            self.confirmation_oracle = oracle_address.map(|address| Global::from(address));
+            self.confirmation_oracle_method_name = method_name;
+            self.max_unconfirmed_price_drop = max_unconfirmed_price_drop;
         }
 
-        /// Sends badges to another component
+        /// Sends badges to another component, which needs to be an allowed badge receiver
         pub fn send_badges(&mut self, amount: Decimal, receiver_address: ComponentAddress) {
+            assert!(
+                self.badge_receivers.contains(&receiver_address),
+                "Receiver not allowed."
+            );
             let receiver: Global<AnyComponent> = Global::from(receiver_address);
             let badge_bucket: Bucket = self.badge_vault.take(amount).into();
+            self.record_custody_change(CustodyAction::SendBadges, Some(receiver_address), amount);
             receiver.call_raw("receive_badges", scrypto_args!(badge_bucket))
         }
 
+        /// Allows (or disallows) a component to receive controller badges through `send_badges`
+        pub fn set_badge_receiver(&mut self, receiver_address: ComponentAddress, allowed: bool) {
+            if allowed {
+                self.badge_receivers.insert(receiver_address);
+            } else {
+                self.badge_receivers.remove(&receiver_address);
+            }
+            self.record_custody_change(
+                CustodyAction::SetBadgeReceiver { allowed },
+                Some(receiver_address),
+                dec!(0),
+            );
+        }
+
+        /// Mints the insurance badge of the Stabilis component, to be deposited in the insurance claims component (only possible once)
+        pub fn mint_insurance_badge(&mut self) -> Bucket {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.mint_insurance_badge())
+        }
+
+        pub fn get_badge_receivers(&self) -> Vec<ComponentAddress> {
+            self.badge_receivers.iter().cloned().collect()
+        }
+
+        /// Gets the custody log entries from an id onwards (at most the Stabilis component's max vector length), so the DAO can audit controller badge custody over time
+        pub fn get_custody_log(&self, from_id: u64) -> Vec<CustodyChange> {
+            let to_id: u64 = self
+                .custody_log_counter
+                .min(from_id.saturating_add(self.stabilis.get_parameters().max_vector_length));
+
+            (from_id..to_id)
+                .filter_map(|id| self.custody_log.get(&id).map(|change| change.clone()))
+                .collect()
+        }
+
+        /// Appends a controller badge movement or oracle change to the custody log, and emits it
+        fn record_custody_change(
+            &mut self,
+            action: CustodyAction,
+            target: Option<ComponentAddress>,
+            amount: Decimal,
+        ) {
+            let change = CustodyChange {
+                id: self.custody_log_counter,
+                action,
+                target,
+                amount,
+                badges_held: self.badge_vault.amount(),
+                changed_at: Clock::current_time_rounded_to_seconds(),
+            };
+            self.custody_log
+                .insert(self.custody_log_counter, change.clone());
+            self.custody_log_counter += 1;
+
+            Runtime::emit_event(EventCustodyChange { change });
+        }
+
         /// Sets the reward per second for updating the prices
         pub fn set_reward_per_second(&mut self, reward_per_second: Decimal) {
             self.reward_per_second = reward_per_second;
         }
 
-        /// Puts the reward in the reward vault
+        /// Puts the reward in the reward vault of its resource
         pub fn put_reward_in_vault(&mut self, rewards: Bucket) {
-            self.reward_vault.put(rewards);
+            self.reward_vaults
+                .get_mut(&rewards.resource_address())
+                .expect("Not a reward resource.")
+                .put(rewards);
+        }
+
+        /// Adds a resource that can be used to reward updating the prices
+        pub fn add_reward_resource(&mut self, address: ResourceAddress) {
+            assert!(
+                self.reward_vaults.get(&address).is_none(),
+                "Already a reward resource."
+            );
+            self.reward_vaults.insert(address, Vault::new(address));
+        }
+
+        /// Sets the resource used to reward updating the prices (rewards of other resources stay in their vaults, until taken out)
+        pub fn set_reward_resource(&mut self, address: ResourceAddress) {
+            assert!(
+                self.reward_vaults.get(&address).is_some(),
+                "Not a reward resource, add it first."
+            );
+            self.reward_address = address;
+        }
+
+        /// Takes rewards out of the reward vault of a resource
+        pub fn take_rewards(&mut self, address: ResourceAddress, amount: Decimal) -> Bucket {
+            take_from_resource_vaults(&self.reward_vaults, address, amount, &[])
+        }
+
+        /// Rescues tokens of a resource that isn't managed by the protocol (for instance a no longer used reward resource)
+        ///   - the reward vaults are the only vaults of this component able to hold resources not managed by the protocol
+        pub fn rescue_tokens(&mut self, resource: ResourceAddress, amount: Decimal) -> Bucket {
+            let mut protocol_resources: Vec<ResourceAddress> = vec![
+                self.badge_vault.resource_address(),
+                self.cdp_receipt_manager.address(),
+                self.cdp_marker_manager.address(),
+                self.reward_address,
+                self.stab_address,
+            ];
+            protocol_resources.extend(self.accepted_collaterals.keys());
+
+            take_from_resource_vaults(&self.reward_vaults, resource, amount, &protocol_resources)
         }
 
         /// Adds claimed website to the dapp definition
@@ -392,6 +913,168 @@ mod proxy {
             }
         }
 
+        /// Rebrands the protocol for a white-label deployment, in a single call
+        ///
+        /// # Input
+        /// - `branding`: The new branding (protocol name, description, STAB name / symbol, website and icon)
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Validates the branding (non-empty, length limited names, https urls)
+        /// - Sets the name, symbol, info url and icon url of STAB
+        /// - Sets the info url of the receipts (their names and symbols are locked)
+        /// - Sets the name and info url of the Stabilis, flash loan and liquidity pool components
+        /// - Sets the name, description, info url and icon url of the dapp definition
+        /// - Emits a single event with the branding and the rebranded entities
+        /// - The proxy and oracle components aren't owned by the controller badge, so their metadata is set by the owner directly
+        pub fn rebrand(&mut self, branding: Branding) {
+            for text in [
+                &branding.protocol_name,
+                &branding.description,
+                &branding.stab_name,
+            ] {
+                assert!(
+                    !text.is_empty() && text.len() <= MAX_BRANDING_LENGTH,
+                    "Names and description need to be 1 to {} bytes.",
+                    MAX_BRANDING_LENGTH
+                );
+            }
+            assert!(
+                !branding.stab_symbol.is_empty()
+                    && branding.stab_symbol.len() <= MAX_SYMBOL_LENGTH
+                    && branding
+                        .stab_symbol
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric()),
+                "Symbol needs to be 1 to {} alphanumeric characters.",
+                MAX_SYMBOL_LENGTH
+            );
+            for url in [&branding.info_url, &branding.icon_url] {
+                assert!(
+                    url.as_str().starts_with("https://"),
+                    "Urls need to use https."
+                );
+            }
+
+            let addresses: ResourceAddresses = self.stabilis.get_resource_addresses();
+            let stab_manager: ResourceManager = ResourceManager::from_address(addresses.stab);
+            let receipts: Vec<ResourceAddress> = vec![
+                addresses.cdp_receipt,
+                addresses.cdp_marker,
+                addresses.liquidation_receipt,
+                addresses.withdrawal_claim,
+            ];
+            let components: Vec<(ComponentAddress, String)> = vec![
+                (
+                    self.stabilis.address(),
+                    format!("{} Stabilis", branding.protocol_name),
+                ),
+                (
+                    self.flash_loans.address(),
+                    format!("{} Flash Loans", branding.protocol_name),
+                ),
+                (
+                    self.stab_pool.address(),
+                    format!("{}/XRD Liquidity Pool", branding.stab_symbol),
+                ),
+            ];
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                stab_manager.set_metadata("name", branding.stab_name.clone());
+                stab_manager.set_metadata("symbol", branding.stab_symbol.clone());
+                stab_manager.set_metadata("info_url", branding.info_url.clone());
+                stab_manager.set_metadata("icon_url", branding.icon_url.clone());
+
+                for receipt in receipts.iter() {
+                    ResourceManager::from_address(*receipt)
+                        .set_metadata("info_url", branding.info_url.clone());
+                }
+
+                for (address, name) in components.iter() {
+                    let component: Global<AnyComponent> = Global::from(*address);
+                    component.set_metadata("name", name.clone());
+                    component.set_metadata("info_url", branding.info_url.clone());
+                }
+
+                self.dapp_def_account
+                    .set_metadata("name", branding.protocol_name.clone());
+                self.dapp_def_account
+                    .set_metadata("description", branding.description.clone());
+                self.dapp_def_account
+                    .set_metadata("info_url", branding.info_url.clone());
+                self.dapp_def_account
+                    .set_metadata("icon_url", branding.icon_url.clone());
+            });
+
+            let mut resources: Vec<ResourceAddress> = vec![addresses.stab];
+            resources.extend(receipts);
+            Runtime::emit_event(EventRebrand {
+                branding,
+                resources,
+                components: components.into_iter().map(|(address, _)| address).collect(),
+            });
+        }
+
+        /// Sets the public key allowed to sign admin actions (None disables signed admin actions)
+        pub fn set_admin_signer(&mut self, admin_signer: Option<Bls12381G1PublicKey>) {
+            self.admin_signer = admin_signer;
+        }
+
+        /// Executes an admin action signed by the admin signer, which can be submitted by anyone (for instance a keeper)
+        ///
+        /// # Input
+        /// - `action`: The admin action to execute
+        /// - `nonce`: The nonce of the action, which needs to equal the current admin action nonce
+        /// - `signature`: The signature of the admin signer over this component's address, the nonce and the action
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Checks whether an admin signer is set and the nonce is correct
+        /// - Verifies the signature over the SBOR encoded (component address, nonce, action)
+        /// - Increments the nonce, so the signed action can't be replayed
+        /// - Executes the action
+        pub fn execute_signed_admin_action(
+            &mut self,
+            action: SignedAdminAction,
+            nonce: u64,
+            signature: Bls12381G2Signature,
+        ) {
+            let admin_signer: Bls12381G1PublicKey = self
+                .admin_signer
+                .expect("Signed admin actions are disabled.");
+            assert!(nonce == self.admin_action_nonce, "Invalid nonce.");
+
+            let message: Vec<u8> =
+                scrypto_encode(&(Runtime::global_address(), nonce, action.clone())).unwrap();
+            assert!(
+                CryptoUtils::bls12381_v1_verify(message, admin_signer, signature),
+                "Invalid signature."
+            );
+
+            self.admin_action_nonce += 1;
+
+            match action {
+                SignedAdminAction::SetKs { new_ki, new_kp } => self.set_ks(new_ki, new_kp),
+                SignedAdminAction::SetUpdateDelays { update_delay } => {
+                    self.set_update_delays(update_delay)
+                }
+                SignedAdminAction::SetAllowedDeviation { allowed_deviation } => {
+                    self.set_allowed_deviation(allowed_deviation)
+                }
+                SignedAdminAction::SetMinmaxInterest {
+                    min_interest,
+                    max_interest,
+                } => self.set_minmax_interest(min_interest, max_interest),
+                SignedAdminAction::SetRewardPerSecond { reward_per_second } => {
+                    self.set_reward_per_second(reward_per_second)
+                }
+            }
+        }
+
         //==================================================================
         //                         HELPER METHODS
         //==================================================================
@@ -406,33 +1089,148 @@ mod proxy {
         ///
         /// # Logic
         /// - Calls the oracle component to get the latest prices
-        /// - Iterates over them and updates the collateral prices in the Stabilis component
+        /// - Iterates over them and updates the collateral prices in the Stabilis component (in a single batched call)
+        ///   - If a price dropped more than the maximum unconfirmed price drop, the drop needs to be confirmed by the confirmation oracle (if set)
+        ///     - If confirmed, the highest of both prices is applied
+        ///     - If not confirmed, the old price is kept for marking and liquidations, and the new price is stored as unconfirmed price (see `check_unconfirmed_price`)
+        ///   - If a price override is active for a collateral, the oracle price is ignored
+        ///     - If the override has expired, it is removed and the oracle price is used again
+        ///   - Prices of XRD-denominated markets are converted to USD with the XRD price, after all USD prices (including XRD's) are processed
+        ///     - If the XRD price is older than the maximum XRD price age, XRD-denominated prices are not applied
         fn update_collateral_prices(&mut self) -> Option<Bucket> {
             let prices: Vec<(ResourceAddress, Decimal, u64, String)> =
                 self.oracle.call(&self.oracle_method_name, &());
 
+            let xrd_quoted_pairs: HashSet<String> = match &self.xrd_quoted_method {
+                Some(method_name) => {
+                    let pairs: Vec<String> = self.oracle.call(method_name, &());
+                    pairs.into_iter().collect()
+                }
+                None => HashSet::new(),
+            };
+            let (xrd_quoted_prices, usd_quoted_prices): (
+                Vec<(ResourceAddress, Decimal, u64, String)>,
+                Vec<(ResourceAddress, Decimal, u64, String)>,
+            ) = prices
+                .into_iter()
+                .partition(|(_, _, _, pair)| xrd_quoted_pairs.contains(pair));
+
+            let mut confirmation_prices: Option<Vec<(ResourceAddress, Decimal, u64, String)>> =
+                None;
             let mut updated_seconds: u64 = 0;
+            let mut price_updates: Vec<(ResourceAddress, Decimal)> = Vec::new();
+
+            for (address, mut price, timestamp, pair) in
+                usd_quoted_prices.into_iter().chain(xrd_quoted_prices)
+            {
+                if xrd_quoted_pairs.contains(&pair) {
+                    let xrd_timestamp: u64 =
+                        self.accepted_collaterals.get(&XRD).copied().unwrap_or(0);
+                    let now: u64 =
+                        Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch as u64;
+                    if now.saturating_sub(xrd_timestamp) > self.max_xrd_price_age {
+                        continue;
+                    }
+                    price = price * self.xrd_price;
+                }
 
-            for (address, price, timestamp, _pair) in prices {
                 if let Some(stored_timestamp) = self.accepted_collaterals.get_mut(&address) {
+                    if let Some((_, expires_at)) = self.price_overrides.get(&address) {
+                        if Clock::current_time_is_strictly_before(
+                            *expires_at,
+                            TimePrecision::Second,
+                        ) {
+                            *stored_timestamp = timestamp;
+                            continue;
+                        }
+                        self.price_overrides.remove(&address);
+                        Runtime::emit_event(EventPriceOverrideExpired {
+                            collateral: address,
+                        });
+                    }
+
+                    if let Some(last_price) = self.collateral_prices.get(&address) {
+                        let drop_threshold: Decimal =
+                            *last_price * (dec!(1) - self.max_unconfirmed_price_drop);
+
+                        if self.confirmation_oracle.is_some() && price < drop_threshold {
+                            if confirmation_prices.is_none() {
+                                confirmation_prices = Some(
+                                    self.confirmation_oracle
+                                        .as_ref()
+                                        .unwrap()
+                                        .call(&self.confirmation_oracle_method_name, &()),
+                                );
+                            }
+
+                            let confirmed_price: Option<Decimal> = confirmation_prices
+                                .as_ref()
+                                .unwrap()
+                                .iter()
+                                .find(|(confirmed_address, _, _, _)| *confirmed_address == address)
+                                .map(|(_, confirmed_price, _, confirmed_pair)| {
+                                    if xrd_quoted_pairs.contains(confirmed_pair) {
+                                        *confirmed_price * self.xrd_price
+                                    } else {
+                                        *confirmed_price
+                                    }
+                                });
+
+                            match confirmed_price {
+                                Some(confirmed_price) if confirmed_price < drop_threshold => {
+                                    if confirmed_price > price {
+                                        price = confirmed_price;
+                                    }
+                                }
+                                _ => {
+                                    self.unconfirmed_prices.insert(address, price);
+                                    continue;
+                                }
+                            }
+                        }
+                    }
+
+                    self.unconfirmed_prices.remove(&address);
+                    self.collateral_prices.insert(address, price);
                     if address == XRD {
                         self.xrd_price = price;
                     }
-                    self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                        self.stabilis.change_collateral_price(address, price)
-                    });
+                    price_updates.push((address, price));
                     updated_seconds += timestamp - stored_timestamp.clone();
                     *stored_timestamp = timestamp;
                 }
             }
+
+            if !price_updates.is_empty() {
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.change_collateral_prices(price_updates)
+                });
+            }
+
             let reward: Decimal = Decimal::from(updated_seconds) * self.reward_per_second;
-            if self.reward_vault.amount() > reward {
-                Some(self.reward_vault.take(reward))
+            let mut reward_vault = self.reward_vaults.get_mut(&self.reward_address).unwrap();
+            if reward_vault.amount() > reward {
+                Some(reward_vault.take(reward))
             } else {
                 None
             }
         }
 
+        /// Emits an event containing the state of the system, so it can be monitored through events
+        fn emit_system_state(&self) {
+            let state: StabilisState = self
+                .stabilis
+                .get_state(self.accepted_collaterals.keys().cloned().collect());
+
+            Runtime::emit_event(EventSystemState {
+                circulating_stab: state.circulating_stab,
+                internal_price: state.internal_price,
+                interest_rate: self.stab_price_data.interest_rate,
+                marked_cdps: state.marked_cdps,
+                collaterals: state.collaterals,
+            });
+        }
+
         /// Updates the internal price of the STAB token
         ///
         /// # Input
@@ -442,86 +1240,553 @@ mod proxy {
         /// - None
         ///
         /// # Logic
-        /// - Calculates the price error
-        /// - Updates the latest price errors
+        /// - Reverts a fixed interest rate to the PID controller if its period has passed (see `set_rate_mode`)
+        /// - Calculates the interest update (see `calculate_interest_update`)
+        /// - Updates the latest price errors, unless the sample is ignored because the max price range was exceeded
         ///   - System keeps track of the latest n (by default 50) price errors and their totals
         ///     - If this cache is full, it replaces the oldest price error with the new one
-        /// - Calculates the new interest rate using the PID controller
-        /// - Updates the internal price using the new interest rate
+        /// - Updates the interest rate and the internal price
+        /// - Checks the peg deviation alarm (see `check_peg`), also if no update is possible yet
         fn update_internal_price(&mut self) {
-            let passed_minutes: Decimal = (Clock::current_time_rounded_to_seconds()
+            self.check_rate_mode();
+            let (preview, to_change_id, full_cache): (InterestPreview, u64, bool) =
+                self.calculate_interest_update();
+            self.check_peg(preview.market_price);
+
+            if !preview.update_possible {
+                return;
+            }
+
+            if !preview.price_range_exceeded {
+                self.stab_price_data.full_cache = full_cache;
+                self.stab_price_data.latest_stab_price_errors_total = preview.price_errors_total;
+                self.stab_price_data.last_changed_price = to_change_id;
+                self.stab_price_data
+                    .latest_stab_price_errors
+                    .insert(to_change_id, preview.price_error);
+                self.stab_price_data
+                    .latest_stab_price_error_times
+                    .insert(to_change_id, Clock::current_time_rounded_to_seconds());
+            }
+            self.accumulate_interest_rate();
+            self.stab_price_data.last_rate_delta =
+                preview.interest_rate - self.stab_price_data.interest_rate;
+            self.stab_price_data.last_rate_clamped = preview.interest_rate_clamped;
+            self.stab_price_data.interest_rate = preview.interest_rate;
+            self.equalization.negative_since = match preview.interest_rate < dec!(1) {
+                true => self
+                    .equalization
+                    .negative_since
+                    .or(Some(Clock::current_time_rounded_to_seconds())),
+                false => None,
+            };
+
+            self.stab_price_data.last_update = Clock::current_time_rounded_to_seconds();
+            self.stab_price_data.price_drift_remainder = preview.price_drift_remainder;
+            self.change_internal_price(preview.internal_price);
+
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stab_pool.take_price_range());
+        }
+
+        /// Sets the peg deviation alarm thresholds (fraction of the internal price) and duration (minutes)
+        ///   - an alert is emitted once the deviation exceeds the alert threshold for longer than the duration
+        ///   - recovery is emitted once the deviation is back within the (lower) recovery threshold, so alarms don't flap around a single threshold
+        pub fn set_peg_alarm(
+            &mut self,
+            alert_threshold: Decimal,
+            recovery_threshold: Decimal,
+            duration: i64,
+        ) {
+            assert!(
+                recovery_threshold > dec!(0) && recovery_threshold <= alert_threshold,
+                "Recovery threshold needs to be positive and at most the alert threshold."
+            );
+            assert!(duration >= 0, "Duration can't be negative.");
+            self.peg_alarm.alert_threshold = alert_threshold;
+            self.peg_alarm.recovery_threshold = recovery_threshold;
+            self.peg_alarm.duration = duration;
+        }
+
+        /// Gets the peg deviation alarm parameters and state
+        pub fn get_peg_alarm(&self) -> PegAlarm {
+            self.peg_alarm.clone()
+        }
+
+        /// Checks the deviation of the market price from the internal price, emitting peg alerts / recoveries
+        ///   - outside the alert threshold: start the deviation timer, and alert once it has run for longer than the duration
+        ///   - within the recovery threshold: reset the timer, and emit a recovery if an alert was emitted
+        ///   - in between: keep the current state
+        fn check_peg(&mut self, market_price: Decimal) {
+            let internal_price: Decimal = self.stab_price_data.internal_price;
+            let deviation: Decimal = (market_price - internal_price) / internal_price;
+            let abs_deviation: Decimal = deviation.checked_abs().unwrap();
+            let alarm: &mut PegAlarm = &mut self.peg_alarm;
+
+            if abs_deviation > alarm.alert_threshold {
+                let deviating_since: Instant = *alarm
+                    .deviating_since
+                    .get_or_insert(Clock::current_time_rounded_to_seconds());
+                if !alarm.alerted
+                    && Clock::current_time_is_strictly_after(
+                        deviating_since.add_minutes(alarm.duration).unwrap(),
+                        TimePrecision::Second,
+                    )
+                {
+                    alarm.alerted = true;
+                    Runtime::emit_event(EventPegAlert {
+                        market_price,
+                        internal_price,
+                        deviation,
+                        deviating_since,
+                    });
+                }
+            } else if abs_deviation <= alarm.recovery_threshold {
+                alarm.deviating_since = None;
+                if alarm.alerted {
+                    alarm.alerted = false;
+                    Runtime::emit_event(EventPegRecovered {
+                        market_price,
+                        internal_price,
+                        deviation,
+                    });
+                }
+            }
+        }
+
+        /// Sets the distribution of flash loan income to STAB savers during negative interest rates
+        ///   - while the interest rate is negative, the internal price falls, so STAB holders lose peg value
+        ///   - once the rate has been negative for `delay` minutes, updates send `share` of the collected flash loan interest per day to the savings component (stSTAB)
+        ///     - the distributed amount is proportional to the time since the last distribution, so frequent updates can't drain the collected interest faster
+        ///   - a share of 0 or no savings component disables the distribution
+        pub fn set_equalization(
+            &mut self,
+            savings: Option<ComponentAddress>,
+            share: Decimal,
+            delay: i64,
+        ) {
+            assert!(
+                share >= dec!(0) && share <= dec!(1),
+                "Share needs to be between 0 and 1."
+            );
+            assert!(delay >= 0, "Delay can't be negative.");
+            self.equalization.savings = savings.map(|address| Global::from(address));
+            self.equalization.share = share;
+            self.equalization.delay = delay;
+            self.equalization.last_distribution = Clock::current_time_rounded_to_seconds();
+        }
+
+        /// Sends a share of the collected flash loan interest to the savings component, if the interest rate has been negative for long enough
+        ///   - the share is scaled by the days passed since the last distribution (or since the delay passed, if later), capped at the full collected interest
+        fn distribute_equalization(&mut self) {
+            let negative_since: Instant = match self.equalization.negative_since {
+                Some(negative_since) => negative_since,
+                None => return,
+            };
+            if self.equalization.savings.is_none()
+                || self.equalization.share == dec!(0)
+                || Clock::current_time_is_strictly_before(
+                    negative_since.add_minutes(self.equalization.delay).unwrap(),
+                    TimePrecision::Second,
+                )
+            {
+                return;
+            }
+
+            let now: i64 = Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch;
+            let from: i64 = negative_since
+                .add_minutes(self.equalization.delay)
+                .unwrap()
                 .seconds_since_unix_epoch
-                - self.stab_price_data.last_update.seconds_since_unix_epoch)
-                / dec!(60);
+                .max(self.equalization.last_distribution.seconds_since_unix_epoch);
+            let elapsed_days: Decimal = Decimal::from(now - from) / dec!(86400);
+            self.equalization.last_distribution = Clock::current_time_rounded_to_seconds();
 
-            if passed_minutes < Decimal::from(self.update_delay) {
+            let amount: Decimal = self.flash_loans.get_collected_interest()
+                * (self.equalization.share * elapsed_days).min(dec!(1));
+            if amount == dec!(0) {
                 return;
             }
 
-            let mut price_error: Decimal = self.stab_pool.get_stab_price()
-                * self.xrd_price
-                * self.parameters.price_error_offset
+            let income: Bucket = self
+                .badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.flash_loans.take_interest(amount));
+            self.equalization
+                .savings
+                .as_ref()
+                .unwrap()
+                .add_yield(income);
+
+            Runtime::emit_event(EventEqualization {
+                amount,
+                negative_since,
+            });
+        }
+
+        /// Adds the time-weighted current interest rate to the cumulative interest rate, before the rate changes
+        ///   - stores a checkpoint of the cumulative interest rate if the checkpoint interval passed since the last one
+        ///     - checkpoints are stored in a ring buffer, overwriting the oldest checkpoint if it is full
+        fn accumulate_interest_rate(&mut self) {
+            let now: i64 = Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch;
+            let history: &mut InterestRateHistory = &mut self.interest_rate_history;
+
+            history.cumulative += self.stab_price_data.interest_rate
+                * Decimal::from(now - history.last_accumulation.seconds_since_unix_epoch);
+            history.last_accumulation = Instant::new(now);
+
+            let last_checkpoint: i64 = history
+                .checkpoints
+                .get(&((history.checkpoint_counter - 1) % history.max_checkpoints))
+                .unwrap()
+                .timestamp;
+            if now - last_checkpoint >= history.checkpoint_interval {
+                history.checkpoints.insert(
+                    history.checkpoint_counter % history.max_checkpoints,
+                    RateCheckpoint {
+                        timestamp: now,
+                        cumulative: history.cumulative,
+                    },
+                );
+                history.checkpoint_counter += 1;
+            }
+        }
+
+        /// Gets the time-weighted average interest rate (per minute) over each configured window (seconds)
+        ///
+        /// # Input
+        /// - None
+        ///
+        /// # Output
+        /// - The windows and their average interest rates
+        ///
+        /// # Logic
+        /// - Extends the cumulative interest rate to now, at the current interest rate
+        /// - Per window, finds the latest checkpoint at or before the start of the window (binary search through the ring buffer)
+        ///   - If the history is shorter than the window, the oldest checkpoint is used
+        ///   - So the averaged period can exceed the window by up to one checkpoint interval
+        /// - Divides the change of the cumulative interest rate since the checkpoint by the time passed
+        pub fn get_average_interest_rates(&self) -> Vec<(i64, Decimal)> {
+            let now: i64 = Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch;
+            let history: &InterestRateHistory = &self.interest_rate_history;
+            let cumulative: Decimal = history.cumulative
+                + self.stab_price_data.interest_rate
+                    * Decimal::from(now - history.last_accumulation.seconds_since_unix_epoch);
+            let oldest: u64 = history
+                .checkpoint_counter
+                .saturating_sub(history.max_checkpoints);
+
+            history
+                .windows
+                .iter()
+                .map(|window| {
+                    let start: i64 = now - window;
+                    let mut low: u64 = oldest;
+                    let mut high: u64 = history.checkpoint_counter - 1;
+                    while low < high {
+                        let middle: u64 = (low + high + 1) / 2;
+                        let timestamp: i64 = history
+                            .checkpoints
+                            .get(&(middle % history.max_checkpoints))
+                            .unwrap()
+                            .timestamp;
+                        if timestamp <= start {
+                            low = middle;
+                        } else {
+                            high = middle - 1;
+                        }
+                    }
+
+                    let checkpoint: RateCheckpoint = history
+                        .checkpoints
+                        .get(&(low % history.max_checkpoints))
+                        .unwrap()
+                        .clone();
+                    let average: Decimal = match now > checkpoint.timestamp {
+                        true => {
+                            (cumulative - checkpoint.cumulative)
+                                / Decimal::from(now - checkpoint.timestamp)
+                        }
+                        false => self.stab_price_data.interest_rate,
+                    };
+                    (*window, average)
+                })
+                .collect()
+        }
+
+        /// Previews the next interest rate update, without changing anything
+        ///   - useful for tuning the kp and ki parameters
+        pub fn preview_interest_update(&self) -> InterestPreview {
+            let (preview, _to_change_id, _full_cache): (InterestPreview, u64, bool) =
+                self.calculate_interest_update();
+            preview
+        }
+
+        /// Gets the state of the interest rate PID controller, so governance can diagnose the interest rate before adjusting kp and ki
+        ///
+        /// # Input
+        /// - None
+        ///
+        /// # Output
+        /// - The `ControllerState`
+        ///
+        /// # Logic
+        /// - Counts the cached price errors: the full cache, or the errors cached since it was last reset
+        /// - Finds the oldest and newest cached price error
+        ///   - the cache is a ring buffer, so once it is full, the oldest error is the one after the last changed one
+        /// - Calculates the current integral term from the cached price errors
+        /// - Returns these, with the interest rate change applied by the last update and whether it was clamped
+        pub fn get_controller_state(&self) -> ControllerState {
+            let data: &StabPriceData = &self.stab_price_data;
+            let cached_errors: u64 = match data.full_cache {
+                true => self.number_of_cached_prices,
+                false => data.last_changed_price,
+            };
+            let oldest_id: u64 = match data.full_cache {
+                true => data.last_changed_price % self.number_of_cached_prices + 1,
+                false => 1,
+            };
+            let error_time = |id: u64| -> Option<Instant> {
+                match cached_errors > 0 {
+                    true => data
+                        .latest_stab_price_error_times
+                        .get(&id)
+                        .map(|time| *time),
+                    false => None,
+                }
+            };
+
+            ControllerState {
+                cached_errors,
+                price_errors_total: data.latest_stab_price_errors_total,
+                oldest_error_at: error_time(oldest_id),
+                newest_error_at: error_time(data.last_changed_price),
+                i_term: self.parameters.ki
+                    * (data.latest_stab_price_errors_total
+                        / (data.internal_price * Decimal::from(self.number_of_cached_prices))),
+                interest_rate: data.interest_rate,
+                last_rate_delta: data.last_rate_delta,
+                last_rate_clamped: data.last_rate_clamped,
+                rate_mode: self.rate_mode.clone(),
+            }
+        }
+
+        /// Calculates the next interest rate and internal price of the STAB token
+        ///
+        /// # Input
+        /// - None
+        ///
+        /// # Output
+        /// - The `InterestPreview` with the new values and intermediate terms
+        /// - The key of the price error to change in the price errors KVS
+        /// - Whether the price errors cache is full
+        ///
+        /// # Logic
+        /// - Calculates the price error
+        ///   - If the pool price moved more than the max price range since the last update, the sample is ignored (price error of 0), as the pool price might be manipulated
+        /// - Calculates the new total of the latest price errors
+        ///   - An ignored sample isn't cached, so the total stays the same
+        /// - Calculates the new interest rate using the PID controller
+        ///   - Only if the price error is bigger than the allowed deviation
+        /// - Calculates the new internal price using the new interest rate (see `compound_internal_price`)
+        ///   - the change is capped at the max price drift, what exceeds it is carried to the next updates
+        fn calculate_interest_update(&self) -> (InterestPreview, u64, bool) {
+            let passed_seconds: i64 = Clock::current_time_rounded_to_seconds()
+                .seconds_since_unix_epoch
+                - self.stab_price_data.last_update.seconds_since_unix_epoch;
+            let passed_minutes: Decimal = Decimal::from(passed_seconds) / dec!(60);
+
+            let market_price: Decimal = self.stab_pool.get_stab_price() * self.xrd_price;
+
+            let mut price_error: Decimal = market_price * self.parameters.price_error_offset
                 - self.stab_price_data.internal_price;
 
             if price_error > self.parameters.max_price_error {
                 price_error = self.parameters.max_price_error;
             }
 
+            let price_range_exceeded: bool = match self.stab_pool.get_price_range() {
+                Some((low, high)) => {
+                    low > dec!(0) && (high - low) / low > self.parameters.max_price_range
+                }
+                None => false,
+            };
+
+            if price_range_exceeded {
+                price_error = dec!(0);
+            }
+
+            let mut full_cache: bool = self.stab_price_data.full_cache;
             let to_change_id: u64 =
                 match self.stab_price_data.last_changed_price >= self.number_of_cached_prices {
                     true => {
-                        self.stab_price_data.full_cache = true;
+                        full_cache = true;
                         1
                     }
                     false => self.stab_price_data.last_changed_price + 1,
                 };
 
-            if !self.stab_price_data.full_cache {
-                self.stab_price_data.latest_stab_price_errors_total += price_error;
-            } else {
-                self.stab_price_data.latest_stab_price_errors_total += price_error
-                    - *self
-                        .stab_price_data
-                        .latest_stab_price_errors
-                        .get(&to_change_id)
-                        .unwrap();
-            }
+            let price_errors_total: Decimal = match full_cache {
+                _ if price_range_exceeded => self.stab_price_data.latest_stab_price_errors_total,
+                false => self.stab_price_data.latest_stab_price_errors_total + price_error,
+                true => {
+                    self.stab_price_data.latest_stab_price_errors_total + price_error
+                        - *self
+                            .stab_price_data
+                            .latest_stab_price_errors
+                            .get(&to_change_id)
+                            .unwrap()
+                }
+            };
 
-            self.stab_price_data.last_changed_price = to_change_id;
-            self.stab_price_data
-                .latest_stab_price_errors
-                .insert(to_change_id, price_error);
+            let p_term: Decimal =
+                self.parameters.kp * (price_error / self.stab_price_data.internal_price);
+            let i_term: Decimal = self.parameters.ki
+                * (price_errors_total
+                    / (self.stab_price_data.internal_price
+                        * Decimal::from(self.number_of_cached_prices)));
 
-            if price_error.checked_abs().unwrap()
-                > self.parameters.allowed_deviation * self.stab_price_data.internal_price
-            {
-                self.stab_price_data.interest_rate -= (self.parameters.kp
-                    * (price_error / self.stab_price_data.internal_price)
-                    + self.parameters.ki
-                        * (self.stab_price_data.latest_stab_price_errors_total
-                            / (self.stab_price_data.internal_price
-                                * Decimal::from(self.number_of_cached_prices))))
-                    * passed_minutes;
-
-                if self.stab_price_data.interest_rate > self.parameters.max_interest_rate {
-                    self.stab_price_data.interest_rate = self.parameters.max_interest_rate;
-                } else if self.stab_price_data.interest_rate < self.parameters.min_interest_rate {
-                    self.stab_price_data.interest_rate = self.parameters.min_interest_rate;
+            let outside_allowed_deviation: bool = price_error.checked_abs().unwrap()
+                > self.parameters.allowed_deviation * self.stab_price_data.internal_price;
+
+            let mut interest_rate: Decimal = self.stab_price_data.interest_rate;
+            let mut interest_rate_clamped: bool = false;
+            let fixed_rate: Option<Decimal> = self.active_fixed_rate();
+
+            if let Some(rate) = fixed_rate {
+                interest_rate = rate;
+            } else if outside_allowed_deviation {
+                interest_rate -= (p_term + i_term) * passed_minutes;
+
+                if interest_rate > self.parameters.max_interest_rate {
+                    interest_rate = self.parameters.max_interest_rate;
+                    interest_rate_clamped = true;
+                } else if interest_rate < self.parameters.min_interest_rate {
+                    interest_rate = self.parameters.min_interest_rate;
+                    interest_rate_clamped = true;
                 }
             }
 
-            let calculated_price: Decimal = self.stab_price_data.internal_price
-                * self
-                    .stab_price_data
-                    .interest_rate
-                    .pow(passed_minutes)
-                    .unwrap();
+            let (internal_price, price_drift_remainder): (Decimal, PreciseDecimal) =
+                compound_internal_price(
+                    self.stab_price_data.internal_price,
+                    interest_rate,
+                    passed_seconds,
+                    self.stab_price_data.price_drift_remainder,
+                    self.parameters.max_price_drift,
+                );
 
-            self.stab_price_data.last_update = Clock::current_time_rounded_to_seconds();
-            self.change_internal_price(calculated_price);
+            (
+                InterestPreview {
+                    update_possible: passed_minutes >= Decimal::from(self.update_delay),
+                    passed_minutes,
+                    market_price,
+                    price_range_exceeded,
+                    price_error,
+                    price_errors_total,
+                    p_term,
+                    i_term,
+                    outside_allowed_deviation,
+                    interest_rate,
+                    interest_rate_clamped,
+                    rate_fixed: fixed_rate.is_some(),
+                    internal_price,
+                    price_drift_remainder,
+                },
+                to_change_id,
+                full_cache,
+            )
         }
 
-        //==================================================================
+        /// Aborts the transaction if the ledger time is past the deadline, protecting users from delayed (stale) executions
+        fn check_deadline(&self, deadline: Option<Instant>) {
+            if let Some(deadline) = deadline {
+                assert!(
+                    Clock::current_time_is_at_or_before(deadline, TimePrecision::Second),
+                    "Transaction deadline has passed."
+                );
+            }
+        }
+
+        /// Aborts the transaction if the internal price rose above, or the (parent) collateral price fell below the borrower's bounds
+        ///   - protects borrowers from getting worse terms than they signed for, if an update lands before their transaction
+        fn check_price_bounds(
+            &self,
+            parent_address: ResourceAddress,
+            max_internal_price: Option<Decimal>,
+            min_collateral_price: Option<Decimal>,
+        ) {
+            if let Some(max_internal_price) = max_internal_price {
+                assert!(
+                    self.stabilis.return_internal_price() <= max_internal_price,
+                    "Internal price above the maximum."
+                );
+            }
+            if let Some(min_collateral_price) = min_collateral_price {
+                assert!(
+                    self.stabilis.get_collateral_price(parent_address) >= min_collateral_price,
+                    "Collateral price below the minimum."
+                );
+            }
+        }
+
+        /// Aborts the transaction if a loan / CDP would be liquidatable at the unconfirmed price of its (parent) collateral
+        ///   - while a large price drop awaits confirmation, marking and liquidations use the old, confirmed price, but withdrawals and borrowing are checked against the lower price
+        ///   - the loan is liquidatable once the internal price reaches its liquidation internal price, which scales with the collateral price
+        fn check_unconfirmed_price(
+            &self,
+            cdp_id: NonFungibleLocalId,
+            parent_address: ResourceAddress,
+        ) {
+            let unconfirmed_price: Decimal = match self.unconfirmed_prices.get(&parent_address) {
+                Some(price) => *price,
+                None => return,
+            };
+            let confirmed_price: Decimal = self.stabilis.get_collateral_price(parent_address);
+            if unconfirmed_price >= confirmed_price {
+                return;
+            }
+
+            let liquidation_internal_price: Decimal =
+                self.stabilis.get_liquidation_internal_price(cdp_id) * unconfirmed_price
+                    / confirmed_price;
+            assert!(
+                liquidation_internal_price > self.stabilis.return_internal_price(),
+                "Not allowed at the unconfirmed collateral price."
+            );
+        }
+
+        /// Calls the `on_cdp_event` method of all enabled hooks subscribed to the event
+        ///   - hooks only receive the event, the loan id, its collateral and the STAB amount involved (no buckets or proofs)
+        ///   - hooks are called after the Stabilis component has finished, and can't re-enter the Proxy as it is still locked
+        ///   - a failing hook can't be caught, so it reverts the whole transaction: until the owner disables it (`set_hook_enabled`), the loan actions it's subscribed to are blocked
+        fn call_hooks(
+            &self,
+            event: CdpHookEvent,
+            cdp_id: NonFungibleLocalId,
+            collateral: ResourceAddress,
+            stab_amount: Decimal,
+        ) {
+            for (address, hook) in self.hooks.iter() {
+                if hook.enabled && hook.events.contains(&event) {
+                    let component: Global<AnyComponent> = Global::from(*address);
+                    component.call_raw::<()>(
+                        "on_cdp_event",
+                        scrypto_args!(event.clone(), cdp_id.clone(), collateral, stab_amount),
+                    );
+                }
+            }
+        }
+
+        /// Total weight of the hooks subscribed to an event
+        fn hook_weight(&self, event: &CdpHookEvent) -> u64 {
+            self.hooks
+                .values()
+                .filter(|hook| hook.events.contains(event))
+                .map(|hook| hook.weight)
+                .sum()
+        }
+
+        //==================================================================
         //    PROXY FUNCTIONALITY FROM HERE (CONTROL OTHER COMPONENTS)
         //==================================================================
 
@@ -529,23 +1794,118 @@ mod proxy {
         //                       STABILIS COMPONENT
         //==================================================================
 
-        pub fn open_cdp(&mut self, collateral: Bucket, stab_to_mint: Decimal) -> (Bucket, Bucket) {
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.open_cdp(collateral, stab_to_mint)
-            })
+        pub fn open_cdp(
+            &mut self,
+            collateral: Bucket,
+            stab_to_mint: Decimal,
+            deadline: Option<Instant>,
+            max_internal_price: Option<Decimal>,
+            min_collateral_price: Option<Decimal>,
+        ) -> (Bucket, Bucket) {
+            self.check_deadline(deadline);
+            self.check_lp_borrowing(collateral.resource_address());
+            let (stab, receipt): (Bucket, Bucket) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.open_cdp(collateral, stab_to_mint)
+                });
+
+            let cdp_id: NonFungibleLocalId = receipt.as_non_fungible().non_fungible_local_id();
+            let cdp: Cdp = self.cdp_receipt_manager.get_non_fungible_data(&cdp_id);
+            self.check_price_bounds(cdp.parent_address, max_internal_price, min_collateral_price);
+            self.check_unconfirmed_price(cdp_id.clone(), cdp.parent_address);
+            self.call_hooks(
+                CdpHookEvent::Opened,
+                cdp_id,
+                cdp.collateral,
+                cdp.minted_stab,
+            );
+
+            (stab, receipt)
+        }
+
+        /// Opens a loan / CDP with collateral from multiple buckets of the same resource, merged before opening
+        ///   - for integrations aggregating funds (e.g. dust from multiple accounts) in a single manifest
+        pub fn open_cdp_from_buckets(
+            &mut self,
+            collaterals: Vec<Bucket>,
+            stab_to_mint: Decimal,
+            deadline: Option<Instant>,
+            max_internal_price: Option<Decimal>,
+            min_collateral_price: Option<Decimal>,
+        ) -> (Bucket, Bucket) {
+            let mut buckets = collaterals.into_iter();
+            let mut collateral: Bucket = buckets.next().expect("No collateral supplied.");
+            for bucket in buckets {
+                assert!(
+                    bucket.resource_address() == collateral.resource_address(),
+                    "All buckets need to contain the same resource."
+                );
+                collateral.put(bucket);
+            }
+
+            self.open_cdp(
+                collateral,
+                stab_to_mint,
+                deadline,
+                max_internal_price,
+                min_collateral_price,
+            )
+        }
+
+        pub fn open_cdps(
+            &mut self,
+            cdps: Vec<(Bucket, Decimal)>,
+            deadline: Option<Instant>,
+        ) -> (Bucket, Vec<Bucket>) {
+            self.check_deadline(deadline);
+            for (collateral, _) in cdps.iter() {
+                self.check_lp_borrowing(collateral.resource_address());
+            }
+            let (stab, receipts): (Bucket, Vec<Bucket>) = self
+                .badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.open_cdps(cdps));
+
+            for receipt in receipts.iter() {
+                let cdp_id: NonFungibleLocalId = receipt.as_non_fungible().non_fungible_local_id();
+                let cdp: Cdp = self.cdp_receipt_manager.get_non_fungible_data(&cdp_id);
+                self.check_unconfirmed_price(cdp_id.clone(), cdp.parent_address);
+                self.call_hooks(
+                    CdpHookEvent::Opened,
+                    cdp_id,
+                    cdp.collateral,
+                    cdp.minted_stab,
+                );
+            }
+
+            (stab, receipts)
         }
 
-        pub fn borrow_more(&mut self, receipt_proof: NonFungibleProof, amount: Decimal) -> Bucket {
+        pub fn borrow_more(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            amount: Decimal,
+            max_internal_price: Option<Decimal>,
+            min_collateral_price: Option<Decimal>,
+        ) -> Bucket {
             let receipt_proof = receipt_proof.check_with_message(
                 self.cdp_receipt_manager.address(),
                 "Incorrect proof! Are you sure this loan is yours?",
             );
             let receipt = receipt_proof.non_fungible::<Cdp>();
             let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+            self.check_price_bounds(
+                receipt.data().parent_address,
+                max_internal_price,
+                min_collateral_price,
+            );
+            self.check_lp_borrowing(receipt.data().collateral);
 
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.borrow_more(receipt_id, amount)
-            })
+            let stab: Bucket = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.borrow_more(receipt_id.clone(), amount)
+            });
+            self.check_unconfirmed_price(receipt_id, receipt.data().parent_address);
+
+            stab
         }
 
         pub fn add_collateral(
@@ -562,13 +1922,47 @@ mod proxy {
                 address,
                 Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch as u64,
             );
+            self.collateral_prices.insert(address, initial_price);
+            self.collateral_displays
+                .insert(address, Self::default_display(address));
+        }
+
+        /// Stops updating the price of a delisted collateral, so it no longer uses oracle and reward resources
+        ///   - the collateral can't be accepted by the Stabilis component anymore, and no STAB can be minted with it (so no loans rely on its price)
+        pub fn remove_collateral_tracking(&mut self, address: ResourceAddress) {
+            assert!(address != XRD, "XRD price is needed for the StabilisPool.");
+            assert!(
+                self.accepted_collaterals.contains_key(&address),
+                "Collateral not tracked."
+            );
+
+            let state: StabilisState = self.stabilis.get_state(vec![address]);
+            let utilization: &CollateralUtilization = state
+                .collaterals
+                .first()
+                .expect("Collateral unknown to the Stabilis component.");
+
+            assert!(
+                !utilization.accepted,
+                "Collateral still accepted, delist it first."
+            );
+            assert!(
+                utilization.minted_stab == dec!(0),
+                "Loans still rely on this collateral's price."
+            );
+
+            self.accepted_collaterals.remove(&address);
+            self.collateral_prices.remove(&address);
+            self.unconfirmed_prices.remove(&address);
+            self.price_overrides.remove(&address);
+            self.collateral_displays.remove(&address);
         }
 
         pub fn remove_collateral(
             &mut self,
             receipt_proof: NonFungibleProof,
             amount: Decimal,
-        ) -> Bucket {
+        ) -> (Bucket, Option<Bucket>) {
             let receipt_proof = receipt_proof.check_with_message(
                 self.cdp_receipt_manager.address(),
                 "Incorrect proof! Are you sure this loan is yours?",
@@ -576,204 +1970,1831 @@ mod proxy {
             let receipt = receipt_proof.non_fungible::<Cdp>();
             let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
 
+            let (collateral, claim): (Bucket, Option<Bucket>) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.remove_collateral(receipt_id.clone(), amount)
+                });
+            self.check_unconfirmed_price(receipt_id, receipt.data().parent_address);
+
+            (collateral, claim)
+        }
+
+        /// Moves collateral between two of the caller's loans / CDPs using the same collateral
+        pub fn move_collateral(
+            &mut self,
+            from_proof: NonFungibleProof,
+            to_proof: NonFungibleProof,
+            amount: Decimal,
+        ) {
+            let from_proof = from_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let to_proof = to_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let from_id: NonFungibleLocalId = from_proof.non_fungible::<Cdp>().local_id().clone();
+            let to_id: NonFungibleLocalId = to_proof.non_fungible::<Cdp>().local_id().clone();
+            let parent_address: ResourceAddress =
+                from_proof.non_fungible::<Cdp>().data().parent_address;
+
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.remove_collateral(receipt_id, amount)
-            })
+                self.stabilis
+                    .move_collateral(from_id.clone(), to_id, amount)
+            });
+            self.check_unconfirmed_price(from_id, parent_address);
         }
 
         pub fn close_cdp(
             &mut self,
             receipt_proof: NonFungibleProof,
             stab_payment: Bucket,
-        ) -> (Bucket, Bucket) {
+            deadline: Option<Instant>,
+        ) -> (Bucket, Bucket, Option<Bucket>) {
+            self.check_deadline(deadline);
             let receipt_proof = receipt_proof.check_with_message(
                 self.cdp_receipt_manager.address(),
                 "Incorrect proof! Are you sure this loan is yours?",
             );
             let receipt = receipt_proof.non_fungible::<Cdp>();
             let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+            let cdp: Cdp = receipt.data();
 
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.close_cdp(receipt_id, stab_payment)
-            })
+            let (collateral, leftover_payment, claim): (Bucket, Bucket, Option<Bucket>) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.close_cdp(receipt_id.clone(), stab_payment)
+                });
+
+            self.call_hooks(
+                CdpHookEvent::Closed,
+                receipt_id,
+                cdp.collateral,
+                cdp.minted_stab,
+            );
+
+            (collateral, leftover_payment, claim)
         }
 
-        pub fn partial_close_cdp(
+        pub fn close_dust_cdp(
             &mut self,
             receipt_proof: NonFungibleProof,
             stab_payment: Bucket,
-        ) -> (Option<Bucket>, Option<Bucket>) {
+            deadline: Option<Instant>,
+        ) -> (Bucket, Bucket, Option<Bucket>) {
+            self.check_deadline(deadline);
             let receipt_proof = receipt_proof.check_with_message(
                 self.cdp_receipt_manager.address(),
                 "Incorrect proof! Are you sure this loan is yours?",
             );
             let receipt = receipt_proof.non_fungible::<Cdp>();
             let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+            let cdp: Cdp = receipt.data();
 
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.partial_close_cdp(receipt_id, stab_payment)
-            })
+            let (collateral, leftover_payment, claim): (Bucket, Bucket, Option<Bucket>) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis
+                        .close_dust_cdp(receipt_id.clone(), stab_payment)
+                });
+
+            self.call_hooks(
+                CdpHookEvent::Closed,
+                receipt_id,
+                cdp.collateral,
+                cdp.minted_stab,
+            );
+
+            (collateral, leftover_payment, claim)
         }
 
-        pub fn retrieve_leftover_collateral(&mut self, receipt_proof: NonFungibleProof) -> Bucket {
+        pub fn partial_close_cdp(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            stab_payment: Bucket,
+            withdrawal: Option<Decimal>,
+            deadline: Option<Instant>,
+        ) -> (Option<Bucket>, Option<Bucket>, Option<Bucket>) {
+            self.check_deadline(deadline);
             let receipt_proof = receipt_proof.check_with_message(
                 self.cdp_receipt_manager.address(),
                 "Incorrect proof! Are you sure this loan is yours?",
             );
             let receipt = receipt_proof.non_fungible::<Cdp>();
             let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+            let cdp: Cdp = receipt.data();
+            let payment_amount: Decimal = stab_payment.amount();
 
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.retrieve_leftover_collateral(receipt_id)
-            })
+            let (collateral, leftover_payment, claim): (
+                Option<Bucket>,
+                Option<Bucket>,
+                Option<Bucket>,
+            ) = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .partial_close_cdp(receipt_id.clone(), stab_payment, withdrawal)
+            });
+            if withdrawal.is_some() && leftover_payment.is_none() {
+                self.check_unconfirmed_price(receipt_id.clone(), cdp.parent_address);
+            }
+
+            // a repayment above the debt closes the loan entirely
+            match &leftover_payment {
+                Some(leftover) => self.call_hooks(
+                    CdpHookEvent::Closed,
+                    receipt_id,
+                    cdp.collateral,
+                    payment_amount - leftover.amount(),
+                ),
+                None => self.call_hooks(
+                    CdpHookEvent::PartiallyClosed,
+                    receipt_id,
+                    cdp.collateral,
+                    payment_amount,
+                ),
+            }
+
+            (collateral, leftover_payment, claim)
         }
 
-        pub fn top_up_cdp(&mut self, receipt_proof: NonFungibleProof, collateral: Bucket) {
+        pub fn migrate_cdp(&mut self, receipt_proof: NonFungibleProof, target: ResourceAddress) {
             let receipt_proof = receipt_proof.check_with_message(
                 self.cdp_receipt_manager.address(),
                 "Incorrect proof! Are you sure this loan is yours?",
             );
             let receipt = receipt_proof.non_fungible::<Cdp>();
             let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+            let from: ResourceAddress = receipt.data().collateral;
 
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.top_up_cdp(receipt_id, collateral)
+                self.stabilis.migrate_cdp(receipt_id.clone(), target)
             });
-        }
-
-        pub fn mark_for_liquidation(&mut self, collateral: ResourceAddress) -> Bucket {
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.mark_for_liquidation(collateral)
-            })
-        }
-
-        pub fn burn_marker(&self, marker: Bucket) {
-            self.badge_vault
-                .authorize_with_amount(dec!("0.75"), || self.stabilis.burn_marker(marker));
-        }
 
-        pub fn burn_loan_receipt(&self, receipt: Bucket) {
-            self.badge_vault
-                .authorize_with_amount(dec!("0.75"), || self.stabilis.burn_loan_receipt(receipt));
+            self.record_custody_change(
+                CustodyAction::MigrateCdp {
+                    cdp_id: receipt_id,
+                    from,
+                    to: target,
+                },
+                None,
+                dec!(0),
+            );
         }
 
-        pub fn liquidate_position_with_marker(
+        pub fn retrieve_leftover_collateral(
             &mut self,
-            marker_proof: NonFungibleProof,
-            payment: Bucket,
-        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
-            let marker_proof = marker_proof.check_with_message(
-                self.cdp_marker_manager.address(),
-                "Incorrect proof! Are you sure this is a correct marker?",
+            receipt_proof: NonFungibleProof,
+        ) -> (Bucket, Option<Bucket>) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
             );
-            let marker = marker_proof.non_fungible::<CdpMarker>();
-            let marker_id: NonFungibleLocalId = marker.local_id().clone();
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
 
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis
-                    .liquidate_position_with_marker(marker_id, payment)
+                self.stabilis.retrieve_leftover_collateral(receipt_id)
             })
         }
 
-        pub fn force_liquidate(
+        /// Retrieves the leftover collateral of multiple liquidated loans / CDPs at once, a single bucket per resource (and the withdrawal claims of queued leftovers)
+        ///   - every loan needs to be in the proof, loans without leftover collateral are skipped
+        pub fn retrieve_leftovers(
             &mut self,
-            collateral: ResourceAddress,
-            payment: Bucket,
-        ) -> (Bucket, Bucket) {
+            receipts_proof: NonFungibleProof,
+            receipt_ids: Vec<NonFungibleLocalId>,
+        ) -> (Vec<Bucket>, Option<Bucket>) {
+            let receipts_proof = receipts_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure these loans are yours?",
+            );
+            let proof_ids: IndexSet<NonFungibleLocalId> = receipts_proof.non_fungible_local_ids();
+            assert!(
+                receipt_ids.iter().all(|id| proof_ids.contains(id)),
+                "Incorrect proof! Are you sure these loans are yours?"
+            );
+
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis
-                    .force_liquidate(collateral, payment, self.percentage_to_take, true)
+                self.stabilis.retrieve_leftovers(receipt_ids)
             })
         }
 
-        pub fn force_mint(
-            &mut self,
-            collateral: ResourceAddress,
-            payment: Bucket,
-        ) -> (Bucket, Option<Bucket>) {
+        pub fn sweep_unclaimed_leftover(&mut self, cdp_id: NonFungibleLocalId) {
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis
-                    .force_mint(collateral, payment, self.percentage_to_supply)
-            })
+                self.stabilis.sweep_unclaimed_leftover(cdp_id)
+            });
         }
 
-        pub fn liquidate_position_without_marker(
-            &mut self,
-            payment: Bucket,
-            skip: Option<i64>,
-            cdp_id: NonFungibleLocalId,
-        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
+        pub fn reimburse_swept_leftover(&mut self, receipt_proof: NonFungibleProof) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis
-                    .liquidate_position_without_marker(payment, skip, cdp_id)
+                self.stabilis.reimburse_swept_leftover(receipt_id)
             })
         }
 
-        pub fn change_collateral_price(&self, collateral: ResourceAddress, new_price: Decimal) {
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.change_collateral_price(collateral, new_price)
-            });
+        pub fn get_swept_leftover(&self, cdp_id: NonFungibleLocalId) -> Option<SweptLeftover> {
+            self.stabilis.get_swept_leftover(cdp_id)
         }
 
-        pub fn add_pool_collateral(
+        pub fn claim_withdrawal(&mut self, claim: Bucket) -> Bucket {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.claim_withdrawal(claim))
+        }
+
+        pub fn get_withdrawal_window(
             &self,
-            address: ResourceAddress,
-            parent_address: ResourceAddress,
-            validator: ComponentAddress,
-            lsu: bool,
-            initial_acceptance: bool,
-        ) {
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.add_pool_collateral(
-                    address,
-                    parent_address,
-                    validator,
-                    lsu,
-                    initial_acceptance,
-                )
-            });
+            collateral: ResourceAddress,
+        ) -> Option<WithdrawalWindow> {
+            self.stabilis.get_withdrawal_window(collateral)
         }
 
-        pub fn change_internal_price(&mut self, new_price: Decimal) {
-            self.stab_price_data.internal_price = new_price;
-            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.change_internal_price(new_price)
-            });
+        pub fn get_queued_withdrawals(&self, collateral: ResourceAddress) -> Decimal {
+            self.stabilis.get_queued_withdrawals(collateral)
         }
 
-        pub fn set_max_vector_length(&mut self, new_stabilis_length: u64) {
+        pub fn top_up_cdp(&mut self, receipt_proof: NonFungibleProof, collateral: Bucket) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.set_max_vector_length(new_stabilis_length)
+                self.stabilis.top_up_cdp(receipt_id, collateral)
             });
         }
 
-        pub fn get_internal_price(&self) -> Decimal {
-            self.stab_price_data.internal_price
-        }
-
-        //==================================================================
-        //                      FLASH LOANS COMPONENT
-        //==================================================================
+        pub fn deposit_to_buffer(&mut self, receipt_proof: NonFungibleProof, collateral: Bucket) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
 
-        pub fn flash_borrow(&mut self, amount: Decimal) -> (Bucket, Bucket) {
-            self.badge_vault
-                .authorize_with_amount(dec!("0.75"), || self.flash_loans.borrow(amount))
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.deposit_to_buffer(receipt_id, collateral)
+            });
         }
 
-        pub fn flash_pay_back(&mut self, receipt_bucket: Bucket, payment_bucket: Bucket) -> Bucket {
+        pub fn withdraw_from_buffer(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            amount: Decimal,
+        ) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.flash_loans.pay_back(receipt_bucket, payment_bucket)
+                self.stabilis.withdraw_from_buffer(receipt_id, amount)
             })
         }
 
-        pub fn flash_retrieve_interest(&mut self) -> Bucket {
+        /// Saves a marked loan with its auto-top-up buffer, anyone can call this and receives a bounty from the buffer
+        pub fn apply_buffer(&mut self, cdp_id: NonFungibleLocalId) -> Bucket {
             self.badge_vault
-                .authorize_with_amount(dec!("0.75"), || self.flash_loans.retrieve_interest())
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.apply_buffer(cdp_id))
         }
 
-        //==================================================================
-        //                      ORACLE COMPONENT
-        //==================================================================
+        pub fn get_buffer(&self, cdp_id: NonFungibleLocalId) -> Decimal {
+            self.stabilis.get_buffer(cdp_id)
+        }
+
+        pub fn set_auto_deleverage(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            settings: Option<DeleverageSettings>,
+        ) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_auto_deleverage(receipt_id, settings)
+            });
+        }
+
+        pub fn get_auto_deleverage(
+            &self,
+            cdp_id: NonFungibleLocalId,
+        ) -> Option<DeleverageSettings> {
+            self.stabilis.get_auto_deleverage(cdp_id)
+        }
+
+        /// Sets (or unsets, with None) the account the leftover collateral of a loan / CDP is returned to when it is liquidated, see the Stabilis component's `set_refund_account`
+        pub fn set_refund_account(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            account: Option<ComponentAddress>,
+        ) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_refund_account(receipt_id, account)
+            });
+        }
+
+        pub fn get_refund_account(&self, cdp_id: NonFungibleLocalId) -> Option<ComponentAddress> {
+            self.stabilis.get_refund_account(cdp_id)
+        }
+
+        /// Caps (or uncaps, with None) the debt force minting can add to a loan / CDP, see the Stabilis component's `set_force_mint_cap`
+        pub fn set_force_mint_cap(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            limit: Option<ForceMintLimit>,
+        ) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_force_mint_cap(receipt_id, limit)
+            });
+        }
+
+        pub fn get_force_mint_cap(&self, cdp_id: NonFungibleLocalId) -> Option<ForceMintCap> {
+            self.stabilis.get_force_mint_cap(cdp_id)
+        }
+
+        /// Partially deleverages a loan / CDP that crossed its auto-deleverage trigger CR, callable by anyone
+        ///
+        /// # Input
+        /// - `cdp_id`: The id of the loan to deleverage
+        ///
+        /// # Output
+        /// - The bounty for the caller, in collateral
+        ///
+        /// # Logic
+        /// - Flash-borrows the STAB to repay (see the Stabilis component's `auto_deleverage`)
+        /// - Repays the debt, receiving the collateral to sell and the bounty
+        /// - Sells the collateral for STAB (see `swap_route`) and pays back the flash loan
+        /// - Repays more of the loan's debt with the STAB left over (the unused slippage margin)
+        pub fn auto_deleverage(&mut self, cdp_id: NonFungibleLocalId) -> Bucket {
+            self.enter();
+            let (stab_to_repay, _collateral_to_sell, _bounty): (Decimal, Decimal, Decimal) =
+                self.stabilis.quote_auto_deleverage(cdp_id.clone());
+
+            let (loan, loan_receipt): (Bucket, Bucket) = self
+                .badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.flash_loans.borrow(stab_to_repay));
+
+            let (collateral, bounty, leftover): (Bucket, Bucket, Bucket) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.auto_deleverage(cdp_id.clone(), loan)
+                });
+
+            let mut stab: Bucket = self.swap_route(collateral, self.stab_address, dec!(0));
+            stab.put(leftover);
+
+            let surplus: Bucket = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.flash_loans.pay_back(loan_receipt, stab)
+            });
+
+            if surplus.is_empty() {
+                surplus.drop_empty();
+            } else {
+                let (collateral, leftover_payment, claim): (
+                    Option<Bucket>,
+                    Option<Bucket>,
+                    Option<Bucket>,
+                ) = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.partial_close_cdp(cdp_id, surplus, None)
+                });
+                assert!(
+                    collateral.is_none() && leftover_payment.is_none() && claim.is_none(),
+                    "Surplus would close the loan."
+                );
+            }
+
+            self.exit();
+            bounty
+        }
+
+        /// Opts a pool unit loan / CDP in to (or out of) yield harvesting, repaying its debt with the appreciation of its collateral
+        pub fn set_yield_harvest(&mut self, receipt_proof: NonFungibleProof, enabled: bool) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_yield_harvest(receipt_id, enabled)
+            });
+        }
+
+        pub fn get_yield_harvest(&self, cdp_id: NonFungibleLocalId) -> Option<Decimal> {
+            self.stabilis.get_yield_harvest(cdp_id)
+        }
+
+        /// Harvests the appreciation of the pool unit collateral of a loan / CDP to repay its debt, callable by anyone
+        ///
+        /// # Input
+        /// - `cdp_id`: The id of the loan to harvest
+        ///
+        /// # Output
+        /// - The bounty for the caller, in pool units
+        /// - The STAB the pool units were sold for above the maximum repayment, if the swap beat the oracle price
+        ///
+        /// # Logic
+        /// - Harvests the pool units worth the redemption rate growth, capped at the debt above the minimum mint (see the Stabilis component's `harvest_yield`)
+        /// - Sells the pool units for STAB (see `swap_route`), reverting if the output is below the oracle value minus the slippage margin
+        /// - Repays the loan's debt with the STAB, clamped to the maximum repayment so the loan stays open
+        pub fn harvest_yield(&mut self, cdp_id: NonFungibleLocalId) -> (Bucket, Bucket) {
+            self.enter();
+            let (harvested, bounty, min_stab, max_repayment): (Bucket, Bucket, Decimal, Decimal) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.harvest_yield(cdp_id.clone())
+                });
+
+            let mut surplus: Bucket = self.swap_route(harvested, self.stab_address, min_stab);
+            let stab: Bucket = surplus.take(surplus.amount().min(max_repayment));
+
+            let (collateral, leftover_payment, claim): (
+                Option<Bucket>,
+                Option<Bucket>,
+                Option<Bucket>,
+            ) = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.partial_close_cdp(cdp_id, stab, None)
+            });
+            assert!(
+                collateral.is_none() && leftover_payment.is_none() && claim.is_none(),
+                "Harvest would close the loan."
+            );
+
+            self.exit();
+            (bounty, surplus)
+        }
+
+        pub fn get_cdp_freeze(&self, cdp_id: NonFungibleLocalId) -> Option<CdpFreeze> {
+            self.stabilis.get_cdp_freeze(cdp_id)
+        }
+
+        pub fn get_cdp_stats(&self, cdp_id: NonFungibleLocalId) -> CdpStats {
+            self.stabilis.get_cdp_stats(cdp_id)
+        }
+
+        pub fn get_marker(&self, marker_id: NonFungibleLocalId) -> Option<MarkerInfo> {
+            self.stabilis.get_marker(marker_id)
+        }
+
+        pub fn get_markers_for_cdp(&self, cdp_id: NonFungibleLocalId) -> Vec<MarkerInfo> {
+            self.stabilis.get_markers_for_cdp(cdp_id)
+        }
+
+        pub fn get_collateral_activity(
+            &self,
+            collateral: ResourceAddress,
+            hours: i64,
+        ) -> Vec<HourlyActivity> {
+            self.stabilis.get_collateral_activity(collateral, hours)
+        }
+
+        /// Claims the borrow rewards accrued by a loan / CDP
+        pub fn claim_borrow_rewards(&mut self, receipt_proof: NonFungibleProof) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.claim_borrow_rewards(receipt_id)
+            })
+        }
+
+        pub fn get_borrow_rewards(&self) -> BorrowRewardsInfo {
+            self.stabilis.get_borrow_rewards()
+        }
+
+        pub fn get_pending_borrow_rewards(&self, cdp_id: NonFungibleLocalId) -> Decimal {
+            self.stabilis.get_pending_borrow_rewards(cdp_id)
+        }
+
+        pub fn set_incentive_route(
+            &mut self,
+            collateral: ResourceAddress,
+            staking_component: ComponentAddress,
+            method_name: String,
+            fine_share: Decimal,
+            fee_share: Decimal,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_incentive_route(
+                    collateral,
+                    staking_component,
+                    method_name,
+                    fine_share,
+                    fee_share,
+                )
+            });
+        }
+
+        pub fn get_incentive_route(
+            &self,
+            collateral: ResourceAddress,
+        ) -> Option<IncentiveRouteInfo> {
+            self.stabilis.get_incentive_route(collateral)
+        }
+
+        pub fn distribute_incentives(&mut self, collateral: ResourceAddress) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.distribute_incentives(collateral)
+            });
+        }
+
+        pub fn get_risk_parameter_changes(
+            &self,
+            from_id: u64,
+            collateral: Option<ResourceAddress>,
+        ) -> Vec<RiskParameterChange> {
+            self.stabilis
+                .get_risk_parameter_changes(from_id, collateral)
+        }
+
+        pub fn set_cdp_label(&mut self, receipt_proof: NonFungibleProof, label: String) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_cdp_label(receipt_id, label)
+            });
+        }
+
+        pub fn protect_cdp(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            days: i64,
+            premium: Bucket,
+        ) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.protect_cdp(receipt_id, days, premium)
+            })
+        }
+
+        pub fn mark_for_liquidation(&mut self, collateral: ResourceAddress) -> Bucket {
+            self.enter();
+            let marker: Bucket = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.mark_for_liquidation(collateral)
+            });
+            self.exit();
+            marker
+        }
+
+        pub fn get_mark_rate(&self, collateral: ResourceAddress) -> u64 {
+            self.stabilis.get_mark_rate(collateral)
+        }
+
+        pub fn get_mcr_surcharge(&self, collateral: ResourceAddress) -> Decimal {
+            self.stabilis.get_mcr_surcharge(collateral)
+        }
+
+        pub fn get_share_enforcement(&self, collateral: ResourceAddress) -> ShareEnforcementStatus {
+            self.stabilis.get_share_enforcement(collateral)
+        }
+
+        pub fn get_cr_histogram(
+            &self,
+            collateral: ResourceAddress,
+            bounds: Vec<Decimal>,
+        ) -> CrHistogram {
+            self.stabilis.get_cr_histogram(collateral, bounds)
+        }
+
+        pub fn get_global_cr(&self) -> GlobalCr {
+            self.stabilis.get_global_cr()
+        }
+
+        pub fn get_supply_breakdown(&self) -> SupplyBreakdown {
+            self.stabilis.get_supply_breakdown()
+        }
+
+        pub fn export_cdps(&self, cdp_ids: Vec<NonFungibleLocalId>) -> Vec<CdpExport> {
+            self.stabilis.export_cdps(cdp_ids)
+        }
+
+        pub fn burn_marker(&self, marker: Bucket) {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.burn_marker(marker));
+        }
+
+        /// Burns all used markers in a bucket at once, returning the markers that aren't used yet
+        pub fn burn_markers(&self, markers: Bucket) -> Bucket {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.burn_markers(markers))
+        }
+
+        pub fn burn_loan_receipt(&self, receipt: Bucket) {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.burn_loan_receipt(receipt));
+        }
+
+        pub fn liquidate_position_with_marker(
+            &mut self,
+            marker_proof: NonFungibleProof,
+            payment: Bucket,
+            deadline: Option<Instant>,
+        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
+            self.check_deadline(deadline);
+            self.enter();
+            let marker_proof = marker_proof.check_with_message(
+                self.cdp_marker_manager.address(),
+                "Incorrect proof! Are you sure this is a correct marker?",
+            );
+            let marker = marker_proof.non_fungible::<CdpMarker>();
+
+            let result: (Option<Bucket>, Option<Bucket>, Bucket) = self.liquidate_with_marker_id(
+                marker.local_id().clone(),
+                marker.data().marked_id,
+                payment,
+            );
+
+            self.exit();
+            result
+        }
+
+        /// Liquidates a marked loan / CDP with the marker itself instead of a proof, burning the marker afterwards
+        ///   - the marker is used after both a liquidation and a save, so it would otherwise linger in the wallet
+        pub fn liquidate_and_burn_marker(
+            &mut self,
+            marker: Bucket,
+            payment: Bucket,
+            deadline: Option<Instant>,
+        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
+            self.check_deadline(deadline);
+            self.enter();
+            assert!(
+                marker.resource_address() == self.cdp_marker_manager.address(),
+                "Incorrect marker! Are you sure this is a correct marker?"
+            );
+            let marker_data = marker.as_non_fungible().non_fungible::<CdpMarker>();
+
+            let result: (Option<Bucket>, Option<Bucket>, Bucket) = self.liquidate_with_marker_id(
+                marker_data.local_id().clone(),
+                marker_data.data().marked_id,
+                payment,
+            );
+            self.burn_marker(marker);
+
+            self.exit();
+            result
+        }
+
+        /// Liquidates a marked loan / CDP with a marker whose ownership has been checked, and calls the liquidation hooks
+        fn liquidate_with_marker_id(
+            &mut self,
+            marker_id: NonFungibleLocalId,
+            cdp_id: NonFungibleLocalId,
+            payment: Bucket,
+        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
+            let result: (Option<Bucket>, Option<Bucket>, Bucket) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis
+                        .liquidate_position_with_marker(marker_id, payment)
+                });
+
+            self.call_liquidation_hooks(cdp_id);
+            result
+        }
+
+        /// Liquidates a marked loan / CDP with flash-borrowed STAB, so keepers need no STAB of their own
+        ///
+        /// # Input
+        /// - `marker_proof`: The proof of the marker receipt
+        /// - `min_profit`: The minimum amount of collateral to keep after paying back the flash loan, reverting otherwise
+        /// - `deadline`: The optional deadline for the transaction
+        ///
+        /// # Output
+        /// - The profit: the seized collateral that didn't need to be sold
+        /// - The STAB left after paying back the flash loan (rounding dust of the swap)
+        /// - The liquidation receipt
+        ///
+        /// # Logic
+        /// - Flash-borrows the STAB minted by the loan
+        /// - Liquidates the loan with the borrowed STAB (reverting if the loan was saved)
+        /// - Quotes the seized collateral needed to pay back the flash loan and its interest, and sells only that for STAB (see `swap_route`)
+        ///     - swap outputs grow less than proportionally with the input, so selling the share of the collateral equal to the share of the quoted output needed yields at least the output needed
+        /// - Pays back the flash loan and its interest, returning the rest of the collateral as profit
+        pub fn flash_liquidate(
+            &mut self,
+            marker_proof: NonFungibleProof,
+            min_profit: Decimal,
+            deadline: Option<Instant>,
+        ) -> (Bucket, Bucket, Bucket) {
+            self.check_deadline(deadline);
+            self.enter();
+            let marker_proof = marker_proof.check_with_message(
+                self.cdp_marker_manager.address(),
+                "Incorrect proof! Are you sure this is a correct marker?",
+            );
+            let marker = marker_proof.non_fungible::<CdpMarker>();
+            let marker_id: NonFungibleLocalId = marker.local_id().clone();
+            let cdp_id: NonFungibleLocalId = marker.data().marked_id;
+            let cdp: Cdp = self.cdp_receipt_manager.get_non_fungible_data(&cdp_id);
+
+            let (loan, loan_receipt): (Bucket, Bucket) = self
+                .badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.flash_loans.borrow(cdp.minted_stab));
+
+            let (collateral, remainder, liquidation_receipt): (
+                Option<Bucket>,
+                Option<Bucket>,
+                Bucket,
+            ) = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .liquidate_position_with_marker(marker_id, loan)
+            });
+            let mut collateral: Bucket = collateral.expect("Loan was saved, nothing to liquidate.");
+
+            self.call_liquidation_hooks(cdp_id);
+
+            let owed: Decimal =
+                cdp.minted_stab * (dec!(1) + self.flash_loans.get_flash_terms().interest);
+            let mut stab: Bucket = remainder.unwrap_or_else(|| Bucket::new(self.stab_address));
+            let shortfall: Decimal = owed - stab.amount();
+            if shortfall > dec!(0) {
+                let collateral_address: ResourceAddress = collateral.resource_address();
+                let full_output: Decimal =
+                    self.quote_route(collateral_address, collateral.amount(), self.stab_address);
+                assert!(
+                    full_output >= shortfall,
+                    "Collateral can't pay back the flash loan."
+                );
+
+                let to_sell: Bucket = collateral.take_advanced(
+                    collateral.amount() * shortfall / full_output,
+                    WithdrawStrategy::Rounded(RoundingMode::AwayFromZero),
+                );
+                stab.put(self.swap_route(to_sell, self.stab_address, shortfall));
+            }
+
+            let stab_left: Bucket = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.flash_loans.pay_back(loan_receipt, stab)
+            });
+            assert!(collateral.amount() >= min_profit, "Profit too low.");
+
+            self.exit();
+            (collateral, stab_left, liquidation_receipt)
+        }
+
+        pub fn force_liquidate(
+            &mut self,
+            collateral: ResourceAddress,
+            payment: Bucket,
+            deadline: Option<Instant>,
+        ) -> (Bucket, Bucket) {
+            self.check_deadline(deadline);
+            self.enter();
+            let cdp_id: NonFungibleLocalId = self.stabilis.get_force_liquidation_target(collateral);
+            let payment_amount: Decimal = payment.amount();
+            let result: (Bucket, Bucket) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.force_liquidate(
+                        collateral,
+                        payment,
+                        self.percentage_to_take,
+                        true,
+                    )
+                });
+
+            let cdp: Cdp = self.cdp_receipt_manager.get_non_fungible_data(&cdp_id);
+            self.call_hooks(
+                CdpHookEvent::ForceLiquidated,
+                cdp_id,
+                cdp.collateral,
+                payment_amount - result.1.amount(),
+            );
+
+            self.exit();
+            result
+        }
+
+        pub fn force_mint(
+            &mut self,
+            collateral: ResourceAddress,
+            payment: Bucket,
+            deadline: Option<Instant>,
+        ) -> (Bucket, Option<Bucket>) {
+            self.check_deadline(deadline);
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .force_mint(collateral, payment, self.percentage_to_supply)
+            })
+        }
+
+        pub fn liquidate_position_without_marker(
+            &mut self,
+            payment: Bucket,
+            skip: Option<i64>,
+            cdp_id: NonFungibleLocalId,
+            deadline: Option<Instant>,
+        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
+            self.check_deadline(deadline);
+            self.enter();
+            let result: (Option<Bucket>, Option<Bucket>, Bucket) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis
+                        .liquidate_position_without_marker(payment, skip, cdp_id.clone())
+                });
+
+            self.call_liquidation_hooks(cdp_id);
+
+            self.exit();
+            result
+        }
+
+        /// Liquidates a marked loan / CDP like `liquidate_position_with_marker`, splitting the collateral reward into multiple buckets (see `split_payout`)
+        pub fn liquidate_position_with_marker_split(
+            &mut self,
+            marker_proof: NonFungibleProof,
+            payment: Bucket,
+            payout_split: Vec<Decimal>,
+            deadline: Option<Instant>,
+        ) -> (Vec<Bucket>, Option<Bucket>, Bucket) {
+            let (collateral, remainder, receipt): (Option<Bucket>, Option<Bucket>, Bucket) =
+                self.liquidate_position_with_marker(marker_proof, payment, deadline);
+            (
+                Self::split_payout(collateral, payout_split),
+                remainder,
+                receipt,
+            )
+        }
+
+        /// Liquidates a marked loan / CDP like `liquidate_and_burn_marker`, splitting the collateral reward into multiple buckets (see `split_payout`)
+        pub fn liquidate_and_burn_marker_split(
+            &mut self,
+            marker: Bucket,
+            payment: Bucket,
+            payout_split: Vec<Decimal>,
+            deadline: Option<Instant>,
+        ) -> (Vec<Bucket>, Option<Bucket>, Bucket) {
+            let (collateral, remainder, receipt): (Option<Bucket>, Option<Bucket>, Bucket) =
+                self.liquidate_and_burn_marker(marker, payment, deadline);
+            (
+                Self::split_payout(collateral, payout_split),
+                remainder,
+                receipt,
+            )
+        }
+
+        /// Liquidates a marked loan / CDP like `liquidate_position_without_marker`, splitting the collateral reward into multiple buckets (see `split_payout`)
+        pub fn liquidate_position_without_marker_split(
+            &mut self,
+            payment: Bucket,
+            skip: Option<i64>,
+            cdp_id: NonFungibleLocalId,
+            payout_split: Vec<Decimal>,
+            deadline: Option<Instant>,
+        ) -> (Vec<Bucket>, Option<Bucket>, Bucket) {
+            let (collateral, remainder, receipt): (Option<Bucket>, Option<Bucket>, Bucket) =
+                self.liquidate_position_without_marker(payment, skip, cdp_id, deadline);
+            (
+                Self::split_payout(collateral, payout_split),
+                remainder,
+                receipt,
+            )
+        }
+
+        /// Splits the collateral reward of a liquidation into multiple buckets, e.g. to share revenue between a bot operator and its capital providers
+        ///   - the split is a list of fractions adding up to 1, the last bucket receives any rounding dust (see `split_payout_amounts`)
+        ///   - no buckets are returned if the loan was saved instead of liquidated
+        fn split_payout(collateral: Option<Bucket>, payout_split: Vec<Decimal>) -> Vec<Bucket> {
+            let mut collateral: Bucket = match collateral {
+                Some(collateral) => collateral,
+                None => return vec![],
+            };
+
+            let divisibility: u8 = collateral
+                .resource_manager()
+                .resource_type()
+                .divisibility()
+                .unwrap_or(0);
+            let amounts: Vec<Decimal> =
+                split_payout_amounts(collateral.amount(), &payout_split, divisibility);
+
+            let mut payouts: Vec<Bucket> = vec![];
+            for amount in amounts.iter().take(amounts.len() - 1) {
+                payouts.push(collateral.take(*amount));
+            }
+            payouts.push(collateral);
+            payouts
+        }
+
+        /// Marks the start of a guarded method, reverting if another guarded method is still executing
+        ///   - the flag is only set within a single transaction, a reverted transaction reverts it as well
+        fn enter(&mut self) {
+            assert!(!self.entered, "Reentrant call.");
+            self.entered = true;
+        }
+
+        /// Marks the end of a guarded method
+        fn exit(&mut self) {
+            self.entered = false;
+        }
+
+        /// Calls the hooks subscribed to liquidations, if the loan was liquidated (and not saved)
+        fn call_liquidation_hooks(&self, cdp_id: NonFungibleLocalId) {
+            let cdp: Cdp = self.cdp_receipt_manager.get_non_fungible_data(&cdp_id);
+            if cdp.status == CdpStatus::Liquidated {
+                self.call_hooks(
+                    CdpHookEvent::Liquidated,
+                    cdp_id,
+                    cdp.collateral,
+                    cdp.minted_stab,
+                );
+            }
+        }
+
+        /// Whitelists an extension component, called through `on_cdp_event(event, cdp_id, collateral, stab_amount)` after the chosen loan events
+        ///   - calls can't be limited in fees on Radix, so every hook gets a weight, and the hooks called for a single event can't exceed the max hook weight
+        pub fn add_hook(
+            &mut self,
+            component: ComponentAddress,
+            events: Vec<CdpHookEvent>,
+            weight: u64,
+        ) {
+            assert!(!self.hooks.contains_key(&component), "Hook already added.");
+            assert!(weight > 0, "Weight needs to be positive.");
+
+            for event in events.iter() {
+                assert!(
+                    self.hook_weight(event) + weight <= self.max_hook_weight,
+                    "Max hook weight exceeded."
+                );
+            }
+
+            self.hooks.insert(
+                component,
+                CdpHook {
+                    events,
+                    weight,
+                    enabled: true,
+                },
+            );
+        }
+
+        /// Removes an extension component from the whitelist
+        pub fn remove_hook(&mut self, component: ComponentAddress) {
+            assert!(self.hooks.remove(&component).is_some(), "Hook not found.");
+        }
+
+        /// Enables or disables a whitelisted extension component, keeping its configuration
+        ///   - a failing hook reverts every loan action it's subscribed to, so it can be disabled right away, without having to add it again later
+        pub fn set_hook_enabled(&mut self, component: ComponentAddress, enabled: bool) {
+            self.hooks
+                .get_mut(&component)
+                .expect("Hook not found.")
+                .enabled = enabled;
+        }
+
+        /// Sets the maximum total weight of the hooks called for a single event (only applies to hooks added afterwards)
+        pub fn set_max_hook_weight(&mut self, max_hook_weight: u64) {
+            self.max_hook_weight = max_hook_weight;
+        }
+
+        /// Gets all whitelisted extension components
+        pub fn get_hooks(&self) -> Vec<(ComponentAddress, CdpHook)> {
+            self.hooks
+                .iter()
+                .map(|(address, hook)| (*address, hook.clone()))
+                .collect()
+        }
+
+        /// Whitelists an external DEX adapter component as a liquidity source
+        ///   - the adapter needs a `quote(input_address, input_amount, output_address) -> Decimal` and a `swap(input, output_address) -> Bucket` method
+        ///   - `max_stab` is the maximum amount of STAB swapped in or out through the adapter per swap
+        pub fn add_liquidity_source(&mut self, component: ComponentAddress, max_stab: Decimal) {
+            assert!(
+                !self.liquidity_sources.contains_key(&component),
+                "Liquidity source already added."
+            );
+            assert!(max_stab >= dec!(0), "Cap can't be negative.");
+            self.liquidity_sources
+                .insert(component, LiquiditySource { max_stab });
+        }
+
+        /// Removes an external DEX adapter component from the liquidity sources
+        pub fn remove_liquidity_source(&mut self, component: ComponentAddress) {
+            assert!(
+                self.liquidity_sources.remove(&component).is_some(),
+                "Liquidity source not found."
+            );
+        }
+
+        /// Sets the maximum amount of STAB swapped in or out through a liquidity source per swap
+        pub fn set_liquidity_source_cap(&mut self, component: ComponentAddress, max_stab: Decimal) {
+            assert!(max_stab >= dec!(0), "Cap can't be negative.");
+            self.liquidity_sources
+                .get_mut(&component)
+                .expect("Liquidity source not found.")
+                .max_stab = max_stab;
+        }
+
+        /// Gets all whitelisted liquidity sources
+        pub fn get_liquidity_sources(&self) -> Vec<(ComponentAddress, LiquiditySource)> {
+            self.liquidity_sources
+                .iter()
+                .map(|(address, source)| (*address, source.clone()))
+                .collect()
+        }
+
+        /// Gets the best quote for a swap into or out of STAB, over the StabilisPool and all liquidity sources
+        ///
+        /// # Input
+        /// - `input_address`: The address of the resource to swap
+        /// - `input_amount`: The amount to swap
+        /// - `output_address`: The address of the resource to receive
+        ///
+        /// # Output
+        /// - The liquidity source with the best quote (None for the StabilisPool)
+        /// - The quoted output amount
+        ///
+        /// # Logic
+        /// - Quotes the StabilisPool, if it holds both resources
+        /// - Quotes every liquidity source, skipping sources for which the swapped STAB exceeds their cap
+        /// - Returns the source with the highest output
+        pub fn get_best_quote(
+            &self,
+            input_address: ResourceAddress,
+            input_amount: Decimal,
+            output_address: ResourceAddress,
+        ) -> (Option<ComponentAddress>, Decimal) {
+            assert!(
+                input_address == self.stab_address || output_address == self.stab_address,
+                "Only swaps into or out of STAB are supported."
+            );
+
+            let mut best: (Option<ComponentAddress>, Decimal) = (None, dec!(0));
+
+            if input_address == XRD || output_address == XRD {
+                best.1 = self.stab_pool.quote(input_address, input_amount);
+            }
+
+            for (address, source) in self.liquidity_sources.iter() {
+                let component: Global<AnyComponent> = Global::from(*address);
+                let output: Decimal = component.call_raw(
+                    "quote",
+                    scrypto_args!(input_address, input_amount, output_address),
+                );
+                let stab_amount: Decimal = match input_address == self.stab_address {
+                    true => input_amount,
+                    false => output,
+                };
+                if stab_amount <= source.max_stab && output > best.1 {
+                    best = (Some(*address), output);
+                }
+            }
+
+            assert!(best.1 > dec!(0), "No liquidity for this swap.");
+            best
+        }
+
+        /// Swaps into or out of STAB through the liquidity source with the best quote
+        ///   - reverts if the output is lower than `min_output`
+        pub fn swap_with_best_source(
+            &mut self,
+            input: Bucket,
+            output_address: ResourceAddress,
+            min_output: Decimal,
+        ) -> Bucket {
+            let (source, _quote) =
+                self.get_best_quote(input.resource_address(), input.amount(), output_address);
+
+            let output: Bucket = match source {
+                Some(address) => {
+                    let component: Global<AnyComponent> = Global::from(address);
+                    component.call_raw("swap", scrypto_args!(input, output_address))
+                }
+                None => self.stab_pool.swap(input),
+            };
+
+            assert!(
+                output.resource_address() == output_address,
+                "Swap returned the wrong resource."
+            );
+            assert!(output.amount() >= min_output, "Output too low.");
+            output
+        }
+
+        /// Sets (or removes) the external XRD/collateral pool adapter of a collateral
+        ///   - the adapter needs a `quote(input_address, input_amount, output_address) -> Decimal` and a `swap(input, output_address) -> Bucket` method, like a liquidity source
+        pub fn set_xrd_pool_adapter(
+            &mut self,
+            collateral: ResourceAddress,
+            adapter: Option<ComponentAddress>,
+        ) {
+            match adapter {
+                Some(adapter) => {
+                    assert!(
+                        collateral != XRD,
+                        "XRD is swapped through the StabilisPool."
+                    );
+                    self.xrd_pool_adapters.insert(collateral, adapter);
+                }
+                None => {
+                    self.xrd_pool_adapters.remove(&collateral);
+                }
+            }
+        }
+
+        /// Gets all XRD/collateral pool adapters
+        pub fn get_xrd_pool_adapters(&self) -> Vec<(ResourceAddress, ComponentAddress)> {
+            self.xrd_pool_adapters
+                .iter()
+                .map(|(collateral, adapter)| (*collateral, *adapter))
+                .collect()
+        }
+
+        /// Quotes a swap between STAB and any collateral (see `swap_route`)
+        pub fn quote_route(
+            &self,
+            input_address: ResourceAddress,
+            input_amount: Decimal,
+            output_address: ResourceAddress,
+        ) -> Decimal {
+            let collateral: ResourceAddress = match input_address == self.stab_address {
+                true => output_address,
+                false => input_address,
+            };
+
+            match self.xrd_pool_adapters.get(&collateral) {
+                Some(adapter) => {
+                    let adapter: Global<AnyComponent> = Global::from(*adapter);
+                    if input_address == self.stab_address {
+                        let xrd_amount: Decimal = self.stab_pool.quote(input_address, input_amount);
+                        adapter.call_raw("quote", scrypto_args!(XRD, xrd_amount, output_address))
+                    } else {
+                        let xrd_amount: Decimal = adapter
+                            .call_raw("quote", scrypto_args!(input_address, input_amount, XRD));
+                        self.stab_pool.quote(XRD, xrd_amount)
+                    }
+                }
+                None => {
+                    self.get_best_quote(input_address, input_amount, output_address)
+                        .1
+                }
+            }
+        }
+
+        /// Swaps between STAB and any collateral, routing through XRD if the collateral has an XRD pool adapter
+        ///   - STAB -> collateral: the StabilisPool (STAB -> XRD), then the adapter (XRD -> collateral)
+        ///   - collateral -> STAB: the adapter (collateral -> XRD), then the StabilisPool (XRD -> STAB)
+        ///   - without an adapter (or for XRD itself), the liquidity source with the best quote is used
+        ///   - reverts if the output is lower than `min_output`
+        pub fn swap_route(
+            &mut self,
+            input: Bucket,
+            output_address: ResourceAddress,
+            min_output: Decimal,
+        ) -> Bucket {
+            let input_address: ResourceAddress = input.resource_address();
+            assert!(
+                input_address == self.stab_address || output_address == self.stab_address,
+                "Only swaps into or out of STAB are supported."
+            );
+            let collateral: ResourceAddress = match input_address == self.stab_address {
+                true => output_address,
+                false => input_address,
+            };
+
+            let output: Bucket = match self.xrd_pool_adapters.get(&collateral).copied() {
+                Some(adapter) => {
+                    let adapter: Global<AnyComponent> = Global::from(adapter);
+                    if input_address == self.stab_address {
+                        let xrd: Bucket = self.stab_pool.swap(input);
+                        adapter.call_raw("swap", scrypto_args!(xrd, output_address))
+                    } else {
+                        let xrd: Bucket = adapter.call_raw("swap", scrypto_args!(input, XRD));
+                        self.stab_pool.swap(xrd)
+                    }
+                }
+                None => self.swap_with_best_source(input, output_address, dec!(0)),
+            };
+
+            assert!(
+                output.resource_address() == output_address,
+                "Swap returned the wrong resource."
+            );
+            assert!(output.amount() >= min_output, "Output too low.");
+            output
+        }
+
+        pub fn change_collateral_price(&mut self, collateral: ResourceAddress, new_price: Decimal) {
+            self.collateral_prices.insert(collateral, new_price);
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.change_collateral_price(collateral, new_price)
+            });
+        }
+
+        /// Overrides the price of a collateral during oracle outages, oracle prices are ignored until the override expires
+        pub fn override_price(
+            &mut self,
+            collateral: ResourceAddress,
+            price: Decimal,
+            ttl_minutes: i64,
+        ) {
+            assert!(
+                self.accepted_collaterals.get(&collateral).is_some(),
+                "Collateral not accepted."
+            );
+            assert!(ttl_minutes > 0, "Override needs a positive duration.");
+
+            let expires_at: Instant = Clock::current_time_rounded_to_seconds()
+                .add_minutes(ttl_minutes)
+                .unwrap();
+            self.price_overrides.insert(collateral, (price, expires_at));
+            self.change_collateral_price(collateral, price);
+
+            Runtime::emit_event(EventPriceOverride {
+                collateral,
+                price,
+                expires_at,
+            });
+        }
+
+        /// Returns the unconfirmed price of a collateral, if a large price drop awaits confirmation
+        pub fn get_unconfirmed_price(&self, collateral: ResourceAddress) -> Option<Decimal> {
+            self.unconfirmed_prices.get(&collateral).copied()
+        }
+
+        /// Returns the active price override of a collateral and its expiry, if there is one
+        pub fn get_price_override(
+            &self,
+            collateral: ResourceAddress,
+        ) -> Option<(Decimal, Instant)> {
+            self.price_overrides
+                .get(&collateral)
+                .filter(|(_, expires_at)| {
+                    Clock::current_time_is_strictly_before(*expires_at, TimePrecision::Second)
+                })
+                .cloned()
+        }
+
+        pub fn add_pool_collateral(
+            &mut self,
+            address: ResourceAddress,
+            parent_address: ResourceAddress,
+            validator: ComponentAddress,
+            lsu: bool,
+            initial_acceptance: bool,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.add_pool_collateral(
+                    address,
+                    parent_address,
+                    validator,
+                    lsu,
+                    initial_acceptance,
+                )
+            });
+            self.collateral_displays
+                .insert(address, Self::default_display(address));
+        }
+
+        pub fn add_adapter_pool_collateral(
+            &mut self,
+            address: ResourceAddress,
+            parent_address: ResourceAddress,
+            adapter_address: ComponentAddress,
+            initial_acceptance: bool,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.add_adapter_pool_collateral(
+                    address,
+                    parent_address,
+                    adapter_address,
+                    initial_acceptance,
+                )
+            });
+            self.collateral_displays
+                .insert(address, Self::default_display(address));
+        }
+
+        /// Accepts the STAB/XRD LP token of the StabilisPool as collateral
+        ///
+        /// # Input
+        /// - `chosen_mcr`: The minimum collateral ratio of the LP token (at least 2)
+        /// - `max_share`: The maximum share of the circulating STAB minted with the LP token (at most 0.1)
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Checks the LP token isn't accepted yet, and the MCR and maximum share are within their bounds
+        ///   - half of the LP token's value is STAB, which loses value exactly when loans need their collateral most, so the MCR needs to be conservative
+        ///   - STAB minted against the LP token can be added to the pool to mint more LP tokens, so its share is capped to avoid a reflexive feedback loop
+        /// - Prices the LP token from the pool's reserves (see `get_lp_price`)
+        /// - Adds the LP token as collateral to the Stabilis component, and sets its maximum share
+        /// - Tracks the LP token, so its price is updated by `update` instead of the oracle
+        pub fn add_lp_collateral(&mut self, chosen_mcr: Decimal, max_share: Decimal) {
+            let address: ResourceAddress = self.lp_collateral.address;
+            assert!(
+                !self.accepted_collaterals.contains_key(&address),
+                "LP token is already accepted."
+            );
+            assert!(
+                chosen_mcr >= dec!(2),
+                "MCR of the LP token needs to be at least 2."
+            );
+            assert!(
+                max_share > dec!(0) && max_share <= dec!("0.1"),
+                "Maximum share of the LP token needs to be between 0 and 0.1."
+            );
+
+            let price: Decimal = self.get_lp_price();
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.add_collateral(address, chosen_mcr, price);
+                self.stabilis
+                    .edit_collateral(address, chosen_mcr, true, max_share);
+            });
+            self.accepted_collaterals.insert(
+                address,
+                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch as u64,
+            );
+            self.collateral_prices.insert(address, price);
+            self.collateral_displays
+                .insert(address, Self::default_display(address));
+        }
+
+        /// Sets the haircut (0.1 = 10%) applied to the fair value of the STAB/XRD LP token
+        pub fn set_lp_haircut(&mut self, haircut: Decimal) {
+            assert!(
+                haircut >= dec!(0) && haircut < dec!(1),
+                "Haircut needs to be between 0 and 1."
+            );
+            self.lp_collateral.haircut = haircut;
+        }
+
+        /// Gets the STAB/XRD LP token's address and haircut
+        pub fn get_lp_collateral(&self) -> LpCollateral {
+            self.lp_collateral.clone()
+        }
+
+        /// Gets the collateral price of the STAB/XRD LP token
+        ///
+        /// # Input
+        /// - None
+        ///
+        /// # Output
+        /// - The price of a single LP token
+        ///
+        /// # Logic
+        /// - Prices the LP token from the pool's invariant and LP token supply (see `lp_token_price`), valuing STAB at a price that can't be moved by swapping (see `get_lp_stab_price`)
+        pub fn get_lp_price(&self) -> Decimal {
+            lp_token_price(
+                self.stab_pool.get_invariant(),
+                ResourceManager::from_address(self.lp_collateral.address)
+                    .total_supply()
+                    .unwrap(),
+                self.get_lp_stab_price(),
+                self.xrd_price,
+                self.lp_collateral.haircut,
+            )
+        }
+
+        /// Gets the STAB price used to value the STAB/XRD LP token
+        ///   - the internal price, or, while the peg alarm is raised, the lower of the internal price and the average market price of the cached price errors
+        ///   - the peg alarm is only raised once the deviation has lasted for its duration, and a single update only caches one price error, so neither can be moved by skewing the pool within a transaction
+        ///   - so a depegging STAB can't keep propping up the value of the loans backed by it
+        fn get_lp_stab_price(&self) -> Decimal {
+            let internal_price: Decimal = self.stab_price_data.internal_price;
+            if !self.peg_alarm.alerted {
+                return internal_price;
+            }
+
+            let average_market_price: Decimal = internal_price
+                + self.stab_price_data.latest_stab_price_errors_total
+                    / Decimal::from(self.number_of_cached_prices);
+            internal_price.min(average_market_price)
+        }
+
+        /// Updates the price of the STAB/XRD LP token in the Stabilis component, if it is accepted as collateral and not overridden
+        fn update_lp_collateral_price(&mut self) {
+            let address: ResourceAddress = self.lp_collateral.address;
+            if !self.accepted_collaterals.contains_key(&address)
+                || self.get_price_override(address).is_some()
+            {
+                return;
+            }
+
+            let price: Decimal = self.get_lp_price();
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .change_collateral_prices(vec![(address, price)])
+            });
+            self.accepted_collaterals.insert(
+                address,
+                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch as u64,
+            );
+            self.collateral_prices.insert(address, price);
+        }
+
+        /// Aborts the transaction if STAB is minted with the STAB/XRD LP token while the peg alarm is raised
+        ///   - borrowing against the LP token and selling the STAB pushes the peg further away, lowering the value of the LP token itself
+        fn check_lp_borrowing(&self, collateral: ResourceAddress) {
+            assert!(
+                !(collateral == self.lp_collateral.address && self.peg_alarm.alerted),
+                "Can't borrow against the LP token while the peg alarm is raised."
+            );
+        }
+
+        /// Sets the display metadata of a collateral (or pool unit), overriding the defaults taken from its resource metadata at listing
+        pub fn set_collateral_display(
+            &mut self,
+            address: ResourceAddress,
+            symbol: String,
+            icon_url: Option<Url>,
+            market_id: Option<String>,
+        ) {
+            let mut display: CollateralDisplay = Self::default_display(address);
+            display.symbol = symbol;
+            display.icon_url = icon_url;
+            display.market_id = market_id;
+            self.collateral_displays.insert(address, display);
+        }
+
+        /// Gets the display metadata of all listed collaterals (and pool units)
+        pub fn get_collateral_displays(&self) -> Vec<(ResourceAddress, CollateralDisplay)> {
+            self.collateral_displays
+                .iter()
+                .map(|(address, display)| (*address, display.clone()))
+                .collect()
+        }
+
+        /// Checks whether a resource can be listed as collateral, without listing it, so governance can test a listing before executing it
+        ///
+        /// # Input
+        /// - `address`: The address of the resource to list
+        /// - `mcr`: The minimum collateral ratio the resource would be listed with
+        /// - `market_id`: The oracle market id the price of the resource would come from
+        ///
+        /// # Output
+        /// - A `CollateralListingReport` with the findings, and the issues blocking the listing
+        ///
+        /// # Logic
+        /// - Checks the resource is fungible and fully divisible, as computed collateral amounts can't be withdrawn otherwise
+        /// - Checks the resource has name, symbol and icon metadata
+        /// - Checks the oracle provides a positive price for the resource under the market id
+        /// - Checks the MCR is above 1 and the resource isn't tracked yet
+        pub fn validate_collateral_listing(
+            &self,
+            address: ResourceAddress,
+            mcr: Decimal,
+            market_id: String,
+        ) -> CollateralListingReport {
+            let manager: ResourceManager = ResourceManager::from_address(address);
+            let mut issues: Vec<String> = Vec::new();
+
+            let (fungible, divisibility): (bool, Option<u8>) = match manager.resource_type() {
+                ResourceType::Fungible { divisibility } => (true, Some(divisibility)),
+                ResourceType::NonFungible { .. } => (false, None),
+            };
+            if !fungible {
+                issues.push("Resource is not fungible.".to_string());
+            } else if divisibility != Some(18) {
+                issues.push(
+                    "Divisibility is below 18, so computed collateral amounts may not be withdrawable."
+                        .to_string(),
+                );
+            }
+
+            let has_name: bool =
+                matches!(manager.get_metadata::<&str, String>("name"), Ok(Some(_)));
+            let has_symbol: bool =
+                matches!(manager.get_metadata::<&str, String>("symbol"), Ok(Some(_)));
+            let has_icon_url: bool =
+                matches!(manager.get_metadata::<&str, Url>("icon_url"), Ok(Some(_)));
+            if !has_name || !has_symbol || !has_icon_url {
+                issues.push("Name, symbol or icon metadata is missing.".to_string());
+            }
+
+            let prices: Vec<(ResourceAddress, Decimal, u64, String)> =
+                self.oracle.call(&self.oracle_method_name, &());
+            let oracle_price: Option<Decimal> = prices
+                .iter()
+                .find(|(price_address, _, _, pair)| *price_address == address && *pair == market_id)
+                .map(|(_, price, _, _)| *price);
+            match oracle_price {
+                None => {
+                    issues.push("Oracle has no price for this resource and market.".to_string())
+                }
+                Some(price) if price <= dec!(0) => {
+                    issues.push("Oracle price is not positive.".to_string())
+                }
+                _ => {}
+            }
+
+            if mcr <= dec!(1) {
+                issues.push("MCR needs to be above 1.".to_string());
+            }
+
+            let already_tracked: bool = self.accepted_collaterals.contains_key(&address);
+            if already_tracked {
+                issues.push("Collateral is already tracked.".to_string());
+            }
+
+            CollateralListingReport {
+                fungible,
+                divisibility,
+                has_name,
+                has_symbol,
+                has_icon_url,
+                oracle_price,
+                already_tracked,
+                valid: issues.is_empty(),
+                issues,
+            }
+        }
+
+        /// Creates the display metadata of a collateral from its resource metadata
+        fn default_display(address: ResourceAddress) -> CollateralDisplay {
+            let manager: ResourceManager = ResourceManager::from_address(address);
+            let decimals: Option<u8> = match manager.resource_type() {
+                ResourceType::Fungible { divisibility } if divisibility < 18 => Some(divisibility),
+                _ => None,
+            };
+
+            CollateralDisplay {
+                symbol: manager
+                    .get_metadata::<&str, String>("symbol")
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default(),
+                icon_url: manager.get_metadata::<&str, Url>("icon_url").ok().flatten(),
+                decimals,
+                market_id: None,
+            }
+        }
+
+        pub fn change_internal_price(&mut self, new_price: Decimal) {
+            self.stab_price_data.internal_price = new_price;
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.change_internal_price(new_price)
+            });
+        }
+
+        pub fn set_max_vector_length(&mut self, new_stabilis_length: u64) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_max_vector_length(new_stabilis_length)
+            });
+        }
+
+        pub fn get_internal_price(&self) -> Decimal {
+            self.stab_price_data.internal_price
+        }
+
+        pub fn get_liquidation_receipts(
+            &self,
+            from: u64,
+            to: u64,
+        ) -> Vec<(NonFungibleLocalId, LiquidationReceipt)> {
+            self.stabilis.get_liquidation_receipts(from, to)
+        }
+
+        //==================================================================
+        //                      INTEGRATION INTERFACE
+        //==================================================================
+
+        /// Redeems the liquidation receipt of an unprofitable liquidation for compensation from the keeper rewards pool
+        pub fn redeem_liquidation_receipt(&mut self, receipt_proof: NonFungibleProof) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.stabilis.get_liquidation_receipt_address(),
+                "Incorrect proof! Are you sure this is a liquidation receipt?",
+            );
+            let receipt = receipt_proof.non_fungible::<LiquidationReceipt>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.redeem_liquidation_receipt(receipt_id)
+            })
+        }
+
+        pub fn deposit_keeper_rewards(&mut self, rewards: Bucket) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.deposit_keeper_rewards(rewards)
+            });
+        }
+
+        pub fn get_keeper_rewards(&self) -> Decimal {
+            self.stabilis.get_keeper_rewards()
+        }
+
+        pub fn get_stability_reserve(&self) -> Decimal {
+            self.stabilis.get_stability_reserve()
+        }
+
+        /// Donate STAB to be burned against the system's debt, receiving a donation receipt
+        pub fn donate_and_burn(&mut self, stab: Bucket, memo: Option<String>) -> Bucket {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.donate_and_burn(stab, memo))
+        }
+
+        pub fn get_donated_stab(&self) -> Decimal {
+            self.stabilis.get_donated_stab()
+        }
+
+        pub fn get_resource_addresses(&self) -> ResourceAddresses {
+            self.stabilis.get_resource_addresses()
+        }
+
+        pub fn get_treasury_balances(&self, collateral: ResourceAddress) -> (Decimal, Decimal) {
+            self.stabilis.get_treasury_balances(collateral)
+        }
+
+        /// Gets the interest statement of a loan / CDP and its projected liquidation date
+        ///
+        /// # Input
+        /// - `cdp_id`: The id of the loan / CDP
+        ///
+        /// # Output
+        /// - The `CdpProjection` of the loan
+        ///
+        /// # Logic
+        /// - Calculates the debt value and its change over the next day at the current interest rate
+        /// - Gets the internal price at which the loan becomes liquidatable (at constant collateral prices)
+        /// - Projects when the internal price reaches this price, if it does
+        ///     - internal price * interest rate ^ minutes = liquidation internal price
+        pub fn get_cdp_projection(&self, cdp_id: NonFungibleLocalId) -> CdpProjection {
+            let receipt_data: Cdp = self.cdp_receipt_manager.get_non_fungible_data(&cdp_id);
+            let internal_price: Decimal = self.stab_price_data.internal_price;
+            let interest_rate: Decimal = self.stab_price_data.interest_rate;
+
+            let debt_value: Decimal = receipt_data.minted_stab * internal_price;
+            let daily_debt_change: Decimal =
+                debt_value * (interest_rate.pow(dec!(1440)).unwrap() - dec!(1));
+
+            let liquidation_internal_price: Decimal =
+                self.stabilis.get_liquidation_internal_price(cdp_id.clone());
+
+            let now: Instant = Clock::current_time_rounded_to_seconds();
+            let projected_liquidation: Option<Instant> = if liquidation_internal_price
+                <= internal_price
+            {
+                Some(now)
+            } else if interest_rate > dec!(1) {
+                let minutes: Decimal = (liquidation_internal_price / internal_price).ln().unwrap()
+                    / interest_rate.ln().unwrap();
+                i64::try_from(minutes.checked_ceiling().unwrap())
+                    .ok()
+                    .and_then(|minutes| now.add_minutes(minutes))
+            } else {
+                None
+            };
+
+            CdpProjection {
+                cdp_id,
+                minted_stab: receipt_data.minted_stab,
+                debt_value,
+                interest_rate,
+                daily_debt_change,
+                liquidation_internal_price,
+                projected_liquidation,
+            }
+        }
+
+        /// Gets the annualized cost of borrowing STAB at the current interest rate, so UIs can display a familiar APR
+        ///   - the interest rate is applied per minute to the internal price, which is what borrowers pay (the debt value grows with it)
+        ///   - the projections assume a constant interest rate
+        pub fn get_borrow_cost(&self) -> BorrowCost {
+            let internal_price: Decimal = self.stab_price_data.internal_price;
+            let interest_rate: Decimal = self.stab_price_data.interest_rate;
+            let minutes_per_day: Decimal = dec!(1440);
+
+            BorrowCost {
+                interest_rate,
+                apr: (interest_rate - dec!(1)) * minutes_per_day * dec!(365),
+                apy: interest_rate.pow(minutes_per_day * dec!(365)).unwrap() - dec!(1),
+                internal_price,
+                internal_price_30d: internal_price
+                    * interest_rate.pow(minutes_per_day * dec!(30)).unwrap(),
+                internal_price_90d: internal_price
+                    * interest_rate.pow(minutes_per_day * dec!(90)).unwrap(),
+                internal_price_365d: internal_price
+                    * interest_rate.pow(minutes_per_day * dec!(365)).unwrap(),
+            }
+        }
+
+        /// Quotes opening a loan, returning a versioned `OpenQuote` (meant for aggregators and other integrators)
+        pub fn quote_open(
+            &self,
+            collateral: ResourceAddress,
+            collateral_amount: Decimal,
+            stab_to_mint: Decimal,
+        ) -> OpenQuote {
+            self.stabilis
+                .quote_open(collateral, collateral_amount, stab_to_mint)
+        }
+
+        /// Quotes closing a loan, returning a versioned `CloseQuote` (meant for aggregators and other integrators)
+        pub fn quote_close(&self, cdp_id: NonFungibleLocalId) -> CloseQuote {
+            self.stabilis.quote_close(cdp_id)
+        }
+
+        /// Gets the shares of collaterals and pool units compared to their caps, so borrowers can be warned before the share check fails
+        pub fn get_share_warnings(&self, collaterals: Vec<ResourceAddress>) -> Vec<ShareWarning> {
+            self.stabilis.get_share_warnings(collaterals)
+        }
+
+        /// Returns general protocol info in a versioned `ProtocolInfo` (meant for aggregators and other integrators)
+        pub fn protocol_info(&self) -> ProtocolInfo {
+            let parameters: ProtocolParameters = self.stabilis.get_parameters();
+
+            ProtocolInfo {
+                version: INTEGRATION_API_VERSION,
+                stabilis: self.stabilis.address(),
+                cdp_receipt_address: self.cdp_receipt_manager.address(),
+                cdp_marker_address: self.cdp_marker_manager.address(),
+                internal_price: self.stab_price_data.internal_price,
+                interest_rate: self.stab_price_data.interest_rate,
+                accepted_collaterals: self.accepted_collaterals.keys().cloned().collect(),
+                minimum_mint: parameters.minimum_mint,
+                liquidation_delay: parameters.liquidation_delay,
+                unmarked_delay: parameters.unmarked_delay,
+                liquidation_fine: parameters.liquidation_liquidation_fine,
+                marker_liquidation_fine: parameters.marker_liquidation_fine,
+                stabilis_fine: parameters.stabilis_liquidation_fine,
+            }
+        }
+
+        //==================================================================
+        //                      FLASH LOANS COMPONENT
+        //==================================================================
+
+        pub fn flash_borrow(&mut self, amount: Decimal) -> (Bucket, Bucket) {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.flash_loans.borrow(amount))
+        }
+
+        pub fn flash_pay_back(&mut self, receipt_bucket: Bucket, payment_bucket: Bucket) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.flash_loans.pay_back(receipt_bucket, payment_bucket)
+            })
+        }
+
+        pub fn get_flash_terms(&self) -> FlashTerms {
+            self.flash_loans.get_flash_terms()
+        }
+
+        pub fn flash_retrieve_interest(&mut self) -> Bucket {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.flash_loans.retrieve_interest())
+        }
+
+        /// Rescues tokens airdropped to the FlashLoans component (see its `rescue_tokens`)
+        pub fn rescue_flash_loans_tokens(
+            &mut self,
+            resource: ResourceAddress,
+            amount: Decimal,
+        ) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.flash_loans.rescue_tokens(resource, amount)
+            })
+        }
+
+        /// Rescues tokens airdropped to the StabilisPool component (see its `rescue_tokens`)
+        pub fn rescue_pool_tokens(&mut self, resource: ResourceAddress, amount: Decimal) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stab_pool.rescue_tokens(resource, amount)
+            })
+        }
+
+        //==================================================================
+        //                      ORACLE COMPONENT
+        //==================================================================
 
         pub fn add_pair_to_oracle(
             &mut self,
@@ -789,10 +3810,34 @@ mod proxy {
     }
 }
 
+/// Admin actions that can be executed by anyone through `execute_signed_admin_action`, if signed by the admin signer
+#[derive(ScryptoSbor, Clone)]
+pub enum SignedAdminAction {
+    SetKs {
+        new_ki: Decimal,
+        new_kp: Decimal,
+    },
+    SetUpdateDelays {
+        update_delay: i64,
+    },
+    SetAllowedDeviation {
+        allowed_deviation: Decimal,
+    },
+    SetMinmaxInterest {
+        min_interest: Decimal,
+        max_interest: Decimal,
+    },
+    SetRewardPerSecond {
+        reward_per_second: Decimal,
+    },
+}
+
 #[derive(ScryptoSbor)]
 pub struct StabPriceData {
     /// The latest price errors for the STAB token (market price - internal price), used for calculating the interest rate
     pub latest_stab_price_errors: KeyValueStore<u64, Decimal>,
+    /// The times the latest price errors were cached, by the same key
+    pub latest_stab_price_error_times: KeyValueStore<u64, Instant>,
     /// The total of the latest price errors
     pub latest_stab_price_errors_total: Decimal,
     /// The time of the last update
@@ -805,6 +3850,237 @@ pub struct StabPriceData {
     pub full_cache: bool,
     /// The interest rate for the STAB token
     pub interest_rate: Decimal,
+    /// The drift of the internal price above the max price drift (and rounding), carried to the next updates
+    pub price_drift_remainder: PreciseDecimal,
+    /// The change of the interest rate applied by the last update
+    pub last_rate_delta: Decimal,
+    /// Whether the interest rate was clamped to the minimum or maximum interest rate by the last update
+    pub last_rate_clamped: bool,
+}
+
+/// Parameters for distributing flash loan income to STAB savers during negative interest rates
+#[derive(ScryptoSbor)]
+pub struct EqualizationParameters {
+    /// The savings component (stSTAB) receiving the income (None if disabled)
+    pub savings: Option<Global<Savings>>,
+    /// The share of the collected flash loan interest distributed per day
+    pub share: Decimal,
+    /// The time the interest rate needs to be negative before income is distributed (minutes)
+    pub delay: i64,
+    /// Since when the interest rate is negative (None if it isn't)
+    pub negative_since: Option<Instant>,
+    /// When income was last distributed (or the parameters were last set)
+    pub last_distribution: Instant,
+}
+
+/// Peg deviation alarm, with separate alert and recovery thresholds (hysteresis)
+#[derive(ScryptoSbor, Clone)]
+pub struct PegAlarm {
+    /// The deviation from the internal price (fraction) above which the peg is considered lost
+    pub alert_threshold: Decimal,
+    /// The deviation from the internal price (fraction) below which the peg is considered recovered
+    pub recovery_threshold: Decimal,
+    /// The time the deviation needs to last before an alert is emitted (minutes)
+    pub duration: i64,
+    /// Since when the deviation is above the alert threshold (None if it isn't)
+    pub deviating_since: Option<Instant>,
+    /// Whether an alert was emitted and the peg hasn't recovered since
+    pub alerted: bool,
+}
+
+/// The STAB/XRD LP token of the StabilisPool, used as collateral
+#[derive(ScryptoSbor, Clone)]
+pub struct LpCollateral {
+    /// The address of the LP token
+    pub address: ResourceAddress,
+    /// The haircut (fraction) applied to the fair value of the LP token, covering its exposure to STAB
+    pub haircut: Decimal,
+}
+
+/// History of the interest rate, stored as checkpoints of the cumulative interest rate
+#[derive(ScryptoSbor)]
+pub struct InterestRateHistory {
+    /// Ring buffer of checkpoints of the cumulative interest rate
+    pub checkpoints: KeyValueStore<u64, RateCheckpoint>,
+    /// The total number of checkpoints stored
+    pub checkpoint_counter: u64,
+    /// The sum of the interest rate times the seconds it applied
+    pub cumulative: Decimal,
+    /// The time of the last addition to the cumulative interest rate
+    pub last_accumulation: Instant,
+    /// The minimum time between checkpoints (seconds)
+    pub checkpoint_interval: i64,
+    /// The number of checkpoints kept
+    pub max_checkpoints: u64,
+    /// The windows of the time-weighted average interest rates (seconds)
+    pub windows: Vec<i64>,
+}
+
+/// A checkpoint of the cumulative interest rate
+#[derive(ScryptoSbor, Clone)]
+pub struct RateCheckpoint {
+    /// The time of the checkpoint (seconds since unix epoch)
+    pub timestamp: i64,
+    /// The cumulative interest rate at the time of the checkpoint
+    pub cumulative: Decimal,
+}
+
+/// A preview of the next interest rate update
+#[derive(ScryptoSbor, Clone)]
+pub struct InterestPreview {
+    /// Whether enough time has passed for the update to be applied
+    pub update_possible: bool,
+    /// The minutes passed since the last update
+    pub passed_minutes: Decimal,
+    /// The market price of the STAB token (in USD)
+    pub market_price: Decimal,
+    /// Whether the pool price moved more than the max price range since the last update (if so, the price error is ignored)
+    pub price_range_exceeded: bool,
+    /// The price error (market price - internal price), capped at the maximum price error
+    pub price_error: Decimal,
+    /// The total of the latest price errors, including this one
+    pub price_errors_total: Decimal,
+    /// The proportional term of the interest rate change (per minute)
+    pub p_term: Decimal,
+    /// The integral term of the interest rate change (per minute)
+    pub i_term: Decimal,
+    /// Whether the price error is big enough to change the interest rate
+    pub outside_allowed_deviation: bool,
+    /// The new interest rate
+    pub interest_rate: Decimal,
+    /// Whether the new interest rate is clamped to the minimum or maximum interest rate
+    pub interest_rate_clamped: bool,
+    /// Whether the new interest rate is a fixed rate set by the DAO, instead of the PID controller's
+    pub rate_fixed: bool,
+    /// The new internal price of the STAB token
+    pub internal_price: Decimal,
+    /// The drift of the internal price carried to the next update (a factor, 1 if nothing is carried)
+    pub price_drift_remainder: PreciseDecimal,
+}
+
+/// State of the interest rate PID controller, for tuning the kp and ki parameters
+#[derive(ScryptoSbor, Clone)]
+pub struct ControllerState {
+    /// The number of price errors in the cache
+    pub cached_errors: u64,
+    /// The total of the cached price errors
+    pub price_errors_total: Decimal,
+    /// When the oldest cached price error was cached (None if the cache is empty)
+    pub oldest_error_at: Option<Instant>,
+    /// When the newest cached price error was cached (None if the cache is empty)
+    pub newest_error_at: Option<Instant>,
+    /// The current integral term of the interest rate change (per minute)
+    pub i_term: Decimal,
+    /// The current interest rate
+    pub interest_rate: Decimal,
+    /// The change of the interest rate applied by the last update
+    pub last_rate_delta: Decimal,
+    /// Whether the interest rate was clamped to the minimum or maximum interest rate by the last update
+    pub last_rate_clamped: bool,
+    /// Whether the PID controller sets the interest rate, or it is fixed
+    pub rate_mode: RateMode,
+}
+
+/// Loan lifecycle events extension components can subscribe to
+#[derive(ScryptoSbor, Clone, PartialEq, Debug)]
+pub enum CdpHookEvent {
+    Opened,
+    PartiallyClosed,
+    Closed,
+    Liquidated,
+    ForceLiquidated,
+}
+
+/// A whitelisted extension component
+#[derive(ScryptoSbor, Clone)]
+pub struct CdpHook {
+    /// The events the component is called for
+    pub events: Vec<CdpHookEvent>,
+    /// The weight of the component, limiting the number of hooks called for a single event
+    pub weight: u64,
+    /// Whether the component is called, so the owner can disable a failing hook
+    pub enabled: bool,
+}
+
+/// A whitelisted external DEX adapter component
+#[derive(ScryptoSbor, Clone)]
+pub struct LiquiditySource {
+    /// The maximum amount of STAB swapped in or out through the adapter per swap
+    pub max_stab: Decimal,
+}
+
+/// Display metadata of a collateral, so frontends can render collateral lists without a separate config service
+#[derive(ScryptoSbor, Clone)]
+pub struct CollateralDisplay {
+    /// The symbol of the collateral
+    pub symbol: String,
+    /// The icon of the collateral
+    pub icon_url: Option<Url>,
+    /// The divisibility of the collateral, if lower than 18
+    pub decimals: Option<u8>,
+    /// The oracle market id of the collateral
+    pub market_id: Option<String>,
+}
+
+/// The findings of a collateral listing dry-run
+#[derive(ScryptoSbor, Clone)]
+pub struct CollateralListingReport {
+    /// Whether the resource is fungible
+    pub fungible: bool,
+    /// The divisibility of the resource (None if non-fungible)
+    pub divisibility: Option<u8>,
+    /// Whether the resource has name metadata
+    pub has_name: bool,
+    /// Whether the resource has symbol metadata
+    pub has_symbol: bool,
+    /// Whether the resource has icon metadata
+    pub has_icon_url: bool,
+    /// The price the oracle provides for the resource under the market id
+    pub oracle_price: Option<Decimal>,
+    /// Whether the resource is already tracked by the Proxy
+    pub already_tracked: bool,
+    /// Whether the resource can be listed (no issues found)
+    pub valid: bool,
+    /// The issues found
+    pub issues: Vec<String>,
+}
+
+/// Interest statement of a loan / CDP and its projected liquidation date
+#[derive(ScryptoSbor, Clone)]
+pub struct CdpProjection {
+    /// The id of the loan / CDP
+    pub cdp_id: NonFungibleLocalId,
+    /// The amount of STAB minted
+    pub minted_stab: Decimal,
+    /// The value of the minted STAB at the current internal price
+    pub debt_value: Decimal,
+    /// The current interest rate (per minute)
+    pub interest_rate: Decimal,
+    /// The change of the debt value over the next day at the current interest rate
+    pub daily_debt_change: Decimal,
+    /// The internal price at which the loan becomes liquidatable, at constant collateral prices
+    pub liquidation_internal_price: Decimal,
+    /// The projected liquidation time, at constant collateral prices and interest rate (None if the loan never becomes liquidatable)
+    pub projected_liquidation: Option<Instant>,
+}
+
+/// The annualized cost of borrowing STAB at the current interest rate
+#[derive(ScryptoSbor, Clone)]
+pub struct BorrowCost {
+    /// The current interest rate (per minute)
+    pub interest_rate: Decimal,
+    /// The annual percentage rate, without compounding (0.05 = 5%)
+    pub apr: Decimal,
+    /// The effective annual rate, compounding every minute (0.05 = 5%)
+    pub apy: Decimal,
+    /// The current internal price of the STAB token
+    pub internal_price: Decimal,
+    /// The projected internal price in 30 days
+    pub internal_price_30d: Decimal,
+    /// The projected internal price in 90 days
+    pub internal_price_90d: Decimal,
+    /// The projected internal price in 365 days
+    pub internal_price_365d: Decimal,
 }
 
 #[derive(ScryptoSbor)]
@@ -823,4 +4099,8 @@ pub struct InterestParameters {
     pub max_price_error: Decimal,
     /// The offset for the price error
     pub price_error_offset: Decimal,
+    /// The maximum relative range the pool price can move between updates, before a price sample is ignored
+    pub max_price_range: Decimal,
+    /// The maximum relative change of the internal price per update
+    pub max_price_drift: Decimal,
 }
@@ -0,0 +1,129 @@
+//! # Registry Blueprint
+//!
+//! Component recording the current addresses and versions of the components of the STAB protocol (such as stabilis, proxy, oracle, flash_loans and pool).
+//! When a component is migrated to a new version, the owner updates its entry, so integrators and scripts can resolve addresses instead of hardcoding them.
+
+use crate::events::*;
+use scrypto::prelude::*;
+
+/// Entry of a component in the registry
+#[derive(ScryptoSbor, Clone)]
+pub struct RegistryEntry {
+    /// address of the component
+    pub address: GlobalAddress,
+    /// version of the component
+    pub version: u64,
+    /// time of the last update of this entry
+    pub updated_at: Instant,
+}
+
+#[blueprint]
+#[types(String, RegistryEntry)]
+#[events(EventRegistryUpdate)]
+mod registry {
+    enable_method_auth! {
+        methods {
+            resolve => PUBLIC;
+            get_entry => PUBLIC;
+            get_entries => PUBLIC;
+            register => restrict_to: [OWNER];
+            unregister => restrict_to: [OWNER];
+        }
+    }
+
+    struct Registry {
+        /// KVS storing the entry of each registered component, by name
+        entries: KeyValueStore<String, RegistryEntry>,
+        /// The names of all registered components
+        names: Vec<String>,
+    }
+
+    impl Registry {
+        /// Instantiates the Registry component
+        pub fn instantiate(
+            owner_role: OwnerRole,
+            dapp_def_address: GlobalAddress,
+        ) -> Global<Registry> {
+            Self {
+                entries: RegistryKeyValueStore::new_with_registered_type(),
+                names: Vec::new(),
+            }
+            .instantiate()
+            .prepare_to_globalize(owner_role)
+            .metadata(metadata! {
+                init {
+                    "name" => "STAB Registry".to_string(), updatable;
+                    "description" => "A registry of the components of the STAB protocol".to_string(), updatable;
+                    "info_url" => Url::of("https://ilikeitstable.com"), updatable;
+                    "dapp_definition" => dapp_def_address, updatable;
+                }
+            })
+            .globalize()
+        }
+
+        /// Get the current address of a component
+        pub fn resolve(&self, name: String) -> Option<GlobalAddress> {
+            self.entries.get(&name).map(|entry| entry.address)
+        }
+
+        /// Get the registry entry of a component
+        pub fn get_entry(&self, name: String) -> Option<RegistryEntry> {
+            self.entries.get(&name).map(|entry| entry.clone())
+        }
+
+        /// Get the registry entries of all components
+        pub fn get_entries(&self) -> Vec<(String, RegistryEntry)> {
+            self.names
+                .iter()
+                .map(|name| (name.clone(), self.entries.get(name).unwrap().clone()))
+                .collect()
+        }
+
+        /// Register a component, or update its entry after a migration
+        ///   - a new version needs to be higher than the current version
+        pub fn register(&mut self, name: String, address: GlobalAddress, version: u64) {
+            if let Some(entry) = self.entries.get(&name) {
+                assert!(
+                    version > entry.version,
+                    "Version needs to be higher than current version."
+                );
+            } else {
+                self.names.push(name.clone());
+            }
+
+            self.entries.insert(
+                name.clone(),
+                RegistryEntry {
+                    address,
+                    version,
+                    updated_at: Clock::current_time_rounded_to_seconds(),
+                },
+            );
+
+            Runtime::emit_event(EventRegistryUpdate {
+                name,
+                address: Some(address),
+                version,
+            });
+        }
+
+        /// Remove a component from the registry
+        pub fn unregister(&mut self, name: String) {
+            let version: u64 = self
+                .entries
+                .get(&name)
+                .expect("Name not registered.")
+                .version;
+
+            self.entries.remove(&name);
+            self.names
+                .retain(|registered_name| registered_name != &name);
+
+            Runtime::emit_event(EventRegistryUpdate {
+                name,
+                address: None,
+                version,
+            });
+        }
+    }
+}
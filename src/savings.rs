@@ -0,0 +1,231 @@
+//! # Savings Blueprint
+//!
+//! Component wrapping STAB into stSTAB, a yield-bearing version of STAB, similar to a OneResourcePool.
+//! Deposited STAB is pooled, and stSTAB represents a share of the pool. As savings accrue to the pool, the redemption value of stSTAB grows, so yield compounds automatically.
+//!
+//! Savings accrue at the savings rate (per minute, like the interest rate), paid out of a yield reserve that anyone can fund (typically the owner, from protocol revenue).
+//! If the yield reserve runs dry, savings stop accruing until it is funded again. STAB can also be added to the pool directly, raising the redemption value immediately.
+//!
+//! Because STAB can be added to the pool by anyone, part of the first deposit's stSTAB is locked in the component forever (like a liquidity pool's minimum liquidity).
+//! This way the stSTAB supply can never be brought back to a tiny amount, so the redemption value can't be inflated to round later deposits down to nothing.
+
+use scrypto::prelude::*;
+use scrypto_math::*;
+
+#[blueprint]
+mod savings {
+    enable_method_auth! {
+        methods {
+            deposit => PUBLIC;
+            redeem => PUBLIC;
+            fund_yield_reserve => PUBLIC;
+            add_yield => PUBLIC;
+            get_redemption_value => PUBLIC;
+            get_savings_info => PUBLIC;
+            set_savings_rate => restrict_to: [OWNER];
+            withdraw_yield_reserve => restrict_to: [OWNER];
+        }
+    }
+
+    struct Savings {
+        /// The vault holding the pooled STAB, redeemable with stSTAB
+        pool: Vault,
+        /// The vault holding the STAB savings are paid out of
+        yield_reserve: Vault,
+        /// The resource manager for stSTAB
+        ststab_manager: ResourceManager,
+        /// The vault holding the stSTAB locked at the first deposit, which can never be redeemed
+        locked_ststab: Vault,
+        /// The savings rate (per minute, 1.000001 = 0.0001% per minute)
+        savings_rate: Decimal,
+        /// The time savings were last accrued
+        last_accrual: Instant,
+    }
+
+    impl Savings {
+        /// Instantiates the Savings component
+        ///
+        /// # Input
+        /// - `owner_role`: The owner role of the component
+        /// - `stab_address`: The resource address of the STAB token
+        /// - `dapp_def_address`: The address of the dapp definition account
+        ///
+        /// # Output
+        /// - The global instance of the Savings component
+        ///
+        /// # Logic
+        /// - Creates a ResourceManager for stSTAB, mintable and burnable by the component
+        /// - Instantiates the Savings component with a savings rate of 1 (no savings)
+        pub fn instantiate(
+            owner_role: OwnerRole,
+            stab_address: ResourceAddress,
+            dapp_def_address: GlobalAddress,
+        ) -> Global<Savings> {
+            let (address_reservation, component_address) =
+                Runtime::allocate_component_address(Savings::blueprint_id());
+
+            let ststab_manager: ResourceManager = ResourceBuilder::new_fungible(owner_role.clone())
+                .divisibility(DIVISIBILITY_MAXIMUM)
+                .metadata(metadata!(
+                    init {
+                        "name" => "Staked STAB", locked;
+                        "symbol" => "stSTAB", locked;
+                        "description" => "Yield-bearing STAB, redeemable for a growing amount of STAB", locked;
+                        "info_url" => "https://ilikeitstable.com", updatable;
+                    }
+                ))
+                .mint_roles(mint_roles!(
+                    minter => rule!(require(global_caller(component_address)));
+                    minter_updater => rule!(deny_all);
+                ))
+                .burn_roles(burn_roles!(
+                    burner => rule!(require(global_caller(component_address)));
+                    burner_updater => rule!(deny_all);
+                ))
+                .create_with_no_initial_supply();
+
+            Self {
+                pool: Vault::new(stab_address),
+                yield_reserve: Vault::new(stab_address),
+                locked_ststab: Vault::new(ststab_manager.address()),
+                ststab_manager,
+                savings_rate: dec!(1),
+                last_accrual: Clock::current_time_rounded_to_seconds(),
+            }
+            .instantiate()
+            .prepare_to_globalize(owner_role)
+            .with_address(address_reservation)
+            .metadata(metadata! {
+                init {
+                    "name" => "STAB Savings".to_string(), updatable;
+                    "description" => "A component wrapping STAB into yield-bearing stSTAB".to_string(), updatable;
+                    "info_url" => Url::of("https://ilikeitstable.com"), updatable;
+                    "dapp_definition" => dapp_def_address, updatable;
+                }
+            })
+            .globalize()
+        }
+
+        /// Deposit STAB, in return for stSTAB
+        ///   - the stSTAB received is the deposit's share of the pool, after accruing savings
+        ///   - if no stSTAB exists, stSTAB is minted 1:1, and 0.001 stSTAB of it is locked in the component forever
+        ///   - a deposit too small to receive any stSTAB is rejected
+        pub fn deposit(&mut self, stab: Bucket) -> Bucket {
+            assert!(
+                stab.resource_address() == self.pool.resource_address(),
+                "Invalid STAB deposit."
+            );
+            self.accrue();
+
+            let supply: Decimal = self.ststab_manager.total_supply().unwrap();
+            let to_mint: Decimal = match supply > dec!(0) && self.pool.amount() > dec!(0) {
+                true => stab.amount() * supply / self.pool.amount(),
+                false => stab.amount(),
+            };
+
+            assert!(to_mint > dec!(0), "Deposit too small.");
+
+            self.pool.put(stab);
+            let mut ststab: Bucket = self.ststab_manager.mint(to_mint);
+
+            if supply == dec!(0) {
+                assert!(
+                    to_mint > dec!("0.001"),
+                    "First deposit needs to be more than 0.001 STAB."
+                );
+                self.locked_ststab.put(ststab.take(dec!("0.001")));
+            }
+
+            ststab
+        }
+
+        /// Redeem stSTAB for its share of the pooled STAB, after accruing savings
+        pub fn redeem(&mut self, ststab: Bucket) -> Bucket {
+            assert!(
+                ststab.resource_address() == self.ststab_manager.address(),
+                "Invalid stSTAB."
+            );
+            self.accrue();
+
+            let supply: Decimal = self.ststab_manager.total_supply().unwrap();
+            let to_return: Decimal = ststab.amount() * self.pool.amount() / supply;
+
+            ststab.burn();
+            self.pool
+                .take_advanced(to_return, WithdrawStrategy::Rounded(RoundingMode::ToZero))
+        }
+
+        /// Fund the yield reserve savings are paid out of
+        pub fn fund_yield_reserve(&mut self, stab: Bucket) {
+            self.accrue();
+            self.yield_reserve.put(stab);
+        }
+
+        /// Add STAB to the pool directly, raising the redemption value of stSTAB immediately
+        pub fn add_yield(&mut self, stab: Bucket) {
+            self.accrue();
+            self.pool.put(stab);
+        }
+
+        /// Get the amount of STAB 1 stSTAB is redeemable for, including savings not accrued yet
+        pub fn get_redemption_value(&self) -> Decimal {
+            let supply: Decimal = self.ststab_manager.total_supply().unwrap();
+            match supply > dec!(0) {
+                true => (self.pool.amount() + self.pending_savings()) / supply,
+                false => dec!(1),
+            }
+        }
+
+        /// Get the pooled STAB (including savings not accrued yet), the stSTAB supply, the yield reserve and the savings rate
+        pub fn get_savings_info(&self) -> (Decimal, Decimal, Decimal, Decimal) {
+            let pending: Decimal = self.pending_savings();
+            (
+                self.pool.amount() + pending,
+                self.ststab_manager.total_supply().unwrap(),
+                self.yield_reserve.amount() - pending,
+                self.savings_rate,
+            )
+        }
+
+        /// Set the savings rate (per minute), accruing savings at the old rate first
+        pub fn set_savings_rate(&mut self, savings_rate: Decimal) {
+            assert!(savings_rate >= dec!(1), "Savings rate can't be below 1.");
+            self.accrue();
+            self.savings_rate = savings_rate;
+        }
+
+        /// Withdraw STAB from the yield reserve, accruing savings first
+        pub fn withdraw_yield_reserve(&mut self, amount: Decimal) -> Bucket {
+            self.accrue();
+            self.yield_reserve.take(amount)
+        }
+
+        /// Move the savings accrued since the last accrual from the yield reserve to the pool
+        fn accrue(&mut self) {
+            let pending: Decimal = self.pending_savings();
+            if pending > dec!(0) {
+                let savings: Bucket = self
+                    .yield_reserve
+                    .take_advanced(pending, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+                self.pool.put(savings);
+            }
+            self.last_accrual = Clock::current_time_rounded_to_seconds();
+        }
+
+        /// Calculate the savings accrued since the last accrual, compounding per minute and capped by the yield reserve
+        fn pending_savings(&self) -> Decimal {
+            let passed_minutes: Decimal = Decimal::from(
+                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch
+                    - self.last_accrual.seconds_since_unix_epoch,
+            ) / dec!(60);
+
+            if passed_minutes == dec!(0) || self.savings_rate == dec!(1) {
+                return dec!(0);
+            }
+
+            let savings: Decimal =
+                self.pool.amount() * (self.savings_rate.pow(passed_minutes).unwrap() - dec!(1));
+            savings.min(self.yield_reserve.amount())
+        }
+    }
+}
@@ -2,15 +2,344 @@
 //! Structs used by both the Proxy and STAB component
 
 use scrypto::prelude::*;
+use scrypto_math::*;
+
+/// Maximum length (in bytes) of a loan / CDP label
+pub const MAX_CDP_LABEL_LENGTH: usize = 64;
+
+/// Maximum length (in bytes) of the reason for freezing a loan / CDP
+pub const MAX_FREEZE_REASON_LENGTH: usize = 256;
+
+/// Maximum length (in bytes) of the memo of a STAB donation
+pub const MAX_DONATION_MEMO_LENGTH: usize = 256;
+
+/// Maximum length (in bytes) of the protocol name, STAB name and description used when rebranding
+pub const MAX_BRANDING_LENGTH: usize = 128;
+
+/// Maximum length (in bytes) of the STAB symbol used when rebranding
+pub const MAX_SYMBOL_LENGTH: usize = 16;
+
+/// Number of hours the per-collateral loan activity is kept for
+pub const ACTIVITY_HOURS: i64 = 168;
+
+/// Version of the integration structs (`OpenQuote`, `CloseQuote`, `ProtocolInfo`), increased on breaking changes
+pub const INTEGRATION_API_VERSION: u64 = 1;
+
+/// Configuration of a deployment, accepted by the Stabilis component's and Proxy component's instantiation
+///   - start from `GenesisConfig::default()` (the production parameters) and override fields with struct update syntax
+///   - validated at instantiation (see `validate`)
+#[derive(ScryptoSbor, Clone)]
+pub struct GenesisConfig {
+    /// minimum amount of STAB minted per loan
+    pub minimum_mint: Decimal,
+    /// maximum length of vectors (loans with an equal CR, loans returned by getters)
+    pub max_vector_length: u64,
+    /// delay until a marked loan can be liquidated (minutes)
+    pub liquidation_delay: i64,
+    /// delay until a loan can be liquidated without marker, after it could be liquidated with a marker (minutes)
+    pub unmarked_delay: i64,
+    /// fine paid to the liquidator when liquidating without a marker
+    pub liquidator_fine: Decimal,
+    /// fine paid to the liquidator when liquidating with a marker
+    pub marker_fine: Decimal,
+    /// fine paid to the protocol when a loan is liquidated
+    pub stabilis_fine: Decimal,
+    /// multiplier of the MCR below which loans can be force minted
+    pub force_mint_cr_multiplier: Decimal,
+    /// premium for protecting a loan from force liquidations (fraction of the loan's collateral per day)
+    pub redemption_protection_premium: Decimal,
+    /// close fee, a fraction of the repaid debt paid on top when (partially) closing a loan
+    pub close_fee: Decimal,
+    /// Kp value of the interest rate PID controller
+    pub kp: Decimal,
+    /// Ki value of the interest rate PID controller
+    pub ki: Decimal,
+    /// maximum interest rate (per minute)
+    pub max_interest_rate: Decimal,
+    /// minimum interest rate (per minute)
+    pub min_interest_rate: Decimal,
+    /// deviation of the market price from the internal price within which the interest rate isn't changed
+    pub allowed_deviation: Decimal,
+    /// minimum time between internal price updates (minutes)
+    pub update_delay: i64,
+    /// number of price errors cached for the integral term
+    pub number_of_cached_prices: u64,
+    /// reward paid per second of price updates
+    pub reward_per_second: Decimal,
+    /// liquidity (square root of the product of the reserves) that can't be removed from the STAB/XRD pool, so its price stays meaningful
+    pub min_pool_liquidity: Decimal,
+    /// website of the protocol, set as info url of the resources and components
+    pub info_url: String,
+    /// icon of the STAB token and the dapp definition
+    pub stab_icon_url: String,
+    /// websites claimed by the dapp definition
+    pub claimed_websites: Vec<String>,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        Self {
+            minimum_mint: dec!(1),
+            max_vector_length: 250,
+            liquidation_delay: 5,
+            unmarked_delay: 5,
+            liquidator_fine: dec!("0.10"),
+            marker_fine: dec!("0.11"),
+            stabilis_fine: dec!("0.05"),
+            force_mint_cr_multiplier: dec!(3),
+            redemption_protection_premium: dec!("0.0001"),
+            close_fee: dec!(0),
+            kp: dec!("0.00000000076517857"),
+            ki: dec!("0.00000000076517857"),
+            max_interest_rate: dec!("1.0000007715"),
+            min_interest_rate: dec!("0.9999992287"),
+            allowed_deviation: dec!("0.005"),
+            update_delay: 1,
+            number_of_cached_prices: 50,
+            reward_per_second: dec!("0.02"),
+            min_pool_liquidity: dec!(100),
+            info_url: "https://ilikeitstable.com".to_string(),
+            stab_icon_url: "https://ilikeitstable.com/images/stablogo.png".to_string(),
+            claimed_websites: vec![
+                "https://ilikeitstable.com".to_string(),
+                "https://beta.ilikeitstable.com".to_string(),
+            ],
+        }
+    }
+}
+
+impl GenesisConfig {
+    /// Asserts all parameters are within sane ranges
+    pub fn validate(&self) {
+        assert!(
+            self.minimum_mint > dec!(0),
+            "Minimum mint needs to be positive."
+        );
+        assert!(
+            self.max_vector_length > 0,
+            "Max vector length needs to be positive."
+        );
+        assert!(
+            self.liquidation_delay >= 0 && self.unmarked_delay >= 0 && self.update_delay >= 0,
+            "Delays can't be negative."
+        );
+        for fine in [self.liquidator_fine, self.marker_fine, self.stabilis_fine] {
+            assert!(
+                fine >= dec!(0) && fine < dec!(1),
+                "Fines need to be between 0 and 1."
+            );
+        }
+        assert!(
+            self.marker_fine >= self.liquidator_fine,
+            "Marker fine can't be below the liquidator fine."
+        );
+        assert!(
+            self.force_mint_cr_multiplier >= dec!(1),
+            "Force mint multiplier can't be below 1."
+        );
+        assert!(
+            self.redemption_protection_premium >= dec!(0),
+            "Premium can't be negative."
+        );
+        assert!(
+            self.close_fee >= dec!(0) && self.close_fee < dec!(1),
+            "Close fee needs to be between 0 and 1."
+        );
+        assert!(
+            self.kp >= dec!(0) && self.ki >= dec!(0),
+            "PID gains can't be negative."
+        );
+        assert!(
+            self.min_interest_rate > dec!(0)
+                && self.min_interest_rate <= dec!(1)
+                && self.max_interest_rate >= dec!(1),
+            "Interest rate bounds need to be around 1."
+        );
+        assert!(
+            self.allowed_deviation >= dec!(0),
+            "Allowed deviation can't be negative."
+        );
+        assert!(
+            self.number_of_cached_prices > 0,
+            "Number of cached prices needs to be positive."
+        );
+        assert!(
+            self.reward_per_second >= dec!(0),
+            "Reward can't be negative."
+        );
+        assert!(
+            self.min_pool_liquidity >= dec!(0),
+            "Minimum pool liquidity can't be negative."
+        );
+        for url in [&self.info_url, &self.stab_icon_url]
+            .into_iter()
+            .chain(self.claimed_websites.iter())
+        {
+            assert!(url.starts_with("https://"), "Urls need to use https.");
+        }
+    }
+}
+
+/// Compounds the internal price with a (per minute) interest rate over the passed time, capped at a maximum drift
+///
+/// # Input
+/// - `internal_price`: The current internal price
+/// - `interest_rate`: The interest rate (per minute)
+/// - `passed_seconds`: The seconds passed since the last compounding
+/// - `remainder`: The drift carried from earlier compoundings, as a factor (1 if nothing is carried)
+/// - `max_drift`: The maximum relative change of the internal price per compounding
+///
+/// # Output
+/// - The new internal price
+/// - The drift to carry to the next compounding
+///
+/// # Logic
+/// - Compound the whole minutes with a checked integer power and the remaining seconds with a fractional power, at PreciseDecimal precision
+/// - Apply the carried drift
+/// - Cap the change of the internal price at the maximum drift, and round it to a Decimal
+/// - Carry what isn't applied (drift above the cap and rounding) to the next compounding, so long gaps don't lose precision
+pub fn compound_internal_price(
+    internal_price: Decimal,
+    interest_rate: Decimal,
+    passed_seconds: i64,
+    remainder: PreciseDecimal,
+    max_drift: Decimal,
+) -> (Decimal, PreciseDecimal) {
+    let minutes_factor: PreciseDecimal = PreciseDecimal::from(interest_rate)
+        .checked_powi(passed_seconds / 60)
+        .expect("Internal price compounding overflowed.");
+    let seconds_factor: PreciseDecimal = match passed_seconds % 60 {
+        0 => PreciseDecimal::ONE,
+        seconds => PreciseDecimal::from(
+            interest_rate
+                .pow(Decimal::from(seconds) / dec!(60))
+                .unwrap(),
+        ),
+    };
+    let target_price: PreciseDecimal =
+        PreciseDecimal::from(internal_price) * minutes_factor * seconds_factor * remainder;
+
+    let capped_price: PreciseDecimal = target_price
+        .min(PreciseDecimal::from(internal_price) * PreciseDecimal::from(dec!(1) + max_drift))
+        .max(PreciseDecimal::from(internal_price) / PreciseDecimal::from(dec!(1) + max_drift));
+    let new_price: Decimal = Decimal::try_from(
+        capped_price
+            .checked_round(18, RoundingMode::ToNearestMidpointToEven)
+            .unwrap(),
+    )
+    .unwrap();
+
+    (new_price, target_price / PreciseDecimal::from(new_price))
+}
+
+/// Splits a liquidation payout into amounts according to a list of fractions
+///
+/// # Input
+/// - `total`: The amount to split
+/// - `payout_split`: The fractions of the total per amount, which need to be positive and add up to 1
+/// - `divisibility`: The divisibility of the split resource
+///
+/// # Output
+/// - The amounts, one per fraction
+///
+/// # Logic
+/// - Round every amount but the last down to the divisibility
+/// - Give the last amount what remains, so it receives any rounding dust and the amounts add up to the total
+pub fn split_payout_amounts(
+    total: Decimal,
+    payout_split: &[Decimal],
+    divisibility: u8,
+) -> Vec<Decimal> {
+    assert!(!payout_split.is_empty(), "Payout split can't be empty.");
+    assert!(
+        payout_split.iter().all(|share| *share > dec!(0)),
+        "Payout split shares need to be positive."
+    );
+    assert!(
+        payout_split.iter().fold(dec!(0), |sum, share| sum + *share) == dec!(1),
+        "Payout split shares need to add up to 1."
+    );
+
+    let mut amounts: Vec<Decimal> = payout_split
+        .iter()
+        .take(payout_split.len() - 1)
+        .map(|share| {
+            (total * *share)
+                .checked_round(divisibility, RoundingMode::ToZero)
+                .unwrap()
+        })
+        .collect();
+    let rest: Decimal = amounts.iter().fold(total, |rest, amount| rest - *amount);
+    amounts.push(rest);
+    amounts
+}
+
+/// Puts tokens in a component's vault for their resource, creating the vault if there's none yet (for airdropped tokens)
+pub fn put_in_resource_vaults(vaults: &KeyValueStore<ResourceAddress, Vault>, tokens: Bucket) {
+    let resource: ResourceAddress = tokens.resource_address();
+    if vaults.get(&resource).is_none() {
+        vaults.insert(resource, Vault::new(resource));
+    }
+    vaults.get_mut(&resource).unwrap().put(tokens);
+}
+
+/// Takes tokens out of a component's vault for their resource, for components keeping a vault per resource (reward or airdropped tokens)
+///   - resources managed by the protocol can't be taken, so rescuing tokens never touches protocol funds
+pub fn take_from_resource_vaults(
+    vaults: &KeyValueStore<ResourceAddress, Vault>,
+    resource: ResourceAddress,
+    amount: Decimal,
+    protocol_resources: &[ResourceAddress],
+) -> Bucket {
+    assert!(
+        !protocol_resources.contains(&resource),
+        "Can't rescue protocol-managed resources."
+    );
+    vaults
+        .get_mut(&resource)
+        .expect("No tokens of this resource held.")
+        .take(amount)
+}
+
+/// Calculates the collateral price of a single STAB/XRD LP token
+///
+/// # Input
+/// - `invariant`: The invariant (k) of the StabilisPool
+/// - `supply`: The total supply of the LP token
+/// - `stab_price`: The price of STAB (in USD), which can't be taken from the pool itself
+/// - `xrd_price`: The price of XRD (in USD)
+/// - `haircut`: The haircut (fraction) applied to the fair value
+///
+/// # Output
+/// - The price of a single LP token
+///
+/// # Logic
+/// - Calculate the fair value of the pool's reserves from its invariant: 2 * sqrt(k * STAB price * XRD price)
+///   - unlike the reserves themselves, the invariant can't be moved by swapping, so as long as neither price is the pool's spot price, the price can't be manipulated by skewing the pool
+/// - Divide by the LP token supply and apply the haircut
+pub fn lp_token_price(
+    invariant: Decimal,
+    supply: Decimal,
+    stab_price: Decimal,
+    xrd_price: Decimal,
+    haircut: Decimal,
+) -> Decimal {
+    let fair_value: Decimal =
+        dec!(2) * (invariant * stab_price * xrd_price).checked_sqrt().unwrap();
+
+    fair_value / supply * (dec!(1) - haircut)
+}
 
 /// Data struct of a loan receipt / CDP receipt, gained when opening a CDP / loan
 #[derive(ScryptoSbor, NonFungibleData, Clone)]
 pub struct Cdp {
-    /// collateral used for this loan / cdp
+    /// collateral used for this loan / cdp (can change when migrating between a pool unit and its parent collateral)
+    #[mutable]
     pub collateral: ResourceAddress,
     /// parent address of this collateral (only differs from collateral in the case of a pool unit)
     pub parent_address: ResourceAddress,
     /// whether collateral is a pool unit
+    #[mutable]
     pub is_pool_unit_collateral: bool,
 
     /// amount of collateral used
@@ -31,6 +360,32 @@ pub struct Cdp {
     /// id of the marker that last marked this loan
     #[mutable]
     pub marker_id: u64,
+    /// label set by the owner of the loan (max length is `MAX_CDP_LABEL_LENGTH`)
+    #[mutable]
+    pub label: String,
+    /// until when the loan is protected from force liquidations (as long as unprotected loans exist)
+    #[mutable]
+    pub protected_until: Instant,
+    /// time of (force) liquidation, from which unclaimed leftover collateral can eventually be swept
+    #[mutable]
+    pub liquidated_at: Option<Instant>,
+    /// terms under which the loan was opened, never changed afterwards
+    pub origination: CdpOrigination,
+}
+
+/// Terms under which a loan / CDP was opened, stored on its receipt for disputes, analytics and UIs
+#[derive(ScryptoSbor, Clone)]
+pub struct CdpOrigination {
+    /// internal STAB price at opening
+    pub internal_stab_price: Decimal,
+    /// price of the parent collateral at opening
+    pub collateral_price: Decimal,
+    /// minimum collateral ratio of the parent collateral at opening
+    pub mcr: Decimal,
+    /// close fee at opening (no fees are paid when opening a loan)
+    pub close_fee: Decimal,
+    /// time of opening
+    pub opened_at: Instant,
 }
 
 /// Data struct of a CDP Marker, gained when marking a loan / CDP for liquidation
@@ -65,6 +420,63 @@ pub struct LiquidationReceipt {
     pub cdp_liquidated: NonFungibleLocalId,
     /// time of liquidation
     pub date_liquidated: Instant,
+    /// price of the (parent) collateral at the time of liquidation
+    pub collateral_price: Decimal,
+    /// collateral left in the liquidated CDP / loan, retrievable by its borrower
+    pub leftover_collateral: Decimal,
+    /// collateral above the leftover buffer returned to the refund account of the liquidated CDP / loan
+    pub returned_collateral: Decimal,
+    /// STAB received from the keeper rewards pool as compensation for an unprofitable liquidation
+    #[mutable]
+    pub compensated: Decimal,
+}
+
+///Data of Withdrawal Claim, gained when a collateral withdrawal is queued by the withdrawal throttle
+#[derive(ScryptoSbor, NonFungibleData)]
+pub struct WithdrawalClaim {
+    /// collateral that is queued
+    pub collateral: ResourceAddress,
+    /// amount of collateral that is queued
+    pub amount: Decimal,
+    /// time from which the collateral can be claimed
+    pub claimable_from: Instant,
+}
+
+///Data of Donation Receipt, gained when donating STAB to be burned against the system's debt
+#[derive(ScryptoSbor, NonFungibleData)]
+pub struct DonationReceipt {
+    /// amount of STAB donated (and burned)
+    pub amount: Decimal,
+    /// time of the donation
+    pub donated_at: Instant,
+    /// optional memo of the donor (a DAO proposal, for instance)
+    pub memo: Option<String>,
+}
+
+/// Classes of owner actions with their own required amount of controller badges
+#[derive(ScryptoSbor, PartialEq, Clone, Copy, Debug)]
+pub enum AuthClass {
+    /// actions minting STAB (opening loans, borrowing more, force minting, freeing STAB for flash loans)
+    Mint,
+    /// actions changing protocol or collateral parameters
+    Parameters,
+    /// incident response actions (pausing, freezing loans)
+    Emergency,
+}
+
+/// Actions that can be paused, globally or per collateral
+#[derive(ScryptoSbor, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum PausableAction {
+    OpenCdp,
+    BorrowMore,
+    CloseCdp,
+    PartialCloseCdp,
+    RemoveCollateral,
+    RetrieveLeftoverCollateral,
+    Liquidate,
+    ForceMint,
+    ForceLiquidate,
+    MigrateCdp,
 }
 
 /// Status of a CDP
@@ -77,8 +489,26 @@ pub enum CdpStatus {
     Closed,
 }
 
+/// Status of an action queued in the Timelock
+#[derive(ScryptoSbor, PartialEq, Clone, Debug)]
+pub enum TimelockStatus {
+    Queued,
+    Executed,
+    Vetoed,
+    Cancelled,
+}
+
+/// Status of an insurance claim filed in the InsuranceClaims component
+#[derive(ScryptoSbor, PartialEq, Clone, Debug)]
+pub enum ClaimStatus {
+    Filed,
+    Approved,
+    Rejected,
+    Paid,
+}
+
 /// The kind of update that the action has executed.
-#[derive(ScryptoSbor, PartialEq)]
+#[derive(ScryptoSbor, PartialEq, Clone)]
 pub enum CdpUpdate {
     Marked,
     Saved,
@@ -119,3 +549,358 @@ pub struct InterestParameters {
     /// The offset for the price error
     pub price_error_offset: Decimal,
 }
+
+/// Utilization of a collateral
+#[derive(ScryptoSbor, Clone)]
+pub struct CollateralUtilization {
+    /// address of the collateral
+    pub address: ResourceAddress,
+    /// whether new loans can be opened with this collateral
+    pub accepted: bool,
+    /// amount of STAB minted with this collateral (including its pool units)
+    pub minted_stab: Decimal,
+    /// share of the circulating STAB minted with this collateral
+    pub stab_share: Decimal,
+    /// maximum share of the circulating STAB that can be minted with this collateral
+    pub max_stab_share: Decimal,
+    /// amount of collateral backing loans (in parent collateral)
+    pub collateral_amount: Decimal,
+}
+
+/// Debt of a collateral's loans, grouped by collateral ratio
+#[derive(ScryptoSbor, Clone)]
+pub struct CrHistogram {
+    /// collateral ratio bounds of the ranges (ascending, in value terms: 1.5 = 150%)
+    pub bounds: Vec<Decimal>,
+    /// minted STAB per range: below the first bound, between consecutive bounds, and at or above the last bound
+    pub debt: Vec<Decimal>,
+    /// number of loans per range
+    pub cdps: Vec<u64>,
+    /// whether all loans were counted (false if the iteration limit was reached)
+    pub complete: bool,
+}
+
+/// Risk parameters of a collateral (or pool unit)
+#[derive(ScryptoSbor, Clone)]
+pub struct RiskParameters {
+    /// minimum collateral ratio (None for pool units, which use their parent's)
+    pub mcr: Option<Decimal>,
+    /// whether the collateral is accepted for new loans
+    pub accepted: bool,
+    /// maximum share of the circulating STAB minted against the collateral
+    pub max_share: Decimal,
+}
+
+/// A change of the risk parameters of a collateral, part of the append-only risk parameter history
+#[derive(ScryptoSbor, Clone)]
+pub struct RiskParameterChange {
+    /// id of the change, increasing with every change
+    pub id: u64,
+    /// collateral (or pool unit) of which the parameters changed
+    pub collateral: ResourceAddress,
+    /// method that changed the parameters (always authorized by the controller badge)
+    pub changed_by: String,
+    /// time of the change
+    pub changed_at: Instant,
+    /// parameters before the change (None if the collateral was added)
+    pub old: Option<RiskParameters>,
+    /// parameters after the change
+    pub new: RiskParameters,
+}
+
+/// How the Proxy sets the interest rate
+#[derive(ScryptoSbor, PartialEq, Clone, Debug)]
+pub enum RateMode {
+    /// the PID controller sets the interest rate, following the price errors
+    Pid,
+    /// the interest rate is fixed at the given rate (per minute) until the given time, after which the PID controller takes over again
+    Fixed(Decimal, Instant),
+}
+
+/// Action that moved controller badges, changed a component the Proxy relies on, or used the badges to convert a loan's collateral
+#[derive(ScryptoSbor, PartialEq, Clone, Debug)]
+pub enum CustodyAction {
+    SendBadges,
+    ReceiveBadges,
+    SetBadgeReceiver {
+        allowed: bool,
+    },
+    SetOracle {
+        method_name: String,
+    },
+    SetConfirmationOracle {
+        method_name: String,
+    },
+    MigrateCdp {
+        cdp_id: NonFungibleLocalId,
+        from: ResourceAddress,
+        to: ResourceAddress,
+    },
+}
+
+/// A move of controller badges or change of a component the Proxy relies on, part of the append-only custody log
+#[derive(ScryptoSbor, Clone)]
+pub struct CustodyChange {
+    /// id of the change, increasing with every change
+    pub id: u64,
+    /// what was changed
+    pub action: CustodyAction,
+    /// component the badges were sent to, or the component that was set (None if unknown or removed)
+    pub target: Option<ComponentAddress>,
+    /// amount of controller badges moved (0 if no badges were moved)
+    pub amount: Decimal,
+    /// controller badges held by the Proxy after the change
+    pub badges_held: Decimal,
+    /// time of the change
+    pub changed_at: Instant,
+}
+
+/// Lifetime statistics of a loan / CDP, for lenders, credit-scoring integrations and users
+#[derive(ScryptoSbor, Clone)]
+pub struct CdpStats {
+    /// total STAB borrowed (including force mints)
+    pub total_borrowed: Decimal,
+    /// total STAB repaid (including liquidations and force liquidations)
+    pub total_repaid: Decimal,
+    /// number of times the loan was marked for liquidation
+    pub times_marked: u64,
+    /// number of times the loan was saved after being marked
+    pub times_saved: u64,
+}
+
+/// Status of a marker receipt, for keepers managing their markers
+#[derive(ScryptoSbor, Clone)]
+pub struct MarkerInfo {
+    /// id of the marker receipt
+    pub marker_id: NonFungibleLocalId,
+    /// type of marking (Saved if the loan turned out to be healthy when marking)
+    pub mark_type: CdpUpdate,
+    /// id of the marked loan / CDP
+    pub marked_id: NonFungibleLocalId,
+    /// time of marking
+    pub time_marked: Instant,
+    /// time from which the marker receipt can be used to liquidate the loan
+    pub liquidatable_from: Instant,
+    /// time from which anyone can liquidate the loan, without the marker receipt
+    pub public_liquidatable_from: Instant,
+    /// whether the marker has been used (the loan was liquidated or saved)
+    pub used: bool,
+}
+
+/// Data of a loan / CDP with its current collateral ratio, for off-chain risk engines
+#[derive(ScryptoSbor, Clone)]
+pub struct CdpExport {
+    /// id of the loan / CDP
+    pub cdp_id: NonFungibleLocalId,
+    /// data of the loan / CDP
+    pub cdp: Cdp,
+    /// current amount of parent collateral per minted STAB (pool units converted at the current pool_to_real rate, 0 if nothing is minted)
+    pub cr: Decimal,
+    /// current collateral ratio in value terms (1.5 = 150%, 0 if nothing is minted)
+    pub value_cr: Decimal,
+}
+
+/// Share of a collateral or pool unit compared to its cap
+#[derive(ScryptoSbor, Clone)]
+pub struct ShareWarning {
+    /// address of the collateral or pool unit
+    pub address: ResourceAddress,
+    /// parent address of this collateral (only differs from address in the case of a pool unit)
+    pub parent_address: ResourceAddress,
+    /// whether the address is a pool unit
+    pub is_pool_unit_collateral: bool,
+    /// current share (of circulating STAB for collaterals, of the parent's minted STAB for pool units)
+    pub share: Decimal,
+    /// maximum share (max_stab_share for collaterals, max_pool_share for pool units)
+    pub max_share: Decimal,
+    /// whether the share is within the warning margin of the maximum share
+    pub near_cap: bool,
+}
+
+/// Loan activity of a collateral during an hour, for tuning rate limits and circuit breakers
+#[derive(ScryptoSbor, Clone)]
+pub struct HourlyActivity {
+    /// start of the hour (seconds since unix epoch, a multiple of 3600)
+    pub hour_start: i64,
+    /// number of loans opened
+    pub opens: u64,
+    /// number of loans closed
+    pub closes: u64,
+    /// number of loans liquidated (with or without marker)
+    pub liquidations: u64,
+}
+
+/// Collateralization of the STAB minted with a collateral
+#[derive(ScryptoSbor, Clone)]
+pub struct CollateralCr {
+    /// address of the collateral
+    pub address: ResourceAddress,
+    /// usd value of the collateral backing loans (including its pool units, valued at their last pool_to_real snapshots)
+    pub collateral_value: Decimal,
+    /// amount of STAB minted with this collateral (including its pool units)
+    pub minted_stab: Decimal,
+    /// usd value of the STAB minted with this collateral, at internal price
+    pub stab_value: Decimal,
+    /// collateral value / STAB value (None if no STAB is minted with this collateral)
+    pub cr: Option<Decimal>,
+}
+
+/// Aggregate collateralization of the system, the headline solvency metric
+#[derive(ScryptoSbor, Clone)]
+pub struct GlobalCr {
+    /// usd value of all collateral backing loans
+    pub collateral_value: Decimal,
+    /// usd value of the circulating STAB, at internal price
+    pub stab_value: Decimal,
+    /// usd value of the donated STAB credited to the system surplus, at internal price
+    pub surplus_value: Decimal,
+    /// collateral value / (STAB value - surplus value) (None if the surplus covers all circulating STAB)
+    pub cr: Option<Decimal>,
+    /// breakdown per collateral
+    pub collaterals: Vec<CollateralCr>,
+}
+
+/// Breakdown of the STAB supply over the collaterals it was minted with
+#[derive(ScryptoSbor, Clone)]
+pub struct SupplyBreakdown {
+    /// total supply of the STAB resource, including STAB freed outside of loans (flash loans, liquidity pool)
+    pub total_supply: Decimal,
+    /// circulating supply of STAB minted through loans
+    pub circulating_stab: Decimal,
+    /// donated STAB burned into the system surplus, offsetting the circulating STAB
+    pub donated_stab: Decimal,
+    /// utilization of every collateral ever added, including collaterals no longer accepted
+    pub collaterals: Vec<CollateralUtilization>,
+}
+
+/// State of the Stabilis component
+#[derive(ScryptoSbor, Clone)]
+pub struct StabilisState {
+    /// circulating supply of STAB minted through loans
+    pub circulating_stab: Decimal,
+    /// internal price of STAB
+    pub internal_price: Decimal,
+    /// number of currently marked loans
+    pub marked_cdps: u64,
+    /// utilization of the requested collaterals
+    pub collaterals: Vec<CollateralUtilization>,
+}
+
+/// Quote for opening a loan / CDP, part of the integration interface
+#[derive(ScryptoSbor, Clone)]
+pub struct OpenQuote {
+    /// version of the integration interface
+    pub version: u64,
+    /// collateral used for the quote
+    pub collateral: ResourceAddress,
+    /// parent address of this collateral (only differs from collateral in the case of a pool unit)
+    pub parent_address: ResourceAddress,
+    /// whether collateral is a pool unit
+    pub is_pool_unit_collateral: bool,
+    /// amount of parent collateral the supplied collateral is worth
+    pub real_collateral_amount: Decimal,
+    /// amount of STAB to mint
+    pub stab_to_mint: Decimal,
+    /// USD value of the collateral
+    pub collateral_value: Decimal,
+    /// USD value of the minted STAB (at internal price)
+    pub debt_value: Decimal,
+    /// collateral_value / debt_value
+    pub collateral_ratio: Decimal,
+    /// minimum collateral ratio of the collateral
+    pub mcr: Decimal,
+    /// maximum amount of STAB mintable with the supplied collateral
+    pub max_mintable: Decimal,
+    /// USD price of the parent collateral at which the loan would become liquidatable
+    pub liquidation_price: Decimal,
+    /// whether the loan could be opened right now
+    pub possible: bool,
+}
+
+/// Quote for closing a loan / CDP, part of the integration interface
+#[derive(ScryptoSbor, Clone)]
+pub struct CloseQuote {
+    /// version of the integration interface
+    pub version: u64,
+    /// id of the loan / cdp
+    pub cdp_id: NonFungibleLocalId,
+    /// collateral returned when closing
+    pub collateral: ResourceAddress,
+    /// amount of collateral returned when closing
+    pub collateral_returned: Decimal,
+    /// amount of STAB needed to close (including the close fee)
+    pub stab_to_repay: Decimal,
+    /// status of the loan / cdp
+    pub status: CdpStatus,
+    /// whether the loan could be closed right now
+    pub possible: bool,
+}
+
+/// Addresses of the resources managed by the Stabilis component
+#[derive(ScryptoSbor, Clone)]
+pub struct ResourceAddresses {
+    /// resource address of the STAB token
+    pub stab: ResourceAddress,
+    /// resource address of the CDP receipts
+    pub cdp_receipt: ResourceAddress,
+    /// resource address of the CDP markers
+    pub cdp_marker: ResourceAddress,
+    /// resource address of the liquidation receipts
+    pub liquidation_receipt: ResourceAddress,
+    /// resource address of the withdrawal claims
+    pub withdrawal_claim: ResourceAddress,
+    /// resource address of the donation receipts
+    pub donation_receipt: ResourceAddress,
+    /// resource address of the controller badge
+    pub controller_badge: ResourceAddress,
+    /// resource address of the insurance badge, required to pay out of the insurance funds
+    pub insurance_badge: ResourceAddress,
+}
+
+/// Branding of a (white-label) deployment, applied to the resources and components of the protocol at once
+#[derive(ScryptoSbor, Clone)]
+pub struct Branding {
+    /// name of the protocol, used for the dapp definition and component names
+    pub protocol_name: String,
+    /// description of the protocol, used for the dapp definition
+    pub description: String,
+    /// name of the STAB token
+    pub stab_name: String,
+    /// symbol of the STAB token
+    pub stab_symbol: String,
+    /// website of the protocol
+    pub info_url: Url,
+    /// icon of the STAB token and the dapp definition
+    pub icon_url: Url,
+}
+
+/// General protocol info, part of the integration interface
+#[derive(ScryptoSbor, Clone)]
+pub struct ProtocolInfo {
+    /// version of the integration interface
+    pub version: u64,
+    /// address of the Stabilis component
+    pub stabilis: ComponentAddress,
+    /// resource address of the CDP receipts
+    pub cdp_receipt_address: ResourceAddress,
+    /// resource address of the CDP markers
+    pub cdp_marker_address: ResourceAddress,
+    /// internal price of STAB
+    pub internal_price: Decimal,
+    /// current interest rate (per minute)
+    pub interest_rate: Decimal,
+    /// collaterals accepted through the proxy
+    pub accepted_collaterals: Vec<ResourceAddress>,
+    /// minimum amount of STAB to mint
+    pub minimum_mint: Decimal,
+    /// delay until a marked loan can be liquidated with a marker (minutes)
+    pub liquidation_delay: i64,
+    /// extra delay until a marked loan can be liquidated without a marker (minutes)
+    pub unmarked_delay: i64,
+    /// fine paid to the liquidator
+    pub liquidation_fine: Decimal,
+    /// fine paid to the liquidator when liquidating with a marker
+    pub marker_liquidation_fine: Decimal,
+    /// fine paid to the protocol
+    pub stabilis_fine: Decimal,
+}
@@ -13,20 +13,35 @@
 //! If not saved, the marker of the loan has the first opportunity to liquidate it.
 //! If the marker does not liquidate the loan, anyone can liquidate it.
 //! Liquidating a loan means taking part of the collateral and paying back the STAB tokens. The liquidator receives a fee for this.
-//! If there's still collateral left after this fee, a fee is paid to the Stabilis component.
+//! If there's still collateral left after this fee, a fee is paid to the Stabilis component, split between its protocol revenue treasury and its insurance fund (optionally routing a share to the stakers of the collateral's pool, see `set_incentive_route`).
 //! If there's still collateral left after this fee, the original borrower can retrieve the remaining collateral.
 //!
 //! To summarize, the typical life cycle of a loan, and the accompanying methods called on it:
 //! - Open a loan: `open_cdp`
 //! - Close a loan: `close_cdp`
+//!     - Loans with debt below the minimum mint (dust) can be closed with a small shortfall: `close_dust_cdp`
 //! - Add collateral to a loan: `top_up_cdp`
+//! - Move collateral between two loans of the same collateral: `move_collateral`
 //! - Borrow more: `borrow_more`
-//! - Partially close a loan: `partial_close_cdp`
+//! - Partially close a loan, optionally withdrawing collateral in the same call: `partial_close_cdp`
 //! - Force liquidate a loan (liquidate a loan immediately without it being undercollateralized): `force_liquidate`
 //! - Force mint STAB tokens (force a borrower to mint more STAB tokens in return for collateral added to their CDP): `force_mint`
+//! - Opt in to automatic partial deleveraging at a trigger CR: `set_auto_deleverage`, which anyone can then execute: `auto_deleverage`
+//! - Limit the debt force minting can add to a loan (absolute or a percentage of its debt): `set_force_mint_cap`
+//! - Opt a pool unit loan in to yield harvesting: `set_yield_harvest`, after which anyone can harvest the appreciation of its collateral to repay its debt: `harvest_yield`
+//! - Claim borrow rewards, emitted per minute per STAB borrowed against incentivized collaterals: `claim_borrow_rewards`
 //! - Mark a loan to liquidate it: `mark_for_liquidation`
 //! - Liquidate a loan: `liquidate_position_with_marker` or `liquidate_position_without_marker`
-//! - Retrieve leftover collateral after being liquidated: `retrieve_leftover_collateral`
+//! - Burn used markers, one by one (`burn_marker`) or in bulk: `burn_markers`
+//! - Retrieve leftover collateral after being liquidated: `retrieve_leftover_collateral`, or of multiple loans at once: `retrieve_leftovers`
+//!     - Or set a refund account (`set_refund_account`), to have the leftover above the leftover buffer returned to it when liquidated
+//!     - Leftovers unclaimed for a long time are swept into the insurance fund (`sweep_unclaimed_leftover`), and can still be reimbursed later: `reimburse_swept_leftover`
+//! - Redeem a liquidation receipt of an unprofitable liquidation for compensation from the keeper rewards pool: `redeem_liquidation_receipt`
+//!
+//! STAB can also be donated to be burned against the system's debt (DAO buybacks, goodwill burns), which is credited to the system surplus: `donate_and_burn`
+//!
+//! Owner actions are split into classes (mint-related, parameter-related and emergency), each requiring its own amount of controller badges.
+//! The required amounts are stored in the protocol parameters and can be rotated per class with `set_auth_threshold`. Other owner actions require 0.75 controller badges.
 
 use crate::shared_structs::*;
 use scrypto::prelude::*;
@@ -43,8 +58,23 @@ use crate::events::*;
     CdpUpdate,
     Instant,
     NonFungibleLocalId,
+    ComponentAddress,
     CollateralInfo,
     PoolUnitInfo,
+    WithdrawalWindow,
+    SweptLeftover,
+    MarkRate,
+    CdpFreeze,
+    DeleverageSettings,
+    ForceMintCap,
+    CdpStats,
+    Vec<u64>,
+    Vec<HourlyActivity>,
+    BorrowRewardPosition,
+    RiskParameterChange,
+    IncentiveRoute,
+    Vault,
+    HashSet<PausableAction>,
     AvlTree<Decimal, Vec<NonFungibleLocalId>>
 )]
 #[events(
@@ -55,49 +85,176 @@ use crate::events::*;
     EventCloseCdp,
     EventLiquidateCdp,
     EventChangeCollateral,
+    EventMcrSurcharge,
+    EventShareEnforcement,
     EventChangePeg,
-    
+    EventShareWarning,
+    EventWithdrawalQueued,
+    EventWithdrawalClaimed,
+    EventKeeperCompensation,
+    EventLeftoverSwept,
+    EventLeftoverReimbursed,
+    EventLeftoverReturned,
+    EventCloseFee,
+    EventStabilityReserveUsed,
+    EventBufferApplied,
+    EventAutoDeleverage,
+    EventYieldHarvested,
+    EventCdpFrozen,
+    EventCdpUnfrozen,
+    EventBorrowRewardsClaimed,
+    EventDonation,
+    EventIncentivesDistributed
 )]
 mod stabilis_component {
     enable_method_auth! {
+        roles {
+            insurance_manager => updatable_by: [];
+            mint_manager => updatable_by: [SELF, OWNER];
+            parameter_manager => updatable_by: [SELF, OWNER];
+            emergency_manager => updatable_by: [SELF, OWNER];
+        },
         methods {
             return_internal_price => PUBLIC;
-            add_pool_collateral => restrict_to: [OWNER];
-            open_cdp => restrict_to: [OWNER];
+            get_collateral_price => PUBLIC;
+            get_parameters => PUBLIC;
+            quote_open => PUBLIC;
+            quote_close => PUBLIC;
+            get_liquidation_receipts => PUBLIC;
+            get_state => PUBLIC;
+            get_supply_breakdown => PUBLIC;
+            get_global_cr => PUBLIC;
+            get_share_warnings => PUBLIC;
+            get_withdrawal_window => PUBLIC;
+            get_queued_withdrawals => PUBLIC;
+            get_mark_rate => PUBLIC;
+            get_mcr_surcharge => PUBLIC;
+            get_share_enforcement => PUBLIC;
+            get_cr_histogram => PUBLIC;
+            export_cdps => PUBLIC;
+            is_paused => PUBLIC;
+            get_paused_actions => PUBLIC;
+            get_keeper_rewards => PUBLIC;
+            get_stability_reserve => PUBLIC;
+            get_donated_stab => PUBLIC;
+            get_resource_addresses => PUBLIC;
+            get_liquidation_receipt_address => PUBLIC;
+            is_sandbox => PUBLIC;
+            get_swept_leftover => PUBLIC;
+            get_liquidation_internal_price => PUBLIC;
+            get_buffer => PUBLIC;
+            get_cdp_freeze => PUBLIC;
+            get_force_liquidation_target => PUBLIC;
+            get_cdp_stats => PUBLIC;
+            get_marker => PUBLIC;
+            get_markers_for_cdp => PUBLIC;
+            get_collateral_activity => PUBLIC;
+            get_risk_parameter_changes => PUBLIC;
+            get_borrow_rewards => PUBLIC;
+            get_pending_borrow_rewards => PUBLIC;
+            get_incentive_route => PUBLIC;
+            add_pool_collateral => restrict_to: [parameter_manager];
+            add_adapter_pool_collateral => restrict_to: [parameter_manager];
+            open_cdp => restrict_to: [mint_manager];
+            open_cdps => restrict_to: [mint_manager];
             top_up_cdp => restrict_to: [OWNER];
+            move_collateral => restrict_to: [OWNER];
+            deposit_to_buffer => restrict_to: [OWNER];
+            withdraw_from_buffer => restrict_to: [OWNER];
+            apply_buffer => restrict_to: [OWNER];
+            set_auto_deleverage => restrict_to: [OWNER];
+            auto_deleverage => restrict_to: [OWNER];
+            get_auto_deleverage => PUBLIC;
+            quote_auto_deleverage => PUBLIC;
+            set_force_mint_cap => restrict_to: [OWNER];
+            get_force_mint_cap => PUBLIC;
+            set_deleverage_parameters => restrict_to: [parameter_manager];
+            set_yield_harvest => restrict_to: [OWNER];
+            harvest_yield => restrict_to: [OWNER];
+            get_yield_harvest => PUBLIC;
+            quote_yield_harvest => PUBLIC;
+            set_harvest_bounty => restrict_to: [parameter_manager];
+            set_min_force_mint_cap => restrict_to: [parameter_manager];
             close_cdp => restrict_to: [OWNER];
-            borrow_more => restrict_to: [OWNER];
+            close_dust_cdp => restrict_to: [OWNER];
+            borrow_more => restrict_to: [mint_manager];
             partial_close_cdp => restrict_to: [OWNER];
+            migrate_cdp => restrict_to: [OWNER];
             retrieve_leftover_collateral => restrict_to: [OWNER];
+            retrieve_leftovers => restrict_to: [OWNER];
+            sweep_unclaimed_leftover => restrict_to: [OWNER];
+            reimburse_swept_leftover => restrict_to: [OWNER];
+            set_unclaimed_leftover_period => restrict_to: [parameter_manager];
+            set_leftover_buffer => restrict_to: [parameter_manager];
+            set_refund_account => restrict_to: [OWNER];
+            get_refund_account => PUBLIC;
             mark_for_liquidation => restrict_to: [OWNER];
             liquidate_position_with_marker => restrict_to: [OWNER];
             liquidate_position_without_marker => restrict_to: [OWNER];
             change_collateral_price => restrict_to: [OWNER];
+            change_collateral_prices => restrict_to: [OWNER];
             empty_collateral_treasury => restrict_to: [OWNER];
-            edit_collateral => restrict_to: [OWNER];
-            edit_pool_collateral => restrict_to: [OWNER];
+            empty_insurance_fund => restrict_to: [insurance_manager];
+            get_treasury_balances => PUBLIC;
+            set_insurance_share => restrict_to: [parameter_manager];
+            edit_collateral => restrict_to: [parameter_manager];
+            edit_pool_collateral => restrict_to: [parameter_manager];
+            set_pool_haircut => restrict_to: [parameter_manager];
             mint_controller_badge => restrict_to: [OWNER];
-            set_liquidation_delay => restrict_to: [OWNER];
-            set_unmarked_delay => restrict_to: [OWNER];
-            set_stops => restrict_to: [OWNER];
-            set_max_vector_length => restrict_to: [OWNER];
-            set_minimum_mint => restrict_to: [OWNER];
-            set_fines => restrict_to: [OWNER];
-            add_collateral => restrict_to: [OWNER];
+            mint_insurance_badge => restrict_to: [OWNER];
+            set_liquidation_delay => restrict_to: [parameter_manager];
+            set_unmarked_delay => restrict_to: [parameter_manager];
+            set_paused => restrict_to: [emergency_manager];
+            set_collateral_paused => restrict_to: [emergency_manager];
+            set_max_vector_length => restrict_to: [parameter_manager];
+            set_minimum_mint => restrict_to: [parameter_manager];
+            set_fines => restrict_to: [parameter_manager];
+            add_collateral => restrict_to: [parameter_manager];
             change_internal_price => restrict_to: [OWNER];
             remove_collateral => restrict_to: [OWNER];
             force_liquidate => restrict_to: [OWNER];
-            force_mint => restrict_to: [OWNER];
-            set_force_mint_multiplier => restrict_to: [OWNER];
-            free_stab => restrict_to: [OWNER];
+            force_mint => restrict_to: [mint_manager];
+            set_force_mint_multiplier => restrict_to: [parameter_manager];
+            free_stab => restrict_to: [mint_manager];
             burn_stab => restrict_to: [OWNER];
+            donate_and_burn => restrict_to: [OWNER];
             burn_marker => restrict_to: [OWNER];
+            burn_markers => restrict_to: [OWNER];
             burn_loan_receipt => restrict_to: [OWNER];
+            set_cdp_label => restrict_to: [OWNER];
+            protect_cdp => restrict_to: [OWNER];
+            set_redemption_protection_premium => restrict_to: [parameter_manager];
+            set_share_warning_margin => restrict_to: [parameter_manager];
+            set_max_cdp_debt => restrict_to: [parameter_manager];
+            set_collateral_max_cdp_debt => restrict_to: [parameter_manager];
+            set_max_marks_per_minute => restrict_to: [parameter_manager];
+            set_mcr_surcharge => restrict_to: [parameter_manager];
+            set_share_enforcement => restrict_to: [parameter_manager];
+            freeze_cdp => restrict_to: [emergency_manager];
+            unfreeze_cdp => restrict_to: [emergency_manager];
+            set_max_freeze_minutes => restrict_to: [parameter_manager];
+            set_withdrawal_throttle => restrict_to: [parameter_manager];
+            claim_withdrawal => restrict_to: [OWNER];
+            deposit_keeper_rewards => restrict_to: [OWNER];
+            redeem_liquidation_receipt => restrict_to: [OWNER];
+            set_keeper_compensation => restrict_to: [parameter_manager];
+            set_close_fee => restrict_to: [parameter_manager];
+            set_buffer_bounty => restrict_to: [parameter_manager];
+            set_dust_tolerance => restrict_to: [parameter_manager];
+            fund_borrow_rewards => restrict_to: [parameter_manager];
+            set_borrow_reward_schedule => restrict_to: [parameter_manager];
+            set_borrow_reward_weight => restrict_to: [parameter_manager];
+            claim_borrow_rewards => restrict_to: [OWNER];
+            set_incentive_route => restrict_to: [parameter_manager];
+            distribute_incentives => restrict_to: [OWNER];
+            set_auth_threshold => restrict_to: [OWNER];
         }
     }
     struct Stabilis {
         /// KVS storing all accepted collaterals and their information
         collaterals: KeyValueStore<ResourceAddress, CollateralInfo>,
+        /// Addresses of all added collaterals, so they can be iterated over
+        collateral_addresses: Vec<ResourceAddress>,
         /// KVS storing all accepted pool units and their information
         pool_units: KeyValueStore<ResourceAddress, PoolUnitInfo>,
         /// KVS storing all active collateral ratios for each collateral
@@ -111,6 +268,8 @@ mod stabilis_component {
         stab_manager: ResourceManager,
         /// The resource manager for the controller badge
         controller_badge_manager: ResourceManager,
+        /// The resource manager for the insurance badge, required by the insurance manager role
+        insurance_badge_manager: ResourceManager,
         /// The internal price of STAB
         internal_stab_price: Decimal,
         /// The circulating supply of STAB
@@ -129,6 +288,60 @@ mod stabilis_component {
         liquidation_receipt_manager: ResourceManager,
         /// Counter for the liquidation receipts
         liquidation_counter: u64,
+        /// Resource manager for the withdrawal claims
+        withdrawal_claim_manager: ResourceManager,
+        /// Counter for the withdrawal claims
+        withdrawal_claim_counter: u64,
+        /// Resource manager for the donation receipts
+        donation_receipt_manager: ResourceManager,
+        /// Counter for the donation receipts
+        donation_counter: u64,
+        /// STAB donated and burned without repaying a loan, the system surplus (loan debt no longer matched by circulating STAB)
+        donated_stab: Decimal,
+        /// KVS storing the current withdrawal window for each collateral (or pool unit)
+        withdrawal_windows: KeyValueStore<ResourceAddress, WithdrawalWindow>,
+        /// KVS storing the queued withdrawals for each collateral (or pool unit)
+        withdrawal_queue: KeyValueStore<ResourceAddress, Vault>,
+        /// KVS storing the paused actions for each collateral (or pool unit)
+        collateral_paused_actions: KeyValueStore<ResourceAddress, HashSet<PausableAction>>,
+        /// STAB deposited (by the DAO) to compensate liquidators for unprofitable liquidations
+        keeper_rewards: Vault,
+        /// STAB collected by the close fee, covering unprofitable liquidations before the keeper rewards pool is used
+        stability_reserve: Vault,
+        /// Whether this is a sandbox instantiation (set at instantiation, can't be changed)
+        sandbox: bool,
+        /// KVS storing the leftover collateral swept into the insurance fund, per loan, so it can be reimbursed
+        swept_leftovers: KeyValueStore<NonFungibleLocalId, SweptLeftover>,
+        /// KVS storing the number of marks placed in the current minute, for each collateral
+        mark_rates: KeyValueStore<ResourceAddress, MarkRate>,
+        /// KVS storing the loan activity of the last `ACTIVITY_HOURS` hours (hours without activity are skipped), for each collateral
+        collateral_activity: KeyValueStore<ResourceAddress, Vec<HourlyActivity>>,
+        /// KVS storing the auto-top-up buffer of collateral for each loan, used to save it when it gets marked
+        cdp_buffers: KeyValueStore<NonFungibleLocalId, Vault>,
+        /// KVS storing the auto-deleverage settings (trigger and target CR) of loans that opted in
+        deleverage_settings: KeyValueStore<NonFungibleLocalId, DeleverageSettings>,
+        /// KVS storing the account the leftover collateral above the leftover buffer is returned to when a loan is liquidated, of loans that opted in
+        refund_accounts: KeyValueStore<NonFungibleLocalId, ComponentAddress>,
+        /// KVS storing the cap on the debt force minting can add, of loans that opted in
+        force_mint_caps: KeyValueStore<NonFungibleLocalId, ForceMintCap>,
+        /// KVS storing the redemption rate (parent collateral per pool unit) of pool unit loans that opted in to yield harvesting, at opt-in or the last harvest
+        harvest_rates: KeyValueStore<NonFungibleLocalId, Decimal>,
+        /// KVS storing the freezes of loans under dispute
+        frozen_cdps: KeyValueStore<NonFungibleLocalId, CdpFreeze>,
+        /// KVS storing the lifetime statistics of each loan
+        cdp_stats: KeyValueStore<NonFungibleLocalId, CdpStats>,
+        /// KVS storing the ids of the latest markers placed on each loan (at most `max_vector_length`)
+        cdp_markers: KeyValueStore<NonFungibleLocalId, Vec<u64>>,
+        /// The borrow rewards emission program (reward vault, emission schedule and per collateral reward indices)
+        borrow_rewards: BorrowRewards,
+        /// KVS storing the borrow reward position (last seen reward index and accrued rewards) of each loan
+        borrow_reward_positions: KeyValueStore<NonFungibleLocalId, BorrowRewardPosition>,
+        /// KVS storing the incentive route of each (parent) collateral: the staking component receiving a share of its liquidation fines and close fees, and the routed fees not distributed yet
+        incentive_routes: KeyValueStore<ResourceAddress, IncentiveRoute>,
+        /// Append-only KVS storing every change of the risk parameters of the collaterals, by id
+        risk_parameter_changes: KeyValueStore<u64, RiskParameterChange>,
+        /// Counter for the risk parameter changes
+        risk_parameter_change_counter: u64,
         /// The protocol parameters
         parameters: ProtocolParameters,
     }
@@ -136,11 +349,15 @@ mod stabilis_component {
     impl Stabilis {
         /// Instantiates the Stabilis component
         ///
+        /// # Input
+        /// - `config`: The genesis configuration (see `GenesisConfig`)
+        ///
         /// # Output
         /// - The global instance of the Stabilis component
         /// - The controller badge for the Stabilis component
         ///
         /// # Logic
+        /// - Validates the genesis configuration
         /// - Sets the protocol parameters
         /// - Assigns a component address
         /// - Creates the controller badge
@@ -148,21 +365,59 @@ mod stabilis_component {
         /// - Creates the CDP manager
         /// - Creates the CDP marker manager
         /// - Creates the liquidation receipt manager
+        /// - Creates the withdrawal claim manager
         /// - Creates the Stabilis component
-        pub fn instantiate() -> (Global<Stabilis>, Bucket) {
+        pub fn instantiate(config: GenesisConfig) -> (Global<Stabilis>, Bucket) {
+            Self::instantiate_internal(false, config)
+        }
+
+        /// Instantiates the Stabilis component in sandbox mode, for staging environments of integrators
+        ///   - the minted STAB is a distinct test resource (Sandbox STAB), which is clearly marked as such
+        ///   - sandbox mode can only be chosen at instantiation, so it can never be enabled on a production instantiation
+        pub fn instantiate_sandbox(config: GenesisConfig) -> (Global<Stabilis>, Bucket) {
+            Self::instantiate_internal(true, config)
+        }
+
+        /// Instantiates the Stabilis component, in sandbox mode or not
+        fn instantiate_internal(
+            sandbox: bool,
+            config: GenesisConfig,
+        ) -> (Global<Stabilis>, Bucket) {
+            config.validate();
+
             let parameters = ProtocolParameters {
-                minimum_mint: dec!(1),
-                max_vector_length: 250,
-                liquidation_delay: 5,
-                unmarked_delay: 5,
-                liquidation_liquidation_fine: dec!("0.10"),
-                stabilis_liquidation_fine: dec!("0.05"),
-                stop_liquidations: false,
-                stop_openings: false,
-                stop_closings: false,
-                stop_force_mint: true,
-                stop_force_liquidate: false,
-                force_mint_cr_multiplier: dec!(3),
+                minimum_mint: config.minimum_mint,
+                max_vector_length: config.max_vector_length,
+                liquidation_delay: config.liquidation_delay,
+                unmarked_delay: config.unmarked_delay,
+                liquidation_liquidation_fine: config.liquidator_fine,
+                marker_liquidation_fine: config.marker_fine,
+                stabilis_liquidation_fine: config.stabilis_fine,
+                paused_actions: vec![PausableAction::ForceMint].into_iter().collect(),
+                force_mint_cr_multiplier: config.force_mint_cr_multiplier,
+                redemption_protection_premium: config.redemption_protection_premium,
+                share_warning_margin: dec!("0.05"),
+                max_cdp_debt: None,
+                withdrawal_throttle: false,
+                withdrawal_window: 60,
+                withdrawal_threshold: dec!("0.1"),
+                keeper_compensation: dec!("0.5"),
+                insurance_share: dec!(0),
+                unclaimed_leftover_period: 730,
+                leftover_buffer: dec!("0.05"),
+                close_fee: config.close_fee,
+                buffer_bounty: dec!("0.01"),
+                max_freeze_minutes: 10080,
+                dust_tolerance: dec!("0.01"),
+                deleverage_bounty: dec!("0.005"),
+                deleverage_slippage: dec!("0.02"),
+                harvest_bounty: dec!("0.01"),
+                min_force_mint_cap: dec!("0.1"),
+                auth_thresholds: AuthThresholds {
+                    mint: dec!("0.75"),
+                    parameters: dec!("0.75"),
+                    emergency: dec!("0.75"),
+                },
             };
 
             let (address_reservation, component_address) =
@@ -187,16 +442,22 @@ mod stabilis_component {
 
             let controller_badge_manager: ResourceManager = controller_role.resource_manager();
 
+            // Native resources have no transfer hooks, and withdraw / deposit / recall roles can't be added after creation,
+            // so a fee on STAB transfers can't be charged. Protocol revenue is collected through interest and fines instead.
+            let (stab_name, stab_symbol): (&str, &str) = match sandbox {
+                true => ("Sandbox STAB token (no value)", "tSTAB"),
+                false => ("STAB token", "STAB"),
+            };
             let stab_manager: ResourceManager = ResourceBuilder::new_fungible(OwnerRole::Fixed(
                 rule!(require(controller_role.resource_address())),
             ))
             .divisibility(DIVISIBILITY_MAXIMUM)
             .metadata(metadata! (
                 init {
-                    "name" => "STAB token", updatable;
-                    "symbol" => "STAB", updatable;
-                    "info_url" => "https://ilikeitstable.com", updatable;
-                    "icon_url" => Url::of("https://ilikeitstable.com/images/stablogo.png"), updatable;
+                    "name" => stab_name, updatable;
+                    "symbol" => stab_symbol, updatable;
+                    "info_url" => config.info_url.as_str(), updatable;
+                    "icon_url" => Url::of(config.stab_icon_url.clone()), updatable;
                 }
             ))
             .mint_roles(mint_roles!(
@@ -232,7 +493,7 @@ mod stabilis_component {
                         "name" => "Stabilis Loan Receipt", locked;
                         "symbol" => "stabLOAN", locked;
                         "description" => "A receipt for your Stabilis loan", locked;
-                        "info_url" => "https://ilikeitstable.com", updatable;
+                        "info_url" => config.info_url.as_str(), updatable;
                         "icon_url" => Url::of("https://ilikeitstable.com/images/receipt.png"), updatable;
                     }
                 ))
@@ -280,7 +541,7 @@ mod stabilis_component {
                         "name" => "Stabilis Marker Receipt", locked;
                         "symbol" => "stabMARK", locked;
                         "description" => "A receipt received by marking a Stabilis loan", updatable;
-                        "info_url" => "https://ilikeitstable.com", updatable;
+                        "info_url" => config.info_url.as_str(), updatable;
                         "icon_url" => Url::of("https://ilikeitstable.com/images/marker-receipt.png"), updatable;
                     }
                 ))
@@ -331,7 +592,7 @@ mod stabilis_component {
                         "name" => "Stabilis Liquidation Receipt", locked;
                         "symbol" => "stabLIQ", locked;
                         "description" => "A receipt received for liquidating a Stabilis Loan", updatable;
-                        "info_url" => "https://ilikeitstable.com", updatable;
+                        "info_url" => config.info_url.as_str(), updatable;
                         "icon_url" => Url::of("https://ilikeitstable.com/images/liquidation.png"), updatable;
                     }
                 ))
@@ -361,14 +622,108 @@ mod stabilis_component {
                 ))
                 .create_with_no_initial_supply();
 
+            let withdrawal_claim_manager: ResourceManager =
+                ResourceBuilder::new_integer_non_fungible::<WithdrawalClaim>(OwnerRole::Fixed(
+                    rule!(require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    )),
+                ))
+                .metadata(metadata!(
+                    init {
+                        "name" => "Stabilis Withdrawal Claim", locked;
+                        "symbol" => "stabCLAIM", locked;
+                        "description" => "A claim for queued collateral of a Stabilis loan", updatable;
+                        "info_url" => config.info_url.as_str(), updatable;
+                    }
+                ))
+                .mint_roles(mint_roles!(
+                    minter => rule!(require(global_caller(component_address))
+                    || require_amount(dec!("0.75"),
+                    controller_role.resource_address()
+                    ));
+                    minter_updater => rule!(require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    ));
+                ))
+                .burn_roles(burn_roles!(
+                    burner => rule!(require(global_caller(component_address))
+                    || require_amount(dec!("0.75"),
+                    controller_role.resource_address()
+                    ));
+                    burner_updater => rule!(require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    ));
+                ))
+                .create_with_no_initial_supply();
+
+            let donation_receipt_manager: ResourceManager =
+                ResourceBuilder::new_integer_non_fungible::<DonationReceipt>(OwnerRole::Fixed(
+                    rule!(require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    )),
+                ))
+                .metadata(metadata!(
+                    init {
+                        "name" => "Stabilis Donation Receipt", locked;
+                        "symbol" => "stabDONATE", locked;
+                        "description" => "A receipt for STAB donated to be burned against the debt of the Stabilis system", updatable;
+                        "info_url" => config.info_url.as_str(), updatable;
+                    }
+                ))
+                .mint_roles(mint_roles!(
+                    minter => rule!(require(global_caller(component_address))
+                    || require_amount(dec!("0.75"),
+                    controller_role.resource_address()
+                    ));
+                    minter_updater => rule!(require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    ));
+                ))
+                .burn_roles(burn_roles!(
+                    burner => rule!(allow_all);
+                    burner_updater => rule!(deny_all);
+                ))
+                .create_with_no_initial_supply();
+
+            let insurance_badge_manager: ResourceManager =
+                ResourceBuilder::new_fungible(OwnerRole::Fixed(rule!(require_amount(
+                    dec!("0.75"),
+                    controller_role.resource_address()
+                ))))
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata(metadata!(
+                    init {
+                        "name" => "Stabilis Insurance Badge", locked;
+                        "symbol" => "stabINS", locked;
+                        "description" => "A badge authorizing payouts from the insurance funds of Stabilis", updatable;
+                        "info_url" => config.info_url.as_str(), updatable;
+                    }
+                ))
+                .mint_roles(mint_roles!(
+                    minter => rule!(require(global_caller(component_address)));
+                    minter_updater => rule!(deny_all);
+                ))
+                .create_with_no_initial_supply();
+            let insurance_badge_address: ResourceAddress = insurance_badge_manager.address();
+
+            let keeper_rewards: Vault = Vault::new(stab_manager.address());
+            let stability_reserve: Vault = Vault::new(stab_manager.address());
+
             let stabilis = Self {
                 collaterals: StabilisKeyValueStore::new_with_registered_type(),
+                collateral_addresses: vec![],
                 pool_units: StabilisKeyValueStore::new_with_registered_type(),
                 collateral_ratios: StabilisKeyValueStore::new_with_registered_type(),
                 cdp_counter: 0,
                 cdp_manager,
                 stab_manager,
                 controller_badge_manager,
+                insurance_badge_manager,
                 internal_stab_price: dec!(1),
                 circulating_stab: dec!(0),
                 cdp_marker_manager,
@@ -378,6 +733,37 @@ mod stabilis_component {
                 marker_placing_counter: dec!(0),
                 liquidation_receipt_manager,
                 liquidation_counter: 0,
+                withdrawal_claim_manager,
+                withdrawal_claim_counter: 0,
+                donation_receipt_manager,
+                donation_counter: 0,
+                donated_stab: dec!(0),
+                withdrawal_windows: StabilisKeyValueStore::new_with_registered_type(),
+                withdrawal_queue: StabilisKeyValueStore::new_with_registered_type(),
+                collateral_paused_actions: StabilisKeyValueStore::new_with_registered_type(),
+                keeper_rewards,
+                stability_reserve,
+                sandbox,
+                swept_leftovers: StabilisKeyValueStore::new_with_registered_type(),
+                mark_rates: StabilisKeyValueStore::new_with_registered_type(),
+                collateral_activity: StabilisKeyValueStore::new_with_registered_type(),
+                cdp_buffers: StabilisKeyValueStore::new_with_registered_type(),
+                deleverage_settings: StabilisKeyValueStore::new_with_registered_type(),
+                refund_accounts: StabilisKeyValueStore::new_with_registered_type(),
+                force_mint_caps: StabilisKeyValueStore::new_with_registered_type(),
+                harvest_rates: StabilisKeyValueStore::new_with_registered_type(),
+                frozen_cdps: StabilisKeyValueStore::new_with_registered_type(),
+                cdp_stats: StabilisKeyValueStore::new_with_registered_type(),
+                cdp_markers: StabilisKeyValueStore::new_with_registered_type(),
+                borrow_rewards: BorrowRewards {
+                    vault: None,
+                    schedule: vec![],
+                    indices: HashMap::new(),
+                },
+                borrow_reward_positions: StabilisKeyValueStore::new_with_registered_type(),
+                incentive_routes: StabilisKeyValueStore::new_with_registered_type(),
+                risk_parameter_changes: StabilisKeyValueStore::new_with_registered_type(),
+                risk_parameter_change_counter: 0,
                 parameters,
             }
             .instantiate()
@@ -385,6 +771,21 @@ mod stabilis_component {
                 dec!("0.75"),
                 controller_role.resource_address()
             ))))
+            .roles(roles! {
+                insurance_manager => rule!(require(insurance_badge_address));
+                mint_manager => rule!(require_amount(
+                    dec!("0.75"),
+                    controller_role.resource_address()
+                ));
+                parameter_manager => rule!(require_amount(
+                    dec!("0.75"),
+                    controller_role.resource_address()
+                ));
+                emergency_manager => rule!(require_amount(
+                    dec!("0.75"),
+                    controller_role.resource_address()
+                ));
+            })
             .with_address(address_reservation)
             .globalize();
 
@@ -409,12 +810,68 @@ mod stabilis_component {
         /// - Assign parent address, which is equal to the collateral address unless the collateral is a pool unit
         /// - Check whether collateral value is high enough
         /// - Calculate collateral ratio and insert into AvlTree
-        /// - Create CDP struct for the receipt
+        /// - Create CDP struct for the receipt, including its origination terms
         /// - Check whether the share of this collateral's minted STAB is too high and update STAB circulating supply
         /// - Mint the CDP receipt
         /// - Store the collateral in the correct vault
         /// - Return the minted STAB and the CDP receipt
         pub fn open_cdp(&mut self, collateral: Bucket, stab_to_mint: Decimal) -> (Bucket, Bucket) {
+            self.open_cdp_internal(collateral, stab_to_mint, true)
+        }
+
+        /// Borrow STAB by opening multiple CDPs at once
+        ///
+        /// # Input
+        /// - `cdps`: The collateral and amount of STAB to mint for each CDP
+        ///
+        /// # Output
+        /// - The minted STAB in a `Bucket`
+        /// - The CDP receipts in a `Vec<Bucket>`
+        ///
+        /// # Logic
+        /// - Open every CDP, without checking the collateral shares
+        /// - Check the collateral shares once per used collateral
+        /// - Return the combined minted STAB and the CDP receipts
+        pub fn open_cdps(&mut self, cdps: Vec<(Bucket, Decimal)>) -> (Bucket, Vec<Bucket>) {
+            assert!(!cdps.is_empty(), "No loans to open.");
+
+            let mut stab_tokens: Bucket = Bucket::new(self.stab_manager.address());
+            let mut cdp_receipts: Vec<Bucket> = Vec::new();
+            let mut used_collaterals: Vec<ResourceAddress> = Vec::new();
+
+            for (collateral, stab_to_mint) in cdps {
+                if !used_collaterals.contains(&collateral.resource_address()) {
+                    used_collaterals.push(collateral.resource_address());
+                }
+                let (minted_stab, cdp_receipt): (Bucket, Bucket) =
+                    self.open_cdp_internal(collateral, stab_to_mint, false);
+                stab_tokens.put(minted_stab);
+                cdp_receipts.push(cdp_receipt);
+            }
+
+            for collateral in used_collaterals {
+                let is_pool_unit_collateral: bool = self.pool_units.get(&collateral).is_some();
+                let parent_collateral_address: ResourceAddress = match is_pool_unit_collateral {
+                    false => collateral,
+                    true => self.pool_units.get(&collateral).unwrap().parent_address,
+                };
+                self.check_share(
+                    parent_collateral_address,
+                    is_pool_unit_collateral,
+                    collateral,
+                );
+            }
+
+            (stab_tokens, cdp_receipts)
+        }
+
+        /// Open a CDP, see `open_cdp` for details. The collateral share is only checked if `check_share` is true.
+        fn open_cdp_internal(
+            &mut self,
+            collateral: Bucket,
+            stab_to_mint: Decimal,
+            check_share: bool,
+        ) -> (Bucket, Bucket) {
             let mut is_pool_unit_collateral: bool = false;
             let stab_tokens: Bucket = self.stab_manager.mint(stab_to_mint);
 
@@ -423,7 +880,7 @@ mod stabilis_component {
                 "Minted STAB is less than the minimum required amount."
             );
             assert!(
-                !self.parameters.stop_openings,
+                !self.is_paused(PausableAction::OpenCdp, Some(collateral.resource_address())),
                 "Not allowed to open loans right now."
             );
 
@@ -471,22 +928,19 @@ mod stabilis_component {
                 .unwrap()
                 .collateral_amount += collateral_amount;
 
-            let mcr: Decimal = self
-                .collaterals
-                .get(&parent_collateral_address)
-                .unwrap()
-                .mcr;
+            let (mcr, collateral_price): (Decimal, Decimal) = {
+                let info = self.collaterals.get(&parent_collateral_address).unwrap();
+                (info.mcr, info.usd_price)
+            };
 
             assert!(
-                self.collaterals
-                    .get(&parent_collateral_address)
-                    .unwrap()
-                    .usd_price
-                    * collateral_amount
+                collateral_price * collateral_amount
                     >= self.internal_stab_price * stab_tokens.amount() * mcr,
                 "Collateral value too low."
             );
 
+            self.check_max_cdp_debt(parent_collateral_address, stab_tokens.amount());
+
             self.cdp_counter += 1;
 
             let cr: Decimal = collateral_amount / stab_tokens.amount();
@@ -528,12 +982,27 @@ mod stabilis_component {
                 collateral_stab_ratio: cr,
                 status: CdpStatus::Healthy,
                 marker_id: 0u64,
+                label: String::new(),
+                protected_until: Instant::new(0),
+                liquidated_at: None,
+                origination: CdpOrigination {
+                    internal_stab_price: self.internal_stab_price,
+                    collateral_price,
+                    mcr,
+                    close_fee: self.parameters.close_fee,
+                    opened_at: Clock::current_time_rounded_to_seconds(),
+                },
             };
 
+            self.settle_borrow_rewards(
+                &NonFungibleLocalId::integer(self.cdp_counter),
+                parent_collateral_address,
+                dec!(0),
+            );
             self.update_minted_stab(
                 true,
                 is_pool_unit_collateral,
-                true,
+                check_share,
                 stab_tokens.amount(),
                 parent_collateral_address,
                 collateral.resource_address(),
@@ -544,6 +1013,11 @@ mod stabilis_component {
                 .mint_non_fungible(&NonFungibleLocalId::integer(self.cdp_counter), cdp.clone())
                 .as_non_fungible();
 
+            self.update_cdp_stats(&NonFungibleLocalId::integer(self.cdp_counter), |stats| {
+                stats.total_borrowed += cdp.minted_stab
+            });
+            self.record_activity(parent_collateral_address, |activity| activity.opens += 1);
+
             self.put_collateral(
                 collateral.resource_address(),
                 is_pool_unit_collateral,
@@ -565,32 +1039,37 @@ mod stabilis_component {
         /// - `stab_payment`: The STAB tokens to pay back
         ///
         /// # Output
-        /// - The collateral returned
+        /// - The collateral returned (empty if the withdrawal throttle queued it)
         /// - The leftover STAB
+        /// - The withdrawal claim, if the withdrawal throttle queued the collateral
         ///
         /// # Logic
-        /// - Check if the STAB payment is enough to close the loan
+        /// - Check if the STAB payment is enough to close the loan (including the close fee)
         /// - Check if the loan is healthy
         /// - Check if the STAB payment is valid
         /// - Remove collateral from the vault
+        ///   - Queue it if the withdrawal throttle is exceeded
         /// - Update circulating STAB, both for total and chosen collateral
         /// - Burn the paid back STAB
+        /// - Put the close fee in the stability reserve
         /// - Remove the collateral ratio from the AvlTree
         /// - Update the CDP receipt
-        /// - Return the collateral and the leftover STAB
+        /// - Return the collateral, the leftover STAB and the withdrawal claim
         pub fn close_cdp(
             &mut self,
             receipt_id: NonFungibleLocalId,
             mut stab_payment: Bucket,
-        ) -> (Bucket, Bucket) {
+        ) -> (Bucket, Bucket, Option<Bucket>) {
             let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
+            let close_fee: Decimal = receipt_data.minted_stab * self.parameters.close_fee;
+            self.check_not_frozen(&receipt_id);
 
             assert!(
-                stab_payment.amount() >= receipt_data.minted_stab,
+                stab_payment.amount() >= receipt_data.minted_stab + close_fee,
                 "not enough STAB supplied to close completely"
             );
             assert!(
-                !self.parameters.stop_closings,
+                !self.is_paused(PausableAction::CloseCdp, Some(receipt_data.collateral)),
                 "Not allowed to close loans right now."
             );
             assert!(
@@ -607,12 +1086,19 @@ mod stabilis_component {
                 receipt_data.is_pool_unit_collateral,
                 receipt_data.collateral_amount,
             );
+            let (collateral, claim): (Bucket, Option<Bucket>) =
+                self.throttle_withdrawal(collateral, receipt_data.is_pool_unit_collateral);
 
             self.collaterals
                 .get_mut(&receipt_data.parent_address)
                 .unwrap()
                 .collateral_amount -= receipt_data.collateral_stab_ratio * receipt_data.minted_stab;
 
+            self.settle_borrow_rewards(
+                &receipt_id,
+                receipt_data.parent_address,
+                receipt_data.minted_stab,
+            );
             self.update_minted_stab(
                 false,
                 receipt_data.is_pool_unit_collateral,
@@ -623,6 +1109,20 @@ mod stabilis_component {
             );
 
             stab_payment.take(receipt_data.minted_stab).burn();
+            self.update_cdp_stats(&receipt_id, |stats| {
+                stats.total_repaid += receipt_data.minted_stab
+            });
+            self.record_activity(receipt_data.parent_address, |activity| activity.closes += 1);
+
+            if close_fee > dec!(0) {
+                let mut fee: Bucket = stab_payment.take(close_fee);
+                self.route_incentives(receipt_data.parent_address, false, &mut fee);
+                self.stability_reserve.put(fee);
+                Runtime::emit_event(EventCloseFee {
+                    cdp_id: receipt_id.clone(),
+                    amount: close_fee,
+                });
+            }
 
             self.remove_cr(
                 receipt_data.parent_address,
@@ -640,7 +1140,116 @@ mod stabilis_component {
                 cdp_id: receipt_id,
             });
 
-            (collateral, stab_payment)
+            (collateral, stab_payment, claim)
+        }
+
+        /// Close a dust loan / CDP, with debt below the minimum mint (for instance left by a partial force liquidation)
+        ///
+        /// # Input
+        /// - `receipt_id`: The CDP receipt
+        /// - `stab_payment`: The STAB tokens to pay back
+        ///
+        /// # Output
+        /// - The collateral returned (empty if the withdrawal throttle queued it)
+        /// - The leftover STAB
+        /// - The withdrawal claim, if the withdrawal throttle queued the collateral
+        ///
+        /// # Logic
+        /// - Check if the loan is healthy and its debt is below the minimum mint
+        /// - Check if the STAB payment covers the debt, up to the dust tolerance
+        ///   - the shortfall is burned from the stability reserve, so circulating STAB stays correct
+        ///   - no close fee is charged
+        /// - Remove collateral from the vault
+        ///   - Queue it if the withdrawal throttle is exceeded
+        /// - Update circulating STAB, both for total and chosen collateral
+        /// - Remove the collateral ratio from the AvlTree
+        /// - Update the CDP receipt
+        /// - Return the collateral, the leftover STAB and the withdrawal claim
+        pub fn close_dust_cdp(
+            &mut self,
+            receipt_id: NonFungibleLocalId,
+            mut stab_payment: Bucket,
+        ) -> (Bucket, Bucket, Option<Bucket>) {
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
+            self.check_not_frozen(&receipt_id);
+
+            assert!(
+                receipt_data.minted_stab < self.parameters.minimum_mint,
+                "Not a dust loan, use close_cdp."
+            );
+            assert!(
+                !self.is_paused(PausableAction::CloseCdp, Some(receipt_data.collateral)),
+                "Not allowed to close loans right now."
+            );
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy. Can't close right now. In case of liquidation, retrieve collateral. Else, add collateral to save."
+            );
+            assert!(
+                stab_payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+
+            let paid: Decimal = stab_payment.amount().min(receipt_data.minted_stab);
+            let shortfall: Decimal = receipt_data.minted_stab - paid;
+            assert!(
+                shortfall <= self.parameters.dust_tolerance,
+                "Shortfall above the dust tolerance."
+            );
+            assert!(
+                shortfall <= self.stability_reserve.amount(),
+                "Stability reserve can't cover the shortfall."
+            );
+
+            let collateral: Bucket = self.take_collateral(
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+                receipt_data.collateral_amount,
+            );
+            let (collateral, claim): (Bucket, Option<Bucket>) =
+                self.throttle_withdrawal(collateral, receipt_data.is_pool_unit_collateral);
+
+            self.collaterals
+                .get_mut(&receipt_data.parent_address)
+                .unwrap()
+                .collateral_amount -= receipt_data.collateral_stab_ratio * receipt_data.minted_stab;
+
+            self.settle_borrow_rewards(
+                &receipt_id,
+                receipt_data.parent_address,
+                receipt_data.minted_stab,
+            );
+            self.update_minted_stab(
+                false,
+                receipt_data.is_pool_unit_collateral,
+                false,
+                receipt_data.minted_stab,
+                receipt_data.parent_address,
+                receipt_data.collateral,
+            );
+
+            stab_payment.take(paid).burn();
+            if shortfall > dec!(0) {
+                self.stability_reserve.take(shortfall).burn();
+            }
+            self.update_cdp_stats(&receipt_id, |stats| stats.total_repaid += paid);
+            self.record_activity(receipt_data.parent_address, |activity| activity.closes += 1);
+
+            self.remove_cr(
+                receipt_data.parent_address,
+                receipt_data.collateral_stab_ratio,
+                receipt_id.clone(),
+            );
+
+            self.cdp_manager
+                .update_non_fungible_data(&receipt_id, "status", CdpStatus::Closed);
+
+            self.cdp_manager
+                .update_non_fungible_data(&receipt_id, "collateral_amount", dec!(0));
+
+            Runtime::emit_event(EventCloseCdp { cdp_id: receipt_id });
+
+            (collateral, stab_payment, claim)
         }
 
         /// Retrieve leftover collateral from a liquidated loan / cdp
@@ -649,7 +1258,8 @@ mod stabilis_component {
         /// - `receipt_id`: The CDP receipt
         ///
         /// # Output
-        /// - The leftover collateral
+        /// - The leftover collateral (empty if the withdrawal throttle queued it)
+        /// - The withdrawal claim, if the withdrawal throttle queued the leftover collateral
         ///
         /// # Logic
         /// - Check if the loan is liquidated
@@ -657,7 +1267,11 @@ mod stabilis_component {
         /// - Check if it is allowed to close loans right now
         /// - Update CDP receipt to 0 collateral
         /// - Return the leftover collateral
-        pub fn retrieve_leftover_collateral(&mut self, receipt_id: NonFungibleLocalId) -> Bucket {
+        ///   - Queue it if the withdrawal throttle is exceeded
+        pub fn retrieve_leftover_collateral(
+            &mut self,
+            receipt_id: NonFungibleLocalId,
+        ) -> (Bucket, Option<Bucket>) {
             let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
 
             assert!(
@@ -670,306 +1284,506 @@ mod stabilis_component {
                 "No collateral leftover"
             );
             assert!(
-                !self.parameters.stop_closings,
+                !self.is_paused(
+                    PausableAction::RetrieveLeftoverCollateral,
+                    Some(receipt_data.collateral)
+                ),
                 "Not allowed to close loans right now."
             );
 
             self.cdp_manager
                 .update_non_fungible_data(&receipt_id, "collateral_amount", dec!(0));
 
-            self.take_collateral(
+            let leftover: Bucket = self.take_collateral(
                 receipt_data.collateral,
                 receipt_data.is_pool_unit_collateral,
                 receipt_data.collateral_amount,
-            )
+            );
+            self.throttle_withdrawal(leftover, receipt_data.is_pool_unit_collateral)
         }
 
-        /// Add collateral to a loan / CDP
+        /// Retrieve leftover collateral from multiple liquidated loans / CDPs at once
         ///
         /// # Input
-        /// - `collateral_id`: The CDP receipt
-        /// - `collateral`: The collateral to add
+        /// - `receipt_ids`: The CDP receipts (at most `max_vector_length`)
         ///
         /// # Output
-        /// - None
+        /// - The leftover collateral, a single bucket per resource
+        /// - The withdrawal claims of the leftovers queued by the withdrawal throttle
         ///
         /// # Logic
-        /// - Check if the loan is healthy or marked
-        /// - Check if the collateral is compatible
-        /// - Remove the collateral ratio from the AvlTree
-        /// - Calculate new collateral ratio
-        /// - Update the collateral amount in the CDP receipt
-        /// - Check if the new collateral ratio is high enough
-        /// - Insert new collateral ratio into AvlTree
-        /// - Store the collateral in the correct vault
-        /// - Update the CDP receipt
-        /// - If the loan was marked, update the marker receipt
-        pub fn top_up_cdp(&mut self, collateral_id: NonFungibleLocalId, collateral: Bucket) {
-            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
-            let new_collateral_amount = receipt_data.collateral_amount + collateral.amount();
-
-            assert!(
-                receipt_data.status == CdpStatus::Healthy
-                    || receipt_data.status == CdpStatus::Marked,
-                "Loan not healthy or marked."
-            );
+        /// - Skip loans that aren't liquidated or have no collateral left, so a single retrieved loan doesn't block the others
+        /// - Retrieve the leftover collateral of every other loan (see `retrieve_leftover_collateral`)
+        /// - Combine the leftovers of the same resource into a single bucket, and the claims into a single bucket
+        pub fn retrieve_leftovers(
+            &mut self,
+            receipt_ids: Vec<NonFungibleLocalId>,
+        ) -> (Vec<Bucket>, Option<Bucket>) {
             assert!(
-                receipt_data.collateral == collateral.resource_address(),
-                "Incompatible token."
+                receipt_ids.len() as u64 <= self.parameters.max_vector_length,
+                "Too many loans."
             );
 
-            if receipt_data.status == CdpStatus::Healthy {
-                self.remove_cr(
-                    receipt_data.parent_address,
-                    receipt_data.collateral_stab_ratio,
-                    collateral_id.clone(),
-                );
+            let mut leftovers: Vec<Bucket> = vec![];
+            let mut claims: Option<Bucket> = None;
+            for receipt_id in receipt_ids {
+                let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
+                if (receipt_data.status != CdpStatus::Liquidated
+                    && receipt_data.status != CdpStatus::ForceLiquidated)
+                    || receipt_data.collateral_amount == dec!(0)
+                {
+                    continue;
+                }
+
+                let (leftover, claim): (Bucket, Option<Bucket>) =
+                    self.retrieve_leftover_collateral(receipt_id);
+                match leftovers
+                    .iter_mut()
+                    .find(|bucket| bucket.resource_address() == leftover.resource_address())
+                {
+                    Some(bucket) => bucket.put(leftover),
+                    None => leftovers.push(leftover),
+                }
+                if let Some(claim) = claim {
+                    match claims.as_mut() {
+                        Some(claims) => claims.put(claim),
+                        None => claims = Some(claim),
+                    }
+                }
             }
 
-            let cr: Decimal = self.pool_to_real(
-                new_collateral_amount,
-                collateral.resource_address(),
-                receipt_data.is_pool_unit_collateral,
-            ) / receipt_data.minted_stab;
+            (leftovers, claims)
+        }
 
-            self.collaterals
-                .get_mut(&receipt_data.parent_address)
-                .unwrap()
-                .collateral_amount +=
-                (cr - receipt_data.collateral_stab_ratio) * receipt_data.minted_stab;
+        /// Sweep leftover collateral of a liquidated loan / CDP that has been unclaimed for too long into the insurance fund
+        ///
+        /// # Input
+        /// - `receipt_id`: The CDP receipt
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check if the loan is liquidated, has leftover collateral, and was liquidated longer than the unclaimed leftover period ago
+        /// - Update CDP receipt to 0 collateral
+        /// - Move the leftover collateral to the insurance fund
+        /// - Record the swept leftover, so the owner can be reimbursed if they show up
+        pub fn sweep_unclaimed_leftover(&mut self, receipt_id: NonFungibleLocalId) {
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
 
             assert!(
-                cr > self
-                    .collaterals
-                    .get(&receipt_data.parent_address)
-                    .unwrap()
-                    .liquidation_collateral_ratio,
-                "Not enough collateral added to save this loan."
+                receipt_data.status == CdpStatus::Liquidated
+                    || receipt_data.status == CdpStatus::ForceLiquidated,
+                "Loan not liquidated"
+            );
+            assert!(
+                receipt_data.collateral_amount > dec!(0),
+                "No collateral leftover"
+            );
+            let liquidated_at: Instant = receipt_data
+                .liquidated_at
+                .expect("Liquidation time unknown.");
+            assert!(
+                Clock::current_time_is_at_or_after(
+                    liquidated_at
+                        .add_days(self.parameters.unclaimed_leftover_period)
+                        .unwrap(),
+                    TimePrecision::Second
+                ),
+                "Leftover collateral can't be swept yet."
             );
 
-            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
+            self.cdp_manager
+                .update_non_fungible_data(&receipt_id, "collateral_amount", dec!(0));
 
-            self.put_collateral(
+            let leftover: Bucket = self.take_collateral(
                 receipt_data.collateral,
                 receipt_data.is_pool_unit_collateral,
-                collateral,
+                receipt_data.collateral_amount,
+            );
+            let amount: Decimal = leftover.amount();
+            self.put_collateral_in_insurance(
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+                leftover,
             );
 
-            self.cdp_manager
-                .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
-            self.cdp_manager.update_non_fungible_data(
-                &collateral_id,
-                "collateral_amount",
-                new_collateral_amount,
+            self.swept_leftovers.insert(
+                receipt_id.clone(),
+                SweptLeftover {
+                    collateral: receipt_data.collateral,
+                    amount,
+                    swept_at: Clock::current_time_rounded_to_seconds(),
+                },
             );
 
-            if receipt_data.status == CdpStatus::Marked {
-                let marker_data: CdpMarker = self
-                    .cdp_marker_manager
-                    .get_non_fungible_data(&NonFungibleLocalId::integer(receipt_data.marker_id));
-                self.cdp_manager.update_non_fungible_data(
-                    &collateral_id,
-                    "status",
-                    CdpStatus::Healthy,
-                );
-                self.cdp_marker_manager.update_non_fungible_data(
-                    &NonFungibleLocalId::integer(receipt_data.marker_id),
-                    "used",
-                    true,
-                );
-                self.marked_cdps.remove(&marker_data.marker_placing);
-                self.marked_cdps_active -= 1;
-                receipt_data.status = CdpStatus::Healthy;
-            }
+            Runtime::emit_event(EventLeftoverSwept {
+                cdp_id: receipt_id,
+                collateral: receipt_data.collateral,
+                amount,
+            });
+        }
 
-            receipt_data.collateral_stab_ratio = cr;
-            receipt_data.collateral_amount = new_collateral_amount;
-            Runtime::emit_event(EventUpdateCdp {
-                cdp: receipt_data,
-                cdp_id: collateral_id,
+        /// Reimburse swept leftover collateral to the owner of a loan / CDP, out of the insurance fund
+        pub fn reimburse_swept_leftover(&mut self, receipt_id: NonFungibleLocalId) -> Bucket {
+            let swept: SweptLeftover = self
+                .swept_leftovers
+                .remove(&receipt_id)
+                .expect("No swept leftover for this loan.");
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
+
+            let reimbursement: Bucket = if receipt_data.is_pool_unit_collateral {
+                self.pool_units
+                    .get_mut(&swept.collateral)
+                    .unwrap()
+                    .insurance
+                    .take(swept.amount)
+            } else {
+                self.collaterals
+                    .get_mut(&swept.collateral)
+                    .unwrap()
+                    .insurance
+                    .take(swept.amount)
+            };
+
+            Runtime::emit_event(EventLeftoverReimbursed {
+                cdp_id: receipt_id,
+                collateral: swept.collateral,
+                amount: swept.amount,
             });
+
+            reimbursement
         }
 
-        /// Remove collateral from a loan / CDP
+        /// Get the leftover collateral of a loan / CDP swept into the insurance fund, if any
+        pub fn get_swept_leftover(&self, receipt_id: NonFungibleLocalId) -> Option<SweptLeftover> {
+            self.swept_leftovers
+                .get(&receipt_id)
+                .map(|swept| swept.clone())
+        }
+
+        /// Deposit collateral into the auto-top-up buffer of a loan / CDP
+        ///   - the buffer can be applied by anyone once the loan is marked (see `apply_buffer`)
+        pub fn deposit_to_buffer(&mut self, collateral_id: NonFungibleLocalId, collateral: Bucket) {
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+
+            assert!(
+                receipt_data.status == CdpStatus::Healthy
+                    || receipt_data.status == CdpStatus::Marked,
+                "Loan not healthy or marked."
+            );
+            assert!(
+                receipt_data.collateral == collateral.resource_address(),
+                "Incompatible token."
+            );
+            assert!(
+                self.cdp_buffers
+                    .get(&collateral_id)
+                    .map_or(true, |buffer| buffer.resource_address() == receipt_data.collateral),
+                "Buffer holds the collateral the loan was migrated from, migrate it back to use the buffer."
+            );
+
+            if self.cdp_buffers.get(&collateral_id).is_none() {
+                self.cdp_buffers
+                    .insert(collateral_id.clone(), Vault::new(receipt_data.collateral));
+            }
+            self.cdp_buffers
+                .get_mut(&collateral_id)
+                .unwrap()
+                .put(collateral);
+        }
+
+        /// Withdraw collateral from the auto-top-up buffer of a loan / CDP
+        pub fn withdraw_from_buffer(
+            &mut self,
+            collateral_id: NonFungibleLocalId,
+            amount: Decimal,
+        ) -> Bucket {
+            let rounding: WithdrawStrategy = self.rounding(false);
+            self.cdp_buffers
+                .get_mut(&collateral_id)
+                .expect("No buffer for this loan.")
+                .take_advanced(amount, rounding)
+        }
+
+        /// Get the amount of collateral in the auto-top-up buffer of a loan / CDP
+        pub fn get_buffer(&self, collateral_id: NonFungibleLocalId) -> Decimal {
+            self.cdp_buffers
+                .get(&collateral_id)
+                .map_or(dec!(0), |buffer| buffer.amount())
+        }
+
+        /// Save a marked loan / CDP with its auto-top-up buffer
         ///
         /// # Input
         /// - `collateral_id`: The CDP receipt
-        /// - `amount`: The amount of collateral to remove
         ///
         /// # Output
-        /// - The removed collateral
+        /// - The bounty for the caller, in collateral
         ///
         /// # Logic
-        /// - Check if the loan is healthy
-        /// - Remove the collateral ratio from the AvlTree
-        /// - Calculate new collateral ratio
-        /// - Check if the new collateral ratio is high enough
-        /// - Insert new collateral ratio into AvlTree
-        /// - Retrieve the to-be returned collateral from the correct vault
-        /// - Update the CDP receipt
-        /// - Return the removed collateral
-        pub fn remove_collateral(
+        /// - Check if the loan is marked
+        /// - Take the whole buffer, and split off the bounty (buffer bounty * buffer)
+        /// - Top up the loan with the rest (which fails if it isn't enough to save the loan)
+        /// - Return the bounty
+        pub fn apply_buffer(&mut self, collateral_id: NonFungibleLocalId) -> Bucket {
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+
+            assert!(receipt_data.status == CdpStatus::Marked, "Loan not marked.");
+
+            let mut buffer: Bucket = self
+                .cdp_buffers
+                .get_mut(&collateral_id)
+                .expect("No buffer for this loan.")
+                .take_all();
+            assert!(!buffer.is_empty(), "Buffer is empty.");
+
+            let bounty: Bucket = buffer.take_advanced(
+                buffer.amount() * self.parameters.buffer_bounty,
+                self.rounding(false),
+            );
+
+            Runtime::emit_event(EventBufferApplied {
+                cdp_id: collateral_id.clone(),
+                amount: buffer.amount(),
+                bounty: bounty.amount(),
+            });
+
+            self.top_up_cdp(collateral_id, buffer);
+
+            bounty
+        }
+
+        /// Opt a loan / CDP in to (or out of, with None) automatic partial deleveraging
+        ///   - once the loan's CR (collateral value / debt value) drops below the trigger CR, anyone can deleverage it back to the target CR (see `auto_deleverage`)
+        ///   - the trigger CR needs to be above the collateral's MCR, and the target CR above the trigger CR
+        pub fn set_auto_deleverage(
             &mut self,
             collateral_id: NonFungibleLocalId,
-            amount: Decimal,
-        ) -> Bucket {
-            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
-            let new_collateral_amount = receipt_data.collateral_amount - amount;
+            settings: Option<DeleverageSettings>,
+        ) {
+            let settings: DeleverageSettings = match settings {
+                Some(settings) => settings,
+                None => {
+                    self.deleverage_settings.remove(&collateral_id);
+                    return;
+                }
+            };
 
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
             assert!(
-                receipt_data.status == CdpStatus::Healthy,
-                "Loan not healthy. Save it first."
+                !receipt_data.is_pool_unit_collateral,
+                "Auto-deleverage isn't available for pool unit collateral."
             );
-
             assert!(
-                !self.parameters.stop_closings,
-                "Not allowed to close loans / remove collateral right now."
+                settings.trigger_cr
+                    > self
+                        .collaterals
+                        .get(&receipt_data.parent_address)
+                        .unwrap()
+                        .mcr,
+                "Trigger CR needs to be above the MCR."
             );
-
-            self.remove_cr(
-                receipt_data.parent_address,
-                receipt_data.collateral_stab_ratio,
-                collateral_id.clone(),
+            assert!(
+                settings.target_cr > settings.trigger_cr,
+                "Target CR needs to be above the trigger CR."
             );
 
-            let cr: Decimal = self.pool_to_real(
-                new_collateral_amount,
-                receipt_data.collateral,
-                receipt_data.is_pool_unit_collateral,
-            ) / receipt_data.minted_stab;
-
-            self.collaterals
-                .get_mut(&receipt_data.parent_address)
-                .unwrap()
-                .collateral_amount +=
-                (cr - receipt_data.collateral_stab_ratio) * receipt_data.minted_stab;
+            self.deleverage_settings.insert(collateral_id, settings);
+        }
 
-            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
+        /// Set (or unset, with None) the account the leftover collateral of a loan / CDP is returned to when it is liquidated
+        ///   - at liquidation, the leftover collateral above the leftover buffer is deposited in the account, the rest stays in the loan to be retrieved as usual (see `return_leftover`)
+        ///   - only accounts can be set, so the deposit can't call into arbitrary components during a liquidation
+        pub fn set_refund_account(
+            &mut self,
+            collateral_id: NonFungibleLocalId,
+            account: Option<ComponentAddress>,
+        ) {
+            let account: ComponentAddress = match account {
+                Some(account) => account,
+                None => {
+                    self.refund_accounts.remove(&collateral_id);
+                    return;
+                }
+            };
 
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
             assert!(
-                cr > self
-                    .collaterals
-                    .get_mut(&receipt_data.parent_address)
-                    .unwrap()
-                    .liquidation_collateral_ratio,
-                "Removal would put the CR below MCR."
+                receipt_data.status == CdpStatus::Healthy
+                    || receipt_data.status == CdpStatus::Marked,
+                "Loan not healthy or marked."
+            );
+            assert!(
+                account
+                    .as_node_id()
+                    .entity_type()
+                    .map_or(false, |entity_type| entity_type.is_global_account()),
+                "Refund account needs to be an account."
             );
 
-            let removed_collateral: Bucket = self.take_collateral(
-                receipt_data.collateral,
-                receipt_data.is_pool_unit_collateral,
-                amount,
+            self.refund_accounts.insert(collateral_id, account);
+        }
+
+        /// Get the refund account of a loan / CDP, if it set one
+        pub fn get_refund_account(
+            &self,
+            collateral_id: NonFungibleLocalId,
+        ) -> Option<ComponentAddress> {
+            self.refund_accounts
+                .get(&collateral_id)
+                .map(|account| *account)
+        }
+
+        /// Get the auto-deleverage settings of a loan / CDP, if it opted in
+        pub fn get_auto_deleverage(
+            &self,
+            collateral_id: NonFungibleLocalId,
+        ) -> Option<DeleverageSettings> {
+            self.deleverage_settings
+                .get(&collateral_id)
+                .map(|settings| settings.clone())
+        }
+
+        /// Cap (or uncap, with None) the debt force minting can add to a loan / CDP
+        ///   - the cap is an absolute amount of STAB, or a percentage of the loan's debt when setting it (0.1 = 10%)
+        ///   - the cap can't be below the minimum force mint cap (a percentage of the loan's debt), so loans can't opt out of force minting
+        ///   - force minting skips loans whose cap is reached, and only adds debt up to the cap to the others
+        ///   - setting a new cap resets the debt force minted under the old one
+        pub fn set_force_mint_cap(
+            &mut self,
+            collateral_id: NonFungibleLocalId,
+            limit: Option<ForceMintLimit>,
+        ) {
+            let limit: ForceMintLimit = match limit {
+                Some(limit) => limit,
+                None => {
+                    self.force_mint_caps.remove(&collateral_id);
+                    return;
+                }
+            };
+
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let allowance: Decimal = match limit {
+                ForceMintLimit::Absolute(amount) => amount,
+                ForceMintLimit::Percentage(percentage) => percentage * receipt_data.minted_stab,
+            };
+            assert!(
+                allowance >= self.parameters.min_force_mint_cap * receipt_data.minted_stab,
+                "Force mint cap can't be below {} of the loan's debt.",
+                self.parameters.min_force_mint_cap
             );
 
-            self.cdp_manager
-                .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
-            self.cdp_manager.update_non_fungible_data(
-                &collateral_id,
-                "collateral_amount",
-                new_collateral_amount,
+            self.force_mint_caps.insert(
+                collateral_id,
+                ForceMintCap {
+                    limit,
+                    allowance,
+                    force_minted: dec!(0),
+                },
             );
+        }
 
-            receipt_data.collateral_stab_ratio = cr;
-            receipt_data.collateral_amount = new_collateral_amount;
-            Runtime::emit_event(EventUpdateCdp {
-                cdp: receipt_data,
-                cdp_id: collateral_id,
-            });
+        /// Get the force mint cap of a loan / CDP, if it opted in
+        pub fn get_force_mint_cap(
+            &self,
+            collateral_id: NonFungibleLocalId,
+        ) -> Option<ForceMintCap> {
+            self.force_mint_caps
+                .get(&collateral_id)
+                .map(|cap| cap.clone())
+        }
 
-            removed_collateral
+        /// Quote an auto-deleverage of a loan / CDP: the STAB to repay, the collateral to sell and the bounty (in collateral)
+        pub fn quote_auto_deleverage(
+            &self,
+            collateral_id: NonFungibleLocalId,
+        ) -> (Decimal, Decimal, Decimal) {
+            self.calculate_deleverage(&collateral_id)
         }
 
-        /// Partially close a loan / CDP (pay off part of the debt)
+        /// Partially deleverage a loan / CDP that crossed its auto-deleverage trigger CR, back to its target CR
         ///
         /// # Input
         /// - `collateral_id`: The CDP receipt
-        /// - `repayment`: The STAB tokens to pay back
+        /// - `stab_payment`: The STAB to repay the debt with (see `quote_auto_deleverage`)
         ///
         /// # Output
-        /// - None
+        /// - The collateral to sell to cover the repaid debt (including the slippage margin)
+        /// - The bounty for the caller, in collateral
+        /// - The leftover STAB
         ///
         /// # Logic
-        /// - Check if the STAB payment is valid
-        /// - If the repayment > debt, close the loan and return leftover collateral and leftover payment
-        /// - Check if borrowed amount is still above minimum borrow
-        /// - Check if the loan is healthy or marked
-        /// - Remove the collateral ratio from the AvlTree if not marked
-        /// - Calculate new collateral ratio
-        /// - Burn the paid back STAB
-        /// - Insert new collateral ratio into AvlTree
-        /// - Check if the new collateral ratio is high enough
+        /// - Calculate the debt to repay, so the CR is at the target CR after removing the collateral to sell and the bounty
+        /// - Remove the collateral ratio from the AvlTree
+        /// - Burn the repaid STAB and update circulating STAB
+        /// - Take the collateral to sell and the bounty from the vault
+        /// - Insert the new collateral ratio into the AvlTree
         /// - Update the CDP receipt
-        /// - If the loan was marked, update the marker receipt
-        pub fn partial_close_cdp(
+        pub fn auto_deleverage(
             &mut self,
             collateral_id: NonFungibleLocalId,
-            repayment: Bucket,
-        ) -> (Option<Bucket>, Option<Bucket>) {
+            mut stab_payment: Bucket,
+        ) -> (Bucket, Bucket, Bucket) {
+            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            self.check_not_frozen(&collateral_id);
             assert!(
-                !self.parameters.stop_closings,
+                !self.is_paused(
+                    PausableAction::PartialCloseCdp,
+                    Some(receipt_data.collateral)
+                ),
                 "Not allowed to close loans / remove collateral right now."
             );
-
             assert!(
-                repayment.resource_address() == self.stab_manager.address(),
+                stab_payment.resource_address() == self.stab_manager.address(),
                 "Invalid STAB payment."
             );
 
-            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
-            let new_stab_amount = receipt_data.minted_stab - repayment.amount();
-
-            if new_stab_amount < dec!(0) {
-                let (collateral, leftover_payment): (Bucket, Bucket) =
-                    self.close_cdp(collateral_id, repayment);
-                return (Some(collateral), Some(leftover_payment));
-            }
-
-            assert!(
-                new_stab_amount >= self.parameters.minimum_mint,
-                "Resulting borrowed STAB needs to be above minimum mint."
-            );
-
+            let (stab_to_repay, collateral_to_sell, bounty): (Decimal, Decimal, Decimal) =
+                self.calculate_deleverage(&collateral_id);
             assert!(
-                receipt_data.status == CdpStatus::Healthy
-                    || receipt_data.status == CdpStatus::Marked,
-                "Loan not healthy or marked."
+                stab_payment.amount() >= stab_to_repay,
+                "Not enough STAB supplied to deleverage."
             );
 
-            if receipt_data.status == CdpStatus::Healthy {
-                self.remove_cr(
-                    receipt_data.parent_address,
-                    receipt_data.collateral_stab_ratio,
-                    collateral_id.clone(),
-                );
-            }
-
-            let cr: Decimal = self.pool_to_real(
-                receipt_data.collateral_amount,
-                receipt_data.collateral,
-                receipt_data.is_pool_unit_collateral,
-            ) / new_stab_amount;
+            self.remove_cr(
+                receipt_data.parent_address,
+                receipt_data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
 
+            self.settle_borrow_rewards(
+                &collateral_id,
+                receipt_data.parent_address,
+                receipt_data.minted_stab,
+            );
             self.update_minted_stab(
                 false,
-                receipt_data.is_pool_unit_collateral,
                 false,
-                repayment.amount(),
+                false,
+                stab_to_repay,
                 receipt_data.parent_address,
                 receipt_data.collateral,
             );
+            stab_payment.take(stab_to_repay).burn();
+            self.update_cdp_stats(&collateral_id, |stats| stats.total_repaid += stab_to_repay);
 
-            repayment.burn();
+            let mut collateral: Bucket =
+                self.take_collateral(receipt_data.collateral, false, collateral_to_sell + bounty);
+            let bounty: Bucket = collateral.take(bounty);
 
-            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
+            let new_stab_amount: Decimal = receipt_data.minted_stab - stab_to_repay;
+            let new_collateral_amount: Decimal =
+                receipt_data.collateral_amount - collateral.amount() - bounty.amount();
+            let cr: Decimal = new_collateral_amount / new_stab_amount;
 
-            assert!(
-                cr > self
-                    .collaterals
-                    .get_mut(&receipt_data.parent_address)
-                    .unwrap()
-                    .liquidation_collateral_ratio,
-                "CR below MCR."
-            );
+            self.collaterals
+                .get_mut(&receipt_data.parent_address)
+                .unwrap()
+                .collateral_amount += cr * new_stab_amount
+                - receipt_data.collateral_stab_ratio * receipt_data.minted_stab;
+
+            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
 
             self.cdp_manager
                 .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
@@ -978,71 +1792,99 @@ mod stabilis_component {
                 "minted_stab",
                 new_stab_amount,
             );
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "collateral_amount",
+                new_collateral_amount,
+            );
 
-            if receipt_data.status == CdpStatus::Marked {
-                let marker_data: CdpMarker = self
-                    .cdp_marker_manager
-                    .get_non_fungible_data(&NonFungibleLocalId::integer(receipt_data.marker_id));
-                self.cdp_manager.update_non_fungible_data(
-                    &collateral_id,
-                    "status",
-                    CdpStatus::Healthy,
-                );
-                self.cdp_marker_manager.update_non_fungible_data(
-                    &NonFungibleLocalId::integer(receipt_data.marker_id),
-                    "used",
-                    true,
-                );
-                self.marked_cdps.remove(&marker_data.marker_placing);
-                self.marked_cdps_active -= 1;
-                receipt_data.status = CdpStatus::Healthy;
-            }
+            Runtime::emit_event(EventAutoDeleverage {
+                cdp_id: collateral_id.clone(),
+                stab_repaid: stab_to_repay,
+                collateral_sold: collateral.amount(),
+                bounty: bounty.amount(),
+            });
 
             receipt_data.minted_stab = new_stab_amount;
+            receipt_data.collateral_amount = new_collateral_amount;
             receipt_data.collateral_stab_ratio = cr;
             Runtime::emit_event(EventUpdateCdp {
                 cdp: receipt_data,
                 cdp_id: collateral_id,
             });
 
-            (None, None)
+            (collateral, bounty, stab_payment)
         }
 
-        /// Borrow more STAB by adding to the loan / CDP
+        /// Opt a pool unit loan / CDP in to (or out of) yield harvesting, making it self-repaying
+        ///   - the appreciation of the pool units (redemption rate growth since opting in or the last harvest) can be harvested by anyone to repay the loan's debt (see `harvest_yield`)
+        pub fn set_yield_harvest(&mut self, collateral_id: NonFungibleLocalId, enabled: bool) {
+            if !enabled {
+                self.harvest_rates.remove(&collateral_id);
+                return;
+            }
+
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            assert!(
+                receipt_data.is_pool_unit_collateral,
+                "Yield harvesting is only available for pool unit collateral."
+            );
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy."
+            );
+
+            let rate: Decimal = self.redemption_rate(receipt_data.collateral);
+            self.harvest_rates.insert(collateral_id, rate);
+        }
+
+        /// Get the redemption rate yield is harvested above for a loan / CDP, if it opted in to yield harvesting
+        pub fn get_yield_harvest(&self, collateral_id: NonFungibleLocalId) -> Option<Decimal> {
+            self.harvest_rates.get(&collateral_id).map(|rate| *rate)
+        }
+
+        /// Quote a yield harvest of a loan / CDP: the pool units to sell, the bounty (in pool units), the minimum STAB the sold pool units should return and the maximum STAB that can be repaid
+        pub fn quote_yield_harvest(
+            &self,
+            collateral_id: NonFungibleLocalId,
+        ) -> (Decimal, Decimal, Decimal, Decimal) {
+            self.calculate_harvest(&collateral_id)
+        }
+
+        /// Harvest the appreciation of the pool unit collateral of a loan / CDP, to be sold for STAB to repay its debt
         ///
         /// # Input
         /// - `collateral_id`: The CDP receipt
-        /// - `amount`: The amount of STAB to mint / borrow
         ///
         /// # Output
-        /// - The minted STAB in a `Bucket`
+        /// - The pool units to sell for STAB
+        /// - The bounty for the caller, in pool units
+        /// - The minimum STAB the pool units to sell should return (their value minus the deleverage slippage margin)
+        /// - The maximum STAB that can be repaid (including the close fee) without putting the debt below the minimum mint
         ///
         /// # Logic
-        /// - Check if the loan is healthy
+        /// - Calculate the pool units worth the redemption rate growth since opting in or the last harvest, capped at the maximum repayment
         /// - Remove the collateral ratio from the AvlTree
-        /// - Calculate new collateral ratio
-        /// - Update the minted STAB
-        /// - Insert new collateral ratio into AvlTree
-        /// - Check if the new collateral ratio is high enough
+        /// - Take the harvested pool units from the vault (no withdrawal throttle, as they're used to repay the loan)
+        /// - Insert the new collateral ratio into the AvlTree, and check it's still above the liquidation CR
+        /// - Store the current redemption rate, so the same yield can't be harvested twice
         /// - Update the CDP receipt
-        /// - Mint the STAB and return it
-        pub fn borrow_more(
+        pub fn harvest_yield(
             &mut self,
             collateral_id: NonFungibleLocalId,
-            amount: Decimal,
-        ) -> Bucket {
+        ) -> (Bucket, Bucket, Decimal, Decimal) {
             let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
-            let new_stab_amount = receipt_data.minted_stab + amount;
-
+            self.check_not_frozen(&collateral_id);
             assert!(
-                receipt_data.status == CdpStatus::Healthy,
-                "Loan not healthy. Save it first."
+                !self.is_paused(
+                    PausableAction::RemoveCollateral,
+                    Some(receipt_data.collateral)
+                ),
+                "Not allowed to close loans / remove collateral right now."
             );
 
-            assert!(
-                !self.parameters.stop_openings,
-                "Not allowed to open loans right now."
-            );
+            let (to_sell, bounty, min_stab, max_repayment): (Decimal, Decimal, Decimal, Decimal) =
+                self.calculate_harvest(&collateral_id);
 
             self.remove_cr(
                 receipt_data.parent_address,
@@ -1050,884 +1892,3585 @@ mod stabilis_component {
                 collateral_id.clone(),
             );
 
-            let cr: Decimal = self.pool_to_real(
-                receipt_data.collateral_amount,
-                receipt_data.collateral,
-                receipt_data.is_pool_unit_collateral,
-            ) / new_stab_amount;
+            let mut harvested: Bucket =
+                self.take_collateral(receipt_data.collateral, true, to_sell + bounty);
+            let bounty: Bucket = harvested.take(bounty);
 
-            self.update_minted_stab(
-                true,
-                receipt_data.is_pool_unit_collateral,
-                true,
-                amount,
-                receipt_data.parent_address,
-                receipt_data.collateral,
-            );
+            let new_collateral_amount: Decimal =
+                receipt_data.collateral_amount - harvested.amount() - bounty.amount();
+            let cr: Decimal =
+                self.pool_to_real(new_collateral_amount, receipt_data.collateral, true)
+                    / receipt_data.minted_stab;
+
+            self.collaterals
+                .get_mut(&receipt_data.parent_address)
+                .unwrap()
+                .collateral_amount +=
+                (cr - receipt_data.collateral_stab_ratio) * receipt_data.minted_stab;
 
             self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
 
             assert!(
                 cr > self
                     .collaterals
-                    .get_mut(&receipt_data.parent_address)
+                    .get(&receipt_data.parent_address)
                     .unwrap()
                     .liquidation_collateral_ratio,
-                "Removal would put the CR below MCR."
+                "Harvest would put the CR below MCR."
             );
 
+            let rate: Decimal = self.redemption_rate(receipt_data.collateral);
+            self.harvest_rates.insert(collateral_id.clone(), rate);
+
             self.cdp_manager
                 .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
             self.cdp_manager.update_non_fungible_data(
                 &collateral_id,
-                "minted_stab",
-                new_stab_amount,
+                "collateral_amount",
+                new_collateral_amount,
             );
 
-            receipt_data.minted_stab = new_stab_amount;
+            Runtime::emit_event(EventYieldHarvested {
+                cdp_id: collateral_id.clone(),
+                collateral_harvested: harvested.amount(),
+                bounty: bounty.amount(),
+            });
+
+            receipt_data.collateral_amount = new_collateral_amount;
             receipt_data.collateral_stab_ratio = cr;
             Runtime::emit_event(EventUpdateCdp {
                 cdp: receipt_data,
                 cdp_id: collateral_id,
             });
 
-            self.stab_manager.mint(amount)
+            (harvested, bounty, min_stab, max_repayment)
         }
 
-        /// Mark a loan for liquidation
-        ///
-        /// # Input
-        /// - `collateral`: The collateral for which to look for undercollateralized loans to be liquidated
-        ///
-        /// # Output
-        /// - The marker receipt in a `Bucket`
-        ///
-        /// # Logic
-        /// - Get the CDP with the lowest collateral ratio for the chosen collateral
-        /// - Calculate new collateral ratio (as pool unit aren't always up to date)
-        /// - Create the marker receipt struct
-        /// - Insert the CDP into the marked CDPs AvlTree
-        /// - Remove the collateral ratio from the AvlTree
-        /// - Mint marker receipt, which will be returned if the marking is a success
-        /// - Update the Cdp receipt to point to the marker receipt and get marked status
-        /// - Get the collateral ids for the collateral ratio that was newly calculated (which is different if working with pool units)
-        /// - Save CDP if CR is high enough after pool_to_real conversion, unless the collateral_ids vector is full
-        ///     - Return the initial marker receipt if saving wasn't possible
-        ///     - Or return a new marker receipt if saving was possible
-        pub fn mark_for_liquidation(&mut self, collateral: ResourceAddress) -> Bucket {
-            let (_first_cr, collateral_ids, _next_key) = self
-                .collateral_ratios
-                .get_mut(&collateral)
-                .unwrap()
-                .range(dec!(0)..)
-                .next()
-                .unwrap();
-            let collateral_id: NonFungibleLocalId = collateral_ids[0].clone();
-            let lcr: Decimal = self
-                .collaterals
-                .get(&collateral)
-                .unwrap()
-                .liquidation_collateral_ratio;
-            let mut data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+        /// Freeze a loan / CDP under dispute (e.g. a suspected exploit position), blocking borrowing, closing, removing collateral and liquidating it
+        ///   - the freeze ends automatically after `minutes`, which can't exceed the max freeze duration
+        ///   - freezing a frozen loan replaces its freeze, but the freezes of a single dispute (from its first freeze on) can't exceed the max freeze duration in total
+        pub fn freeze_cdp(&mut self, cdp_id: NonFungibleLocalId, minutes: i64, reason: String) {
+            assert!(
+                minutes > 0 && minutes <= self.parameters.max_freeze_minutes,
+                "Freeze duration needs to be between 0 and {} minutes.",
+                self.parameters.max_freeze_minutes
+            );
+            assert!(
+                reason.len() <= MAX_FREEZE_REASON_LENGTH,
+                "Reason too long, max {} bytes.",
+                MAX_FREEZE_REASON_LENGTH
+            );
+            assert!(
+                self.cdp_manager.non_fungible_exists(&cdp_id),
+                "Loan does not exist."
+            );
 
+            let now: Instant = Clock::current_time_rounded_to_seconds();
+            let frozen_since: Instant = match self.is_frozen(&cdp_id) {
+                true => self.frozen_cdps.get(&cdp_id).unwrap().frozen_since,
+                false => now,
+            };
+            let until: Instant = now.add_minutes(minutes).unwrap();
             assert!(
-                data.collateral_stab_ratio
-                    < self
-                        .collaterals
-                        .get(&collateral)
+                until.seconds_since_unix_epoch
+                    <= frozen_since
+                        .add_minutes(self.parameters.max_freeze_minutes)
                         .unwrap()
-                        .liquidation_collateral_ratio,
-                "No possible liquidations."
+                        .seconds_since_unix_epoch,
+                "A dispute can't freeze a loan for longer than {} minutes in total.",
+                self.parameters.max_freeze_minutes
+            );
+            self.frozen_cdps.insert(
+                cdp_id.clone(),
+                CdpFreeze {
+                    until,
+                    reason: reason.clone(),
+                    frozen_since,
+                },
             );
 
-            let cr: Decimal = self.pool_to_real(
-                data.collateral_amount,
-                data.collateral,
-                data.is_pool_unit_collateral,
-            ) / data.minted_stab;
+            Runtime::emit_event(EventCdpFrozen {
+                cdp_id,
+                until,
+                reason,
+            });
+        }
 
-            self.collaterals
-                .get_mut(&collateral)
-                .unwrap()
-                .collateral_amount += (cr - data.collateral_stab_ratio) * data.minted_stab;
+        /// Unfreeze a loan / CDP before its freeze ends
+        pub fn unfreeze_cdp(&mut self, cdp_id: NonFungibleLocalId) {
+            assert!(self.is_frozen(&cdp_id), "Loan not frozen.");
+            self.frozen_cdps.remove(&cdp_id);
 
-            self.marker_placing_counter += dec!(1);
-            self.cdp_marker_counter += 1;
-            let id: Decimal = self.marker_placing_counter;
+            Runtime::emit_event(EventCdpUnfrozen { cdp_id });
+        }
 
-            let mut marker = CdpMarker {
-                mark_type: CdpUpdate::Marked,
-                time_marked: Clock::current_time_rounded_to_seconds(),
-                marked_id: collateral_id.clone(),
-                marker_placing: self.marker_placing_counter,
-                used: false,
-            };
+        /// Get the freeze of a loan / CDP, if it is frozen
+        pub fn get_cdp_freeze(&self, cdp_id: NonFungibleLocalId) -> Option<CdpFreeze> {
+            match self.is_frozen(&cdp_id) {
+                true => self.frozen_cdps.get(&cdp_id).map(|freeze| freeze.clone()),
+                false => None,
+            }
+        }
 
-            self.remove_cr(
-                data.parent_address,
-                data.collateral_stab_ratio,
-                collateral_id.clone(),
-            );
+        /// Get the lifetime statistics of a loan / CDP
+        pub fn get_cdp_stats(&self, cdp_id: NonFungibleLocalId) -> CdpStats {
+            match self.cdp_stats.get(&cdp_id) {
+                Some(stats) => stats.clone(),
+                None => CdpStats {
+                    total_borrowed: dec!(0),
+                    total_repaid: dec!(0),
+                    times_marked: 0,
+                    times_saved: 0,
+                },
+            }
+        }
 
-            let mut cdp_ids: Vec<NonFungibleLocalId> = Vec::new();
+        /// Get the status of a marker receipt, None if it doesn't exist (anymore)
+        pub fn get_marker(&self, marker_id: NonFungibleLocalId) -> Option<MarkerInfo> {
+            if !self.cdp_marker_manager.non_fungible_exists(&marker_id) {
+                return None;
+            }
+            let marker: CdpMarker = self.cdp_marker_manager.get_non_fungible_data(&marker_id);
 
-            if self
-                .collateral_ratios
-                .get_mut(&collateral)
-                .unwrap()
-                .get_mut(&cr)
-                .is_some()
-            {
-                cdp_ids = self
-                    .collateral_ratios
-                    .get_mut(&collateral)
-                    .unwrap()
-                    .get_mut(&cr)
-                    .unwrap()
-                    .to_vec();
+            Some(MarkerInfo {
+                marker_id,
+                mark_type: marker.mark_type,
+                marked_id: marker.marked_id,
+                time_marked: marker.time_marked,
+                liquidatable_from: marker
+                    .time_marked
+                    .add_minutes(self.parameters.liquidation_delay)
+                    .unwrap(),
+                public_liquidatable_from: marker
+                    .time_marked
+                    .add_minutes(self.parameters.liquidation_delay + self.parameters.unmarked_delay)
+                    .unwrap(),
+                used: marker.used,
+            })
+        }
+
+        /// Get the status of the latest markers placed on a loan / CDP (at most `max_vector_length`), skipping burned markers
+        pub fn get_markers_for_cdp(&self, cdp_id: NonFungibleLocalId) -> Vec<MarkerInfo> {
+            match self.cdp_markers.get(&cdp_id) {
+                Some(marker_ids) => marker_ids
+                    .iter()
+                    .filter_map(|id| self.get_marker(NonFungibleLocalId::integer(*id)))
+                    .collect(),
+                None => vec![],
             }
+        }
 
-            if (cr > lcr) && cdp_ids.len() < self.parameters.max_vector_length.try_into().unwrap() {
-                self.insert_cr(data.parent_address, cr, collateral_id.clone());
+        /// Fund the borrow rewards emission program, the first deposit sets the reward token
+        pub fn fund_borrow_rewards(&mut self, rewards: Bucket) {
+            match &mut self.borrow_rewards.vault {
+                Some(vault) => vault.put(rewards),
+                None => self.borrow_rewards.vault = Some(Vault::with_bucket(rewards)),
+            }
+        }
 
-                self.cdp_manager.update_non_fungible_data(
-                    &collateral_id,
-                    "collateral_stab_ratio",
-                    cr,
+        /// Set the emission schedule of the borrow rewards
+        ///
+        /// # Input
+        /// - `schedule`: Periods during which rewards are emitted, each emitting `rewards_per_minute` per STAB borrowed (at a weight of 1)
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check the periods are valid, sorted and non-overlapping
+        /// - Bring all reward indices up to date under the old schedule
+        /// - Replace the schedule
+        pub fn set_borrow_reward_schedule(&mut self, schedule: Vec<EmissionPeriod>) {
+            let mut previous_end: Option<Instant> = None;
+            for period in schedule.iter() {
+                assert!(
+                    period.start.compare(period.end, TimeComparisonOperator::Lt),
+                    "Emission period must end after it starts."
+                );
+                assert!(
+                    period.rewards_per_minute >= dec!(0),
+                    "Emission can't be negative."
                 );
+                if let Some(end) = previous_end {
+                    assert!(
+                        period.start.compare(end, TimeComparisonOperator::Gte),
+                        "Emission periods must be sorted and can't overlap."
+                    );
+                }
+                previous_end = Some(period.end);
+            }
 
-                marker.mark_type = CdpUpdate::Saved;
-
-                self.cdp_marker_manager.mint_non_fungible(
-                    &NonFungibleLocalId::integer(self.cdp_marker_counter),
-                    marker,
-                )
-            } else {
-                self.marked_cdps.insert(id, collateral_id.clone());
-                self.marked_cdps_active += 1;
-
-                self.cdp_manager.update_non_fungible_data(
-                    &collateral_id,
-                    "marker_id",
-                    self.cdp_marker_counter,
-                );
-
-                self.cdp_manager.update_non_fungible_data(
-                    &collateral_id,
-                    "status",
-                    CdpStatus::Marked,
-                );
-
-                data.status = CdpStatus::Marked;
-                data.marker_id = self.cdp_marker_counter;
-
-                Runtime::emit_event(EventUpdateCdp {
-                    cdp: data,
-                    cdp_id: collateral_id,
-                });
-
-                self.cdp_marker_manager.mint_non_fungible(
-                    &NonFungibleLocalId::integer(self.cdp_marker_counter),
-                    marker,
-                )
+            let collaterals: Vec<ResourceAddress> =
+                self.borrow_rewards.indices.keys().cloned().collect();
+            for collateral in collaterals {
+                self.accrue_borrow_reward_index(collateral);
             }
+            self.borrow_rewards.schedule = schedule;
         }
 
-        /// Force liquidate a loan / CDP (liquidating without the loan being undercollateralized, but with a fee that should be beneficial for the borrower)
+        /// Set the reward weight of a (parent) collateral, a weight of 0 stops its emission
+        pub fn set_borrow_reward_weight(&mut self, collateral: ResourceAddress, weight: Decimal) {
+            assert!(
+                self.collaterals.get(&collateral).is_some(),
+                "Collateral is not accepted."
+            );
+            assert!(weight >= dec!(0), "Weight can't be negative.");
+
+            self.accrue_borrow_reward_index(collateral);
+            self.borrow_rewards
+                .indices
+                .entry(collateral)
+                .or_insert(BorrowRewardIndex {
+                    weight: dec!(0),
+                    index: dec!(0),
+                    last_update: Clock::current_time_rounded_to_seconds(),
+                })
+                .weight = weight;
+        }
+
+        /// Claim the borrow rewards accrued by a loan / CDP
         ///
         /// # Input
-        /// - `collateral`: The collateral to be liquidated
-        /// - `payment`: The STAB tokens to pay back
-        /// - `percentage_to_take`: The percentage of the collateral value to take (if < 1, the borrower will profit off the liquidation)
-        /// - `assert_non_markable`: Whether to assert that the loan is not markable via normal means, which would be more profitable for the liquidator
+        /// - `cdp_id`: The id of the loan
         ///
         /// # Output
-        /// - The collateral returned
-        /// - The leftover STAB
+        /// - The claimed reward tokens
         ///
         /// # Logic
-        /// - Get the CDP with lowest collateral ratio for the chosen collateral
-        /// - Remove the collateral ratio from the AvlTree
-        /// - Calculate latest collateral ratio
-        /// - Get liquidation collateral ratio
-        /// - Assert that the collateral ratio is high enough to force liquidate
-        ///    - If LCR > CR, the loan doesn't have to be forced, but can be liquidated via normal means
-        /// - Calculate percentage of collateral value vs. minted STAB
-        /// - Calculate how much of the loan can be liquidated, how much of the payment should be taken, and what the leftover STAB debt will then be
-        /// - Calculate new collateral amount
-        ///    - If CR is too low, not all collateral the liquidator wants to take can be taken, so the new_collateral_amount will be negative
-        ///    - Then we set the new_collateral_amount to 0
-        /// - If CR percentage is not > 100%, the entire loan must be liquidated
-        ///    - Otherwise we can be left with a loan that has debt but 0 collateral
-        /// - Take the payment and burn the STAB
-        /// - Update circulating STAB
-        /// - Take the collateral
-        /// - Calculate the new_collateral_amount again, because of potential rounding in take_collateral method (when working with assets with strange divisilibity)
-        /// - Update the CDP receipt
-        /// - If the new collateral amount is not 0, calculate the new collateral ratio, insert it into the AvlTree and update the CDP receipt
-        /// - If the loan was liquidated, update the CDP receipt to reflect this
-        /// - Return the collateral and the leftover STAB
-        pub fn force_liquidate(
+        /// - Settle the rewards accrued since the loan's last settlement (loans that are closed or liquidated have no debt anymore)
+        /// - Pay out as much of the accrued rewards as the reward vault holds, the rest stays claimable
+        pub fn claim_borrow_rewards(&mut self, cdp_id: NonFungibleLocalId) -> Bucket {
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&cdp_id);
+            let debt: Decimal = match receipt_data.status {
+                CdpStatus::Healthy | CdpStatus::Marked => receipt_data.minted_stab,
+                _ => dec!(0),
+            };
+            self.settle_borrow_rewards(&cdp_id, receipt_data.parent_address, debt);
+
+            let rounding: WithdrawStrategy = self.rounding(false);
+            let vault = self
+                .borrow_rewards
+                .vault
+                .as_mut()
+                .expect("No borrow rewards program.");
+            let accrued: Decimal = self
+                .borrow_reward_positions
+                .get(&cdp_id)
+                .map(|position| position.accrued)
+                .unwrap_or(dec!(0));
+            let rewards: Bucket = vault.take_advanced(accrued.min(vault.amount()), rounding);
+
+            if let Some(mut position) = self.borrow_reward_positions.get_mut(&cdp_id) {
+                position.accrued -= rewards.amount();
+            }
+
+            Runtime::emit_event(EventBorrowRewardsClaimed {
+                cdp_id,
+                amount: rewards.amount(),
+            });
+
+            rewards
+        }
+
+        /// Get the borrow rewards emission program: reward token, available rewards, schedule and collateral weights
+        pub fn get_borrow_rewards(&self) -> BorrowRewardsInfo {
+            BorrowRewardsInfo {
+                reward_address: self
+                    .borrow_rewards
+                    .vault
+                    .as_ref()
+                    .map(|vault| vault.resource_address()),
+                available: self
+                    .borrow_rewards
+                    .vault
+                    .as_ref()
+                    .map(|vault| vault.amount())
+                    .unwrap_or(dec!(0)),
+                schedule: self.borrow_rewards.schedule.clone(),
+                weights: self
+                    .borrow_rewards
+                    .indices
+                    .iter()
+                    .map(|(collateral, index)| (*collateral, index.weight))
+                    .collect(),
+            }
+        }
+
+        /// Get the borrow rewards a loan / CDP could currently claim (ignoring the reward vault's balance)
+        pub fn get_pending_borrow_rewards(&self, cdp_id: NonFungibleLocalId) -> Decimal {
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&cdp_id);
+            let position: BorrowRewardPosition = self.get_borrow_reward_position(&cdp_id);
+            let debt: Decimal = match receipt_data.status {
+                CdpStatus::Healthy | CdpStatus::Marked => receipt_data.minted_stab,
+                _ => dec!(0),
+            };
+
+            match self.current_borrow_reward_index(receipt_data.parent_address) {
+                Some(index) => position.accrued + debt * (index - position.index),
+                None => position.accrued,
+            }
+        }
+
+        /// Set the incentive route of a (parent) collateral, routing a share of its liquidation fines and close fees to the stakers of a pool (e.g. its collateral/STAB pool)
+        ///   - the staking component's method is called with a single bucket when distributing (`distribute_incentives`)
+        ///   - shares of 0 stop the routing, fees routed earlier can still be distributed
+        pub fn set_incentive_route(
             &mut self,
             collateral: ResourceAddress,
-            mut payment: Bucket,
-            percentage_to_take: Decimal,
-            assert_non_markable: bool,
-        ) -> (Bucket, Bucket) {
+            staking_component: ComponentAddress,
+            method_name: String,
+            fine_share: Decimal,
+            fee_share: Decimal,
+        ) {
             assert!(
-                !self.parameters.stop_force_liquidate,
-                "Not allowed to forceliquidate loans right now."
+                self.collaterals.get(&collateral).is_some(),
+                "Collateral is not accepted."
             );
-
             assert!(
-                payment.resource_address() == self.stab_manager.address(),
-                "Invalid STAB payment."
-            );
-
-            let (_first_cr, collateral_ids, _next_key) = self
-                .collateral_ratios
-                .get_mut(&collateral)
-                .unwrap()
-                .range(dec!(0)..)
-                .next()
-                .unwrap();
-            let collateral_id: NonFungibleLocalId = collateral_ids[0].clone();
-            let mut data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
-
-            self.remove_cr(
-                data.parent_address,
-                data.collateral_stab_ratio,
-                collateral_id.clone(),
+                fine_share >= dec!(0)
+                    && fine_share <= dec!(1)
+                    && fee_share >= dec!(0)
+                    && fee_share <= dec!(1),
+                "Shares need to be between 0 and 1."
             );
 
-            let cr: Decimal = self.pool_to_real(
-                data.collateral_amount,
-                data.collateral,
-                data.is_pool_unit_collateral,
-            ) / data.minted_stab;
-
-            let lcr: Decimal = self
-                .collaterals
-                .get(&collateral)
-                .unwrap()
-                .liquidation_collateral_ratio;
-
-            if assert_non_markable {
-                assert!(
-                    cr > lcr,
-                    "CR is too low. Liquidate this loan via the normal procedure."
+            let existing_route: bool = self.incentive_routes.get(&collateral).is_some();
+            if existing_route {
+                let mut route = self.incentive_routes.get_mut(&collateral).unwrap();
+                route.staking_component = staking_component;
+                route.method_name = method_name;
+                route.fine_share = fine_share;
+                route.fee_share = fee_share;
+            } else {
+                self.incentive_routes.insert(
+                    collateral,
+                    IncentiveRoute {
+                        staking_component,
+                        method_name,
+                        fine_share,
+                        fee_share,
+                        pending: HashMap::new(),
+                    },
                 );
             }
+        }
 
-            let cr_percentage: Decimal = self.collaterals.get(&collateral).unwrap().mcr * cr / lcr;
+        /// Get the incentive route of a (parent) collateral, with the routed fees not distributed yet (None if it has no route)
+        pub fn get_incentive_route(
+            &self,
+            collateral: ResourceAddress,
+        ) -> Option<IncentiveRouteInfo> {
+            self.incentive_routes
+                .get(&collateral)
+                .map(|route| IncentiveRouteInfo {
+                    staking_component: route.staking_component,
+                    method_name: route.method_name.clone(),
+                    fine_share: route.fine_share,
+                    fee_share: route.fee_share,
+                    pending: route
+                        .pending
+                        .iter()
+                        .map(|(resource, vault)| (*resource, vault.amount()))
+                        .collect(),
+                })
+        }
 
-            let (percentage_to_liquidate, payment_amount, new_stab_amount): (
-                Decimal,
-                Decimal,
-                Decimal,
-            ) = match payment.amount() > data.minted_stab {
-                true => (dec!(1), data.minted_stab, dec!(0)),
-                false => (
-                    (payment.amount() / data.minted_stab),
-                    payment.amount(),
-                    data.minted_stab - payment.amount(),
-                ),
+        /// Distribute the fees routed to the stakers of a (parent) collateral's pool, by calling its staking component with each routed resource
+        pub fn distribute_incentives(&mut self, collateral: ResourceAddress) {
+            let (staking_component, method_name, rewards): (
+                Global<AnyComponent>,
+                String,
+                Vec<Bucket>,
+            ) = {
+                let mut route = self
+                    .incentive_routes
+                    .get_mut(&collateral)
+                    .expect("Collateral has no incentive route.");
+                (
+                    Global::from(route.staking_component),
+                    route.method_name.clone(),
+                    route
+                        .pending
+                        .values_mut()
+                        .filter(|vault| vault.amount() > dec!(0))
+                        .map(|vault| vault.take_all())
+                        .collect(),
+                )
             };
 
-            let mut new_collateral_amount: Decimal = data.collateral_amount
-                - (data.collateral_amount * percentage_to_liquidate * percentage_to_take
-                    / cr_percentage);
+            for bucket in rewards {
+                Runtime::emit_event(EventIncentivesDistributed {
+                    collateral,
+                    staking_component: staking_component.address(),
+                    resource: bucket.resource_address(),
+                    amount: bucket.amount(),
+                });
+                staking_component.call_raw::<()>(&method_name, scrypto_args!(bucket));
+            }
+        }
 
-            if new_collateral_amount < dec!(0) {
-                new_collateral_amount = dec!(0);
+        /// Take the routed share of a liquidation fine or close fee paid by a loan of a (parent) collateral, and add it to the route's undistributed fees
+        fn route_incentives(
+            &mut self,
+            collateral: ResourceAddress,
+            fine: bool,
+            payment: &mut Bucket,
+        ) {
+            let rounding: WithdrawStrategy = self.rounding(false);
+            if let Some(mut route) = self.incentive_routes.get_mut(&collateral) {
+                let share: Decimal = match fine {
+                    true => route.fine_share,
+                    false => route.fee_share,
+                };
+                let routed: Bucket = payment.take_advanced(payment.amount() * share, rounding);
+                if routed.is_empty() {
+                    routed.drop_empty();
+                    return;
+                }
+                route
+                    .pending
+                    .entry(routed.resource_address())
+                    .or_insert_with(|| Vault::new(routed.resource_address()))
+                    .put(routed);
             }
+        }
+
+        /// Get the loan activity of a collateral during the last `hours` hours (at most `ACTIVITY_HOURS`), oldest first
+        ///   - hours without activity are skipped
+        pub fn get_collateral_activity(
+            &self,
+            collateral: ResourceAddress,
+            hours: i64,
+        ) -> Vec<HourlyActivity> {
+            let from: i64 = Self::current_hour_start() - (hours.min(ACTIVITY_HOURS) - 1) * 3600;
+            self.collateral_activity
+                .get(&collateral)
+                .map(|activity| {
+                    activity
+                        .iter()
+                        .filter(|hour| hour.hour_start >= from)
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
 
+        /// Set the maximum duration of a loan freeze (minutes)
+        pub fn set_max_freeze_minutes(&mut self, max_freeze_minutes: i64) {
             assert!(
-                cr_percentage > dec!(1) || percentage_to_liquidate == dec!(1),
-                "CR < 100%. Entire loan must be liquidated",
+                max_freeze_minutes > 0,
+                "Max freeze duration needs to be positive."
             );
+            self.parameters.max_freeze_minutes = max_freeze_minutes;
+        }
 
-            payment.take(payment_amount).burn();
-
-            self.update_minted_stab(
-                false,
-                data.is_pool_unit_collateral,
-                false,
-                payment_amount,
-                data.parent_address,
-                data.collateral,
-            );
+        /// Whether a loan / CDP is frozen right now
+        fn is_frozen(&self, cdp_id: &NonFungibleLocalId) -> bool {
+            self.frozen_cdps.get(cdp_id).map_or(false, |freeze| {
+                Clock::current_time_is_strictly_before(freeze.until, TimePrecision::Second)
+            })
+        }
 
-            let collateral_payment: Bucket = self.take_collateral(
-                data.collateral,
-                data.is_pool_unit_collateral,
-                data.collateral_amount - new_collateral_amount,
+        /// Calculate an auto-deleverage of a loan / CDP: the STAB to repay, the collateral to sell and the bounty (in collateral)
+        ///   - with collateral value V, debt D (in STAB), internal price I, target CR T, slippage margin s and bounty b, repaying R STAB and removing R * I * (1 + s + b) worth of collateral gives
+        ///     (V - R * I * (1 + s + b)) / ((D - R) * I) = T, so R = (T * D * I - V) / (I * (T - 1 - s - b))
+        fn calculate_deleverage(
+            &self,
+            collateral_id: &NonFungibleLocalId,
+        ) -> (Decimal, Decimal, Decimal) {
+            let settings: DeleverageSettings = self
+                .deleverage_settings
+                .get(collateral_id)
+                .expect("Auto-deleverage not enabled for this loan.")
+                .clone();
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(collateral_id);
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy."
             );
 
-            new_collateral_amount = data.collateral_amount - collateral_payment.amount();
+            let price: Decimal = self
+                .collaterals
+                .get(&receipt_data.parent_address)
+                .unwrap()
+                .usd_price;
+            let collateral_value: Decimal = receipt_data.collateral_amount * price;
+            let debt_value: Decimal = receipt_data.minted_stab * self.internal_stab_price;
+            let margin: Decimal =
+                dec!(1) + self.parameters.deleverage_slippage + self.parameters.deleverage_bounty;
 
-            self.cdp_manager.update_non_fungible_data(
-                &collateral_id,
-                "collateral_amount",
-                new_collateral_amount,
+            assert!(
+                collateral_value / debt_value < settings.trigger_cr,
+                "Trigger CR not crossed."
             );
-            self.cdp_manager.update_non_fungible_data(
-                &collateral_id,
-                "minted_stab",
-                new_stab_amount,
+            assert!(
+                collateral_value / debt_value > margin,
+                "CR too low to deleverage, the loan can only be liquidated."
             );
 
-            if percentage_to_liquidate < dec!(1) {
-                let new_cr: Decimal = self.pool_to_real(
-                    new_collateral_amount,
-                    data.collateral,
-                    data.is_pool_unit_collateral,
-                ) / new_stab_amount;
+            let stab_to_repay: Decimal = (settings.target_cr * debt_value - collateral_value)
+                / (self.internal_stab_price * (settings.target_cr - margin));
+            assert!(
+                receipt_data.minted_stab - stab_to_repay >= self.parameters.minimum_mint,
+                "Deleveraging would put the debt below the minimum mint."
+            );
 
-                self.collaterals
-                    .get_mut(&data.parent_address)
-                    .unwrap()
-                    .collateral_amount += (new_cr - data.collateral_stab_ratio) * data.minted_stab;
+            let collateral_per_stab: Decimal = self.internal_stab_price / price;
+            (
+                stab_to_repay,
+                stab_to_repay
+                    * collateral_per_stab
+                    * (dec!(1) + self.parameters.deleverage_slippage),
+                stab_to_repay * collateral_per_stab * self.parameters.deleverage_bounty,
+            )
+        }
 
-                self.insert_cr(data.parent_address, new_cr, collateral_id.clone());
+        /// Update the lifetime statistics of a loan / CDP, creating them if they don't exist yet
+        fn update_cdp_stats(
+            &mut self,
+            cdp_id: &NonFungibleLocalId,
+            update: impl FnOnce(&mut CdpStats),
+        ) {
+            if self.cdp_stats.get(cdp_id).is_none() {
+                self.cdp_stats
+                    .insert(cdp_id.clone(), self.get_cdp_stats(cdp_id.clone()));
+            }
+            let mut stats = self.cdp_stats.get_mut(cdp_id).unwrap();
+            update(&mut stats);
+        }
 
-                self.cdp_manager.update_non_fungible_data(
-                    &collateral_id,
-                    "collateral_stab_ratio",
-                    new_cr,
-                );
+        /// Store the id of a marker placed on a loan / CDP, keeping the latest `max_vector_length` markers
+        fn record_marker(&mut self, cdp_id: &NonFungibleLocalId, marker_id: u64) {
+            let max_markers: usize = self.parameters.max_vector_length as usize;
+            if self.cdp_markers.get(cdp_id).is_none() {
+                self.cdp_markers.insert(cdp_id.clone(), vec![]);
+            }
+            let mut marker_ids = self.cdp_markers.get_mut(cdp_id).unwrap();
+            marker_ids.push(marker_id);
+            if marker_ids.len() > max_markers {
+                let excess: usize = marker_ids.len() - max_markers;
+                marker_ids.drain(..excess);
+            }
+        }
 
-                data.collateral_stab_ratio = new_cr;
-                data.collateral_amount = new_collateral_amount;
-                data.minted_stab = new_stab_amount;
+        /// Calculate a yield harvest of a loan / CDP: the pool units to sell, the bounty (in pool units), the minimum STAB the sold pool units should return and the maximum STAB that can be repaid
+        ///   - the harvested pool units are worth the redemption rate growth: collateral amount * (1 - harvest rate / current rate)
+        ///   - if the pool units to sell are worth more than the debt that can be repaid without going below the minimum mint, only that much is harvested
+        fn calculate_harvest(
+            &self,
+            collateral_id: &NonFungibleLocalId,
+        ) -> (Decimal, Decimal, Decimal, Decimal) {
+            let harvest_rate: Decimal = *self
+                .harvest_rates
+                .get(collateral_id)
+                .expect("Yield harvesting not enabled for this loan.");
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(collateral_id);
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy."
+            );
+            assert!(
+                receipt_data.is_pool_unit_collateral,
+                "Yield harvesting is only available for pool unit collateral."
+            );
 
-                Runtime::emit_event(EventUpdateCdp {
-                    cdp: data,
-                    cdp_id: collateral_id,
-                });
-            } else {
-                self.cdp_manager.update_non_fungible_data(
-                    &collateral_id,
-                    "status",
-                    CdpStatus::ForceLiquidated,
-                );
+            let rate: Decimal = self.redemption_rate(receipt_data.collateral);
+            assert!(rate > harvest_rate, "No yield to harvest.");
 
-                self.collaterals
-                    .get_mut(&data.parent_address)
-                    .unwrap()
-                    .collateral_amount -= data.collateral_stab_ratio * data.minted_stab;
+            let max_repayment: Decimal = (receipt_data.minted_stab - self.parameters.minimum_mint)
+                * (dec!(1) + self.parameters.close_fee);
+            assert!(
+                max_repayment > dec!(0),
+                "No debt to repay above the minimum mint."
+            );
 
-                Runtime::emit_event(EventLiquidateCdp {
-                    cdp_id: collateral_id,
-                });
+            let mut harvested: Decimal =
+                receipt_data.collateral_amount * (dec!(1) - harvest_rate / rate);
+            let price: Decimal = self
+                .collaterals
+                .get(&receipt_data.parent_address)
+                .unwrap()
+                .usd_price;
+            let stab_per_unit: Decimal = rate * price / self.internal_stab_price;
+            let harvest_value: Decimal =
+                harvested * (dec!(1) - self.parameters.harvest_bounty) * stab_per_unit;
+            if harvest_value > max_repayment {
+                harvested = harvested * max_repayment / harvest_value;
             }
 
-            (collateral_payment, payment)
+            let bounty: Decimal = harvested * self.parameters.harvest_bounty;
+            let min_stab: Decimal = (harvested - bounty)
+                * stab_per_unit
+                * (dec!(1) - self.parameters.deleverage_slippage);
+
+            (harvested - bounty, bounty, min_stab, max_repayment)
         }
 
-        /// Force mint STAB by adding collateral to a loan / CDP
+        /// Get the borrow reward position of a loan / CDP, a loan without one hasn't been settled since its collateral got a reward index
+        fn get_borrow_reward_position(&self, cdp_id: &NonFungibleLocalId) -> BorrowRewardPosition {
+            match self.borrow_reward_positions.get(cdp_id) {
+                Some(position) => position.clone(),
+                None => BorrowRewardPosition {
+                    index: dec!(0),
+                    accrued: dec!(0),
+                },
+            }
+        }
+
+        /// Get the rewards emitted per STAB borrowed (at a weight of 1) between two moments, following the emission schedule
+        fn borrow_rewards_emitted(&self, from: Instant, to: Instant) -> Decimal {
+            let mut emitted: Decimal = dec!(0);
+            for period in self.borrow_rewards.schedule.iter() {
+                let start: i64 = period
+                    .start
+                    .seconds_since_unix_epoch
+                    .max(from.seconds_since_unix_epoch);
+                let end: i64 = period
+                    .end
+                    .seconds_since_unix_epoch
+                    .min(to.seconds_since_unix_epoch);
+                if end > start {
+                    emitted += Decimal::from(end - start) / dec!(60) * period.rewards_per_minute;
+                }
+            }
+            emitted
+        }
+
+        /// Get the up to date reward index of a collateral, if it has one
+        fn current_borrow_reward_index(&self, collateral: ResourceAddress) -> Option<Decimal> {
+            self.borrow_rewards.indices.get(&collateral).map(|index| {
+                index.index
+                    + index.weight
+                        * self.borrow_rewards_emitted(
+                            index.last_update,
+                            Clock::current_time_rounded_to_seconds(),
+                        )
+            })
+        }
+
+        /// Bring the stored reward index of a collateral up to date, needs to happen before its weight or the schedule changes
+        fn accrue_borrow_reward_index(&mut self, collateral: ResourceAddress) {
+            if let Some(index) = self.current_borrow_reward_index(collateral) {
+                let reward_index = self.borrow_rewards.indices.get_mut(&collateral).unwrap();
+                reward_index.index = index;
+                reward_index.last_update = Clock::current_time_rounded_to_seconds();
+            }
+        }
+
+        /// Settle the borrow rewards of a loan / CDP, needs to happen before its debt changes
         ///
         /// # Input
-        /// - `collateral`: The collateral to add
-        /// - `payment`: The STAB tokens to pay back
-        /// - `percentage_to_supply`: The percentage of the collateral value to supply (if > 1, the borrower will profit off the minting)
+        /// - `cdp_id`: The id of the loan
+        /// - `collateral`: The (parent) collateral of the loan
+        /// - `debt`: The debt of the loan since its last settlement
         ///
         /// # Output
-        /// - The minted STAB in a `Bucket`
-        /// - The leftover collateral in a `Bucket`
+        /// - None
         ///
         /// # Logic
-        /// - Check if it is allowed to force mint right now
-        /// - Get the CDP with highest collateral ratio for the chosen collateral
-        /// - Check if the collateral is compatible
-        /// - Calculate minimum allowed collateral ratio
-        /// - Get collateral price
-        /// - Calculate constant k, which is the collateral needed for minting 1 STAB
-        /// - Calculate the max addition of collateral that can be supplied (see code for calculation and explanation)
-        /// - If too much collateral is supplied, remove the excess and put in bucket to return (handle potential rounding errors for strange divisilibity assets)
-        /// - Remove the current collateral ratio from the AvlTree
-        /// - Calculate newly minted STAB, new collateral amount and new collateral ratio
-        /// - Update circulating STAB
-        /// - Update the CDP receipt
-        /// - Insert the new collateral ratio into the AvlTree
-        /// - Mint the STAB
-        /// - Return the minted STAB and the leftover collateral
-        pub fn force_mint(
+        /// - Skip collaterals without a reward index
+        /// - Credit the debt times the growth of the collateral's reward index since the last settlement
+        /// - Store the current reward index as the loan's last seen index
+        fn settle_borrow_rewards(
             &mut self,
+            cdp_id: &NonFungibleLocalId,
             collateral: ResourceAddress,
-            mut payment: Bucket,
-            percentage_to_supply: Decimal,
-        ) -> (Bucket, Option<Bucket>) {
-            assert!(
-                !self.parameters.stop_force_mint,
-                "Not allowed to force mint right now."
+            debt: Decimal,
+        ) {
+            if let Some(index) = self.current_borrow_reward_index(collateral) {
+                self.accrue_borrow_reward_index(collateral);
+                let mut position: BorrowRewardPosition = self.get_borrow_reward_position(cdp_id);
+                position.accrued += debt * (index - position.index);
+                position.index = index;
+                self.borrow_reward_positions
+                    .insert(cdp_id.clone(), position);
+            }
+        }
+
+        /// Set the collateral amount of a healthy loan / CDP, updating its CR everywhere it's stored, and return the new CR
+        fn set_cdp_collateral_amount(
+            &mut self,
+            cdp_id: &NonFungibleLocalId,
+            mut receipt_data: Cdp,
+            new_collateral_amount: Decimal,
+        ) -> Decimal {
+            self.remove_cr(
+                receipt_data.parent_address,
+                receipt_data.collateral_stab_ratio,
+                cdp_id.clone(),
             );
 
-            let mut data_option: Option<Cdp> = None;
-            let mut collateral_id: NonFungibleLocalId = NonFungibleLocalId::integer(0);
-            let mut return_bucket: Option<Bucket> = None;
+            let cr: Decimal = self.pool_to_real(
+                new_collateral_amount,
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+            ) / receipt_data.minted_stab;
 
-            {
-                let collateral_ratios = self.collateral_ratios.get_mut(&collateral).unwrap();
-                let range = collateral_ratios.range_back(
-                    dec!(0)..(self.collaterals.get(&collateral).unwrap().highest_cr + dec!(1)),
-                );
+            self.collaterals
+                .get_mut(&receipt_data.parent_address)
+                .unwrap()
+                .collateral_amount +=
+                (cr - receipt_data.collateral_stab_ratio) * receipt_data.minted_stab;
 
-                'outer_loop: for (_cr, collateral_ids, _next_key) in range {
-                    for found_collateral_id in collateral_ids {
-                        data_option = Some(self.cdp_manager.get_non_fungible_data(&found_collateral_id));
-                        if data_option.as_ref().unwrap().collateral == payment.resource_address() {
-                            collateral_id = found_collateral_id.clone();
-                            break 'outer_loop;
-                        }
-                    }
-                }
-            }
+            self.insert_cr(receipt_data.parent_address, cr, cdp_id.clone());
 
-            let mut data = data_option.expect("No suitable mints found");
-            assert!(
-                data.collateral == payment.resource_address(),
-                "Can only force mint other collaterals right now."
+            self.cdp_manager
+                .update_non_fungible_data(cdp_id, "collateral_stab_ratio", cr);
+            self.cdp_manager.update_non_fungible_data(
+                cdp_id,
+                "collateral_amount",
+                new_collateral_amount,
             );
 
-            let pool_to_real: Decimal =
-                self.pool_to_real(dec!(1), data.collateral, data.is_pool_unit_collateral);
+            receipt_data.collateral_stab_ratio = cr;
+            receipt_data.collateral_amount = new_collateral_amount;
+            Runtime::emit_event(EventUpdateCdp {
+                cdp: receipt_data,
+                cdp_id: cdp_id.clone(),
+            });
 
-            let min_collateral_ratio: Decimal = self.parameters.force_mint_cr_multiplier
-                * self
-                    .collaterals
-                    .get(&collateral)
-                    .unwrap()
-                    .liquidation_collateral_ratio;
+            cr
+        }
 
-            let collateral_price: Decimal = self.collaterals.get(&collateral).unwrap().usd_price;
+        /// Update the loan activity of a collateral in the current hour, dropping hours older than `ACTIVITY_HOURS`
+        fn record_activity(
+            &mut self,
+            collateral: ResourceAddress,
+            update: impl FnOnce(&mut HourlyActivity),
+        ) {
+            let hour_start: i64 = Self::current_hour_start();
 
-            let k: Decimal = (self.internal_stab_price) / (pool_to_real * collateral_price)
-                * percentage_to_supply;
+            if self.collateral_activity.get(&collateral).is_none() {
+                self.collateral_activity.insert(collateral, vec![]);
+            }
+            let mut activity = self.collateral_activity.get_mut(&collateral).unwrap();
+            activity.retain(|hour| hour.hour_start > hour_start - ACTIVITY_HOURS * 3600);
+            if activity.last().map(|hour| hour.hour_start) != Some(hour_start) {
+                activity.push(HourlyActivity {
+                    hour_start,
+                    opens: 0,
+                    closes: 0,
+                    liquidations: 0,
+                });
+            }
+            update(activity.last_mut().unwrap());
+        }
 
-            //we now need to calculate maximum amount of collateral that can be supplied: max_addition
-            //we can do this by first claiming: collateral_amount / stab_amount = min_collateral_ratio (1)
-            //collateral_amount = (initial_collateral_amount + max_col_addition) * pool_to_real (2)
-            //stab_amount = initial_stab_amount + max_col_addition / k (3)
-            //filling in (2) and (3) in (1) gives us an equation of the form: ((c + a) * p) / (s + a / k) = m (4)
-            //solving (4) for max_col_addition (abbreviated 'a') gives: a = (k * (c * p - m * s)) / (m - k * p)
-            //which translates to:
+        /// Get the start of the current hour (seconds since unix epoch)
+        fn current_hour_start() -> i64 {
+            let now: i64 = Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch;
+            now - now.rem_euclid(3600)
+        }
 
-            let max_addition: Decimal = (k
-                * (data.collateral_amount * pool_to_real
-                    - min_collateral_ratio * data.minted_stab))
-                / (min_collateral_ratio - k * pool_to_real);
+        /// Assert a loan / CDP isn't frozen
+        fn check_not_frozen(&self, cdp_id: &NonFungibleLocalId) {
+            assert!(!self.is_frozen(cdp_id), "Loan is frozen.");
+        }
 
-            if payment.amount() > max_addition {
-                return_bucket = Some(payment.take_advanced(
-                    payment.amount() - max_addition,
-                    WithdrawStrategy::Rounded(RoundingMode::AwayFromZero),
-                ));
-            }
+        /// Add collateral to a loan / CDP
+        ///
+        /// # Input
+        /// - `collateral_id`: The CDP receipt
+        /// - `collateral`: The collateral to add
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check if the loan is healthy or marked
+        /// - Check if the collateral is compatible
+        /// - Remove the collateral ratio from the AvlTree
+        /// - Calculate new collateral ratio
+        /// - Update the collateral amount in the CDP receipt
+        /// - Check if the new collateral ratio is high enough
+        /// - Insert new collateral ratio into AvlTree
+        /// - Store the collateral in the correct vault
+        /// - Update the CDP receipt
+        /// - If the loan was marked, update the marker receipt
+        pub fn top_up_cdp(&mut self, collateral_id: NonFungibleLocalId, collateral: Bucket) {
+            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let new_collateral_amount = receipt_data.collateral_amount + collateral.amount();
 
-            self.remove_cr(
-                data.parent_address,
-                data.collateral_stab_ratio,
-                collateral_id.clone(),
+            assert!(
+                receipt_data.status == CdpStatus::Healthy
+                    || receipt_data.status == CdpStatus::Marked,
+                "Loan not healthy or marked."
+            );
+            assert!(
+                receipt_data.collateral == collateral.resource_address(),
+                "Incompatible token."
             );
 
-            let new_minted_stab: Decimal = data.minted_stab + payment.amount() / k;
-            let new_collateral_amount: Decimal = data.collateral_amount + payment.amount();
+            if receipt_data.status == CdpStatus::Healthy {
+                self.remove_cr(
+                    receipt_data.parent_address,
+                    receipt_data.collateral_stab_ratio,
+                    collateral_id.clone(),
+                );
+            }
 
-            let new_cr: Decimal = self.pool_to_real(
+            let cr: Decimal = self.pool_to_real(
                 new_collateral_amount,
-                data.collateral,
-                data.is_pool_unit_collateral,
-            ) / new_minted_stab;
+                collateral.resource_address(),
+                receipt_data.is_pool_unit_collateral,
+            ) / receipt_data.minted_stab;
 
             self.collaterals
-                .get_mut(&data.parent_address)
+                .get_mut(&receipt_data.parent_address)
                 .unwrap()
-                .collateral_amount += (new_cr - data.collateral_stab_ratio) * data.minted_stab;
+                .collateral_amount +=
+                (cr - receipt_data.collateral_stab_ratio) * receipt_data.minted_stab;
 
-            self.cdp_manager.update_non_fungible_data(
-                &collateral_id,
-                "minted_stab",
-                new_minted_stab,
+            assert!(
+                cr > self
+                    .collaterals
+                    .get(&receipt_data.parent_address)
+                    .unwrap()
+                    .liquidation_collateral_ratio,
+                "Not enough collateral added to save this loan."
+            );
+
+            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
+
+            self.put_collateral(
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+                collateral,
             );
+
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
             self.cdp_manager.update_non_fungible_data(
                 &collateral_id,
                 "collateral_amount",
                 new_collateral_amount,
             );
-            self.cdp_manager.update_non_fungible_data(
-                &collateral_id,
-                "collateral_stab_ratio",
-                new_cr,
-            );
-
-            self.insert_cr(data.parent_address, new_cr, collateral_id.clone());
-
-            let stab_tokens: Bucket = self.stab_manager.mint(payment.amount() / k);
-
-            self.update_minted_stab(
-                false,
-                data.is_pool_unit_collateral,
-                false,
-                stab_tokens.amount(),
-                data.parent_address,
-                data.collateral,
-            );
-
-            self.put_collateral(data.collateral, data.is_pool_unit_collateral, payment);
 
-            data.collateral_stab_ratio = new_cr;
-            data.collateral_amount = new_collateral_amount;
-            data.minted_stab = new_minted_stab;
+            if receipt_data.status == CdpStatus::Marked {
+                let marker_data: CdpMarker = self
+                    .cdp_marker_manager
+                    .get_non_fungible_data(&NonFungibleLocalId::integer(receipt_data.marker_id));
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "status",
+                    CdpStatus::Healthy,
+                );
+                self.cdp_marker_manager.update_non_fungible_data(
+                    &NonFungibleLocalId::integer(receipt_data.marker_id),
+                    "used",
+                    true,
+                );
+                self.marked_cdps.remove(&marker_data.marker_placing);
+                self.marked_cdps_active -= 1;
+                self.update_cdp_stats(&collateral_id, |stats| stats.times_saved += 1);
+                receipt_data.status = CdpStatus::Healthy;
+            }
 
+            receipt_data.collateral_stab_ratio = cr;
+            receipt_data.collateral_amount = new_collateral_amount;
             Runtime::emit_event(EventUpdateCdp {
-                cdp: data,
+                cdp: receipt_data,
                 cdp_id: collateral_id,
             });
-
-            (stab_tokens, return_bucket)
         }
 
-        /// Liquidate a marked loan / CDP, using a marker receipt
+        /// Remove collateral from a loan / CDP
         ///
         /// # Input
-        /// - `marker_id`: The marker receipt id
-        /// - `payment`: The STAB tokens to pay back
+        /// - `collateral_id`: The CDP receipt
+        /// - `amount`: The amount of collateral to remove
         ///
-        /// # Output, depends on outcome:
-        /// 1: liquidation successful
-        /// - The collateral reward
-        /// - The leftover STAB
-        /// - A liquidation receipt
-        /// 2: liquidation unsuccessful (because the loan was saved):
-        /// - The STAB tokens
+        /// # Output
+        /// - The removed collateral (empty if the withdrawal throttle queued it)
+        /// - The withdrawal claim, if the withdrawal throttle queued the collateral
+        ///
+        /// # Logic
+        /// - Check if the loan is healthy
+        /// - Remove the collateral ratio from the AvlTree
+        /// - Calculate new collateral ratio
+        /// - Check if the new collateral ratio is high enough
+        /// - Insert new collateral ratio into AvlTree
+        /// - Retrieve the to-be returned collateral from the correct vault
+        ///   - Queue it if the withdrawal throttle is exceeded
+        /// - Update the CDP receipt
+        /// - Return the removed collateral and the withdrawal claim
+        pub fn remove_collateral(
+            &mut self,
+            collateral_id: NonFungibleLocalId,
+            amount: Decimal,
+        ) -> (Bucket, Option<Bucket>) {
+            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let new_collateral_amount = receipt_data.collateral_amount - amount;
+            self.check_not_frozen(&collateral_id);
+
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy. Save it first."
+            );
+
+            assert!(
+                !self.is_paused(
+                    PausableAction::RemoveCollateral,
+                    Some(receipt_data.collateral)
+                ),
+                "Not allowed to close loans / remove collateral right now."
+            );
+
+            self.remove_cr(
+                receipt_data.parent_address,
+                receipt_data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
+
+            let cr: Decimal = self.pool_to_real(
+                new_collateral_amount,
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+            ) / receipt_data.minted_stab;
+
+            self.collaterals
+                .get_mut(&receipt_data.parent_address)
+                .unwrap()
+                .collateral_amount +=
+                (cr - receipt_data.collateral_stab_ratio) * receipt_data.minted_stab;
+
+            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
+
+            assert!(
+                cr > self
+                    .collaterals
+                    .get_mut(&receipt_data.parent_address)
+                    .unwrap()
+                    .liquidation_collateral_ratio,
+                "Removal would put the CR below MCR."
+            );
+
+            let removed_collateral: Bucket = self.take_collateral(
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+                amount,
+            );
+            let (removed_collateral, claim): (Bucket, Option<Bucket>) =
+                self.throttle_withdrawal(removed_collateral, receipt_data.is_pool_unit_collateral);
+
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "collateral_amount",
+                new_collateral_amount,
+            );
+
+            receipt_data.collateral_stab_ratio = cr;
+            receipt_data.collateral_amount = new_collateral_amount;
+            Runtime::emit_event(EventUpdateCdp {
+                cdp: receipt_data,
+                cdp_id: collateral_id,
+            });
+
+            (removed_collateral, claim)
+        }
+
+        /// Move collateral from one loan / CDP to another, without it leaving the protocol
+        ///
+        /// # Input
+        /// - `from_id`: The CDP receipt of the loan to take the collateral from
+        /// - `to_id`: The CDP receipt of the loan to add the collateral to
+        /// - `amount`: The amount of collateral to move
+        ///
+        /// # Output
         /// - None
-        /// - A liquidation receipt (with saved status)
         ///
         /// # Logic
-        /// - Get the marker receipt and data
-        /// - Get the CDP data according to the marker receipt
-        /// - Try to liquidate the CDP, using the try_liquidate method (see that method for more details)
-        pub fn liquidate_position_with_marker(
+        /// - Check both loans are healthy, not frozen and use the same collateral
+        /// - Update the collateral amount and CR of both loans (the collateral stays in the same vault, so no withdrawal throttle or close fee applies, and the minted STAB doesn't change, so no share check is needed)
+        /// - Check the CR of the loan the collateral is taken from is still above the liquidation CR
+        pub fn move_collateral(
             &mut self,
-            marker_id: NonFungibleLocalId,
-            payment: Bucket,
-        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
+            from_id: NonFungibleLocalId,
+            to_id: NonFungibleLocalId,
+            amount: Decimal,
+        ) {
+            assert!(from_id != to_id, "Can't move collateral to the same loan.");
+            assert!(amount > dec!(0), "Amount needs to be positive.");
+            self.check_not_frozen(&from_id);
+            self.check_not_frozen(&to_id);
+
+            let from_data: Cdp = self.cdp_manager.get_non_fungible_data(&from_id);
+            let to_data: Cdp = self.cdp_manager.get_non_fungible_data(&to_id);
+
+            assert!(
+                from_data.status == CdpStatus::Healthy && to_data.status == CdpStatus::Healthy,
+                "Loan not healthy. Save it first."
+            );
+            assert!(
+                from_data.collateral == to_data.collateral,
+                "Loans use different collaterals."
+            );
+            assert!(
+                amount <= from_data.collateral_amount,
+                "Not enough collateral in loan."
+            );
+            assert!(
+                !self.is_paused(PausableAction::RemoveCollateral, Some(from_data.collateral)),
+                "Not allowed to close loans / remove collateral right now."
+            );
+
+            let from_cr: Decimal = self.set_cdp_collateral_amount(
+                &from_id,
+                from_data.clone(),
+                from_data.collateral_amount - amount,
+            );
+            let to_collateral_amount: Decimal = to_data.collateral_amount + amount;
+            self.set_cdp_collateral_amount(&to_id, to_data, to_collateral_amount);
+
+            assert!(
+                from_cr
+                    > self
+                        .collaterals
+                        .get(&from_data.parent_address)
+                        .unwrap()
+                        .liquidation_collateral_ratio,
+                "Move would put the CR below MCR."
+            );
+        }
+
+        /// Partially close a loan / CDP (pay off part of the debt), optionally withdrawing collateral in the same call
+        ///
+        /// # Input
+        /// - `collateral_id`: The CDP receipt
+        /// - `repayment`: The STAB tokens to pay back
+        /// - `withdrawal`: The amount of collateral to withdraw, if any
+        ///
+        /// # Output
+        /// - The withdrawn collateral, if any (or all collateral if the loan is closed, empty if the withdrawal throttle queued it)
+        /// - The leftover payment, if the loan is closed
+        /// - The withdrawal claim, if the withdrawal throttle queued the collateral
+        ///
+        /// # Logic
+        /// - Check if the STAB payment is valid
+        /// - If the repayment > debt (including the close fee), close the loan and return leftover collateral and leftover payment (the withdrawal is ignored)
+        /// - Put the close fee part of the repayment in the stability reserve
+        /// - Check if borrowed amount is still above minimum borrow
+        /// - Check if the loan is healthy or marked
+        /// - Check the withdrawal is valid and removing collateral isn't paused
+        /// - Remove the collateral ratio from the AvlTree if not marked
+        /// - Calculate new collateral ratio, from the remaining debt and remaining collateral
+        /// - Burn the paid back STAB
+        /// - Insert new collateral ratio into AvlTree
+        /// - Check if the new collateral ratio is high enough (one check covering both the repayment and withdrawal)
+        /// - Retrieve the withdrawn collateral from the correct vault
+        /// - Update the CDP receipt
+        /// - If the loan was marked, update the marker receipt
+        pub fn partial_close_cdp(
+            &mut self,
+            collateral_id: NonFungibleLocalId,
+            mut repayment: Bucket,
+            withdrawal: Option<Decimal>,
+        ) -> (Option<Bucket>, Option<Bucket>, Option<Bucket>) {
+            assert!(
+                repayment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+
+            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            self.check_not_frozen(&collateral_id);
+
+            assert!(
+                !self.is_paused(
+                    PausableAction::PartialCloseCdp,
+                    Some(receipt_data.collateral)
+                ),
+                "Not allowed to close loans / remove collateral right now."
+            );
+            if repayment.amount() > receipt_data.minted_stab * (dec!(1) + self.parameters.close_fee)
+            {
+                let (collateral, leftover_payment, claim): (Bucket, Bucket, Option<Bucket>) =
+                    self.close_cdp(collateral_id, repayment);
+                return (Some(collateral), Some(leftover_payment), claim);
+            }
+
+            if self.parameters.close_fee > dec!(0) {
+                let mut close_fee: Bucket = repayment.take_advanced(
+                    repayment.amount() * self.parameters.close_fee
+                        / (dec!(1) + self.parameters.close_fee),
+                    self.rounding(true),
+                );
+                Runtime::emit_event(EventCloseFee {
+                    cdp_id: collateral_id.clone(),
+                    amount: close_fee.amount(),
+                });
+                self.route_incentives(receipt_data.parent_address, false, &mut close_fee);
+                self.stability_reserve.put(close_fee);
+            }
+
+            let new_stab_amount = receipt_data.minted_stab - repayment.amount();
+
+            assert!(
+                new_stab_amount >= self.parameters.minimum_mint,
+                "Resulting borrowed STAB needs to be above minimum mint."
+            );
+
+            assert!(
+                receipt_data.status == CdpStatus::Healthy
+                    || receipt_data.status == CdpStatus::Marked,
+                "Loan not healthy or marked."
+            );
+
+            let withdrawal: Decimal = withdrawal.unwrap_or(dec!(0));
+            assert!(
+                withdrawal >= dec!(0) && withdrawal < receipt_data.collateral_amount,
+                "Withdrawal can't be negative and needs to be below the loan's collateral."
+            );
+            if withdrawal > dec!(0) {
+                assert!(
+                    !self.is_paused(
+                        PausableAction::RemoveCollateral,
+                        Some(receipt_data.collateral)
+                    ),
+                    "Not allowed to close loans / remove collateral right now."
+                );
+            }
+            let new_collateral_amount: Decimal = receipt_data.collateral_amount - withdrawal;
+
+            if receipt_data.status == CdpStatus::Healthy {
+                self.remove_cr(
+                    receipt_data.parent_address,
+                    receipt_data.collateral_stab_ratio,
+                    collateral_id.clone(),
+                );
+            }
+
+            let cr: Decimal = self.pool_to_real(
+                new_collateral_amount,
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+            ) / new_stab_amount;
+
+            self.settle_borrow_rewards(
+                &collateral_id,
+                receipt_data.parent_address,
+                receipt_data.minted_stab,
+            );
+            self.update_minted_stab(
+                false,
+                receipt_data.is_pool_unit_collateral,
+                false,
+                repayment.amount(),
+                receipt_data.parent_address,
+                receipt_data.collateral,
+            );
+
+            self.update_cdp_stats(&collateral_id, |stats| {
+                stats.total_repaid += repayment.amount()
+            });
+            repayment.burn();
+
+            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
+
+            assert!(
+                cr > self
+                    .collaterals
+                    .get_mut(&receipt_data.parent_address)
+                    .unwrap()
+                    .liquidation_collateral_ratio,
+                "CR below MCR."
+            );
+
+            let (withdrawn_collateral, claim): (Option<Bucket>, Option<Bucket>) =
+                match withdrawal > dec!(0) {
+                    true => {
+                        self.collaterals
+                            .get_mut(&receipt_data.parent_address)
+                            .unwrap()
+                            .collateral_amount -= self.pool_to_real(
+                            withdrawal,
+                            receipt_data.collateral,
+                            receipt_data.is_pool_unit_collateral,
+                        );
+                        let removed_collateral: Bucket = self.take_collateral(
+                            receipt_data.collateral,
+                            receipt_data.is_pool_unit_collateral,
+                            withdrawal,
+                        );
+                        self.cdp_manager.update_non_fungible_data(
+                            &collateral_id,
+                            "collateral_amount",
+                            new_collateral_amount,
+                        );
+                        let (removed_collateral, claim): (Bucket, Option<Bucket>) = self
+                            .throttle_withdrawal(
+                                removed_collateral,
+                                receipt_data.is_pool_unit_collateral,
+                            );
+                        (Some(removed_collateral), claim)
+                    }
+                    false => (None, None),
+                };
+
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "minted_stab",
+                new_stab_amount,
+            );
+
+            if receipt_data.status == CdpStatus::Marked {
+                let marker_data: CdpMarker = self
+                    .cdp_marker_manager
+                    .get_non_fungible_data(&NonFungibleLocalId::integer(receipt_data.marker_id));
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "status",
+                    CdpStatus::Healthy,
+                );
+                self.cdp_marker_manager.update_non_fungible_data(
+                    &NonFungibleLocalId::integer(receipt_data.marker_id),
+                    "used",
+                    true,
+                );
+                self.marked_cdps.remove(&marker_data.marker_placing);
+                self.marked_cdps_active -= 1;
+                self.update_cdp_stats(&collateral_id, |stats| stats.times_saved += 1);
+                receipt_data.status = CdpStatus::Healthy;
+            }
+
+            receipt_data.minted_stab = new_stab_amount;
+            receipt_data.collateral_amount = new_collateral_amount;
+            receipt_data.collateral_stab_ratio = cr;
+            Runtime::emit_event(EventUpdateCdp {
+                cdp: receipt_data,
+                cdp_id: collateral_id,
+            });
+
+            (withdrawn_collateral, None, claim)
+        }
+
+        /// Borrow more STAB by adding to the loan / CDP
+        ///
+        /// # Input
+        /// - `collateral_id`: The CDP receipt
+        /// - `amount`: The amount of STAB to mint / borrow
+        ///
+        /// # Output
+        /// - The minted STAB in a `Bucket`
+        ///
+        /// # Logic
+        /// - Check if the loan is healthy
+        /// - Remove the collateral ratio from the AvlTree
+        /// - Calculate new collateral ratio
+        /// - Update the minted STAB
+        /// - Insert new collateral ratio into AvlTree
+        /// - Check if the new collateral ratio is high enough
+        /// - Update the CDP receipt
+        /// - Mint the STAB and return it
+        pub fn borrow_more(
+            &mut self,
+            collateral_id: NonFungibleLocalId,
+            amount: Decimal,
+        ) -> Bucket {
+            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let new_stab_amount = receipt_data.minted_stab + amount;
+            self.check_not_frozen(&collateral_id);
+
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy. Save it first."
+            );
+
+            assert!(
+                !self.is_paused(PausableAction::BorrowMore, Some(receipt_data.collateral)),
+                "Not allowed to open loans right now."
+            );
+
+            self.check_max_cdp_debt(receipt_data.parent_address, new_stab_amount);
+
+            self.remove_cr(
+                receipt_data.parent_address,
+                receipt_data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
+
+            let cr: Decimal = self.pool_to_real(
+                receipt_data.collateral_amount,
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+            ) / new_stab_amount;
+
+            self.settle_borrow_rewards(
+                &collateral_id,
+                receipt_data.parent_address,
+                receipt_data.minted_stab,
+            );
+            self.update_minted_stab(
+                true,
+                receipt_data.is_pool_unit_collateral,
+                true,
+                amount,
+                receipt_data.parent_address,
+                receipt_data.collateral,
+            );
+
+            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
+
+            assert!(
+                cr > self
+                    .collaterals
+                    .get_mut(&receipt_data.parent_address)
+                    .unwrap()
+                    .liquidation_collateral_ratio,
+                "Removal would put the CR below MCR."
+            );
+
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "minted_stab",
+                new_stab_amount,
+            );
+            self.update_cdp_stats(&collateral_id, |stats| stats.total_borrowed += amount);
+
+            receipt_data.minted_stab = new_stab_amount;
+            receipt_data.collateral_stab_ratio = cr;
+            Runtime::emit_event(EventUpdateCdp {
+                cdp: receipt_data,
+                cdp_id: collateral_id,
+            });
+
+            self.stab_manager.mint(amount)
+        }
+
+        /// Migrate a loan / CDP between a pool unit and its parent collateral, keeping its debt and receipt
+        ///
+        /// # Input
+        /// - `collateral_id`: The CDP receipt
+        /// - `target`: The collateral to migrate to, either the parent collateral or one of its pool units
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check if the loan is healthy and the migration is possible
+        ///     - the auto-top-up buffer needs to be empty, as it can only hold the old collateral
+        /// - Remove the collateral ratio from the AvlTree
+        /// - Convert the collateral
+        ///     - pool unit -> parent: redeem through the OneResourcePool (redeeming is public) or the adapter's `redeem(Bucket) -> Bucket` method
+        ///         - not possible for LSUs, as unstaking isn't instant
+        ///     - parent -> pool unit: stake through the validator, or contribute through the adapter's `contribute(Bucket) -> Bucket` method
+        ///         - contributing to a OneResourcePool needs its pool manager role, which this component doesn't hold, so only pool units with an adapter (holding the role) can be migrated to
+        /// - Move the minted STAB from the old to the new collateral and check the new collateral's share
+        /// - Calculate new collateral ratio and check if it's high enough: at least the MCR (like opening a loan), and above the liquidation collateral ratio
+        /// - Insert new collateral ratio into AvlTree
+        /// - Put the converted collateral in the correct vault
+        /// - Update the CDP receipt
+        /// - Clear the opt-ins that don't carry over to the new collateral
+        ///     - yield harvesting, as the harvest rate is the redemption rate of the old pool unit
+        ///     - auto-deleverage, when migrating to a pool unit, as it isn't available for pool unit collateral
+        pub fn migrate_cdp(&mut self, collateral_id: NonFungibleLocalId, target: ResourceAddress) {
+            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            self.check_not_frozen(&collateral_id);
+
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy. Save it first."
+            );
+            assert!(
+                !self.is_paused(PausableAction::MigrateCdp, Some(receipt_data.collateral))
+                    && !self.is_paused(PausableAction::MigrateCdp, Some(target)),
+                "Not allowed to migrate loans right now."
+            );
+            assert!(
+                receipt_data.collateral != target,
+                "Loan already uses this collateral."
+            );
+            assert!(
+                self.get_buffer(collateral_id.clone()) == dec!(0),
+                "Withdraw the auto-top-up buffer first."
+            );
+
+            let to_pool_unit: bool = target != receipt_data.parent_address;
+            if to_pool_unit {
+                assert!(
+                    !receipt_data.is_pool_unit_collateral,
+                    "Migrate to the parent collateral first."
+                );
+                let pool_unit = self
+                    .pool_units
+                    .get(&target)
+                    .expect("Not a pool unit of this loan's collateral.");
+                assert!(
+                    pool_unit.parent_address == receipt_data.parent_address,
+                    "Not a pool unit of this loan's collateral."
+                );
+                assert!(pool_unit.accepted, "Pool unit not accepted.");
+                assert!(
+                    pool_unit.lsu || pool_unit.adapter.is_some(),
+                    "Contributing to this pool unit needs an adapter."
+                );
+            } else {
+                assert!(
+                    self.collaterals.get(&target).unwrap().accepted,
+                    "Collateral not accepted."
+                );
+            }
+
+            self.remove_cr(
+                receipt_data.parent_address,
+                receipt_data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
+
+            let old_collateral: Bucket = self.take_collateral(
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+                receipt_data.collateral_amount,
+            );
+
+            let new_collateral: Bucket = if to_pool_unit {
+                self.convert_to_pool_unit(target, old_collateral)
+            } else {
+                let pool_unit = self.pool_units.get(&receipt_data.collateral).unwrap();
+                assert!(!pool_unit.lsu, "LSUs can't be unstaked instantly.");
+                if let Some(adapter) = pool_unit.adapter {
+                    adapter.call("redeem", &(old_collateral,))
+                } else {
+                    pool_unit.one_resource_pool.unwrap().redeem(old_collateral)
+                }
+            };
+
+            assert!(
+                new_collateral.resource_address() == target,
+                "Migration returned the wrong collateral."
+            );
+            let new_collateral_amount: Decimal = new_collateral.amount();
+
+            self.update_minted_stab(
+                false,
+                receipt_data.is_pool_unit_collateral,
+                false,
+                receipt_data.minted_stab,
+                receipt_data.parent_address,
+                receipt_data.collateral,
+            );
+            self.update_minted_stab(
+                true,
+                to_pool_unit,
+                true,
+                receipt_data.minted_stab,
+                receipt_data.parent_address,
+                target,
+            );
+
+            let cr: Decimal = self.pool_to_real(new_collateral_amount, target, to_pool_unit)
+                / receipt_data.minted_stab;
+
+            self.collaterals
+                .get_mut(&receipt_data.parent_address)
+                .unwrap()
+                .collateral_amount +=
+                (cr - receipt_data.collateral_stab_ratio) * receipt_data.minted_stab;
+
+            let (mcr, collateral_price, liquidation_collateral_ratio): (Decimal, Decimal, Decimal) = {
+                let info = self.collaterals.get(&receipt_data.parent_address).unwrap();
+                (info.mcr, info.usd_price, info.liquidation_collateral_ratio)
+            };
+            assert!(
+                collateral_price * cr >= self.internal_stab_price * mcr
+                    && cr > liquidation_collateral_ratio,
+                "Migration would put the CR below MCR."
+            );
+
+            self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
+
+            self.put_collateral(target, to_pool_unit, new_collateral);
+
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "collateral", target);
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "is_pool_unit_collateral",
+                to_pool_unit,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "collateral_amount",
+                new_collateral_amount,
+            );
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
+
+            self.harvest_rates.remove(&collateral_id);
+            if to_pool_unit {
+                self.deleverage_settings.remove(&collateral_id);
+            }
+
+            receipt_data.collateral = target;
+            receipt_data.is_pool_unit_collateral = to_pool_unit;
+            receipt_data.collateral_amount = new_collateral_amount;
+            receipt_data.collateral_stab_ratio = cr;
+            Runtime::emit_event(EventUpdateCdp {
+                cdp: receipt_data,
+                cdp_id: collateral_id,
+            });
+        }
+
+        /// Mark a loan for liquidation
+        ///
+        /// # Input
+        /// - `collateral`: The collateral for which to look for undercollateralized loans to be liquidated
+        ///
+        /// # Output
+        /// - The marker receipt in a `Bucket`
+        ///
+        /// # Logic
+        /// - Get the CDP with the lowest collateral ratio for the chosen collateral
+        /// - Calculate new collateral ratio (as pool unit aren't always up to date)
+        /// - Create the marker receipt struct
+        /// - Insert the CDP into the marked CDPs AvlTree
+        /// - Remove the collateral ratio from the AvlTree
+        /// - Mint marker receipt, which will be returned if the marking is a success
+        /// - Update the Cdp receipt to point to the marker receipt and get marked status
+        /// - Get the collateral ids for the collateral ratio that was newly calculated (which is different if working with pool units)
+        /// - Save CDP if CR is high enough after pool_to_real conversion, unless the collateral_ids vector is full
+        ///     - Return the initial marker receipt if saving wasn't possible
+        ///     - Or return a new marker receipt if saving was possible
+        pub fn mark_for_liquidation(&mut self, collateral: ResourceAddress) -> Bucket {
+            self.check_mark_rate(collateral);
+
+            let (_first_cr, collateral_ids, _next_key) = self
+                .collateral_ratios
+                .get_mut(&collateral)
+                .unwrap()
+                .range(dec!(0)..)
+                .next()
+                .unwrap();
+            let collateral_id: NonFungibleLocalId = collateral_ids[0].clone();
+            let lcr: Decimal = self
+                .collaterals
+                .get(&collateral)
+                .unwrap()
+                .liquidation_collateral_ratio;
+            let mut data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+
+            assert!(
+                data.collateral_stab_ratio
+                    < self
+                        .collaterals
+                        .get(&collateral)
+                        .unwrap()
+                        .liquidation_collateral_ratio,
+                "No possible liquidations."
+            );
+
+            let cr: Decimal = self.pool_to_real(
+                data.collateral_amount,
+                data.collateral,
+                data.is_pool_unit_collateral,
+            ) / data.minted_stab;
+
+            self.collaterals
+                .get_mut(&collateral)
+                .unwrap()
+                .collateral_amount += (cr - data.collateral_stab_ratio) * data.minted_stab;
+
+            self.marker_placing_counter += dec!(1);
+            self.cdp_marker_counter += 1;
+            let id: Decimal = self.marker_placing_counter;
+
+            let mut marker = CdpMarker {
+                mark_type: CdpUpdate::Marked,
+                time_marked: Clock::current_time_rounded_to_seconds(),
+                marked_id: collateral_id.clone(),
+                marker_placing: self.marker_placing_counter,
+                used: false,
+            };
+            self.record_marker(&collateral_id, self.cdp_marker_counter);
+
+            self.remove_cr(
+                data.parent_address,
+                data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
+
+            let mut cdp_ids: Vec<NonFungibleLocalId> = Vec::new();
+
+            if self
+                .collateral_ratios
+                .get_mut(&collateral)
+                .unwrap()
+                .get_mut(&cr)
+                .is_some()
+            {
+                cdp_ids = self
+                    .collateral_ratios
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .get_mut(&cr)
+                    .unwrap()
+                    .to_vec();
+            }
+
+            if (cr > lcr) && cdp_ids.len() < self.parameters.max_vector_length.try_into().unwrap() {
+                self.insert_cr(data.parent_address, cr, collateral_id.clone());
+
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "collateral_stab_ratio",
+                    cr,
+                );
+
+                marker.mark_type = CdpUpdate::Saved;
+
+                self.cdp_marker_manager.mint_non_fungible(
+                    &NonFungibleLocalId::integer(self.cdp_marker_counter),
+                    marker,
+                )
+            } else {
+                self.marked_cdps.insert(id, collateral_id.clone());
+                self.marked_cdps_active += 1;
+
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "marker_id",
+                    self.cdp_marker_counter,
+                );
+
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "status",
+                    CdpStatus::Marked,
+                );
+
+                data.status = CdpStatus::Marked;
+                data.marker_id = self.cdp_marker_counter;
+                self.update_cdp_stats(&collateral_id, |stats| stats.times_marked += 1);
+
+                Runtime::emit_event(EventUpdateCdp {
+                    cdp: data,
+                    cdp_id: collateral_id,
+                });
+
+                self.cdp_marker_manager.mint_non_fungible(
+                    &NonFungibleLocalId::integer(self.cdp_marker_counter),
+                    marker,
+                )
+            }
+        }
+
+        /// Force liquidate a loan / CDP (liquidating without the loan being undercollateralized, but with a fee that should be beneficial for the borrower)
+        ///
+        /// # Input
+        /// - `collateral`: The collateral to be liquidated
+        /// - `payment`: The STAB tokens to pay back
+        /// - `percentage_to_take`: The percentage of the collateral value to take (if < 1, the borrower will profit off the liquidation)
+        /// - `assert_non_markable`: Whether to assert that the loan is not markable via normal means, which would be more profitable for the liquidator
+        ///
+        /// # Output
+        /// - The collateral returned
+        /// - The leftover STAB
+        ///
+        /// # Logic
+        /// - Get the CDP with lowest collateral ratio for the chosen collateral, skipping redemption-protected CDPs unless only protected CDPs remain
+        /// - Remove the collateral ratio from the AvlTree
+        /// - Calculate latest collateral ratio
+        /// - Get liquidation collateral ratio
+        /// - Assert that the collateral ratio is high enough to force liquidate
+        ///    - If LCR > CR, the loan doesn't have to be forced, but can be liquidated via normal means
+        /// - Calculate percentage of collateral value vs. minted STAB
+        /// - Calculate how much of the loan can be liquidated, how much of the payment should be taken, and what the leftover STAB debt will then be
+        /// - Calculate new collateral amount
+        ///    - If CR is too low, not all collateral the liquidator wants to take can be taken, so the new_collateral_amount will be negative
+        ///    - Then we set the new_collateral_amount to 0
+        /// - If CR percentage is not > 100%, the entire loan must be liquidated
+        ///    - Otherwise we can be left with a loan that has debt but 0 collateral
+        /// - Take the payment and burn the STAB
+        /// - Update circulating STAB
+        /// - Take the collateral
+        /// - Calculate the new_collateral_amount again, because of potential rounding in take_collateral method (when working with assets with strange divisilibity)
+        /// - Update the CDP receipt
+        /// - If the new collateral amount is not 0, calculate the new collateral ratio, insert it into the AvlTree and update the CDP receipt
+        /// - If the loan was liquidated, update the CDP receipt to reflect this
+        /// - Return the collateral and the leftover STAB
+        pub fn force_liquidate(
+            &mut self,
+            collateral: ResourceAddress,
+            mut payment: Bucket,
+            percentage_to_take: Decimal,
+            assert_non_markable: bool,
+        ) -> (Bucket, Bucket) {
+            assert!(
+                !self.is_paused(PausableAction::ForceLiquidate, Some(collateral)),
+                "Not allowed to forceliquidate loans right now."
+            );
+
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+
+            let collateral_id: NonFungibleLocalId = self.find_force_liquidation_target(collateral);
+            let mut data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+
+            self.remove_cr(
+                data.parent_address,
+                data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
+
+            let cr: Decimal = self.pool_to_real(
+                data.collateral_amount,
+                data.collateral,
+                data.is_pool_unit_collateral,
+            ) / data.minted_stab;
+
+            let lcr: Decimal = self
+                .collaterals
+                .get(&collateral)
+                .unwrap()
+                .liquidation_collateral_ratio;
+
+            if assert_non_markable {
+                assert!(
+                    cr > lcr,
+                    "CR is too low. Liquidate this loan via the normal procedure."
+                );
+            }
+
+            let cr_percentage: Decimal = self.liquidation_mcr(collateral) * cr / lcr;
+
+            let (percentage_to_liquidate, payment_amount, new_stab_amount): (
+                Decimal,
+                Decimal,
+                Decimal,
+            ) = match payment.amount() > data.minted_stab {
+                true => (dec!(1), data.minted_stab, dec!(0)),
+                false => (
+                    (payment.amount() / data.minted_stab),
+                    payment.amount(),
+                    data.minted_stab - payment.amount(),
+                ),
+            };
+
+            let mut new_collateral_amount: Decimal = data.collateral_amount
+                - (data.collateral_amount * percentage_to_liquidate * percentage_to_take
+                    / cr_percentage);
+
+            if new_collateral_amount < dec!(0) {
+                new_collateral_amount = dec!(0);
+            }
+
+            assert!(
+                cr_percentage > dec!(1) || percentage_to_liquidate == dec!(1),
+                "CR < 100%. Entire loan must be liquidated",
+            );
+
+            payment.take(payment_amount).burn();
+            self.update_cdp_stats(&collateral_id, |stats| stats.total_repaid += payment_amount);
+
+            self.settle_borrow_rewards(&collateral_id, data.parent_address, data.minted_stab);
+            self.update_minted_stab(
+                false,
+                data.is_pool_unit_collateral,
+                false,
+                payment_amount,
+                data.parent_address,
+                data.collateral,
+            );
+
+            let collateral_payment: Bucket = self.take_collateral(
+                data.collateral,
+                data.is_pool_unit_collateral,
+                data.collateral_amount - new_collateral_amount,
+            );
+
+            new_collateral_amount = data.collateral_amount - collateral_payment.amount();
+
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "collateral_amount",
+                new_collateral_amount,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "minted_stab",
+                new_stab_amount,
+            );
+
+            if percentage_to_liquidate < dec!(1) {
+                let new_cr: Decimal = self.pool_to_real(
+                    new_collateral_amount,
+                    data.collateral,
+                    data.is_pool_unit_collateral,
+                ) / new_stab_amount;
+
+                self.collaterals
+                    .get_mut(&data.parent_address)
+                    .unwrap()
+                    .collateral_amount += (new_cr - data.collateral_stab_ratio) * data.minted_stab;
+
+                self.insert_cr(data.parent_address, new_cr, collateral_id.clone());
+
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "collateral_stab_ratio",
+                    new_cr,
+                );
+
+                data.collateral_stab_ratio = new_cr;
+                data.collateral_amount = new_collateral_amount;
+                data.minted_stab = new_stab_amount;
+
+                Runtime::emit_event(EventUpdateCdp {
+                    cdp: data,
+                    cdp_id: collateral_id,
+                });
+            } else {
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "status",
+                    CdpStatus::ForceLiquidated,
+                );
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "liquidated_at",
+                    Some(Clock::current_time_rounded_to_seconds()),
+                );
+
+                self.collaterals
+                    .get_mut(&data.parent_address)
+                    .unwrap()
+                    .collateral_amount -= data.collateral_stab_ratio * data.minted_stab;
+
+                Runtime::emit_event(EventLiquidateCdp {
+                    cdp_id: collateral_id,
+                });
+            }
+
+            (collateral_payment, payment)
+        }
+
+        /// Get the loan / CDP the next force liquidation of a collateral would hit
+        pub fn get_force_liquidation_target(
+            &self,
+            collateral: ResourceAddress,
+        ) -> NonFungibleLocalId {
+            self.find_force_liquidation_target(collateral)
+        }
+
+        /// Force mint STAB by adding collateral to a loan / CDP
+        ///
+        /// # Input
+        /// - `collateral`: The collateral to add
+        /// - `payment`: The STAB tokens to pay back
+        /// - `percentage_to_supply`: The percentage of the collateral value to supply (if > 1, the borrower will profit off the minting)
+        ///
+        /// # Output
+        /// - The minted STAB in a `Bucket`
+        /// - The leftover collateral in a `Bucket`
+        ///
+        /// # Logic
+        /// - Check if it is allowed to force mint right now
+        /// - Get the CDP with highest collateral ratio for the chosen collateral
+        /// - Check if the collateral is compatible (the CDP's collateral, or the parent asset of its pool unit collateral), skipping frozen CDPs and CDPs that reached their force mint cap or the max loan debt
+        ///    - at most max_vector_length CDPs are checked, so capped loans can't block force minting
+        /// - Calculate minimum allowed collateral ratio
+        /// - Get collateral price
+        /// - Calculate constant k, which is the collateral needed for minting 1 STAB
+        /// - Calculate the max addition of collateral that can be supplied (see code for calculation and explanation), limited by the CDP's force mint cap and the max loan debt
+        /// - If too much collateral is supplied, remove the excess and put in bucket to return (handle potential rounding errors for strange divisilibity assets)
+        /// - If the parent asset is supplied, convert it to the CDP's pool unit (stake to validator / contribute to pool)
+        /// - Remove the current collateral ratio from the AvlTree
+        /// - Calculate newly minted STAB, new collateral amount and new collateral ratio
+        /// - Update circulating STAB
+        /// - Update the CDP receipt
+        /// - Insert the new collateral ratio into the AvlTree
+        /// - Mint the STAB
+        /// - Return the minted STAB and the leftover collateral
+        pub fn force_mint(
+            &mut self,
+            collateral: ResourceAddress,
+            mut payment: Bucket,
+            percentage_to_supply: Decimal,
+        ) -> (Bucket, Option<Bucket>) {
+            assert!(
+                !self.is_paused(PausableAction::ForceMint, Some(payment.resource_address())),
+                "Not allowed to force mint right now."
+            );
+
+            let mut data_option: Option<Cdp> = None;
+            let mut collateral_id: NonFungibleLocalId = NonFungibleLocalId::integer(0);
+            let mut return_bucket: Option<Bucket> = None;
+            let mut found: bool = false;
+            let max_iterations: u64 = self.parameters.max_vector_length;
+            let mut iterations: u64 = 0;
+            let max_cdp_debt: Option<Decimal> = self.get_max_cdp_debt(collateral);
+
+            {
+                let collateral_ratios = self.collateral_ratios.get_mut(&collateral).unwrap();
+                let range = collateral_ratios.range_back(
+                    dec!(0)..(self.collaterals.get(&collateral).unwrap().highest_cr + dec!(1)),
+                );
+
+                'outer_loop: for (_cr, collateral_ids, _next_key) in range {
+                    for found_collateral_id in collateral_ids {
+                        if iterations == max_iterations {
+                            break 'outer_loop;
+                        }
+                        iterations += 1;
+
+                        data_option = Some(self.cdp_manager.get_non_fungible_data(&found_collateral_id));
+                        let data = data_option.as_ref().unwrap();
+                        let capped: bool = self
+                            .force_mint_caps
+                            .get(&found_collateral_id)
+                            .map_or(false, |cap| cap.force_minted >= cap.allowance)
+                            || max_cdp_debt.map_or(false, |max_debt| data.minted_stab >= max_debt);
+                        let frozen: bool =
+                            self.frozen_cdps
+                                .get(&found_collateral_id)
+                                .map_or(false, |freeze| {
+                                    Clock::current_time_is_strictly_before(
+                                        freeze.until,
+                                        TimePrecision::Second,
+                                    )
+                                });
+                        if (data.collateral == payment.resource_address()
+                            || (data.is_pool_unit_collateral
+                                && data.parent_address == payment.resource_address()
+                                && self.pool_units.get(&data.collateral).unwrap().accepted))
+                            && !capped
+                            && !frozen
+                        {
+                            collateral_id = found_collateral_id.clone();
+                            found = true;
+                            break 'outer_loop;
+                        }
+                    }
+                }
+            }
+
+            let mut data = data_option.expect("No suitable mints found");
+            assert!(found, "Can only force mint other collaterals right now.");
+            let supplies_parent: bool = data.collateral != payment.resource_address();
+
+            let pool_to_real: Decimal =
+                self.pool_to_real(dec!(1), data.collateral, data.is_pool_unit_collateral);
+
+            let min_collateral_ratio: Decimal = self.parameters.force_mint_cr_multiplier
+                * self
+                    .collaterals
+                    .get(&collateral)
+                    .unwrap()
+                    .liquidation_collateral_ratio;
+
+            let collateral_price: Decimal = self.collaterals.get(&collateral).unwrap().usd_price;
+
+            let k: Decimal = (self.internal_stab_price) / (pool_to_real * collateral_price)
+                * percentage_to_supply;
+
+            //we now need to calculate maximum amount of collateral that can be supplied: max_addition
+            //we can do this by first claiming: collateral_amount / stab_amount = min_collateral_ratio (1)
+            //collateral_amount = (initial_collateral_amount + max_col_addition) * pool_to_real (2)
+            //stab_amount = initial_stab_amount + max_col_addition / k (3)
+            //filling in (2) and (3) in (1) gives us an equation of the form: ((c + a) * p) / (s + a / k) = m (4)
+            //solving (4) for max_col_addition (abbreviated 'a') gives: a = (k * (c * p - m * s)) / (m - k * p)
+            //which translates to:
+
+            let mut max_addition: Decimal = (k
+                * (data.collateral_amount * pool_to_real
+                    - min_collateral_ratio * data.minted_stab))
+                / (min_collateral_ratio - k * pool_to_real);
+
+            //a CDP with a force mint cap can only get the STAB left under its cap minted, which costs k collateral per STAB
+            if let Some(cap) = self.force_mint_caps.get(&collateral_id) {
+                max_addition = max_addition.min((cap.allowance - cap.force_minted) * k);
+            }
+
+            //likewise, a CDP can only get the STAB left under the max loan debt minted
+            if let Some(max_debt) = max_cdp_debt {
+                max_addition = max_addition.min((max_debt - data.minted_stab) * k);
+            }
+
+            let max_payment: Decimal = match supplies_parent {
+                true => max_addition * pool_to_real,
+                false => max_addition,
+            };
+
+            //the returned payment protects the protocol: rounding it up keeps the supplied collateral within max_payment, so the loan never ends below the minimum collateral ratio
+            if payment.amount() > max_payment {
+                return_bucket = Some(
+                    payment.take_advanced(payment.amount() - max_payment, self.rounding(true)),
+                );
+            }
+
+            if supplies_parent {
+                payment = self.convert_to_pool_unit(data.collateral, payment);
+            }
+
+            self.remove_cr(
+                data.parent_address,
+                data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
+
+            let new_minted_stab: Decimal = data.minted_stab + payment.amount() / k;
+            let new_collateral_amount: Decimal = data.collateral_amount + payment.amount();
+
+            let new_cr: Decimal = self.pool_to_real(
+                new_collateral_amount,
+                data.collateral,
+                data.is_pool_unit_collateral,
+            ) / new_minted_stab;
+
+            self.collaterals
+                .get_mut(&data.parent_address)
+                .unwrap()
+                .collateral_amount += (new_cr - data.collateral_stab_ratio) * data.minted_stab;
+
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "minted_stab",
+                new_minted_stab,
+            );
+            self.update_cdp_stats(&collateral_id, |stats| {
+                stats.total_borrowed += new_minted_stab - data.minted_stab
+            });
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "collateral_amount",
+                new_collateral_amount,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "collateral_stab_ratio",
+                new_cr,
+            );
+
+            self.insert_cr(data.parent_address, new_cr, collateral_id.clone());
+
+            let stab_tokens: Bucket = self.stab_manager.mint(payment.amount() / k);
+
+            if let Some(mut cap) = self.force_mint_caps.get_mut(&collateral_id) {
+                cap.force_minted += stab_tokens.amount();
+            }
+
+            self.settle_borrow_rewards(&collateral_id, data.parent_address, data.minted_stab);
+            self.update_minted_stab(
+                false,
+                data.is_pool_unit_collateral,
+                false,
+                stab_tokens.amount(),
+                data.parent_address,
+                data.collateral,
+            );
+
+            self.put_collateral(data.collateral, data.is_pool_unit_collateral, payment);
+
+            data.collateral_stab_ratio = new_cr;
+            data.collateral_amount = new_collateral_amount;
+            data.minted_stab = new_minted_stab;
+
+            Runtime::emit_event(EventUpdateCdp {
+                cdp: data,
+                cdp_id: collateral_id,
+            });
+
+            (stab_tokens, return_bucket)
+        }
+
+        /// Liquidate a marked loan / CDP, using a marker receipt
+        ///
+        /// # Input
+        /// - `marker_id`: The marker receipt id
+        /// - `payment`: The STAB tokens to pay back
+        ///
+        /// # Output, depends on outcome:
+        /// 1: liquidation successful
+        /// - The collateral reward
+        /// - The leftover STAB
+        /// - A liquidation receipt
+        /// 2: liquidation unsuccessful (because the loan was saved):
+        /// - The STAB tokens
+        /// - None
+        /// - A liquidation receipt (with saved status)
+        ///
+        /// # Logic
+        /// - Get the marker receipt and data
+        /// - Get the CDP data according to the marker receipt
+        /// - Try to liquidate the CDP, using the try_liquidate method (see that method for more details)
+        pub fn liquidate_position_with_marker(
+            &mut self,
+            marker_id: NonFungibleLocalId,
+            payment: Bucket,
+        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+            let marker_data: CdpMarker = self.cdp_marker_manager.get_non_fungible_data(&marker_id);
+
+            let cdp_data: Cdp = self
+                .cdp_manager
+                .get_non_fungible_data(&marker_data.marked_id);
+
+            self.try_liquidate(
+                payment,
+                cdp_data,
+                marker_data,
+                marker_id,
+                self.parameters.liquidation_delay,
+                true,
+            )
+        }
+
+        /// Liquidate a marked loan / CDP, without a marker receipt
+        ///
+        /// # Input
+        /// - `marker_id`: The marker receipt id
+        /// - `payment`: The STAB tokens to pay back
+        ///
+        /// # Output, depends on outcome:
+        /// 1: liquidation successful
+        /// - The collateral reward
+        /// - The leftover STAB
+        /// - A liquidation receipt
+        /// 2: liquidation unsuccessful (because the loan was saved):
+        /// - The STAB tokens
+        /// - None
+        /// - A liquidation receipt (with saved status)
+        ///
+        /// # Logic
+        /// - Get the CDP to be liquidated:
+        ///   - If automatic is true, find the next to-be liquidated CDP, skipping over the amount of CDPs specified by the skip parameter.
+        ///     - If no CDPs are found, panic.
+        ///     - If too many CDPs are skipped, panic.
+        ///   - If automatic is false, use the CDP receipt specified by the cdp_id parameter.
+        /// - Get the marker receipt through the CDP data
+        /// - Try to liquidate the CDP, using the try_liquidate method (see that method for more details)
+        pub fn liquidate_position_without_marker(
+            &mut self,
+            payment: Bucket,
+            skip: Option<i64>,
+            cdp_id: NonFungibleLocalId,
+        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+            let mut collateral_id: NonFungibleLocalId = cdp_id;
+            let mut skip_counter: i64 = 0;
+            let mut found: bool = false;
+
+            if let Some(skip) = skip {
+                for (_identifier, found_collateral_id, _next_key) in
+                    self.marked_cdps.range(dec!(0)..)
+                {
+                    collateral_id = found_collateral_id.clone();
+                    skip_counter += 1;
+                    if (skip_counter - 1) == skip {
+                        found = true;
+                        break;
+                    }
+                }
+                if skip_counter == 0 {
+                    panic!("No loans available to liquidate.");
+                } else if !found {
+                    panic!(
+                        "Too many skipped. Skip a maximum of {} loans.",
+                        skip_counter - 1
+                    );
+                }
+            }
+
+            let cdp_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let marker_data: CdpMarker = self
+                .cdp_marker_manager
+                .get_non_fungible_data(&NonFungibleLocalId::integer(cdp_data.marker_id));
+
+            let marker_id: NonFungibleLocalId = NonFungibleLocalId::integer(cdp_data.marker_id);
+
+            self.try_liquidate(
+                payment,
+                cdp_data,
+                marker_data,
+                marker_id,
+                self.parameters.liquidation_delay + self.parameters.unmarked_delay,
+                false,
+            )
+        }
+
+        /// Changes the price of a collateral, which will also update the liquidation collateral ratio
+        ///   - a price drop of at least a collateral's surcharge trigger (since the last price change) adds the MCR surcharge to its liquidation collateral ratio
+        ///   - the surcharge decays linearly back to zero over the surcharge's decay period
+        ///   - a collateral whose share is being enforced also gets its share surcharge added (see `set_share_enforcement`)
+        pub fn change_collateral_price(&mut self, collateral: ResourceAddress, new_price: Decimal) {
+            let (mcr, old_price, surcharge_parameters): (
+                Decimal,
+                Decimal,
+                Option<SurchargeParameters>,
+            ) = {
+                let info = self.collaterals.get(&collateral).unwrap();
+                (info.mcr, info.usd_price, info.surcharge_parameters.clone())
+            };
+            let mut surcharge: Decimal = self.get_mcr_surcharge(collateral);
+
+            if let Some(parameters) = surcharge_parameters {
+                if new_price < old_price
+                    && (old_price - new_price) / old_price >= parameters.trigger_drop
+                    && parameters.surcharge > surcharge
+                {
+                    surcharge = parameters.surcharge;
+                    let mut info = self.collaterals.get_mut(&collateral).unwrap();
+                    info.surcharge = surcharge;
+                    info.surcharge_triggered_at = Some(Clock::current_time_rounded_to_seconds());
+
+                    Runtime::emit_event(EventMcrSurcharge {
+                        address: collateral,
+                        surcharge,
+                    });
+                }
+            }
+
+            surcharge += self.update_share_enforcement(collateral);
+
+            self.collaterals.get_mut(&collateral).unwrap().usd_price = new_price;
+            self.collaterals
+                .get_mut(&collateral)
+                .unwrap()
+                .applied_surcharge = surcharge;
+            self.collaterals
+                .get_mut(&collateral)
+                .unwrap()
+                .liquidation_collateral_ratio =
+                (mcr + surcharge) * (self.internal_stab_price / new_price);
+
+            Runtime::emit_event(EventChangeCollateral {
+                address: collateral,
+                new_mcr: None,
+                new_usd_price: Some(new_price),
+            });
+        }
+
+        /// Changes the prices of multiple collaterals at once, so they can be updated in a single call
+        pub fn change_collateral_prices(&mut self, prices: Vec<(ResourceAddress, Decimal)>) {
+            for (collateral, new_price) in prices {
+                self.change_collateral_price(collateral, new_price);
+            }
+        }
+
+        /// Add a possible collateral to the protocol
+        pub fn add_collateral(
+            &mut self,
+            address: ResourceAddress,
+            chosen_mcr: Decimal,
+            initial_price: Decimal,
+        ) {
+            assert!(
+                self.collaterals.get(&address).is_none(),
+                "Collateral is already accepted."
+            );
+
+            let info = CollateralInfo {
+                mcr: chosen_mcr,
+                usd_price: initial_price,
+                liquidation_collateral_ratio: chosen_mcr * self.internal_stab_price / initial_price,
+                vault: Vault::new(address),
+                resource_address: address,
+                treasury: Vault::new(address),
+                insurance: Vault::new(address),
+                accepted: true,
+                initialized: false,
+                max_stab_share: dec!(1),
+                minted_stab: dec!(0),
+                collateral_amount: dec!(0),
+                highest_cr: dec!(0),
+                max_cdp_debt: None,
+                max_marks_per_minute: None,
+                surcharge_parameters: None,
+                surcharge: dec!(0),
+                surcharge_triggered_at: None,
+                applied_surcharge: dec!(0),
+                share_enforcement: None,
+                share_enforced_since: None,
+            };
+
+            self.collaterals.insert(address, info);
+            self.collateral_addresses.push(address);
+            self.record_risk_parameter_change(
+                address,
+                "add_collateral",
+                None,
+                RiskParameters {
+                    mcr: Some(chosen_mcr),
+                    accepted: true,
+                    max_share: dec!(1),
+                },
+            );
+
+            Runtime::emit_event(EventAddCollateral {
+                address,
+                mcr: chosen_mcr,
+                usd_price: initial_price,
+            });
+        }
+
+        /// Add a possible pool collateral to the protocol
+        ///   - pool collaterals are collaterals are some kind of pool unit (such as LSUs), with an underlying asset that is already a collateral
+        ///       - the collateral amount is calculated when a loan is opened and interacted with, so not continuously updated
+        ///          - this means that sometimes a loan can be liquidated, but when interacting with it, the collateral amount is updated so it can't be anymore
+        ///             - this results in the loan being saved
+        pub fn add_pool_collateral(
+            &mut self,
+            address: ResourceAddress,
+            parent_address: ResourceAddress,
+            pool_address: ComponentAddress,
+            lsu: bool,
+            initial_acceptance: bool,
+        ) {
+            assert!(
+                self.pool_units.get(&address).is_none(),
+                "Collateral is already accepted."
+            );
+
+            let mut validator: Option<Global<Validator>> = None;
+            let mut one_resource_pool: Option<Global<OneResourcePool>> = None;
+
+            if lsu {
+                validator = Some(Global::from(pool_address));
+            } else {
+                one_resource_pool = Some(Global::from(pool_address));
+            }
+
+            let info = PoolUnitInfo {
+                vault: Vault::new(address),
+                treasury: Vault::new(address),
+                insurance: Vault::new(address),
+                lsu,
+                validator,
+                one_resource_pool,
+                adapter: None,
+                parent_address,
+                address,
+                accepted: initial_acceptance,
+                max_pool_share: dec!(1),
+                minted_stab: dec!(0),
+                haircut: dec!(0),
+            };
+
+            self.pool_units.insert(address, info);
+            self.record_risk_parameter_change(
+                address,
+                "add_pool_collateral",
+                None,
+                RiskParameters {
+                    mcr: None,
+                    accepted: initial_acceptance,
+                    max_share: dec!(1),
+                },
+            );
+
+            Runtime::emit_event(EventAddPoolCollateral {
+                address,
+                parent_address,
+            });
+        }
+
+        /// Add a possible pool collateral that is priced through an adapter component
+        ///   - used for wrapped yield-bearing tokens (such as vault shares) that aren't a OneResourcePool
+        ///   - the adapter needs to implement `get_redemption_value(amount: Decimal) -> Decimal`, returning the amount of parent collateral the wrapped tokens are worth
+        ///   - the pool unit's haircut is applied to the adapter's redemption value, like for other non-LSU pool units
+        ///   - to allow migrating loans (`migrate_cdp`), the adapter can implement `redeem(Bucket) -> Bucket` and `contribute(Bucket) -> Bucket`
+        ///       - an adapter wrapping a OneResourcePool needs to hold its pool manager role to contribute
+        pub fn add_adapter_pool_collateral(
+            &mut self,
+            address: ResourceAddress,
+            parent_address: ResourceAddress,
+            adapter_address: ComponentAddress,
+            initial_acceptance: bool,
+        ) {
+            assert!(
+                self.pool_units.get(&address).is_none(),
+                "Collateral is already accepted."
+            );
+
+            let info = PoolUnitInfo {
+                vault: Vault::new(address),
+                treasury: Vault::new(address),
+                insurance: Vault::new(address),
+                lsu: false,
+                validator: None,
+                one_resource_pool: None,
+                adapter: Some(Global::from(adapter_address)),
+                parent_address,
+                address,
+                accepted: initial_acceptance,
+                max_pool_share: dec!(1),
+                minted_stab: dec!(0),
+                haircut: dec!(0),
+            };
+
+            self.pool_units.insert(address, info);
+            self.record_risk_parameter_change(
+                address,
+                "add_adapter_pool_collateral",
+                None,
+                RiskParameters {
+                    mcr: None,
+                    accepted: initial_acceptance,
+                    max_share: dec!(1),
+                },
+            );
+
+            Runtime::emit_event(EventAddPoolCollateral {
+                address,
+                parent_address,
+            });
+        }
+
+        /// Changes the internal price of the STAB token
+        pub fn change_internal_price(&mut self, new_price: Decimal) {
+            self.internal_stab_price = new_price;
+
+            Runtime::emit_event(EventChangePeg {
+                internal_price: new_price,
+            });
+        }
+
+        ///Emptying the treasury of a collateral, error_fallback exists if a pool unit is also in self.collaterals
+        pub fn empty_collateral_treasury(
+            &mut self,
+            amount: Decimal,
+            collateral: ResourceAddress,
+            error_fallback: bool,
+        ) -> Bucket {
+            if self.pool_units.get(&collateral).is_some() && !error_fallback {
+                return self
+                    .pool_units
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .treasury
+                    .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+            } else {
+                return self
+                    .collaterals
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .treasury
+                    .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+            }
+        }
+
+        /// Emptying the insurance fund of a collateral, error_fallback exists if a pool unit is also in self.collaterals
+        ///   - only possible for the insurance manager, which requires the insurance badge instead of controller badges, so insurance funds can't be spent as revenue
+        ///   - the insurance manager role is locked, and only a single insurance badge can ever be minted, so the owner can't authorize payouts once the badge is handed over
+        pub fn empty_insurance_fund(
+            &mut self,
+            amount: Decimal,
+            collateral: ResourceAddress,
+            error_fallback: bool,
+        ) -> Bucket {
+            if self.pool_units.get(&collateral).is_some() && !error_fallback {
+                self.pool_units
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .insurance
+                    .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero))
+            } else {
+                self.collaterals
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .insurance
+                    .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero))
+            }
+        }
+
+        /// Get the treasury balances of a collateral or pool unit: (protocol revenue, insurance fund)
+        pub fn get_treasury_balances(&self, collateral: ResourceAddress) -> (Decimal, Decimal) {
+            if let Some(pool_unit) = self.pool_units.get(&collateral) {
+                (pool_unit.treasury.amount(), pool_unit.insurance.amount())
+            } else {
+                let collateral_info = self.collaterals.get(&collateral).unwrap();
+                (
+                    collateral_info.treasury.amount(),
+                    collateral_info.insurance.amount(),
+                )
+            }
+        }
+
+        /// Set the amount of controller badges required for a class of owner actions, updating the role of the class
+        ///   - the Proxy authorizes its calls with 0.75 controller badges, so a threshold above that takes the class out of the Proxy's hands
+        pub fn set_auth_threshold(&mut self, class: AuthClass, amount: Decimal) {
+            assert!(amount > dec!(0), "Threshold needs to be positive.");
+
+            let role: &str = match class {
+                AuthClass::Mint => {
+                    self.parameters.auth_thresholds.mint = amount;
+                    "mint_manager"
+                }
+                AuthClass::Parameters => {
+                    self.parameters.auth_thresholds.parameters = amount;
+                    "parameter_manager"
+                }
+                AuthClass::Emergency => {
+                    self.parameters.auth_thresholds.emergency = amount;
+                    "emergency_manager"
+                }
+            };
+
+            Runtime::global_component().set_role(
+                role,
+                rule!(require_amount(
+                    amount,
+                    self.controller_badge_manager.address()
+                )),
+            );
+        }
+
+        /// Mint a controller badge
+        pub fn mint_controller_badge(&self, amount: Decimal) -> Bucket {
+            self.controller_badge_manager.mint(amount)
+        }
+
+        /// Mint the insurance badge, required by the insurance manager role (for instance for the insurance claims component)
+        ///   - only a single badge can ever be minted, so whoever holds it (verifiable on ledger) is the only one able to pay out of the insurance funds
+        pub fn mint_insurance_badge(&self) -> Bucket {
+            assert!(
+                self.insurance_badge_manager.total_supply().unwrap() == dec!(0),
+                "Insurance badge already minted."
+            );
+            self.insurance_badge_manager.mint(1)
+        }
+
+        /// Edit a collateral's parameters
+        pub fn edit_collateral(
+            &mut self,
+            address: ResourceAddress,
+            new_mcr: Decimal,
+            new_acceptance: bool,
+            new_max_share: Decimal,
+        ) {
+            let old: RiskParameters = {
+                let info = self.collaterals.get(&address).unwrap();
+                RiskParameters {
+                    mcr: Some(info.mcr),
+                    accepted: info.accepted,
+                    max_share: info.max_stab_share,
+                }
+            };
+
+            self.collaterals.get_mut(&address).unwrap().accepted = new_acceptance;
+            self.collaterals.get_mut(&address).unwrap().mcr = new_mcr;
+            self.collaterals.get_mut(&address).unwrap().max_stab_share = new_max_share;
+
+            self.record_risk_parameter_change(
+                address,
+                "edit_collateral",
+                Some(old),
+                RiskParameters {
+                    mcr: Some(new_mcr),
+                    accepted: new_acceptance,
+                    max_share: new_max_share,
+                },
+            );
+
+            Runtime::emit_event(EventChangeCollateral {
+                address,
+                new_mcr: Some(new_mcr),
+                new_usd_price: None,
+            });
+        }
+
+        /// Edit a pool collateral's parameters
+        pub fn edit_pool_collateral(
+            &mut self,
+            address: ResourceAddress,
+            new_acceptance: bool,
+            new_max_share: Decimal,
+        ) {
+            let old: RiskParameters = {
+                let info = self.pool_units.get(&address).unwrap();
+                RiskParameters {
+                    mcr: None,
+                    accepted: info.accepted,
+                    max_share: info.max_pool_share,
+                }
+            };
+
+            self.pool_units.get_mut(&address).unwrap().accepted = new_acceptance;
+            self.pool_units.get_mut(&address).unwrap().max_pool_share = new_max_share;
+
+            self.record_risk_parameter_change(
+                address,
+                "edit_pool_collateral",
+                Some(old),
+                RiskParameters {
+                    mcr: None,
+                    accepted: new_acceptance,
+                    max_share: new_max_share,
+                },
+            );
+        }
+
+        /// Get the risk parameter changes from an id onwards (at most the max vector length of changes are scanned)
+        ///   - if a collateral is supplied, only changes of that collateral are returned
+        pub fn get_risk_parameter_changes(
+            &self,
+            from_id: u64,
+            collateral: Option<ResourceAddress>,
+        ) -> Vec<RiskParameterChange> {
+            let to_id: u64 = self
+                .risk_parameter_change_counter
+                .min(from_id.saturating_add(self.parameters.max_vector_length));
+
+            (from_id..to_id)
+                .filter_map(|id| {
+                    self.risk_parameter_changes
+                        .get(&id)
+                        .map(|change| change.clone())
+                })
+                .filter(|change| collateral.map_or(true, |address| change.collateral == address))
+                .collect()
+        }
+
+        /// Append a change of the risk parameters of a collateral to the risk parameter history
+        fn record_risk_parameter_change(
+            &mut self,
+            collateral: ResourceAddress,
+            changed_by: &str,
+            old: Option<RiskParameters>,
+            new: RiskParameters,
+        ) {
+            self.risk_parameter_changes.insert(
+                self.risk_parameter_change_counter,
+                RiskParameterChange {
+                    id: self.risk_parameter_change_counter,
+                    collateral,
+                    changed_by: changed_by.to_string(),
+                    changed_at: Clock::current_time_rounded_to_seconds(),
+                    old,
+                    new,
+                },
+            );
+            self.risk_parameter_change_counter += 1;
+        }
+
+        /// Set the haircut applied to the redemption value of a (non-LSU) pool collateral
+        ///   - a haircut of 0.05 means a pool unit is valued at 95% of its redemption value, limiting the effect of donations to the pool on collateral ratios
+        pub fn set_pool_haircut(&mut self, address: ResourceAddress, haircut: Decimal) {
+            assert!(
+                haircut >= dec!(0) && haircut < dec!(1),
+                "Haircut needs to be between 0 and 1."
+            );
+            self.pool_units.get_mut(&address).unwrap().haircut = haircut;
+        }
+
+        /// Set delay until a loan can be liquidated after marking (in minutes)
+        pub fn set_liquidation_delay(&mut self, new_delay: i64) {
+            self.parameters.liquidation_delay = new_delay;
+        }
+
+        /// Set delay until a loan can be liquited without marker, after it could be liquidated with a marker (in minutes)
+        pub fn set_unmarked_delay(&mut self, new_delay: i64) {
+            self.parameters.unmarked_delay = new_delay;
+        }
+
+        /// Pause or unpause an action for all collaterals
+        pub fn set_paused(&mut self, action: PausableAction, paused: bool) {
+            match paused {
+                true => self.parameters.paused_actions.insert(action),
+                false => self.parameters.paused_actions.remove(&action),
+            };
+        }
+
+        /// Pause or unpause an action for a single collateral or pool unit
+        ///   - pausing an action for a collateral also pauses it for the pool units of that collateral
+        pub fn set_collateral_paused(
+            &mut self,
+            collateral: ResourceAddress,
+            action: PausableAction,
+            paused: bool,
+        ) {
+            if self.collateral_paused_actions.get(&collateral).is_none() {
+                self.collateral_paused_actions
+                    .insert(collateral, HashSet::new());
+            }
+
+            let mut paused_actions = self.collateral_paused_actions.get_mut(&collateral).unwrap();
+            match paused {
+                true => paused_actions.insert(action),
+                false => paused_actions.remove(&action),
+            };
+        }
+
+        /// Check whether an action is paused, globally or (if supplied) for a collateral / pool unit or its parent collateral
+        pub fn is_paused(
+            &self,
+            action: PausableAction,
+            collateral: Option<ResourceAddress>,
+        ) -> bool {
+            if self.parameters.paused_actions.contains(&action) {
+                return true;
+            }
+
+            if let Some(collateral) = collateral {
+                let collateral_paused = |address: &ResourceAddress| -> bool {
+                    self.collateral_paused_actions
+                        .get(address)
+                        .map(|paused_actions| paused_actions.contains(&action))
+                        .unwrap_or(false)
+                };
+
+                if collateral_paused(&collateral) {
+                    return true;
+                }
+                if let Some(pool_unit) = self.pool_units.get(&collateral) {
+                    return collateral_paused(&pool_unit.parent_address);
+                }
+            }
+
+            false
+        }
+
+        /// Get the paused actions, globally (if no collateral is supplied) or for a single collateral / pool unit
+        pub fn get_paused_actions(
+            &self,
+            collateral: Option<ResourceAddress>,
+        ) -> Vec<PausableAction> {
+            match collateral {
+                None => self.parameters.paused_actions.iter().cloned().collect(),
+                Some(collateral) => self
+                    .collateral_paused_actions
+                    .get(&collateral)
+                    .map(|paused_actions| paused_actions.iter().cloned().collect())
+                    .unwrap_or_default(),
+            }
+        }
+
+        /// Set the maximum vector length for the collateral ratios (to prevent state explosion, vectors are non-lazily loaded)
+        pub fn set_max_vector_length(&mut self, new_max_length: u64) {
+            self.parameters.max_vector_length = new_max_length;
+        }
+
+        /// Set the minimum mintable amount of STAB (to prevent unprofitable liquidations)
+        pub fn set_minimum_mint(&mut self, new_minimum_mint: Decimal) {
+            self.parameters.minimum_mint = new_minimum_mint;
+        }
+
+        /// Set fines for being liquidated (for liquidators and the protocol)
+        ///   - a liquidator fine of 0.05 and protocol fine of 0.03 would mean a liquidation would result in 1 + 0.05 + 0.03 = 1.08 times the minted STAB's value collateral being taken from the borrower.
+        ///   - the marker fine replaces the liquidator fine when liquidating with a marker receipt, and should be higher to incentivize marking
+        pub fn set_fines(
+            &mut self,
+            liquidator_fine: Decimal,
+            marker_fine: Decimal,
+            stabilis_fine: Decimal,
+        ) {
+            assert!(
+                marker_fine >= liquidator_fine,
+                "Marker fine can't be below the liquidator fine."
+            );
+            self.parameters.liquidation_liquidation_fine = liquidator_fine;
+            self.parameters.marker_liquidation_fine = marker_fine;
+            self.parameters.stabilis_liquidation_fine = stabilis_fine;
+        }
+
+        /// Set the premium for protecting a loan from force liquidations (fraction of the loan's collateral per day)
+        pub fn set_redemption_protection_premium(&mut self, new_premium: Decimal) {
+            self.parameters.redemption_protection_premium = new_premium;
+        }
+
+        /// Set the maximum debt (in STAB) a single loan can have, for all collaterals (None means no maximum)
+        pub fn set_max_cdp_debt(&mut self, new_max: Option<Decimal>) {
+            self.parameters.max_cdp_debt = new_max;
+        }
+
+        /// Set the maximum debt (in STAB) a single loan using this collateral can have (None means only the global maximum applies)
+        pub fn set_collateral_max_cdp_debt(
+            &mut self,
+            address: ResourceAddress,
+            new_max: Option<Decimal>,
+        ) {
+            self.collaterals.get_mut(&address).unwrap().max_cdp_debt = new_max;
+        }
+
+        /// Set the MCR surcharge of a collateral, temporarily raising its liquidation collateral ratio after fast price drops (a surcharge of 0 disables it)
+        ///   - `trigger_drop` is the relative price drop between two price changes that triggers the surcharge (0.1 = 10%)
+        ///   - `surcharge` is added to the MCR when triggered (0.2 = MCR of 150% becomes 170%)
+        ///   - `decay_period` is the number of minutes in which the surcharge decays back to zero
+        pub fn set_mcr_surcharge(
+            &mut self,
+            address: ResourceAddress,
+            trigger_drop: Decimal,
+            surcharge: Decimal,
+            decay_period: i64,
+        ) {
+            assert!(surcharge >= dec!(0), "Surcharge can't be negative.");
+            let mut info = self.collaterals.get_mut(&address).unwrap();
+            if surcharge == dec!(0) {
+                info.surcharge_parameters = None;
+            } else {
+                assert!(
+                    trigger_drop > dec!(0) && trigger_drop < dec!(1),
+                    "Trigger drop needs to be between 0 and 1."
+                );
+                assert!(decay_period > 0, "Decay period needs to be positive.");
+                info.surcharge_parameters = Some(SurchargeParameters {
+                    trigger_drop,
+                    surcharge,
+                    decay_period,
+                });
+            }
+        }
+
+        /// Set the share enforcement of a collateral, raising its liquidation collateral ratio while its share of the circulating STAB exceeds its maximum share (None disables it)
+        ///   - the share check only blocks new debt, so a share that grows over the maximum (e.g. as other collaterals' loans are repaid) would otherwise stay there
+        ///   - enforcement starts at the first price change with the share above the maximum share, and stops once it is back at or below the release share (hysteresis)
+        ///   - while enforced, `surcharge_per_day` is added to the MCR per day, up to `max_surcharge`, pushing borrowers to repay or top up
+        pub fn set_share_enforcement(
+            &mut self,
+            address: ResourceAddress,
+            enforcement: Option<ShareEnforcement>,
+        ) {
+            let mut info = self.collaterals.get_mut(&address).unwrap();
+            if let Some(enforcement) = &enforcement {
+                assert!(
+                    enforcement.surcharge_per_day > dec!(0) && enforcement.max_surcharge > dec!(0),
+                    "Surcharges need to be positive."
+                );
+                assert!(
+                    enforcement.release_share >= dec!(0)
+                        && enforcement.release_share <= info.max_stab_share,
+                    "Release share needs to be between 0 and the maximum share."
+                );
+            } else {
+                info.share_enforced_since = None;
+            }
+            info.share_enforcement = enforcement;
+        }
+
+        /// Set the maximum number of loans of a collateral that can be marked for liquidation per minute (None for no limit)
+        pub fn set_max_marks_per_minute(&mut self, address: ResourceAddress, new_max: Option<u64>) {
+            if let Some(max) = new_max {
+                assert!(max > 0, "Max marks per minute needs to be positive.");
+            }
+            self.collaterals
+                .get_mut(&address)
+                .unwrap()
+                .max_marks_per_minute = new_max;
+        }
+
+        /// Set the withdrawal throttle, queueing collateral withdrawals (by closing loans, removing collateral or retrieving leftover collateral) above a threshold per window
+        ///   - collateral paid out by liquidations, redemptions, deleverages and harvests isn't throttled, as it pays for debt repaid in the same transaction
+        ///   - `window` is the length of a window in minutes, queued withdrawals can be claimed after one window
+        ///   - `threshold` is the fraction of a collateral's vault that can be withdrawn per window (0.1 = 10%)
+        pub fn set_withdrawal_throttle(&mut self, enabled: bool, window: i64, threshold: Decimal) {
+            assert!(window > 0, "Window needs to be positive.");
+            assert!(
+                threshold > dec!(0) && threshold <= dec!(1),
+                "Threshold needs to be between 0 and 1."
+            );
+            self.parameters.withdrawal_throttle = enabled;
+            self.parameters.withdrawal_window = window;
+            self.parameters.withdrawal_threshold = threshold;
+        }
+
+        /// Set the number of days after a liquidation from which unclaimed leftover collateral can be swept into the insurance fund
+        pub fn set_unclaimed_leftover_period(&mut self, days: i64) {
+            assert!(days > 0, "Period needs to be positive.");
+            self.parameters.unclaimed_leftover_period = days;
+        }
+
+        /// Set the leftover buffer (as a fraction of the liquidated debt's value), above which leftover collateral is returned to the refund account of a liquidated loan
+        pub fn set_leftover_buffer(&mut self, buffer: Decimal) {
+            assert!(buffer >= dec!(0), "Buffer can't be negative.");
+            self.parameters.leftover_buffer = buffer;
+        }
+
+        /// Set the share of the protocol's liquidation fines put in the insurance fund instead of the protocol revenue treasury
+        pub fn set_insurance_share(&mut self, new_share: Decimal) {
+            assert!(
+                new_share >= dec!(0) && new_share <= dec!(1),
+                "Insurance share needs to be between 0 and 1."
+            );
+            self.parameters.insurance_share = new_share;
+        }
+
+        /// Set the bounty paid to the caller of `auto_deleverage` and the slippage margin of the sold collateral (both as a fraction of the repaid debt's value)
+        pub fn set_deleverage_parameters(&mut self, bounty: Decimal, slippage: Decimal) {
+            assert!(
+                bounty >= dec!(0) && slippage >= dec!(0) && bounty + slippage < dec!("0.5"),
+                "Bounty and slippage need to be positive, and together below 0.5."
+            );
+            self.parameters.deleverage_bounty = bounty;
+            self.parameters.deleverage_slippage = slippage;
+        }
+
+        /// Set the share of the harvested yield paid to the caller of `harvest_yield`
+        pub fn set_harvest_bounty(&mut self, new_bounty: Decimal) {
+            assert!(
+                new_bounty >= dec!(0) && new_bounty < dec!(1),
+                "Bounty needs to be between 0 and 1."
+            );
+            self.parameters.harvest_bounty = new_bounty;
+        }
+
+        /// Set the minimum force mint cap of a loan / CDP, as a percentage of its debt (0.1 = 10%)
+        pub fn set_min_force_mint_cap(&mut self, new_min_cap: Decimal) {
             assert!(
-                payment.resource_address() == self.stab_manager.address(),
-                "Invalid STAB payment."
+                new_min_cap > dec!(0),
+                "Minimum force mint cap needs to be positive."
             );
-            let marker_data: CdpMarker = self.cdp_marker_manager.get_non_fungible_data(&marker_id);
+            self.parameters.min_force_mint_cap = new_min_cap;
+        }
 
-            let cdp_data: Cdp = self
-                .cdp_manager
-                .get_non_fungible_data(&marker_data.marked_id);
+        /// Set the share of an auto-top-up buffer paid to the caller of `apply_buffer`
+        pub fn set_buffer_bounty(&mut self, new_bounty: Decimal) {
+            assert!(
+                new_bounty >= dec!(0) && new_bounty < dec!(1),
+                "Bounty needs to be between 0 and 1."
+            );
+            self.parameters.buffer_bounty = new_bounty;
+        }
 
-            self.try_liquidate(
-                payment,
-                cdp_data,
-                marker_data,
-                marker_id,
-                self.parameters.liquidation_delay,
-            )
+        /// Set the maximum shortfall (in STAB) covered by the stability reserve when closing a dust loan
+        pub fn set_dust_tolerance(&mut self, new_tolerance: Decimal) {
+            assert!(new_tolerance >= dec!(0), "Tolerance can't be negative.");
+            self.parameters.dust_tolerance = new_tolerance;
         }
 
-        /// Liquidate a marked loan / CDP, without a marker receipt
+        /// Set the close fee, a fraction of the repaid debt paid on top when (partially) closing a loan (0.001 = 10 bps), routed to the stability reserve
+        pub fn set_close_fee(&mut self, new_fee: Decimal) {
+            assert!(
+                new_fee >= dec!(0) && new_fee < dec!(1),
+                "Close fee needs to be between 0 and 1."
+            );
+            self.parameters.close_fee = new_fee;
+        }
+
+        /// Set the share of a liquidation's shortfall (percentage owed - percentage received) that is compensated from the keeper rewards pool
+        pub fn set_keeper_compensation(&mut self, new_compensation: Decimal) {
+            assert!(
+                new_compensation >= dec!(0) && new_compensation <= dec!(1),
+                "Compensation needs to be between 0 and 1."
+            );
+            self.parameters.keeper_compensation = new_compensation;
+        }
+
+        /// Set the margin below a maximum share from which a collateral's share is considered near its cap (0.05 = 5 percentage points)
+        pub fn set_share_warning_margin(&mut self, new_margin: Decimal) {
+            self.parameters.share_warning_margin = new_margin;
+        }
+
+        /// Set the force mint multiplier
+        ///   - multiplier is used to calculate the minimum collateral ratio that will ever be reached through force minting
+        ///       - a multiplier of 2, and an mcr of 1.5 would mean the lowest collateralization ratio reached by forced minting would be 300%
+        pub fn set_force_mint_multiplier(&mut self, new_multiplier: Decimal) {
+            self.parameters.force_mint_cr_multiplier = new_multiplier;
+        }
+
+        /// Gets the internal price of the STAB token
+        pub fn return_internal_price(&self) -> Decimal {
+            self.internal_stab_price
+        }
+
+        /// Gets the USD price of a collateral
+        pub fn get_collateral_price(&self, collateral: ResourceAddress) -> Decimal {
+            self.collaterals
+                .get(&collateral)
+                .expect("Collateral not found.")
+                .usd_price
+        }
+
+        /// Gets the protocol parameters
+        pub fn get_parameters(&self) -> ProtocolParameters {
+            self.parameters.clone()
+        }
+
+        /// Quote opening a loan / CDP, without actually opening it
         ///
         /// # Input
-        /// - `marker_id`: The marker receipt id
-        /// - `payment`: The STAB tokens to pay back
+        /// - `collateral`: The collateral to be used
+        /// - `collateral_amount`: The amount of collateral to be used
+        /// - `stab_to_mint`: The amount of STAB to mint
         ///
-        /// # Output, depends on outcome:
-        /// 1: liquidation successful
-        /// - The collateral reward
-        /// - The leftover STAB
-        /// - A liquidation receipt
-        /// 2: liquidation unsuccessful (because the loan was saved):
-        /// - The STAB tokens
-        /// - None
-        /// - A liquidation receipt (with saved status)
+        /// # Output
+        /// - An `OpenQuote` describing the resulting loan
         ///
         /// # Logic
-        /// - Get the CDP to be liquidated:
-        ///   - If automatic is true, find the next to-be liquidated CDP, skipping over the amount of CDPs specified by the skip parameter.
-        ///     - If no CDPs are found, panic.
-        ///     - If too many CDPs are skipped, panic.
-        ///   - If automatic is false, use the CDP receipt specified by the cdp_id parameter.
-        /// - Get the marker receipt through the CDP data
-        /// - Try to liquidate the CDP, using the try_liquidate method (see that method for more details)
-        pub fn liquidate_position_without_marker(
-            &mut self,
-            payment: Bucket,
-            skip: Option<i64>,
-            cdp_id: NonFungibleLocalId,
-        ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
-            assert!(
-                payment.resource_address() == self.stab_manager.address(),
-                "Invalid STAB payment."
-            );
-            let mut collateral_id: NonFungibleLocalId = cdp_id;
-            let mut skip_counter: i64 = 0;
-            let mut found: bool = false;
+        /// - Check whether collateral is accepted and if it is a pool unit
+        /// - Calculate real collateral amount, converting pool unit to real (underlying asset) if necessary
+        /// - Calculate collateral value, debt value and resulting collateral ratio
+        /// - Calculate the maximum mintable STAB and the collateral price at which the loan becomes liquidatable
+        /// - Check whether the loan could be opened right now
+        pub fn quote_open(
+            &self,
+            collateral: ResourceAddress,
+            collateral_amount: Decimal,
+            stab_to_mint: Decimal,
+        ) -> OpenQuote {
+            let is_pool_unit_collateral: bool = self.pool_units.get(&collateral).is_some();
 
-            if let Some(skip) = skip {
-                for (_identifier, found_collateral_id, _next_key) in
-                    self.marked_cdps.range(dec!(0)..)
-                {
-                    collateral_id = found_collateral_id.clone();
-                    skip_counter += 1;
-                    if (skip_counter - 1) == skip {
-                        found = true;
-                        break;
-                    }
-                }
-                if skip_counter == 0 {
-                    panic!("No loans available to liquidate.");
-                } else if !found {
-                    panic!(
-                        "Too many skipped. Skip a maximum of {} loans.",
-                        skip_counter - 1
-                    );
+            let (accepted, parent_address): (bool, ResourceAddress) = match is_pool_unit_collateral
+            {
+                true => {
+                    let pool_unit = self.pool_units.get(&collateral).unwrap();
+                    (pool_unit.accepted, pool_unit.parent_address)
                 }
-            }
+                false => (
+                    self.collaterals
+                        .get(&collateral)
+                        .map(|c| c.accepted)
+                        .unwrap_or(false),
+                    collateral,
+                ),
+            };
 
-            let cdp_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
-            let marker_data: CdpMarker = self
-                .cdp_marker_manager
-                .get_non_fungible_data(&NonFungibleLocalId::integer(cdp_data.marker_id));
+            let real_collateral_amount: Decimal =
+                self.pool_to_real(collateral_amount, collateral, is_pool_unit_collateral);
 
-            let marker_id: NonFungibleLocalId = NonFungibleLocalId::integer(cdp_data.marker_id);
+            let collateral_info = self
+                .collaterals
+                .get(&parent_address)
+                .expect("Collateral not known.");
 
-            self.try_liquidate(
-                payment,
-                cdp_data,
-                marker_data,
-                marker_id,
-                self.parameters.liquidation_delay + self.parameters.unmarked_delay,
-            )
+            let collateral_value: Decimal = collateral_info.usd_price * real_collateral_amount;
+            let debt_value: Decimal = self.internal_stab_price * stab_to_mint;
+
+            let collateral_ratio: Decimal = match debt_value > dec!(0) {
+                true => collateral_value / debt_value,
+                false => dec!(0),
+            };
+
+            let liquidation_price: Decimal = match real_collateral_amount > dec!(0) {
+                true => debt_value * collateral_info.mcr / real_collateral_amount,
+                false => dec!(0),
+            };
+
+            OpenQuote {
+                version: INTEGRATION_API_VERSION,
+                collateral,
+                parent_address,
+                is_pool_unit_collateral,
+                real_collateral_amount,
+                stab_to_mint,
+                collateral_value,
+                debt_value,
+                collateral_ratio,
+                mcr: collateral_info.mcr,
+                max_mintable: collateral_value / (self.internal_stab_price * collateral_info.mcr),
+                liquidation_price,
+                possible: accepted
+                    && !self.is_paused(PausableAction::OpenCdp, Some(collateral))
+                    && stab_to_mint >= self.parameters.minimum_mint
+                    && self
+                        .get_max_cdp_debt(parent_address)
+                        .map_or(true, |max_debt| stab_to_mint <= max_debt)
+                    && collateral_value >= debt_value * collateral_info.mcr,
+            }
         }
 
-        /// Changes the price of a collateral, which will also update the liquidation collateral ratio
-        pub fn change_collateral_price(&mut self, collateral: ResourceAddress, new_price: Decimal) {
-            let mcr: Decimal = self.collaterals.get_mut(&collateral).unwrap().mcr;
-            self.collaterals.get_mut(&collateral).unwrap().usd_price = new_price;
-            self.collaterals
-                .get_mut(&collateral)
-                .unwrap()
-                .liquidation_collateral_ratio = mcr * (self.internal_stab_price / new_price);
+        /// Quote closing a loan / CDP, without actually closing it
+        ///
+        /// # Input
+        /// - `receipt_id`: The CDP receipt
+        ///
+        /// # Output
+        /// - A `CloseQuote` describing what closing the loan requires and returns
+        pub fn quote_close(&self, receipt_id: NonFungibleLocalId) -> CloseQuote {
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
 
-            Runtime::emit_event(EventChangeCollateral {
-                address: collateral,
-                new_mcr: None,
-                new_usd_price: Some(new_price),
-            });
+            CloseQuote {
+                version: INTEGRATION_API_VERSION,
+                cdp_id: receipt_id,
+                collateral: receipt_data.collateral,
+                collateral_returned: receipt_data.collateral_amount,
+                stab_to_repay: receipt_data.minted_stab * (dec!(1) + self.parameters.close_fee),
+                possible: receipt_data.status == CdpStatus::Healthy
+                    && !self.is_paused(PausableAction::CloseCdp, Some(receipt_data.collateral)),
+                status: receipt_data.status,
+            }
         }
 
-        /// Add a possible collateral to the protocol
-        pub fn add_collateral(
-            &mut self,
-            address: ResourceAddress,
-            chosen_mcr: Decimal,
-            initial_price: Decimal,
-        ) {
+        /// Gets the internal STAB price at which a loan / CDP becomes liquidatable, assuming constant collateral prices
+        ///   - the loan is liquidatable once its collateral ratio drops below mcr * internal price / collateral price
+        pub fn get_liquidation_internal_price(&self, receipt_id: NonFungibleLocalId) -> Decimal {
+            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
             assert!(
-                self.collaterals.get(&address).is_none(),
-                "Collateral is already accepted."
+                receipt_data.status == CdpStatus::Healthy
+                    || receipt_data.status == CdpStatus::Marked,
+                "Loan not healthy or marked."
             );
 
-            let info = CollateralInfo {
-                mcr: chosen_mcr,
-                usd_price: initial_price,
-                liquidation_collateral_ratio: chosen_mcr * self.internal_stab_price / initial_price,
-                vault: Vault::new(address),
-                resource_address: address,
-                treasury: Vault::new(address),
-                accepted: true,
-                initialized: false,
-                max_stab_share: dec!(1),
-                minted_stab: dec!(0),
-                collateral_amount: dec!(0),
-                highest_cr: dec!(0),
-            };
-
-            self.collaterals.insert(address, info);
+            let cr: Decimal = self.pool_to_real(
+                receipt_data.collateral_amount,
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+            ) / receipt_data.minted_stab;
 
-            Runtime::emit_event(EventAddCollateral {
-                address,
-                mcr: chosen_mcr,
-                usd_price: initial_price,
-            });
+            let usd_price: Decimal = self
+                .collaterals
+                .get(&receipt_data.parent_address)
+                .unwrap()
+                .usd_price;
+            cr * usd_price / self.liquidation_mcr(receipt_data.parent_address)
         }
 
-        /// Add a possible pool collateral to the protocol
-        ///   - pool collaterals are collaterals are some kind of pool unit (such as LSUs), with an underlying asset that is already a collateral
-        ///       - the collateral amount is calculated when a loan is opened and interacted with, so not continuously updated
-        ///          - this means that sometimes a loan can be liquidated, but when interacting with it, the collateral amount is updated so it can't be anymore
-        ///             - this results in the loan being saved
-        pub fn add_pool_collateral(
+        /// Gets the data of all liquidation receipts minted within a range of receipt ids
+        ///
+        /// # Input
+        /// - `from`: The first liquidation receipt id to include
+        /// - `to`: The last liquidation receipt id to include (capped to the latest minted receipt)
+        ///
+        /// # Output
+        /// - A vector of liquidation receipt ids and their data, skipping burned receipts
+        pub fn get_liquidation_receipts(
             &self,
-            address: ResourceAddress,
-            parent_address: ResourceAddress,
-            pool_address: ComponentAddress,
-            lsu: bool,
-            initial_acceptance: bool,
-        ) {
+            from: u64,
+            to: u64,
+        ) -> Vec<(NonFungibleLocalId, LiquidationReceipt)> {
+            let last: u64 = to.min(self.liquidation_counter);
             assert!(
-                self.pool_units.get(&address).is_none(),
-                "Collateral is already accepted."
+                from <= last && last - from < self.parameters.max_vector_length,
+                "Invalid range, request at most {} receipts.",
+                self.parameters.max_vector_length
             );
 
-            let mut validator: Option<Global<Validator>> = None;
-            let mut one_resource_pool: Option<Global<OneResourcePool>> = None;
+            let mut receipts: Vec<(NonFungibleLocalId, LiquidationReceipt)> = Vec::new();
 
-            if lsu {
-                validator = Some(Global::from(pool_address));
-            } else {
-                one_resource_pool = Some(Global::from(pool_address));
+            for id in from.max(1)..=last {
+                let receipt_id: NonFungibleLocalId = NonFungibleLocalId::integer(id);
+                if self
+                    .liquidation_receipt_manager
+                    .non_fungible_exists(&receipt_id)
+                {
+                    let receipt: LiquidationReceipt = self
+                        .liquidation_receipt_manager
+                        .get_non_fungible_data(&receipt_id);
+                    receipts.push((receipt_id, receipt));
+                }
             }
 
-            let info = PoolUnitInfo {
-                vault: Vault::new(address),
-                treasury: Vault::new(address),
-                lsu,
-                validator,
-                one_resource_pool,
-                parent_address,
-                address,
-                accepted: initial_acceptance,
-                max_pool_share: dec!(1),
-                minted_stab: dec!(0),
-            };
+            receipts
+        }
+
+        /// Deposit STAB into the keeper rewards pool, used to compensate unprofitable liquidations
+        pub fn deposit_keeper_rewards(&mut self, rewards: Bucket) {
+            assert!(
+                rewards.resource_address() == self.stab_manager.address(),
+                "Only STAB can be deposited."
+            );
+            self.keeper_rewards.put(rewards);
+        }
+
+        /// Get the amount of STAB in the keeper rewards pool
+        pub fn get_keeper_rewards(&self) -> Decimal {
+            self.keeper_rewards.amount()
+        }
+
+        /// Get the amount of STAB in the stability reserve
+        pub fn get_stability_reserve(&self) -> Decimal {
+            self.stability_reserve.amount()
+        }
+
+        /// Get the total amount of STAB donated and burned against the system's debt
+        pub fn get_donated_stab(&self) -> Decimal {
+            self.donated_stab
+        }
+
+        /// Get the addresses of all resources managed by the component, so integrators don't need to parse instantiation events
+        pub fn get_resource_addresses(&self) -> ResourceAddresses {
+            ResourceAddresses {
+                stab: self.stab_manager.address(),
+                cdp_receipt: self.cdp_manager.address(),
+                cdp_marker: self.cdp_marker_manager.address(),
+                liquidation_receipt: self.liquidation_receipt_manager.address(),
+                withdrawal_claim: self.withdrawal_claim_manager.address(),
+                donation_receipt: self.donation_receipt_manager.address(),
+                controller_badge: self.controller_badge_manager.address(),
+                insurance_badge: self.insurance_badge_manager.address(),
+            }
+        }
+
+        /// Whether this is a sandbox instantiation
+        pub fn is_sandbox(&self) -> bool {
+            self.sandbox
+        }
+
+        /// Get the resource address of the liquidation receipts
+        pub fn get_liquidation_receipt_address(&self) -> ResourceAddress {
+            self.liquidation_receipt_manager.address()
+        }
+
+        /// Redeem a liquidation receipt for compensation from the keeper rewards pool
+        ///
+        /// # Input
+        /// - `receipt_id`: The liquidation receipt
+        ///
+        /// # Output
+        /// - The compensation in STAB
+        ///
+        /// # Logic
+        /// - Calculate the shortfall of the liquidation (stab paid * (percentage owed - percentage received))
+        /// - Calculate the compensation still owed (shortfall * keeper compensation - already compensated, by the stability reserve or earlier redemptions)
+        /// - Cap the compensation at the STAB in the keeper rewards pool (the rest can be redeemed after the pool is refilled)
+        /// - Update the liquidation receipt and return the compensation
+        pub fn redeem_liquidation_receipt(&mut self, receipt_id: NonFungibleLocalId) -> Bucket {
+            let receipt_data: LiquidationReceipt = self
+                .liquidation_receipt_manager
+                .get_non_fungible_data(&receipt_id);
 
-            self.pool_units.insert(address, info);
+            assert!(
+                receipt_data.percentage_received < receipt_data.percentage_owed,
+                "Liquidation was profitable, no compensation owed."
+            );
 
-            Runtime::emit_event(EventAddPoolCollateral {
-                address,
-                parent_address,
-            });
-        }
+            let shortfall: Decimal = receipt_data.stab_paid
+                * (receipt_data.percentage_owed - receipt_data.percentage_received);
+            let owed: Decimal =
+                shortfall * self.parameters.keeper_compensation - receipt_data.compensated;
+            let amount: Decimal = owed.min(self.keeper_rewards.amount());
 
-        /// Changes the internal price of the STAB token
-        pub fn change_internal_price(&mut self, new_price: Decimal) {
-            self.internal_stab_price = new_price;
+            assert!(amount > dec!(0), "No compensation available.");
 
-            Runtime::emit_event(EventChangePeg {
-                internal_price: new_price,
-            });
+            self.liquidation_receipt_manager.update_non_fungible_data(
+                &receipt_id,
+                "compensated",
+                receipt_data.compensated + amount,
+            );
+
+            Runtime::emit_event(EventKeeperCompensation { receipt_id, amount });
+
+            self.keeper_rewards.take(amount)
         }
 
-        ///Emptying the treasury of a collateral, error_fallback exists if a pool unit is also in self.collaterals
-        pub fn empty_collateral_treasury(
-            &mut self,
-            amount: Decimal,
-            collateral: ResourceAddress,
-            error_fallback: bool,
-        ) -> Bucket {
-            if self.pool_units.get(&collateral).is_some() && !error_fallback {
-                return self
-                    .pool_units
-                    .get_mut(&collateral)
-                    .unwrap()
-                    .treasury
-                    .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero));
-            } else {
-                return self
-                    .collaterals
-                    .get_mut(&collateral)
-                    .unwrap()
-                    .treasury
-                    .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+        /// Gets the state of the component, including the utilization of the requested collaterals (unknown collaterals are skipped)
+        pub fn get_state(&self, collaterals: Vec<ResourceAddress>) -> StabilisState {
+            StabilisState {
+                circulating_stab: self.circulating_stab,
+                internal_price: self.internal_stab_price,
+                marked_cdps: self.marked_cdps_active,
+                collaterals: collaterals
+                    .into_iter()
+                    .filter_map(|address| self.get_utilization(address))
+                    .collect(),
             }
         }
 
-        /// Mint a controller badge
-        pub fn mint_controller_badge(&self, amount: Decimal) -> Bucket {
-            self.controller_badge_manager.mint(amount)
+        /// Gets the breakdown of the STAB supply: the total supply, the circulating STAB minted through loans, the donated STAB, and the STAB minted per collateral
+        ///   - minted STAB of pool units is included in their parent collateral's
+        pub fn get_supply_breakdown(&self) -> SupplyBreakdown {
+            SupplyBreakdown {
+                total_supply: self.stab_manager.total_supply().unwrap(),
+                circulating_stab: self.circulating_stab,
+                donated_stab: self.donated_stab,
+                collaterals: self
+                    .collateral_addresses
+                    .iter()
+                    .filter_map(|address| self.get_utilization(*address))
+                    .collect(),
+            }
         }
 
-        /// Edit a collateral's parameters
-        pub fn edit_collateral(
-            &mut self,
-            address: ResourceAddress,
-            new_mcr: Decimal,
-            new_acceptance: bool,
-            new_max_share: Decimal,
-        ) {
-            self.collaterals.get_mut(&address).unwrap().accepted = new_acceptance;
-            self.collaterals.get_mut(&address).unwrap().mcr = new_mcr;
-            self.collaterals.get_mut(&address).unwrap().max_stab_share = new_max_share;
+        /// Gets the utilization of a collateral (None for unknown collaterals)
+        fn get_utilization(&self, address: ResourceAddress) -> Option<CollateralUtilization> {
+            self.collaterals
+                .get(&address)
+                .map(|info| CollateralUtilization {
+                    address,
+                    accepted: info.accepted,
+                    minted_stab: info.minted_stab,
+                    stab_share: match self.circulating_stab > dec!(0) {
+                        true => info.minted_stab / self.circulating_stab,
+                        false => dec!(0),
+                    },
+                    max_stab_share: info.max_stab_share,
+                    collateral_amount: info.collateral_amount,
+                })
+        }
 
-            Runtime::emit_event(EventChangeCollateral {
-                address,
-                new_mcr: Some(new_mcr),
-                new_usd_price: None,
-            });
+        /// Gets the aggregate collateralization ratio of the system, with a breakdown per collateral
+        ///
+        /// # Output
+        /// - The total collateral value, circulating STAB value and their ratio, per collateral and in total
+        ///
+        /// # Logic
+        /// - Value the collateral backing loans at the stored collateral prices
+        ///     - pool unit collateral is counted in parent collateral, at the pool_to_real snapshots taken when their loans' CRs were last calculated
+        /// - Value the minted STAB at the internal price
+        /// - Value the donated STAB at the internal price, and offset it against the circulating STAB for the total ratio
+        pub fn get_global_cr(&self) -> GlobalCr {
+            let mut breakdown: Vec<CollateralCr> = Vec::new();
+            let mut collateral_value: Decimal = dec!(0);
+
+            for address in self.collateral_addresses.iter() {
+                let info = self.collaterals.get(address).unwrap();
+                let value: Decimal = info.collateral_amount * info.usd_price;
+                let stab_value: Decimal = info.minted_stab * self.internal_stab_price;
+                collateral_value += value;
+                breakdown.push(CollateralCr {
+                    address: *address,
+                    collateral_value: value,
+                    minted_stab: info.minted_stab,
+                    stab_value,
+                    cr: match stab_value > dec!(0) {
+                        true => Some(value / stab_value),
+                        false => None,
+                    },
+                });
+            }
+
+            let stab_value: Decimal = self.circulating_stab * self.internal_stab_price;
+            let surplus_value: Decimal = self.donated_stab * self.internal_stab_price;
+
+            GlobalCr {
+                collateral_value,
+                stab_value,
+                surplus_value,
+                cr: match stab_value > surplus_value {
+                    true => Some(collateral_value / (stab_value - surplus_value)),
+                    false => None,
+                },
+                collaterals: breakdown,
+            }
         }
 
-        /// Edit a pool collateral's parameters
-        pub fn edit_pool_collateral(
-            &mut self,
-            address: ResourceAddress,
-            new_acceptance: bool,
-            new_max_share: Decimal,
-        ) {
-            self.pool_units.get_mut(&address).unwrap().accepted = new_acceptance;
-            self.pool_units.get_mut(&address).unwrap().max_pool_share = new_max_share;
+        /// Get the shares of collaterals and pool units compared to their caps, so borrowers can be warned before the share check fails
+        ///   - collateral shares are compared to the circulating STAB, pool unit shares to the STAB minted with their parent collateral
+        ///   - unknown addresses are skipped
+        pub fn get_share_warnings(&self, collaterals: Vec<ResourceAddress>) -> Vec<ShareWarning> {
+            collaterals
+                .into_iter()
+                .filter_map(|address| self.get_share_warning(address))
+                .collect()
         }
 
-        /// Set delay until a loan can be liquidated after marking (in minutes)
-        pub fn set_liquidation_delay(&mut self, new_delay: i64) {
-            self.parameters.liquidation_delay = new_delay;
+        /// Claim collateral that was queued by the withdrawal throttle
+        ///
+        /// # Input
+        /// - `claim`: The withdrawal claim
+        ///
+        /// # Output
+        /// - The queued collateral
+        ///
+        /// # Logic
+        /// - Check if the claim is valid and claimable
+        /// - Take the collateral from the withdrawal queue
+        /// - Burn the claim
+        pub fn claim_withdrawal(&mut self, claim: Bucket) -> Bucket {
+            assert!(
+                claim.resource_address() == self.withdrawal_claim_manager.address(),
+                "Invalid withdrawal claim."
+            );
+
+            let claim_id: NonFungibleLocalId = claim.as_non_fungible().non_fungible_local_id();
+            let claim_data: WithdrawalClaim = self
+                .withdrawal_claim_manager
+                .get_non_fungible_data(&claim_id);
+
+            assert!(
+                Clock::current_time_is_at_or_after(
+                    claim_data.claimable_from,
+                    TimePrecision::Second
+                ),
+                "Withdrawal can't be claimed yet."
+            );
+
+            let collateral: Bucket = self
+                .withdrawal_queue
+                .get_mut(&claim_data.collateral)
+                .unwrap()
+                .take(claim_data.amount);
+
+            claim.burn();
+
+            Runtime::emit_event(EventWithdrawalClaimed {
+                claim_id,
+                collateral: claim_data.collateral,
+                amount: claim_data.amount,
+            });
+
+            collateral
         }
 
-        /// Set delay until a loan can be liquited without marker, after it could be liquidated with a marker (in minutes)
-        pub fn set_unmarked_delay(&mut self, new_delay: i64) {
-            self.parameters.unmarked_delay = new_delay;
+        /// Get the debt of a collateral's healthy loans, grouped by collateral ratio, for risk dashboards
+        ///
+        /// # Input
+        /// - `collateral`: The (parent) collateral
+        /// - `bounds`: The collateral ratio bounds of the ranges (ascending, in value terms: 1.5 = 150%)
+        ///
+        /// # Output
+        /// - The `CrHistogram`, with one more range than there are bounds
+        ///
+        /// # Logic
+        /// - Walk the AvlTree of the collateral once, from the lowest collateral ratio up
+        /// - Convert every collateral ratio to value terms, using the collateral's price and the internal STAB price
+        /// - Add the loan's minted STAB to its range
+        /// - Stop after max_vector_length loans, marking the histogram incomplete
+        pub fn get_cr_histogram(
+            &self,
+            collateral: ResourceAddress,
+            bounds: Vec<Decimal>,
+        ) -> CrHistogram {
+            assert!(
+                bounds.windows(2).all(|pair| pair[0] < pair[1]),
+                "Bounds need to be ascending."
+            );
+
+            let value_factor: Decimal =
+                self.collaterals.get(&collateral).unwrap().usd_price / self.internal_stab_price;
+            let max_iterations: u64 = self.parameters.max_vector_length;
+
+            let mut debt: Vec<Decimal> = vec![dec!(0); bounds.len() + 1];
+            let mut cdps: Vec<u64> = vec![0; bounds.len() + 1];
+            let mut iterations: u64 = 0;
+            let mut complete: bool = true;
+
+            let collateral_ratios = self.collateral_ratios.get(&collateral).unwrap();
+
+            'outer_loop: for (cr, collateral_ids, _next_key) in collateral_ratios.range(dec!(0)..) {
+                let range: usize = bounds.partition_point(|bound| *bound <= cr * value_factor);
+                for collateral_id in collateral_ids {
+                    if iterations == max_iterations {
+                        complete = false;
+                        break 'outer_loop;
+                    }
+                    iterations += 1;
+
+                    let data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+                    debt[range] += data.minted_stab;
+                    cdps[range] += 1;
+                }
+            }
+
+            CrHistogram {
+                bounds,
+                debt,
+                cdps,
+                complete,
+            }
         }
 
-        /// Set availability of liquidations, openings, closings, force minting and force liquidations
-        pub fn set_stops(
-            &mut self,
-            liquidations: bool,
-            openings: bool,
-            closings: bool,
-            force_mint: bool,
-            force_liquidate: bool,
-        ) {
-            self.parameters.stop_closings = closings;
-            self.parameters.stop_liquidations = liquidations;
-            self.parameters.stop_openings = openings;
-            self.parameters.stop_force_liquidate = force_liquidate;
-            self.parameters.stop_force_mint = force_mint;
+        /// Get the data and current collateral ratios of a list of loans / CDPs in one call, so risk engines can refresh them in batches
+        ///   - the list can't be longer than max_vector_length
+        ///   - collateral ratios use the current pool_to_real rate of pool units, and the current prices
+        pub fn export_cdps(&self, cdp_ids: Vec<NonFungibleLocalId>) -> Vec<CdpExport> {
+            assert!(
+                cdp_ids.len() as u64 <= self.parameters.max_vector_length,
+                "Too many loans requested."
+            );
+
+            cdp_ids
+                .into_iter()
+                .map(|cdp_id| {
+                    let cdp: Cdp = self.cdp_manager.get_non_fungible_data(&cdp_id);
+                    let (cr, value_cr): (Decimal, Decimal) = match cdp.minted_stab > dec!(0) {
+                        true => {
+                            let cr: Decimal = self.pool_to_real(
+                                cdp.collateral_amount,
+                                cdp.collateral,
+                                cdp.is_pool_unit_collateral,
+                            ) / cdp.minted_stab;
+                            let usd_price: Decimal =
+                                self.collaterals.get(&cdp.parent_address).unwrap().usd_price;
+                            (cr, cr * usd_price / self.internal_stab_price)
+                        }
+                        false => (dec!(0), dec!(0)),
+                    };
+
+                    CdpExport {
+                        cdp_id,
+                        cdp,
+                        cr,
+                        value_cr,
+                    }
+                })
+                .collect()
         }
 
-        /// Set the maximum vector length for the collateral ratios (to prevent state explosion, vectors are non-lazily loaded)
-        pub fn set_max_vector_length(&mut self, new_max_length: u64) {
-            self.parameters.max_vector_length = new_max_length;
+        /// Get the current MCR surcharge of a collateral (added to its MCR for the liquidation collateral ratio)
+        pub fn get_mcr_surcharge(&self, collateral: ResourceAddress) -> Decimal {
+            let info = self.collaterals.get(&collateral).unwrap();
+            match (&info.surcharge_parameters, info.surcharge_triggered_at) {
+                (Some(parameters), Some(triggered_at)) => {
+                    let elapsed_minutes: Decimal = Decimal::from(
+                        Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch
+                            - triggered_at.seconds_since_unix_epoch,
+                    ) / dec!(60);
+                    let remaining: Decimal =
+                        dec!(1) - elapsed_minutes / Decimal::from(parameters.decay_period);
+                    if remaining > dec!(0) {
+                        info.surcharge * remaining
+                    } else {
+                        dec!(0)
+                    }
+                }
+                _ => dec!(0),
+            }
         }
 
-        /// Set the minimum mintable amount of STAB (to prevent unprofitable liquidations)
-        pub fn set_minimum_mint(&mut self, new_minimum_mint: Decimal) {
-            self.parameters.minimum_mint = new_minimum_mint;
+        /// Get the share enforcement status of a collateral: its share, whether it is enforced and the current share surcharge
+        ///   - `stab_to_repay` suggests the debt to repay (or shift to other collaterals) to bring the share back to the release share
+        pub fn get_share_enforcement(&self, collateral: ResourceAddress) -> ShareEnforcementStatus {
+            let info = self.collaterals.get(&collateral).unwrap();
+            let share: Decimal = match self.circulating_stab > dec!(0) {
+                true => info.minted_stab / self.circulating_stab,
+                false => dec!(0),
+            };
+            let target_share: Decimal = info
+                .share_enforcement
+                .as_ref()
+                .map_or(info.max_stab_share, |enforcement| enforcement.release_share);
+            let stab_to_repay: Decimal = match share > target_share && target_share < dec!(1) {
+                true => {
+                    (info.minted_stab - target_share * self.circulating_stab)
+                        / (dec!(1) - target_share)
+                }
+                false => dec!(0),
+            };
+
+            ShareEnforcementStatus {
+                share,
+                max_share: info.max_stab_share,
+                enforcement: info.share_enforcement.clone(),
+                enforced_since: info.share_enforced_since,
+                surcharge: self.get_share_surcharge(collateral),
+                stab_to_repay,
+            }
         }
 
-        /// Set fines for being liquidated (for liquidators and the protocol)
-        ///   - a liquidator fine of 0.05 and protocol fine of 0.03 would mean a liquidation would result in 1 + 0.05 + 0.03 = 1.08 times the minted STAB's value collateral being taken from the borrower.
-        pub fn set_fines(&mut self, liquidator_fine: Decimal, stabilis_fine: Decimal) {
-            self.parameters.liquidation_liquidation_fine = liquidator_fine;
-            self.parameters.stabilis_liquidation_fine = stabilis_fine;
+        /// Get the number of marks placed for a collateral in the current minute
+        pub fn get_mark_rate(&self, collateral: ResourceAddress) -> u64 {
+            match self.mark_rates.get(&collateral) {
+                Some(rate)
+                    if Clock::current_time_is_strictly_before(
+                        rate.minute_start.add_minutes(1).unwrap(),
+                        TimePrecision::Second,
+                    ) =>
+                {
+                    rate.marks
+                }
+                _ => 0,
+            }
         }
 
-        /// Set the force mint multiplier
-        ///   - multiplier is used to calculate the minimum collateral ratio that will ever be reached through force minting
-        ///       - a multiplier of 2, and an mcr of 1.5 would mean the lowest collateralization ratio reached by forced minting would be 300%
-        pub fn set_force_mint_multiplier(&mut self, new_multiplier: Decimal) {
-            self.parameters.force_mint_cr_multiplier = new_multiplier;
+        /// Get the current withdrawal window of a collateral (or pool unit), if there is one
+        pub fn get_withdrawal_window(
+            &self,
+            collateral: ResourceAddress,
+        ) -> Option<WithdrawalWindow> {
+            self.withdrawal_windows
+                .get(&collateral)
+                .map(|window| window.clone())
         }
 
-        /// Gets the internal price of the STAB token
-        pub fn return_internal_price(&self) -> Decimal {
-            self.internal_stab_price
+        /// Get the total amount of a collateral (or pool unit) that is queued by the withdrawal throttle
+        pub fn get_queued_withdrawals(&self, collateral: ResourceAddress) -> Decimal {
+            self.withdrawal_queue
+                .get(&collateral)
+                .map(|vault| vault.amount())
+                .unwrap_or(dec!(0))
         }
 
         /// Mints free STAB (used by the flash loan component, for instance)
@@ -1944,6 +5487,57 @@ mod stabilis_component {
             bucket.burn();
         }
 
+        /// Burns donated STAB against the system's debt, for DAO buybacks or goodwill burns
+        ///
+        /// # Input
+        /// - `stab`: The STAB to donate
+        /// - `memo`: An optional memo of the donor
+        ///
+        /// # Output
+        /// - The donation receipt
+        ///
+        /// # Logic
+        /// - Burn the STAB, without repaying any loan (circulating STAB, the debt of all loans, stays the same)
+        /// - Credit the donated STAB to the system surplus
+        /// - Mint a donation receipt
+        pub fn donate_and_burn(&mut self, stab: Bucket, memo: Option<String>) -> Bucket {
+            assert!(
+                stab.resource_address() == self.stab_manager.address(),
+                "Can only donate STAB, not another token."
+            );
+            assert!(stab.amount() > dec!(0), "Can't donate nothing.");
+            if let Some(memo) = &memo {
+                assert!(
+                    memo.len() <= MAX_DONATION_MEMO_LENGTH,
+                    "Memo too long, max {} bytes.",
+                    MAX_DONATION_MEMO_LENGTH
+                );
+            }
+
+            let amount: Decimal = stab.amount();
+            stab.burn();
+            self.donated_stab += amount;
+
+            self.donation_counter += 1;
+            let receipt_id: NonFungibleLocalId = NonFungibleLocalId::integer(self.donation_counter);
+            let receipt: Bucket = self.donation_receipt_manager.mint_non_fungible(
+                &receipt_id,
+                DonationReceipt {
+                    amount,
+                    donated_at: Clock::current_time_rounded_to_seconds(),
+                    memo,
+                },
+            );
+
+            Runtime::emit_event(EventDonation {
+                receipt_id,
+                amount,
+                donated_stab: self.donated_stab,
+            });
+
+            receipt
+        }
+
         /// Burns a used marker
         pub fn burn_marker(&self, marker: Bucket) {
             let data: CdpMarker = marker.as_non_fungible().non_fungible().data();
@@ -1955,6 +5549,26 @@ mod stabilis_component {
             marker.burn();
         }
 
+        /// Burns all used markers in a bucket at once, returning the markers that aren't used yet
+        pub fn burn_markers(&self, markers: Bucket) -> Bucket {
+            assert!(
+                self.cdp_marker_manager.address() == markers.resource_address(),
+                "Can only burn markers, not another token."
+            );
+            let mut markers: NonFungibleBucket = markers.as_non_fungible();
+            let used_ids: IndexSet<NonFungibleLocalId> = markers
+                .non_fungibles::<CdpMarker>()
+                .into_iter()
+                .filter(|marker| marker.data().used)
+                .map(|marker| marker.local_id().clone())
+                .collect();
+
+            if !used_ids.is_empty() {
+                markers.take_non_fungibles(&used_ids).burn();
+            }
+            markers.into()
+        }
+
         /// Burns a used loan receipt (has to be liquidated, closed or force liquidated, and have no collateral left)
         pub fn burn_loan_receipt(&self, receipt: Bucket) {
             let data: Cdp = receipt.as_non_fungible().non_fungible().data();
@@ -1975,6 +5589,94 @@ mod stabilis_component {
             receipt.burn();
         }
 
+        /// Protect a loan / CDP from force liquidations for a number of days, by paying a premium in its collateral
+        ///
+        /// # Input
+        /// - `receipt_id`: The CDP receipt
+        /// - `days`: The number of days to protect the loan (extending a current protection)
+        /// - `premium`: The collateral to pay the premium with
+        ///
+        /// # Output
+        /// - The leftover premium
+        ///
+        /// # Logic
+        /// - Check if the loan is healthy and the premium is paid in the loan's collateral
+        /// - Calculate the premium: collateral amount * premium per day * days
+        /// - Put the premium in the treasury
+        /// - Extend the protection of the loan
+        pub fn protect_cdp(
+            &mut self,
+            receipt_id: NonFungibleLocalId,
+            days: i64,
+            mut premium: Bucket,
+        ) -> Bucket {
+            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
+
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy."
+            );
+            assert!(days > 0, "Protect for at least a day.");
+            assert!(
+                premium.resource_address() == receipt_data.collateral,
+                "Premium needs to be paid in the loan's collateral."
+            );
+
+            let premium_amount: Decimal = receipt_data.collateral_amount
+                * self.parameters.redemption_protection_premium
+                * Decimal::from(days);
+
+            self.put_collateral_in_treasury(
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+                premium.take_advanced(premium_amount, self.rounding(true)),
+            );
+
+            let now: Instant = Clock::current_time_rounded_to_seconds();
+            let protection_start: Instant = match receipt_data
+                .protected_until
+                .compare(now, TimeComparisonOperator::Gt)
+            {
+                true => receipt_data.protected_until,
+                false => now,
+            };
+            let protected_until: Instant = protection_start.add_days(days).unwrap();
+
+            self.cdp_manager.update_non_fungible_data(
+                &receipt_id,
+                "protected_until",
+                protected_until,
+            );
+
+            receipt_data.protected_until = protected_until;
+            Runtime::emit_event(EventUpdateCdp {
+                cdp: receipt_data,
+                cdp_id: receipt_id,
+            });
+
+            premium
+        }
+
+        /// Sets the label of a loan / CDP
+        pub fn set_cdp_label(&mut self, receipt_id: NonFungibleLocalId, label: String) {
+            assert!(
+                label.len() <= MAX_CDP_LABEL_LENGTH,
+                "Label too long, max {} bytes.",
+                MAX_CDP_LABEL_LENGTH
+            );
+
+            let mut receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
+
+            self.cdp_manager
+                .update_non_fungible_data(&receipt_id, "label", label.clone());
+
+            receipt_data.label = label;
+            Runtime::emit_event(EventUpdateCdp {
+                cdp: receipt_data,
+                cdp_id: receipt_id,
+            });
+        }
+
         //HELPER METHODS
 
         /// Try to liquidate a CDP / loan
@@ -1985,6 +5687,7 @@ mod stabilis_component {
         /// - `marker_data`: The marker data
         /// - `marker_id`: The marker receipt id
         /// - `delay`: The delay until the loan can be liquidated from when it was marked
+        /// - `with_marker`: Whether the liquidation is done using a marker receipt
         ///
         /// # Output, depends on outcome:
         /// 1: liquidation successful
@@ -1997,10 +5700,12 @@ mod stabilis_component {
         /// - A liquidation receipt (with saved status)
         ///
         /// # Logic
-        /// - Get the liquidation collateral ratio
-        /// - Assert that liquidation is currently enabled, the marker is valid, the payment is sufficient, the time has passed, and the loan is marked
+        /// - Get the liquidation collateral ratio (which reflects the current collateral price)
+        /// - Assert that liquidation is currently enabled, the marker is valid and the loan is marked
         /// - Get the newest collateral ratio for the CDP
-        /// - Check whether the collateral ratio is sufficient, liquidate if not, save if it is
+        /// - If the collateral ratio is sufficient at current prices, save the loan (this is possible during the delay window, unmarking loans as soon as prices recover)
+        /// - Otherwise, assert the payment is sufficient and the time has passed, and liquidate
+        /// - Count the repaid debt in the loan's statistics
         fn try_liquidate(
             &mut self,
             payment: Bucket,
@@ -2008,6 +5713,7 @@ mod stabilis_component {
             marker_data: CdpMarker,
             marker_id: NonFungibleLocalId,
             delay: i64,
+            with_marker: bool,
         ) -> (Option<Bucket>, Option<Bucket>, Bucket) {
             let liquidation_collateral_ratio = self
                 .collaterals
@@ -2015,19 +5721,32 @@ mod stabilis_component {
                 .unwrap()
                 .liquidation_collateral_ratio;
 
-            assert!(
-                !self.parameters.stop_liquidations,
-                "Not allowed to liquidate loans right now."
-            );
-            assert!(
-                !marker_data.used && marker_data.mark_type == CdpUpdate::Marked,
-                "Non-valid marker."
-            );
+            assert!(
+                !self.is_paused(PausableAction::Liquidate, Some(cdp_data.collateral)),
+                "Not allowed to liquidate loans right now."
+            );
+            assert!(
+                !marker_data.used && marker_data.mark_type == CdpUpdate::Marked,
+                "Non-valid marker."
+            );
+            assert!(cdp_data.status == CdpStatus::Marked, "Loan not marked");
+            self.check_not_frozen(&marker_data.marked_id);
+
+            let cr: Decimal = self.pool_to_real(
+                cdp_data.collateral_amount,
+                cdp_data.collateral,
+                cdp_data.is_pool_unit_collateral,
+            ) / cdp_data.minted_stab;
+
+            if cr >= liquidation_collateral_ratio {
+                let marker_receipt: Bucket = self.save(marker_data, cdp_data, cr);
+                return (None, Some(payment), marker_receipt);
+            }
+
             assert!(
                 payment.amount() >= cdp_data.minted_stab,
                 "not enough STAB supplied to close completely"
             );
-
             assert!(
                 Clock::current_time_is_at_or_after(
                     marker_data.time_marked.add_minutes(delay).unwrap(),
@@ -2038,22 +5757,12 @@ mod stabilis_component {
                 marker_data.time_marked.seconds_since_unix_epoch
             );
 
-            assert!(cdp_data.status == CdpStatus::Marked, "Loan not marked");
-
-            let cr: Decimal = self.pool_to_real(
-                cdp_data.collateral_amount,
-                cdp_data.collateral,
-                cdp_data.is_pool_unit_collateral,
-            ) / cdp_data.minted_stab;
-
-            if cr < liquidation_collateral_ratio {
-                let (liquidation_payment, remainder, receipt): (Bucket, Bucket, Bucket) =
-                    self.liquidate(payment, marker_data, marker_id, cdp_data, cr);
-                (Some(liquidation_payment), Some(remainder), receipt)
-            } else {
-                let marker_receipt: Bucket = self.save(marker_data, cdp_data, cr);
-                (None, Some(payment), marker_receipt)
-            }
+            let cdp_id = marker_data.marked_id.clone();
+            let repaid = cdp_data.minted_stab;
+            let (liquidation_payment, remainder, receipt): (Bucket, Bucket, Bucket) =
+                self.liquidate(payment, marker_data, marker_id, cdp_data, cr, with_marker);
+            self.update_cdp_stats(&cdp_id, |stats| stats.total_repaid += repaid);
+            (Some(liquidation_payment), Some(remainder), receipt)
         }
 
         /// Liquidate a loan / CDP
@@ -2064,6 +5773,7 @@ mod stabilis_component {
         /// - `marker_id`: The marker receipt id
         /// - `cdp_data`: The CDP data
         /// - `cr`: The collateral ratio
+        /// - `with_marker`: Whether the liquidation is done using a marker receipt (which results in the higher marker liquidation fine)
         ///
         /// # Output
         /// - The collateral reward
@@ -2074,6 +5784,7 @@ mod stabilis_component {
         /// - Update minted STAB
         /// - Update collateral amount of the parent address
         /// - Calculate the minimum collateral ratio and the liquidation collateral ratio
+        /// - Determine the liquidator fine, depending on whether a marker was used
         /// - Create the liquidation receipt data
         /// - Update the marker and CDP receipts
         /// - Take the payment, check whether it's enough, and burn it
@@ -2090,7 +5801,13 @@ mod stabilis_component {
             marker_id: NonFungibleLocalId,
             cdp_data: Cdp,
             cr: Decimal,
+            with_marker: bool,
         ) -> (Bucket, Bucket, Bucket) {
+            self.settle_borrow_rewards(
+                &marker_data.marked_id,
+                cdp_data.parent_address,
+                cdp_data.minted_stab,
+            );
             self.update_minted_stab(
                 false,
                 cdp_data.is_pool_unit_collateral,
@@ -2104,22 +5821,37 @@ mod stabilis_component {
                 .get_mut(&cdp_data.parent_address)
                 .unwrap()
                 .collateral_amount -= cdp_data.collateral_stab_ratio * cdp_data.minted_stab;
+            self.record_activity(cdp_data.parent_address, |activity| {
+                activity.liquidations += 1
+            });
 
-            let mcr: Decimal = self.collaterals.get(&cdp_data.parent_address).unwrap().mcr;
+            let mcr: Decimal = self.liquidation_mcr(cdp_data.parent_address);
             let liq_cr: Decimal = self
                 .collaterals
                 .get(&cdp_data.parent_address)
                 .unwrap()
                 .liquidation_collateral_ratio;
+            let liquidator_fine: Decimal = match with_marker {
+                true => self.parameters.marker_liquidation_fine,
+                false => self.parameters.liquidation_liquidation_fine,
+            };
             let mut treasury_payment_amount: Option<Decimal> = None;
             let liquidation_payment_amount;
             let mut liquidation_receipt = LiquidationReceipt {
                 collateral: cdp_data.collateral,
                 stab_paid: cdp_data.minted_stab,
-                percentage_owed: dec!(1) + self.parameters.liquidation_liquidation_fine,
-                percentage_received: dec!(1) + self.parameters.liquidation_liquidation_fine,
+                percentage_owed: dec!(1) + liquidator_fine,
+                percentage_received: dec!(1) + liquidator_fine,
                 cdp_liquidated: marker_data.marked_id.clone(),
                 date_liquidated: Clock::current_time_rounded_to_seconds(),
+                collateral_price: self
+                    .collaterals
+                    .get(&cdp_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                leftover_collateral: dec!(0),
+                returned_collateral: dec!(0),
+                compensated: dec!(0),
             };
 
             self.liquidation_counter += 1;
@@ -2133,6 +5865,11 @@ mod stabilis_component {
                 "status",
                 CdpStatus::Liquidated,
             );
+            self.cdp_manager.update_non_fungible_data(
+                &marker_data.marked_id,
+                "liquidated_at",
+                Some(Clock::current_time_rounded_to_seconds()),
+            );
 
             assert!(
                 cdp_data.minted_stab <= payment.amount(),
@@ -2149,11 +5886,11 @@ mod stabilis_component {
             //sit 1: cr > 1 + liquidation fine + stabilis fine   -> everyone can receive complete fines
             //sit 2: cr > 1 + liquidation fine                   -> liquidator receives whole fine, stabilis a partial fine
             //sit 3: cr <= 1                                     -> liquidator receives whole collateral, which might be less than minted stab
+            //in sit 1, the collateral left after the fines stays in the CDP, for the borrower to retrieve (through retrieve_leftover_collateral)
+            //  - if the borrower set a refund account, the leftover above the leftover buffer is returned to it right away (see return_leftover)
+            //in sit 3, the liquidator's loss on the paid STAB (not the missed fine) is covered from the stability reserve (as far as possible), before the keeper rewards pool is used
 
-            if cr_percentage
-                > dec!(1)
-                    + self.parameters.liquidation_liquidation_fine
-                    + self.parameters.stabilis_liquidation_fine
+            if cr_percentage > dec!(1) + liquidator_fine + self.parameters.stabilis_liquidation_fine
             {
                 if self.parameters.stabilis_liquidation_fine > dec!(0) {
                     treasury_payment_amount = Some(
@@ -2161,13 +5898,11 @@ mod stabilis_component {
                             * (cdp_data.collateral_amount / cr_percentage),
                     );
                 }
-                liquidation_payment_amount = (dec!(1)
-                    + self.parameters.liquidation_liquidation_fine)
-                    * (cdp_data.collateral_amount / cr_percentage);
-            } else if cr_percentage > dec!(1) + self.parameters.liquidation_liquidation_fine {
-                liquidation_payment_amount = (dec!(1)
-                    + self.parameters.liquidation_liquidation_fine)
-                    * (cdp_data.collateral_amount / cr_percentage);
+                liquidation_payment_amount =
+                    (dec!(1) + liquidator_fine) * (cdp_data.collateral_amount / cr_percentage);
+            } else if cr_percentage > dec!(1) + liquidator_fine {
+                liquidation_payment_amount =
+                    (dec!(1) + liquidator_fine) * (cdp_data.collateral_amount / cr_percentage);
 
                 treasury_payment_amount =
                     Some(cdp_data.collateral_amount - liquidation_payment_amount);
@@ -2176,14 +5911,6 @@ mod stabilis_component {
                 liquidation_payment_amount = cdp_data.collateral_amount;
             }
 
-            let receipt: NonFungibleBucket = self
-                .liquidation_receipt_manager
-                .mint_non_fungible(
-                    &NonFungibleLocalId::integer(self.liquidation_counter),
-                    liquidation_receipt,
-                )
-                .as_non_fungible();
-
             let treasury_payment = if let Some(payment_amount) = treasury_payment_amount {
                 Some(self.take_collateral(
                     cdp_data.collateral,
@@ -2200,19 +5927,62 @@ mod stabilis_component {
                 liquidation_payment_amount,
             );
 
-            let leftover_collateral: Decimal = cdp_data.collateral_amount
+            let mut leftover_collateral: Decimal = cdp_data.collateral_amount
                 - liquidation_payment.amount()
                 - treasury_payment
                     .as_ref()
                     .map_or(dec!(0), |payment_bucket| payment_bucket.amount());
 
+            if leftover_collateral > dec!(0) {
+                let returned_collateral: Decimal = self.return_leftover(
+                    &marker_data.marked_id,
+                    &cdp_data,
+                    leftover_collateral,
+                    cdp_data.collateral_amount / cr_percentage,
+                );
+                leftover_collateral -= returned_collateral;
+                liquidation_receipt.returned_collateral = returned_collateral;
+            }
+
             self.cdp_manager.update_non_fungible_data(
                 &marker_data.marked_id,
                 "collateral_amount",
                 leftover_collateral,
             );
 
-            if let Some(payment) = treasury_payment {
+            liquidation_receipt.leftover_collateral = leftover_collateral;
+
+            let loss: Decimal = liquidation_receipt.stab_paid
+                * (dec!(1) - liquidation_receipt.percentage_received).max(dec!(0));
+            let reserve_cover: Decimal = loss.min(self.stability_reserve.amount());
+            if reserve_cover > dec!(0) {
+                payment.put(self.stability_reserve.take(reserve_cover));
+                liquidation_receipt.compensated = reserve_cover;
+                Runtime::emit_event(EventStabilityReserveUsed {
+                    receipt_id: NonFungibleLocalId::integer(self.liquidation_counter),
+                    amount: reserve_cover,
+                });
+            }
+
+            let receipt: NonFungibleBucket = self
+                .liquidation_receipt_manager
+                .mint_non_fungible(
+                    &NonFungibleLocalId::integer(self.liquidation_counter),
+                    liquidation_receipt,
+                )
+                .as_non_fungible();
+
+            if let Some(mut payment) = treasury_payment {
+                self.route_incentives(cdp_data.parent_address, true, &mut payment);
+                let insurance_payment: Bucket = payment.take_advanced(
+                    payment.amount() * self.parameters.insurance_share,
+                    self.rounding(false),
+                );
+                self.put_collateral_in_insurance(
+                    cdp_data.collateral,
+                    cdp_data.is_pool_unit_collateral,
+                    insurance_payment,
+                );
                 self.put_collateral_in_treasury(
                     cdp_data.collateral,
                     cdp_data.is_pool_unit_collateral,
@@ -2272,6 +6042,7 @@ mod stabilis_component {
 
             self.marked_cdps.remove(&marker_data.marker_placing);
             self.marked_cdps_active -= 1;
+            self.update_cdp_stats(&marker_data.marked_id, |stats| stats.times_saved += 1);
             self.cdp_manager.update_non_fungible_data(
                 &marker_data.marked_id,
                 "status",
@@ -2300,7 +6071,49 @@ mod stabilis_component {
             marker_receipt.into()
         }
 
+        /// Find the loan / CDP to force liquidate: the loan with the lowest collateral ratio that isn't redemption-protected
+        ///    - If only protected loans remain, the protected loan with the lowest collateral ratio is returned
+        ///    - Frozen loans are skipped
+        ///    - At most max_vector_length loans are checked, falling back to the first protected loan found, so cheap protected loans can't block force liquidations
+        fn find_force_liquidation_target(&self, collateral: ResourceAddress) -> NonFungibleLocalId {
+            let now: Instant = Clock::current_time_rounded_to_seconds();
+            let max_iterations: u64 = self.parameters.max_vector_length;
+            let mut iterations: u64 = 0;
+            let mut first_protected_id: Option<NonFungibleLocalId> = None;
+
+            let collateral_ratios = self.collateral_ratios.get(&collateral).unwrap();
+
+            'outer_loop: for (_cr, collateral_ids, _next_key) in collateral_ratios.range(dec!(0)..)
+            {
+                for collateral_id in collateral_ids {
+                    if iterations == max_iterations {
+                        break 'outer_loop;
+                    }
+                    iterations += 1;
+
+                    if self.is_frozen(&collateral_id) {
+                        continue;
+                    }
+                    let data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+                    if !data
+                        .protected_until
+                        .compare(now, TimeComparisonOperator::Gt)
+                    {
+                        return collateral_id;
+                    }
+                    if first_protected_id.is_none() {
+                        first_protected_id = Some(collateral_id);
+                    }
+                }
+            }
+
+            first_protected_id.expect("No loans available to force liquidate.")
+        }
+
         /// Insert a collateral ratio into the AvlTree
+        ///    - Loans with an equal collateral ratio are ordered by their id, so the oldest loan comes first
+        ///       - this ordering decides which loan is marked / force liquidated (lowest CR) or force minted (highest CR) first
+        ///       - it does not depend on when a loan was (re)inserted, so it can't be gamed by updating a loan
         fn insert_cr(
             &mut self,
             parent_address: ResourceAddress,
@@ -2326,7 +6139,10 @@ mod stabilis_component {
                     cdp_ids.len() < self.parameters.max_vector_length.try_into().unwrap(),
                     "CR vector is full..."
                 );
-                cdp_ids.push(cdp_id);
+                let position: usize = cdp_ids
+                    .binary_search(&cdp_id)
+                    .unwrap_or_else(|position| position);
+                cdp_ids.insert(position, cdp_id);
                 self.collateral_ratios
                     .get_mut(&parent_address)
                     .unwrap()
@@ -2379,33 +6195,55 @@ mod stabilis_component {
 
         /// Calculate the real value of a pool collateral, if it is a pool unit
         ///    - Example: a resource is an LSU, 1 LSU = 1.1 XRD. If the collateral amount is 10 LSU, 11 XRD is returned.
+        ///    - For non-LSU pool units, the pool unit's haircut is subtracted from the redemption value
+        ///    - Pool units priced through an adapter use the adapter's `get_redemption_value` method
         fn pool_to_real(
-            &mut self,
+            &self,
             amount: Decimal,
             collateral: ResourceAddress,
             pool: bool,
         ) -> Decimal {
             if pool {
-                if self.pool_units.get_mut(&collateral).unwrap().lsu {
+                if self.pool_units.get(&collateral).unwrap().lsu {
                     self.pool_units
-                        .get_mut(&collateral)
+                        .get(&collateral)
                         .unwrap()
                         .validator
                         .unwrap()
                         .get_redemption_value(amount)
+                } else if let Some(adapter) = self.pool_units.get(&collateral).unwrap().adapter {
+                    let redemption_value: Decimal =
+                        adapter.call("get_redemption_value", &(amount,));
+                    redemption_value * (dec!(1) - self.pool_units.get(&collateral).unwrap().haircut)
                 } else {
                     self.pool_units
-                        .get_mut(&collateral)
+                        .get(&collateral)
                         .unwrap()
                         .one_resource_pool
                         .unwrap()
                         .get_redemption_value(amount)
+                        * (dec!(1) - self.pool_units.get(&collateral).unwrap().haircut)
                 }
             } else {
                 amount
             }
         }
 
+        /// Get the redemption rate of a pool unit collateral (parent collateral per pool unit), without the pool haircut
+        fn redemption_rate(&self, collateral: ResourceAddress) -> Decimal {
+            let pool_unit = self.pool_units.get(&collateral).unwrap();
+            if pool_unit.lsu {
+                pool_unit.validator.unwrap().get_redemption_value(dec!(1))
+            } else if let Some(adapter) = pool_unit.adapter {
+                adapter.call("get_redemption_value", &(dec!(1),))
+            } else {
+                pool_unit
+                    .one_resource_pool
+                    .unwrap()
+                    .get_redemption_value(dec!(1))
+            }
+        }
+
         /// Check whether a collateral's share is too big
         fn check_share(
             &mut self,
@@ -2447,6 +6285,66 @@ mod stabilis_component {
             }
         }
 
+        /// Get the maximum debt of a single loan for a collateral, the lowest of the global and collateral maximum
+        fn get_max_cdp_debt(&self, parent_address: ResourceAddress) -> Option<Decimal> {
+            let collateral_max: Option<Decimal> =
+                self.collaterals.get(&parent_address).unwrap().max_cdp_debt;
+
+            match (self.parameters.max_cdp_debt, collateral_max) {
+                (Some(global_max), Some(collateral_max)) => Some(global_max.min(collateral_max)),
+                (global_max, None) => global_max,
+                (None, collateral_max) => collateral_max,
+            }
+        }
+
+        /// Count a mark for a collateral, rejecting it if the collateral's marks per minute limit is reached
+        ///    - Checked before searching for a liquidatable loan, so rejected marks are cheap
+        ///    - A new minute starts when the previous one has ended
+        fn check_mark_rate(&mut self, collateral: ResourceAddress) {
+            let max_marks: Option<u64> = self
+                .collaterals
+                .get(&collateral)
+                .expect("Collateral not accepted.")
+                .max_marks_per_minute;
+
+            let marks: u64 = self.get_mark_rate(collateral);
+
+            if let Some(max) = max_marks {
+                assert!(
+                    marks < max,
+                    "Mark rate limit reached for this collateral, try again later."
+                );
+            }
+
+            if marks == 0 {
+                self.mark_rates.insert(
+                    collateral,
+                    MarkRate {
+                        minute_start: Clock::current_time_rounded_to_seconds(),
+                        marks: 1,
+                    },
+                );
+            } else {
+                self.mark_rates.get_mut(&collateral).unwrap().marks += 1;
+            }
+        }
+
+        /// Get the MCR a collateral's liquidation collateral ratio is based on: its MCR plus the surcharge applied at the last price change
+        fn liquidation_mcr(&self, collateral: ResourceAddress) -> Decimal {
+            let info = self.collaterals.get(&collateral).unwrap();
+            info.mcr + info.applied_surcharge
+        }
+
+        /// Check whether a loan's debt is too big
+        fn check_max_cdp_debt(&self, parent_address: ResourceAddress, debt: Decimal) {
+            if let Some(max_debt) = self.get_max_cdp_debt(parent_address) {
+                assert!(
+                    debt <= max_debt,
+                    "Loan debt too big, split it into multiple loans."
+                );
+            }
+        }
+
         /// Update minted STAB
         fn update_minted_stab(
             &mut self,
@@ -2474,6 +6372,225 @@ mod stabilis_component {
             if check_share {
                 self.check_share(collateral, is_pool_unit_collateral, pool_unit);
             }
+
+            if add {
+                let mut addresses: Vec<ResourceAddress> = vec![collateral];
+                if is_pool_unit_collateral {
+                    addresses.push(pool_unit);
+                }
+                for warning in self.get_share_warnings(addresses) {
+                    if warning.near_cap {
+                        Runtime::emit_event(EventShareWarning { warning });
+                    }
+                }
+            }
+        }
+
+        /// Get the share of a collateral or pool unit compared to its cap
+        fn get_share_warning(&self, address: ResourceAddress) -> Option<ShareWarning> {
+            let (parent_address, is_pool_unit_collateral, share, max_share) =
+                if let Some(info) = self.collaterals.get(&address) {
+                    let share: Decimal = match self.circulating_stab > dec!(0) {
+                        true => info.minted_stab / self.circulating_stab,
+                        false => dec!(0),
+                    };
+                    (address, false, share, info.max_stab_share)
+                } else if let Some(info) = self.pool_units.get(&address) {
+                    let parent_minted_stab: Decimal = self
+                        .collaterals
+                        .get(&info.parent_address)
+                        .unwrap()
+                        .minted_stab;
+                    let share: Decimal = match parent_minted_stab > dec!(0) {
+                        true => info.minted_stab / parent_minted_stab,
+                        false => dec!(0),
+                    };
+                    (info.parent_address, true, share, info.max_pool_share)
+                } else {
+                    return None;
+                };
+
+            Some(ShareWarning {
+                address,
+                parent_address,
+                is_pool_unit_collateral,
+                share,
+                max_share,
+                near_cap: share >= max_share - self.parameters.share_warning_margin,
+            })
+        }
+
+        /// Start or stop the share enforcement of a collateral, and return its current share surcharge
+        ///   - enforcement starts when the share is above the maximum share, and stops when it is at or below the release share
+        fn update_share_enforcement(&mut self, collateral: ResourceAddress) -> Decimal {
+            let (release_share, max_share, minted_stab, enforced): (
+                Decimal,
+                Decimal,
+                Decimal,
+                bool,
+            ) = {
+                let info = self.collaterals.get(&collateral).unwrap();
+                match &info.share_enforcement {
+                    Some(enforcement) => (
+                        enforcement.release_share,
+                        info.max_stab_share,
+                        info.minted_stab,
+                        info.share_enforced_since.is_some(),
+                    ),
+                    None => return dec!(0),
+                }
+            };
+            let share: Decimal = match self.circulating_stab > dec!(0) {
+                true => minted_stab / self.circulating_stab,
+                false => dec!(0),
+            };
+
+            if !enforced && share > max_share {
+                self.collaterals
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .share_enforced_since = Some(Clock::current_time_rounded_to_seconds());
+                Runtime::emit_event(EventShareEnforcement {
+                    address: collateral,
+                    enforced: true,
+                    share,
+                });
+            } else if enforced && share <= release_share {
+                self.collaterals
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .share_enforced_since = None;
+                Runtime::emit_event(EventShareEnforcement {
+                    address: collateral,
+                    enforced: false,
+                    share,
+                });
+            }
+
+            self.get_share_surcharge(collateral)
+        }
+
+        /// Get the share surcharge of a collateral: the surcharge per day times the days its share has been enforced, capped at the maximum surcharge
+        fn get_share_surcharge(&self, collateral: ResourceAddress) -> Decimal {
+            let info = self.collaterals.get(&collateral).unwrap();
+            match (&info.share_enforcement, info.share_enforced_since) {
+                (Some(enforcement), Some(enforced_since)) => {
+                    let elapsed_days: Decimal = Decimal::from(
+                        Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch
+                            - enforced_since.seconds_since_unix_epoch,
+                    ) / dec!(86400);
+                    (enforcement.surcharge_per_day * elapsed_days).min(enforcement.max_surcharge)
+                }
+                _ => dec!(0),
+            }
+        }
+
+        /// Apply the withdrawal throttle to withdrawn collateral, returning the collateral and a withdrawal claim (if queued)
+        ///    - If the throttle is disabled, or the withdrawal fits in the current window, the collateral is returned
+        ///    - Else, the collateral is queued (leaving the returned bucket empty) and a withdrawal claim is returned, claimable after one window
+        ///    - A new window starts when the previous one has ended, its limit is a fraction of the collateral's vault at that time
+        fn throttle_withdrawal(
+            &mut self,
+            mut collateral: Bucket,
+            pool: bool,
+        ) -> (Bucket, Option<Bucket>) {
+            if !self.parameters.withdrawal_throttle {
+                return (collateral, None);
+            }
+
+            let address: ResourceAddress = collateral.resource_address();
+            let amount: Decimal = collateral.amount();
+            let now: Instant = Clock::current_time_rounded_to_seconds();
+
+            let window_ended: bool = match self.withdrawal_windows.get(&address) {
+                Some(window) => Clock::current_time_is_at_or_after(
+                    window
+                        .start
+                        .add_minutes(self.parameters.withdrawal_window)
+                        .unwrap(),
+                    TimePrecision::Second,
+                ),
+                None => true,
+            };
+
+            if window_ended {
+                let vault_amount: Decimal = match pool {
+                    true => self.pool_units.get(&address).unwrap().vault.amount(),
+                    false => self.collaterals.get(&address).unwrap().vault.amount(),
+                };
+                self.withdrawal_windows.insert(
+                    address,
+                    WithdrawalWindow {
+                        start: now,
+                        withdrawn: dec!(0),
+                        limit: (vault_amount + amount) * self.parameters.withdrawal_threshold,
+                    },
+                );
+            }
+
+            {
+                let mut window = self.withdrawal_windows.get_mut(&address).unwrap();
+                if window.withdrawn + amount <= window.limit {
+                    window.withdrawn += amount;
+                    return (collateral, None);
+                }
+            }
+
+            self.withdrawal_claim_counter += 1;
+            let claim_id: NonFungibleLocalId =
+                NonFungibleLocalId::integer(self.withdrawal_claim_counter);
+            let claimable_from: Instant =
+                now.add_minutes(self.parameters.withdrawal_window).unwrap();
+
+            let queued: Bucket = collateral.take(amount);
+            if self.withdrawal_queue.get(&address).is_some() {
+                self.withdrawal_queue.get_mut(&address).unwrap().put(queued);
+            } else {
+                self.withdrawal_queue
+                    .insert(address, Vault::with_bucket(queued));
+            }
+
+            let claim: Bucket = self.withdrawal_claim_manager.mint_non_fungible(
+                &claim_id,
+                WithdrawalClaim {
+                    collateral: address,
+                    amount,
+                    claimable_from,
+                },
+            );
+
+            Runtime::emit_event(EventWithdrawalQueued {
+                claim_id,
+                collateral: address,
+                amount,
+                claimable_from,
+            });
+
+            (collateral, Some(claim))
+        }
+
+        /// Convert a parent asset to a pool unit, by staking it to the validator or contributing it through the adapter
+        fn convert_to_pool_unit(
+            &self,
+            pool_unit_address: ResourceAddress,
+            parent: Bucket,
+        ) -> Bucket {
+            let pool_unit = self.pool_units.get(&pool_unit_address).unwrap();
+            //contributing to a OneResourcePool needs its pool manager role, which only an adapter can hold
+            let pool_units: Bucket = if let Some(mut validator) = pool_unit.validator {
+                validator.stake(parent)
+            } else {
+                pool_unit
+                    .adapter
+                    .expect("Contributing to this pool unit needs an adapter.")
+                    .call("contribute", &(parent,))
+            };
+
+            assert!(
+                pool_units.resource_address() == pool_unit_address,
+                "Conversion returned the wrong pool unit."
+            );
+            pool_units
         }
 
         /// Take collateral out of the correct vault
@@ -2483,18 +6600,29 @@ mod stabilis_component {
             pool: bool,
             amount: Decimal,
         ) -> Bucket {
+            let rounding: WithdrawStrategy = self.rounding(false);
             if pool {
                 self.pool_units
                     .get_mut(&collateral)
                     .unwrap()
                     .vault
-                    .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero))
+                    .take_advanced(amount, rounding)
             } else {
                 self.collaterals
                     .get_mut(&collateral)
                     .unwrap()
                     .vault
-                    .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero))
+                    .take_advanced(amount, rounding)
+            }
+        }
+
+        /// Get the withdraw strategy for an amount, always rounded in favor of the protocol
+        ///   - `to_protocol` is whether the amount is kept by the protocol (fees, premiums) or protects it, instead of being paid out to a user
+        ///   - amounts paid out are rounded down and amounts kept are rounded up, so no value leaks to users through rounding
+        fn rounding(&self, to_protocol: bool) -> WithdrawStrategy {
+            match to_protocol {
+                true => WithdrawStrategy::Rounded(RoundingMode::AwayFromZero),
+                false => WithdrawStrategy::Rounded(RoundingMode::ToZero),
             }
         }
 
@@ -2520,6 +6648,62 @@ mod stabilis_component {
             }
         }
 
+        /// Return the leftover collateral of a liquidated loan / CDP above the leftover buffer to the loan's refund account
+        ///   - the buffer is a fraction of the liquidated debt's value (in collateral), which stays in the loan to be retrieved as usual
+        ///   - nothing is returned if the loan has no refund account, retrieving leftovers is paused, or the account rejects the deposit
+        ///   - the collateral is paid out along with the liquidator's reward, so it isn't throttled
+        ///   - returns the amount of collateral returned
+        fn return_leftover(
+            &mut self,
+            cdp_id: &NonFungibleLocalId,
+            cdp_data: &Cdp,
+            leftover_collateral: Decimal,
+            debt_collateral: Decimal,
+        ) -> Decimal {
+            let account: ComponentAddress = match self.get_refund_account(cdp_id.clone()) {
+                Some(account) => account,
+                None => return dec!(0),
+            };
+            let excess: Decimal =
+                leftover_collateral - debt_collateral * self.parameters.leftover_buffer;
+            if excess <= dec!(0)
+                || self.is_paused(
+                    PausableAction::RetrieveLeftoverCollateral,
+                    Some(cdp_data.collateral),
+                )
+            {
+                return dec!(0);
+            }
+
+            let returned: Bucket = self.take_collateral(
+                cdp_data.collateral,
+                cdp_data.is_pool_unit_collateral,
+                excess,
+            );
+            let amount: Decimal = returned.amount();
+            let account: Global<Account> = Global::from(account);
+
+            match account.try_deposit_or_refund(returned, None) {
+                Some(refund) => {
+                    self.put_collateral(
+                        cdp_data.collateral,
+                        cdp_data.is_pool_unit_collateral,
+                        refund,
+                    );
+                    dec!(0)
+                }
+                None => {
+                    Runtime::emit_event(EventLeftoverReturned {
+                        cdp_id: cdp_id.clone(),
+                        collateral: cdp_data.collateral,
+                        account: account.address(),
+                        amount,
+                    });
+                    amount
+                }
+            }
+        }
+
         /// Put collateral in the treasury
         fn put_collateral_in_treasury(
             &mut self,
@@ -2541,6 +6725,28 @@ mod stabilis_component {
                     .put(collateral_bucket)
             }
         }
+
+        /// Put collateral in the insurance fund
+        fn put_collateral_in_insurance(
+            &mut self,
+            collateral: ResourceAddress,
+            pool: bool,
+            collateral_bucket: Bucket,
+        ) {
+            if pool {
+                self.pool_units
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .insurance
+                    .put(collateral_bucket)
+            } else {
+                self.collaterals
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .insurance
+                    .put(collateral_bucket)
+            }
+        }
     }
 }
 
@@ -2553,40 +6759,198 @@ pub struct CollateralInfo {
     pub vault: Vault,
     pub resource_address: ResourceAddress,
     pub treasury: Vault,
+    pub insurance: Vault,
     pub accepted: bool,
     pub initialized: bool,
     pub max_stab_share: Decimal,
     pub minted_stab: Decimal,
     pub collateral_amount: Decimal,
     pub highest_cr: Decimal,
+    pub max_cdp_debt: Option<Decimal>,
+    pub max_marks_per_minute: Option<u64>,
+    pub surcharge_parameters: Option<SurchargeParameters>,
+    pub surcharge: Decimal,
+    pub surcharge_triggered_at: Option<Instant>,
+    pub applied_surcharge: Decimal,
+    pub share_enforcement: Option<ShareEnforcement>,
+    pub share_enforced_since: Option<Instant>,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct SurchargeParameters {
+    pub trigger_drop: Decimal,
+    pub surcharge: Decimal,
+    pub decay_period: i64,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct ShareEnforcement {
+    pub surcharge_per_day: Decimal,
+    pub max_surcharge: Decimal,
+    pub release_share: Decimal,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct ShareEnforcementStatus {
+    pub share: Decimal,
+    pub max_share: Decimal,
+    pub enforcement: Option<ShareEnforcement>,
+    pub enforced_since: Option<Instant>,
+    pub surcharge: Decimal,
+    pub stab_to_repay: Decimal,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct DeleverageSettings {
+    pub trigger_cr: Decimal,
+    pub target_cr: Decimal,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub enum ForceMintLimit {
+    Absolute(Decimal),
+    Percentage(Decimal),
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct ForceMintCap {
+    pub limit: ForceMintLimit,
+    pub allowance: Decimal,
+    pub force_minted: Decimal,
+}
+
+#[derive(ScryptoSbor)]
+pub struct BorrowRewards {
+    pub vault: Option<Vault>,
+    pub schedule: Vec<EmissionPeriod>,
+    pub indices: HashMap<ResourceAddress, BorrowRewardIndex>,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct EmissionPeriod {
+    pub start: Instant,
+    pub end: Instant,
+    pub rewards_per_minute: Decimal,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct BorrowRewardIndex {
+    pub weight: Decimal,
+    pub index: Decimal,
+    pub last_update: Instant,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct BorrowRewardPosition {
+    pub index: Decimal,
+    pub accrued: Decimal,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct BorrowRewardsInfo {
+    pub reward_address: Option<ResourceAddress>,
+    pub available: Decimal,
+    pub schedule: Vec<EmissionPeriod>,
+    pub weights: HashMap<ResourceAddress, Decimal>,
+}
+
+#[derive(ScryptoSbor)]
+pub struct IncentiveRoute {
+    pub staking_component: ComponentAddress,
+    pub method_name: String,
+    pub fine_share: Decimal,
+    pub fee_share: Decimal,
+    pub pending: HashMap<ResourceAddress, Vault>,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct IncentiveRouteInfo {
+    pub staking_component: ComponentAddress,
+    pub method_name: String,
+    pub fine_share: Decimal,
+    pub fee_share: Decimal,
+    pub pending: HashMap<ResourceAddress, Decimal>,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct CdpFreeze {
+    pub until: Instant,
+    pub reason: String,
+    pub frozen_since: Instant,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct MarkRate {
+    pub minute_start: Instant,
+    pub marks: u64,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct SweptLeftover {
+    pub collateral: ResourceAddress,
+    pub amount: Decimal,
+    pub swept_at: Instant,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct WithdrawalWindow {
+    pub start: Instant,
+    pub withdrawn: Decimal,
+    pub limit: Decimal,
 }
 
 #[derive(ScryptoSbor)]
 pub struct PoolUnitInfo {
     pub vault: Vault,
     pub treasury: Vault,
+    pub insurance: Vault,
     pub lsu: bool,
     pub validator: Option<Global<Validator>>,
     pub one_resource_pool: Option<Global<OneResourcePool>>,
+    pub adapter: Option<Global<AnyComponent>>,
     pub parent_address: ResourceAddress,
     pub address: ResourceAddress,
     pub accepted: bool,
     pub minted_stab: Decimal,
     pub max_pool_share: Decimal,
+    pub haircut: Decimal,
 }
 
-#[derive(ScryptoSbor)]
+#[derive(ScryptoSbor, Clone)]
 pub struct ProtocolParameters {
     pub minimum_mint: Decimal,
     pub max_vector_length: u64,
     pub liquidation_delay: i64,
     pub unmarked_delay: i64,
     pub liquidation_liquidation_fine: Decimal,
+    pub marker_liquidation_fine: Decimal,
     pub stabilis_liquidation_fine: Decimal,
-    pub stop_liquidations: bool,
-    pub stop_openings: bool,
-    pub stop_closings: bool,
-    pub stop_force_mint: bool,
-    pub stop_force_liquidate: bool,
+    pub paused_actions: HashSet<PausableAction>,
     pub force_mint_cr_multiplier: Decimal,
+    pub redemption_protection_premium: Decimal,
+    pub share_warning_margin: Decimal,
+    pub max_cdp_debt: Option<Decimal>,
+    pub withdrawal_throttle: bool,
+    pub withdrawal_window: i64,
+    pub withdrawal_threshold: Decimal,
+    pub keeper_compensation: Decimal,
+    pub insurance_share: Decimal,
+    pub unclaimed_leftover_period: i64,
+    pub leftover_buffer: Decimal,
+    pub close_fee: Decimal,
+    pub buffer_bounty: Decimal,
+    pub max_freeze_minutes: i64,
+    pub dust_tolerance: Decimal,
+    pub deleverage_bounty: Decimal,
+    pub deleverage_slippage: Decimal,
+    pub harvest_bounty: Decimal,
+    pub min_force_mint_cap: Decimal,
+    pub auth_thresholds: AuthThresholds,
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct AuthThresholds {
+    pub mint: Decimal,
+    pub parameters: Decimal,
+    pub emergency: Decimal,
 }
\ No newline at end of file
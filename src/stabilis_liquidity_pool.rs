@@ -1,18 +1,47 @@
 //! # Stabilis Liquidity Pool Blueprint
 //!
 //! This blueprint instantiates a liquidity pool for the Stabilis protocol. The pool is a native STAB/XRD liquidity pool, and is used to determine the price of STAB tokens.
+//!
+//! Swap fees are not split off or stored separately: the full input of a swap is deposited into the pool's reserves, so fees automatically compound into the position of every LP.
+//! Redeeming pool units (`remove_liquidity`) returns the LP's share of the reserves including accrued fees, so no separate harvest or compound step is needed.
+//!
+//! A minimum liquidity (square root of the product of the reserves) is set at instantiation, below which liquidity can't be removed, so the pool can't be drained to a point where its price is meaningless.
+//!
+//! Collected fees are accounted per day (valued in the second resource, XRD), together with the pool's value at the time, so the fee APR over a window can be read on-chain (`get_fee_apr`).
 
+use crate::shared_structs::*;
 use scrypto::prelude::*;
 
+/// Fees collected by the pool in a single day
+#[derive(ScryptoSbor, Clone)]
+pub struct FeePeriod {
+    /// fees collected, valued in the second resource
+    pub fees: Decimal,
+    /// value of the pool's reserves at the last swap of the day, in the second resource
+    pub tvl: Decimal,
+}
+
 #[blueprint]
+#[types(u64, FeePeriod, ResourceAddress, Vault)]
 mod stabilis_liquidity_pool {
     enable_method_auth! {
         methods {
             add_liquidity => PUBLIC;
             remove_liquidity => PUBLIC;
+            preview_remove_liquidity => PUBLIC;
+            get_invariant => PUBLIC;
+            get_min_liquidity => PUBLIC;
+            quote => PUBLIC;
             get_stab_price => PUBLIC;
+            get_price_range => PUBLIC;
+            take_price_range => restrict_to: [OWNER];
             swap => PUBLIC;
             set_fee => restrict_to: [OWNER];
+            sweep_dust => restrict_to: [OWNER];
+            get_dust => PUBLIC;
+            get_fee_apr => PUBLIC;
+            deposit_airdrop => PUBLIC;
+            rescue_tokens => restrict_to: [OWNER];
         }
     }
 
@@ -21,6 +50,16 @@ mod stabilis_liquidity_pool {
         pool_component: Global<TwoResourcePool>,
         /// The fee charged for swaps
         fee: Decimal,
+        /// The liquidity (square root of the product of the reserves) below which liquidity can't be removed
+        min_liquidity: Decimal,
+        /// Rounding dust left in the pool by swaps, per resource
+        dust: HashMap<ResourceAddress, Decimal>,
+        /// The lowest and highest price reached by swaps since the range was last taken (None if there were no swaps)
+        price_range: Option<(Decimal, Decimal)>,
+        /// KVS storing the fees collected per day (days since unix epoch)
+        fee_periods: KeyValueStore<u64, FeePeriod>,
+        /// Vaults for tokens airdropped to this component (resources not managed by the protocol), until rescued by the owner
+        airdrop_vaults: KeyValueStore<ResourceAddress, Vault>,
     }
 
     impl StabilisPool {
@@ -31,6 +70,7 @@ mod stabilis_liquidity_pool {
         /// - `resource_address1`: The address of the first resource in the pool
         /// - `resource_address2`: The address of the second resource in the pool
         /// - `fee`: The fee charged for swaps
+        /// - `min_liquidity`: The liquidity (square root of the product of the reserves) below which liquidity can't be removed
         ///
         /// # Output
         /// - The global instance of the StabilisPool component
@@ -43,6 +83,7 @@ mod stabilis_liquidity_pool {
             resource_address1: ResourceAddress,
             resource_address2: ResourceAddress,
             fee: Decimal,
+            min_liquidity: Decimal,
             dapp_def_address: GlobalAddress,
         ) -> Global<StabilisPool> {
             let (address_reservation, component_address) =
@@ -60,6 +101,11 @@ mod stabilis_liquidity_pool {
             Self {
                 pool_component,
                 fee,
+                min_liquidity,
+                dust: HashMap::new(),
+                price_range: None,
+                fee_periods: StabilisPoolKeyValueStore::new_with_registered_type(),
+                airdrop_vaults: StabilisPoolKeyValueStore::new_with_registered_type(),
             }
             .instantiate()
             .prepare_to_globalize(owner_role)
@@ -105,11 +151,78 @@ mod stabilis_liquidity_pool {
         /// - The resource2 received
         ///
         /// # Logic
-        /// - Redeems and returns the pool units
+        /// - Checks the pool's liquidity doesn't fall below the minimum liquidity (see `preview_remove_liquidity`)
+        /// - Redeems the pool units and returns the redeemed resources
         pub fn remove_liquidity(&mut self, pool_units: Bucket) -> (Bucket, Bucket) {
+            self.preview_remove_liquidity(pool_units.amount());
             self.pool_component.redeem(pool_units)
         }
 
+        /// Previews the resources received when removing liquidity, without removing it
+        ///
+        /// # Input
+        /// - `amount`: The amount of pool units to redeem
+        ///
+        /// # Output
+        /// - The amount of each resource a redemption would yield (including accrued fees)
+        ///
+        /// # Logic
+        /// - Gets the redemption value of the pool units
+        /// - Checks the pool's liquidity (square root of the product of the reserves left) doesn't fall below the minimum liquidity, as `remove_liquidity` would
+        pub fn preview_remove_liquidity(
+            &self,
+            amount: Decimal,
+        ) -> IndexMap<ResourceAddress, Decimal> {
+            let redemption_value: IndexMap<ResourceAddress, Decimal> =
+                self.pool_component.get_redemption_value(amount);
+            let liquidity_left: Decimal = self
+                .vault_reserves()
+                .iter()
+                .fold(dec!(1), |invariant, (resource, reserve)| {
+                    invariant * (*reserve - *redemption_value.get(resource).unwrap())
+                })
+                .checked_sqrt()
+                .unwrap();
+            assert!(
+                liquidity_left >= self.min_liquidity,
+                "Removal would take the pool below its minimum liquidity."
+            );
+            redemption_value
+        }
+
+        /// Gets the liquidity (square root of the product of the reserves) below which liquidity can't be removed
+        pub fn get_min_liquidity(&self) -> Decimal {
+            self.min_liquidity
+        }
+
+        /// Gets the invariant of the pool (k = reserve1 * reserve2)
+        ///   - swap fees stay in the pool, so k grows over time
+        pub fn get_invariant(&self) -> Decimal {
+            self.vault_reserves()
+                .values()
+                .fold(dec!(1), |invariant, reserve| invariant * *reserve)
+        }
+
+        /// Gets the output amount of a swap (including fees), without swapping
+        ///
+        /// # Input
+        /// - `input_address`: The address of the resource to swap
+        /// - `input_amount`: The amount to swap
+        ///
+        /// # Output
+        /// - The amount of the other resource the swap would yield
+        pub fn quote(&self, input_address: ResourceAddress, input_amount: Decimal) -> Decimal {
+            let mut reserves = self.vault_reserves();
+
+            let input_reserves = reserves
+                .swap_remove(&input_address)
+                .expect("Resource does not belong to the pool");
+            let (_output_resource_address, output_reserves) = reserves.into_iter().next().unwrap();
+
+            (input_amount * output_reserves * (dec!("1") - self.fee))
+                / (input_reserves + input_amount * (dec!("1") - self.fee))
+        }
+
         /// Swaps one resource for another
         ///
         /// # Input
@@ -121,24 +234,36 @@ mod stabilis_liquidity_pool {
         /// # Logic
         /// - Checks the token reserves for the pool
         /// - Calculates the output amount for the input amount
+        /// - Records the fee of the swap
         /// - Deposits the input bucket
-        /// - Withdraws and returns the output bucket
+        /// - Withdraws the output bucket
+        /// - Registers the rounding dust left in the pool by the withdrawal
+        /// - Registers the prices before and after the swap in the price range
+        /// - Returns the output bucket
         pub fn swap(&mut self, input_bucket: Bucket) -> Bucket {
-            let mut reserves = self.vault_reserves();
+            self.track_price();
 
-            let input_reserves = reserves
-                .swap_remove(&input_bucket.resource_address())
-                .expect("Resource does not belong to the pool");
-            let (output_resource_address, output_reserves) = reserves.into_iter().next().unwrap();
+            let output_amount = self.quote(input_bucket.resource_address(), input_bucket.amount());
+            let output_resource_address: ResourceAddress = *self
+                .vault_reserves()
+                .keys()
+                .find(|resource| **resource != input_bucket.resource_address())
+                .unwrap();
 
-            let input_amount = input_bucket.amount();
+            self.record_fee(
+                input_bucket.resource_address(),
+                input_bucket.amount() * self.fee,
+            );
+            self.deposit(input_bucket);
 
-            let output_amount = (input_amount * output_reserves * (dec!("1") - self.fee))
-                / (input_reserves + input_amount * (dec!("1") - self.fee));
+            let output_bucket: Bucket = self.withdraw(output_resource_address, output_amount);
 
-            self.deposit(input_bucket);
+            *self.dust.entry(output_resource_address).or_insert(dec!(0)) +=
+                output_amount - output_bucket.amount();
+
+            self.track_price();
 
-            self.withdraw(output_resource_address, output_amount)
+            output_bucket
         }
 
         /// Gets the price of STAB tokens (or, if you've instantiated a different pool, the price of the first resource in the pool)
@@ -156,11 +281,158 @@ mod stabilis_liquidity_pool {
             last_amount / first_amount
         }
 
+        /// Gets the lowest and highest price reached by swaps since the range was last taken (None if there were no swaps)
+        pub fn get_price_range(&self) -> Option<(Decimal, Decimal)> {
+            self.price_range
+        }
+
+        /// Gets the lowest and highest price reached by swaps since the range was last taken, and resets the range
+        ///   - used by the Proxy to detect manipulation of the pool price between interest rate updates
+        pub fn take_price_range(&mut self) -> Option<(Decimal, Decimal)> {
+            self.price_range.take()
+        }
+
+        /// Gets the rounding dust left in the pool by swaps, per resource
+        pub fn get_dust(&self) -> HashMap<ResourceAddress, Decimal> {
+            self.dust.clone()
+        }
+
+        /// Withdraws the rounding dust left in the pool by swaps
+        ///
+        /// # Output
+        /// - The withdrawn dust, one bucket per resource
+        ///
+        /// # Logic
+        /// - Withdraws the registered dust of each resource from the pool
+        /// - Subtracts the withdrawn amounts from the registered dust (withdrawals round down, so some dust might remain registered)
+        pub fn sweep_dust(&mut self) -> Vec<Bucket> {
+            let mut swept: Vec<Bucket> = Vec::new();
+
+            for (resource_address, amount) in self.dust.clone() {
+                if amount > dec!(0) {
+                    let dust_bucket: Bucket = self.withdraw(resource_address, amount);
+                    *self.dust.get_mut(&resource_address).unwrap() -= dust_bucket.amount();
+                    swept.push(dust_bucket);
+                }
+            }
+
+            swept
+        }
+
+        /// Gets the annualized fee return of the pool over the last `window_days` days, including today (0.05 = 5%)
+        ///
+        /// # Input
+        /// - `window_days`: The number of days to average over (1 to 365)
+        ///
+        /// # Output
+        /// - The fee APR
+        ///
+        /// # Logic
+        /// - For each day in the window, divides the collected fees by the pool's value on that day (days without swaps add nothing)
+        /// - Annualizes the sum of these daily returns
+        pub fn get_fee_apr(&self, window_days: u64) -> Decimal {
+            assert!(
+                window_days > 0 && window_days <= 365,
+                "Window needs to be between 1 and 365 days."
+            );
+
+            let today: u64 = Self::current_day();
+            let mut total_return: Decimal = dec!(0);
+
+            for day in today.saturating_sub(window_days - 1)..=today {
+                if let Some(period) = self.fee_periods.get(&day) {
+                    if period.tvl > dec!(0) {
+                        total_return += period.fees / period.tvl;
+                    }
+                }
+            }
+
+            total_return * dec!(365) / Decimal::from(window_days)
+        }
+
         /// Sets the fee charged for swaps
         pub fn set_fee(&mut self, fee: Decimal) {
             self.fee = fee;
         }
 
+        /// Receives tokens airdropped to the pool, kept until the owner rescues them
+        pub fn deposit_airdrop(&mut self, tokens: Bucket) {
+            assert!(
+                !self
+                    .protocol_resources()
+                    .contains(&tokens.resource_address()),
+                "Protocol-managed resources can't be airdropped."
+            );
+            put_in_resource_vaults(&self.airdrop_vaults, tokens);
+        }
+
+        /// Rescues airdropped tokens of a resource that isn't managed by the protocol (the pooled resources and the pool units)
+        pub fn rescue_tokens(&mut self, resource: ResourceAddress, amount: Decimal) -> Bucket {
+            take_from_resource_vaults(
+                &self.airdrop_vaults,
+                resource,
+                amount,
+                &self.protocol_resources(),
+            )
+        }
+
+        /// Widens the price range to include the current price
+        fn track_price(&mut self) {
+            let price: Decimal = self.get_stab_price();
+            self.price_range = match self.price_range {
+                None => Some((price, price)),
+                Some((low, high)) => Some((low.min(price), high.max(price))),
+            };
+        }
+
+        /// Adds the fee of a swap to today's fees, valued in the second resource, and updates today's pool value
+        fn record_fee(&mut self, input_address: ResourceAddress, fee_amount: Decimal) {
+            let reserves = self.vault_reserves();
+            let (first_address, first_amount): (ResourceAddress, Decimal) = reserves
+                .first()
+                .map(|(address, amount)| (*address, *amount))
+                .unwrap();
+            let last_amount: Decimal = *reserves.last().map(|(_, v)| v).unwrap();
+            let price: Decimal = last_amount / first_amount;
+
+            let fee_value: Decimal = match input_address == first_address {
+                true => fee_amount * price,
+                false => fee_amount,
+            };
+            let tvl: Decimal = first_amount * price + last_amount;
+
+            let today: u64 = Self::current_day();
+            let fees: Decimal = self
+                .fee_periods
+                .get(&today)
+                .map_or(dec!(0), |period| period.fees);
+            self.fee_periods.insert(
+                today,
+                FeePeriod {
+                    fees: fees + fee_value,
+                    tvl,
+                },
+            );
+        }
+
+        /// Gets the current day (days since unix epoch)
+        fn current_day() -> u64 {
+            (Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch / 86400) as u64
+        }
+
+        /// Gets the resources managed by the protocol, which can't be airdropped or rescued: the pooled resources and the pool units
+        fn protocol_resources(&self) -> Vec<ResourceAddress> {
+            let pool_unit: GlobalAddress = self
+                .pool_component
+                .get_metadata("pool_unit")
+                .unwrap()
+                .expect("Pool has no pool unit.");
+            let mut resources: Vec<ResourceAddress> =
+                self.vault_reserves().keys().copied().collect();
+            resources.push(ResourceAddress::try_from(pool_unit).unwrap());
+            resources
+        }
+
         /// Gets the reserves of the pool
         fn vault_reserves(&self) -> IndexMap<ResourceAddress, Decimal> {
             self.pool_component.get_vault_amounts()
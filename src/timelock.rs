@@ -0,0 +1,259 @@
+//! # Timelock Blueprint
+//!
+//! Execution queue for owner actions on the components of the STAB protocol.
+//! Actions (a component method and its arguments) are queued by the owner (ideally a DAO), become executable by anyone once their timelock has passed, and can be vetoed by a guardian until they are executed.
+//!
+//! The Timelock is the recommended owner of the protocol:
+//! - instantiate the Proxy component with an owner role requiring a badge, and deposit that badge into the Timelock (`deposit_badge`)
+//! - the Stabilis component is owned by the Proxy's controller badge, so its owner methods are covered through the Proxy
+//!
+//! Arguments are stored until execution, so methods taking buckets can't be queued.
+
+use crate::events::*;
+use crate::shared_structs::*;
+use scrypto::prelude::*;
+
+/// An owner action queued in the timelock
+#[derive(ScryptoSbor, Clone)]
+pub struct TimelockAction {
+    /// component to call
+    pub component: ComponentAddress,
+    /// method to call
+    pub method: String,
+    /// encoded arguments of the method (a tuple)
+    pub args: Vec<u8>,
+    /// time the action was queued
+    pub queued_at: Instant,
+    /// time from which the action can be executed
+    pub executable_from: Instant,
+    /// status of the action
+    pub status: TimelockStatus,
+}
+
+#[blueprint]
+#[types(u64, TimelockAction)]
+#[events(EventTimelockQueued, EventTimelockUpdate)]
+mod timelock {
+    enable_method_auth! {
+        roles {
+            guardian => updatable_by: [OWNER];
+        },
+        methods {
+            get_action => PUBLIC;
+            get_delay => PUBLIC;
+            execute => PUBLIC;
+            deposit_badge => PUBLIC;
+            queue => restrict_to: [OWNER];
+            cancel => restrict_to: [OWNER];
+            set_delay => restrict_to: [OWNER];
+            veto => restrict_to: [guardian];
+        }
+    }
+
+    struct Timelock {
+        /// The badges used to authorize executed actions (the owner badge of the Proxy component)
+        badge_vault: Vault,
+        /// KVS storing all queued actions, by id
+        actions: KeyValueStore<u64, TimelockAction>,
+        /// Counter for the action ids
+        action_counter: u64,
+        /// Minutes between queueing and executing an action
+        delay: i64,
+        /// A lowered delay and the time from which it applies
+        pending_delay: Option<(i64, Instant)>,
+    }
+
+    impl Timelock {
+        /// Instantiates the Timelock component
+        ///
+        /// # Input
+        /// - `owner_role`: The owner role of the Timelock, allowed to queue actions (ideally a DAO)
+        /// - `guardian_rule`: The access rule of the guardian, allowed to veto actions
+        /// - `badge_address`: The address of the badge used to authorize executed actions
+        /// - `delay`: Minutes between queueing and executing an action
+        /// - `dapp_def_address`: The dapp definition address
+        ///
+        /// # Output
+        /// - The global instance of the Timelock component
+        pub fn instantiate(
+            owner_role: OwnerRole,
+            guardian_rule: AccessRule,
+            badge_address: ResourceAddress,
+            delay: i64,
+            dapp_def_address: GlobalAddress,
+        ) -> Global<Timelock> {
+            assert!(delay >= 0, "Delay can't be negative.");
+
+            Self {
+                badge_vault: Vault::new(badge_address),
+                actions: TimelockKeyValueStore::new_with_registered_type(),
+                action_counter: 0,
+                delay,
+                pending_delay: None,
+            }
+            .instantiate()
+            .prepare_to_globalize(owner_role)
+            .roles(roles! {
+                guardian => guardian_rule;
+            })
+            .metadata(metadata! {
+                init {
+                    "name" => "STAB Timelock".to_string(), updatable;
+                    "description" => "A timelocked execution queue for owner actions of the STAB protocol".to_string(), updatable;
+                    "info_url" => Url::of("https://ilikeitstable.com"), updatable;
+                    "dapp_definition" => dapp_def_address, updatable;
+                }
+            })
+            .globalize()
+        }
+
+        /// Get a queued action
+        pub fn get_action(&self, id: u64) -> Option<TimelockAction> {
+            self.actions.get(&id).map(|action| action.clone())
+        }
+
+        /// Get the current delay (minutes between queueing and executing an action)
+        pub fn get_delay(&self) -> i64 {
+            match self.pending_delay {
+                Some((delay, applies_from))
+                    if Clock::current_time_is_at_or_after(applies_from, TimePrecision::Second) =>
+                {
+                    delay
+                }
+                _ => self.delay,
+            }
+        }
+
+        /// Deposit badges used to authorize executed actions
+        pub fn deposit_badge(&mut self, badge: Bucket) {
+            self.badge_vault.put(badge);
+        }
+
+        /// Queue an action
+        ///
+        /// # Input
+        /// - `component`: The component to call
+        /// - `method`: The method to call
+        /// - `args`: The arguments of the method, as a tuple
+        ///
+        /// # Output
+        /// - The id of the queued action
+        ///
+        /// # Logic
+        /// - Encodes and stores the action, executable after the current delay
+        pub fn queue(
+            &mut self,
+            component: ComponentAddress,
+            method: String,
+            args: ScryptoValue,
+        ) -> u64 {
+            assert!(
+                matches!(args, ScryptoValue::Tuple { .. }),
+                "Arguments need to be a tuple."
+            );
+
+            self.action_counter += 1;
+
+            let queued_at: Instant = Clock::current_time_rounded_to_seconds();
+            let executable_from: Instant = queued_at.add_minutes(self.get_delay()).unwrap();
+
+            self.actions.insert(
+                self.action_counter,
+                TimelockAction {
+                    component,
+                    method: method.clone(),
+                    args: scrypto_encode(&args).unwrap(),
+                    queued_at,
+                    executable_from,
+                    status: TimelockStatus::Queued,
+                },
+            );
+
+            Runtime::emit_event(EventTimelockQueued {
+                id: self.action_counter,
+                component,
+                method,
+                executable_from,
+            });
+
+            self.action_counter
+        }
+
+        /// Execute a queued action after its timelock has passed
+        ///   - anyone can execute an action, the owner badge authorizes the call
+        ///   - returns the output of the called method
+        pub fn execute(&mut self, id: u64) -> ScryptoValue {
+            let (component, method, args): (ComponentAddress, String, Vec<u8>) = {
+                let mut action = self.actions.get_mut(&id).expect("Action not found.");
+                assert!(
+                    action.status == TimelockStatus::Queued,
+                    "Action not queued."
+                );
+                assert!(
+                    Clock::current_time_is_at_or_after(
+                        action.executable_from,
+                        TimePrecision::Second
+                    ),
+                    "Timelock has not passed yet."
+                );
+                action.status = TimelockStatus::Executed;
+                (action.component, action.method.clone(), action.args.clone())
+            };
+
+            let target: Global<AnyComponent> = Global::from(component);
+            let output: ScryptoValue = self
+                .badge_vault
+                .authorize_with_all(|| target.call_raw(&method, args));
+
+            Runtime::emit_event(EventTimelockUpdate {
+                id,
+                status: TimelockStatus::Executed,
+            });
+
+            output
+        }
+
+        /// Cancel a queued action
+        pub fn cancel(&mut self, id: u64) {
+            self.close_action(id, TimelockStatus::Cancelled);
+        }
+
+        /// Veto a queued action, only possible for the guardian
+        pub fn veto(&mut self, id: u64) {
+            self.close_action(id, TimelockStatus::Vetoed);
+        }
+
+        /// Set the delay (minutes between queueing and executing an action)
+        ///   - raising the delay applies immediately
+        ///   - lowering the delay only applies after the current delay has passed, so it can't be used to rush through actions
+        pub fn set_delay(&mut self, new_delay: i64) {
+            assert!(new_delay >= 0, "Delay can't be negative.");
+
+            let current_delay: i64 = self.get_delay();
+            if new_delay >= current_delay {
+                self.delay = new_delay;
+                self.pending_delay = None;
+            } else {
+                self.delay = current_delay;
+                self.pending_delay = Some((
+                    new_delay,
+                    Clock::current_time_rounded_to_seconds()
+                        .add_minutes(current_delay)
+                        .unwrap(),
+                ));
+            }
+        }
+
+        /// Close a queued action without executing it
+        fn close_action(&mut self, id: u64, status: TimelockStatus) {
+            let mut action = self.actions.get_mut(&id).expect("Action not found.");
+            assert!(
+                action.status == TimelockStatus::Queued,
+                "Action not queued."
+            );
+            action.status = status.clone();
+
+            Runtime::emit_event(EventTimelockUpdate { id, status });
+        }
+    }
+}
@@ -0,0 +1,567 @@
+/// Invariant testing of the Stabilis component, by random-walking through its operations.
+/// After every step, the accounting of the component is checked against a simple model of all loans:
+/// - the circulating STAB equals the sum of the debts of all open loans
+/// - collateral is conserved (wallet + loans + treasury + insurance equals the initial supply)
+/// - no healthy loan sits below the liquidation collateral ratio (after marking all liquidatable loans)
+/// - the AvlTree holds exactly the healthy loans and their debt
+///
+/// The random walk is deterministic per seed, so failures can be reproduced.
+/// Set STAB_FUZZ_SEEDS and STAB_FUZZ_STEPS to fuzz longer than the default run.
+use scrypto_test::prelude::*;
+use stab_module::shared_structs::*;
+use stab_module::stabilis_component::stabilis_component_test::*;
+
+const INITIAL_SUPPLY: u64 = 10000;
+
+fn mcr() -> Decimal {
+    dec!("1.5")
+}
+
+fn total_debt(loans: &[Loan]) -> Decimal {
+    loans.iter().fold(dec!(0), |total, loan| total + loan.debt)
+}
+
+fn total_collateral(loans: &[Loan]) -> Decimal {
+    loans
+        .iter()
+        .fold(dec!(0), |total, loan| total + loan.collateral)
+}
+
+// Checks an amount returned by the component against the model's expectation, allowing for rounding
+fn assert_close(actual: Decimal, expected: Decimal, what: &str) {
+    let difference: Decimal = actual - expected;
+    assert!(
+        difference < dec!("0.000001") && difference > dec!("-0.000001"),
+        "{}: expected {}, got {}",
+        what,
+        expected,
+        actual
+    );
+}
+
+// The index of the only loan with the lowest (or highest) collateral ratio, None if there are none or several
+fn extreme_cr_loan(loans: &[Loan], highest: bool) -> Option<usize> {
+    let crs: Vec<Decimal> = loans.iter().map(|loan| loan.cr()).collect();
+    let extreme: Decimal = match highest {
+        true => *crs.iter().max()?,
+        false => *crs.iter().min()?,
+    };
+    let mut indices = crs.iter().enumerate().filter(|(_, cr)| **cr == extreme);
+    let (index, _) = indices.next()?;
+    match indices.next() {
+        Some(_) => None,
+        None => Some(index),
+    }
+}
+
+// Deterministic xorshift random number generator
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+
+    // A random decimal between low and high, with 2 decimals
+    fn decimal(&mut self, low: u64, high: u64) -> Decimal {
+        Decimal::from(low * 100 + self.next() % ((high - low) * 100 + 1)) / dec!(100)
+    }
+}
+
+// A loan as tracked by the model
+#[derive(Clone)]
+struct Loan {
+    id: NonFungibleLocalId,
+    collateral: Decimal,
+    debt: Decimal,
+}
+
+impl Loan {
+    fn cr(&self) -> Decimal {
+        self.collateral / self.debt
+    }
+}
+
+struct Harness {
+    env: TestEnvironment<InMemorySubstateDatabase>,
+    stab_comp: Stabilis,
+    wallet: Bucket,
+    free_stab: Bucket,
+    collateral: ResourceAddress,
+    price: Decimal,
+    healthy: Vec<Loan>,
+    marked: Vec<(NonFungibleLocalId, Loan)>,
+}
+
+impl Harness {
+    fn new() -> Result<Self, RuntimeError> {
+        let fake_oracle_address = GlobalAddress::try_from_hex(
+            "0d906318c6318c60f716464c6318c6318cf7bfcad6a3152b46318c6318c6",
+        )
+        .unwrap();
+        let mut env = TestEnvironmentBuilder::new()
+            .add_global_references(vec![fake_oracle_address])
+            .build();
+        env.disable_auth_module();
+        let package =
+            PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+        let (mut stab_comp, _controller_badge) =
+            Stabilis::instantiate(GenesisConfig::default(), package, &mut env)?;
+
+        let wallet = ResourceBuilder::new_fungible(OwnerRole::None)
+            .divisibility(18)
+            .mint_initial_supply(INITIAL_SUPPLY, &mut env)?;
+        let collateral = wallet.resource_address(&mut env)?;
+
+        stab_comp.add_collateral(collateral, mcr(), dec!(1), &mut env)?;
+        stab_comp.set_paused(PausableAction::ForceMint, false, &mut env)?;
+
+        let (stab, cdp) =
+            stab_comp.open_cdp(wallet.take(dec!(100), &mut env)?, dec!(20), &mut env)?;
+        let free_stab = BucketFactory::create_fungible_bucket(
+            stab.resource_address(&mut env)?,
+            dec!(10000000),
+            Mock,
+            &mut env,
+        )?;
+        free_stab.put(stab, &mut env)?;
+
+        let id = cdp
+            .non_fungible_local_ids(&mut env)?
+            .first()
+            .unwrap()
+            .clone();
+
+        Ok(Self {
+            env,
+            stab_comp,
+            wallet,
+            free_stab,
+            collateral,
+            price: dec!(1),
+            healthy: vec![Loan {
+                id,
+                collateral: dec!(100),
+                debt: dec!(20),
+            }],
+            marked: Vec::new(),
+        })
+    }
+
+    // The liquidation collateral ratio (in collateral per STAB), the internal price stays 1
+    fn lcr(&self) -> Decimal {
+        mcr() / self.price
+    }
+
+    fn open(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        let collateral = rng.decimal(50, 300);
+        if self.wallet.amount(&mut self.env)? < collateral {
+            return Ok(());
+        }
+        let debt = collateral / (self.lcr() * rng.decimal(12, 30) / dec!(10));
+        if debt < dec!(20) {
+            return Ok(());
+        }
+
+        let (stab, cdp) = self.stab_comp.open_cdp(
+            self.wallet.take(collateral, &mut self.env)?,
+            debt,
+            &mut self.env,
+        )?;
+        assert_eq!(stab.amount(&mut self.env)?, debt);
+        self.free_stab.put(stab, &mut self.env)?;
+
+        let id = cdp
+            .non_fungible_local_ids(&mut self.env)?
+            .first()
+            .unwrap()
+            .clone();
+        self.healthy.push(Loan {
+            id,
+            collateral,
+            debt,
+        });
+        Ok(())
+    }
+
+    fn top_up(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        if self.healthy.is_empty() {
+            return Ok(());
+        }
+        let index = rng.below(self.healthy.len());
+        let amount = rng.decimal(10, 50);
+        if self.wallet.amount(&mut self.env)? < amount {
+            return Ok(());
+        }
+
+        self.stab_comp.top_up_cdp(
+            self.healthy[index].id.clone(),
+            self.wallet.take(amount, &mut self.env)?,
+            &mut self.env,
+        )?;
+        self.healthy[index].collateral += amount;
+        Ok(())
+    }
+
+    fn borrow_more(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        if self.healthy.is_empty() {
+            return Ok(());
+        }
+        let index = rng.below(self.healthy.len());
+        let loan = self.healthy[index].clone();
+        let max_debt = loan.collateral / (self.lcr() * dec!("1.2"));
+        if max_debt <= loan.debt + dec!(1) {
+            return Ok(());
+        }
+        let amount = (max_debt - loan.debt) * rng.decimal(1, 100) / dec!(100);
+
+        let stab = self
+            .stab_comp
+            .borrow_more(loan.id.clone(), amount, &mut self.env)?;
+        self.free_stab.put(stab, &mut self.env)?;
+        self.healthy[index].debt += amount;
+        Ok(())
+    }
+
+    fn partial_close(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        if self.healthy.is_empty() {
+            return Ok(());
+        }
+        let index = rng.below(self.healthy.len());
+        let amount = self.healthy[index].debt * rng.decimal(1, 50) / dec!(100);
+        if self.healthy[index].debt - amount < dec!(20) {
+            return Ok(());
+        }
+
+        self.stab_comp.partial_close_cdp(
+            self.healthy[index].id.clone(),
+            self.free_stab.take(amount, &mut self.env)?,
+            &mut self.env,
+        )?;
+        self.healthy[index].debt -= amount;
+        Ok(())
+    }
+
+    fn close(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        if self.healthy.is_empty() {
+            return Ok(());
+        }
+        let loan = self.healthy.remove(rng.below(self.healthy.len()));
+
+        let (collateral, leftover_stab, _claim) = self.stab_comp.close_cdp(
+            loan.id.clone(),
+            self.free_stab.take(loan.debt, &mut self.env)?,
+            &mut self.env,
+        )?;
+        assert_eq!(collateral.amount(&mut self.env)?, loan.collateral);
+        self.wallet.put(collateral, &mut self.env)?;
+        self.free_stab.put(leftover_stab, &mut self.env)?;
+        Ok(())
+    }
+
+    // Move the collateral price, and mark every loan that became liquidatable
+    fn move_price(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        self.price = (self.price * rng.decimal(70, 130) / dec!(100))
+            .max(dec!("0.2"))
+            .min(dec!(5));
+        self.stab_comp
+            .change_collateral_price(self.collateral, self.price, &mut self.env)?;
+
+        let liquidatable = self
+            .healthy
+            .iter()
+            .filter(|loan| loan.cr() < self.lcr())
+            .count();
+
+        for _ in 0..liquidatable {
+            let marker = self
+                .stab_comp
+                .mark_for_liquidation(self.collateral, &mut self.env)?;
+            let marker_id = marker
+                .non_fungible_local_ids(&mut self.env)?
+                .first()
+                .unwrap()
+                .clone();
+
+            let mut marked_index: Option<usize> = None;
+            for (index, loan) in self.healthy.iter().enumerate() {
+                let quote = self.stab_comp.quote_close(loan.id.clone(), &mut self.env)?;
+                if quote.status == CdpStatus::Marked {
+                    marked_index = Some(index);
+                }
+            }
+            let loan = self
+                .healthy
+                .remove(marked_index.expect("Marked loan not found."));
+            assert!(loan.cr() < self.lcr(), "Marked a loan above the LCR.");
+            self.marked.push((marker_id, loan));
+        }
+        Ok(())
+    }
+
+    // Liquidate a marked loan (or save it, if the price recovered)
+    fn liquidate(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        if self.marked.is_empty() {
+            return Ok(());
+        }
+        let (marker_id, loan) = self.marked.remove(rng.below(self.marked.len()));
+
+        let time = self.env.get_current_time();
+        self.env.set_current_time(time.add_minutes(6).unwrap());
+
+        let (collateral_reward, leftover_stab, _receipt) =
+            self.stab_comp.liquidate_position_with_marker(
+                marker_id,
+                self.free_stab.take(loan.debt + dec!(1), &mut self.env)?,
+                &mut self.env,
+            )?;
+
+        if let Some(stab) = leftover_stab {
+            self.free_stab.put(stab, &mut self.env)?;
+        }
+
+        match collateral_reward {
+            Some(collateral) => {
+                self.wallet.put(collateral, &mut self.env)?;
+                let quote = self.stab_comp.quote_close(loan.id.clone(), &mut self.env)?;
+                assert!(quote.status == CdpStatus::Liquidated);
+                if quote.collateral_returned > dec!(0) {
+                    let (leftover, _claim) = self
+                        .stab_comp
+                        .retrieve_leftover_collateral(loan.id.clone(), &mut self.env)?;
+                    self.wallet.put(leftover, &mut self.env)?;
+                }
+            }
+            None => {
+                assert!(loan.cr() >= self.lcr(), "Saved a liquidatable loan.");
+                self.healthy.push(loan);
+            }
+        }
+        Ok(())
+    }
+
+    // Force mint into the loan with the highest collateral ratio
+    //   - the model picks the loan itself, and expects STAB worth the supplied collateral (k = internal price / collateral price)
+    fn force_mint(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        let index = match extreme_cr_loan(&self.healthy, true) {
+            Some(index) if self.healthy[index].cr() > self.lcr() * dec!("3.3") => index,
+            _ => return Ok(()),
+        };
+        let amount = rng.decimal(1, 10);
+
+        let (stab, leftover_collateral) = self.stab_comp.force_mint(
+            self.collateral,
+            self.wallet.take(amount, &mut self.env)?,
+            dec!(1),
+            &mut self.env,
+        )?;
+        let mut supplied: Decimal = amount;
+        if let Some(collateral) = leftover_collateral {
+            supplied -= collateral.amount(&mut self.env)?;
+            self.wallet.put(collateral, &mut self.env)?;
+        }
+        let minted: Decimal = stab.amount(&mut self.env)?;
+        assert_close(minted, supplied * self.price, "force minted STAB");
+        self.free_stab.put(stab, &mut self.env)?;
+
+        self.healthy[index].collateral += supplied;
+        self.healthy[index].debt += minted;
+        Ok(())
+    }
+
+    // Force liquidate part of the loan with the lowest collateral ratio
+    //   - the model picks the loan itself, and expects collateral worth the repaid STAB (percentage to take = 1)
+    fn force_liquidate(&mut self, rng: &mut Rng) -> Result<(), RuntimeError> {
+        let index = match extreme_cr_loan(&self.healthy, false) {
+            Some(index)
+                if self.healthy[index].debt >= dec!(30)
+                    && self.healthy[index].cr() > self.lcr() * dec!("1.01") =>
+            {
+                index
+            }
+            _ => return Ok(()),
+        };
+        let amount = rng.decimal(1, 10);
+
+        let (collateral, leftover_stab) = self.stab_comp.force_liquidate(
+            self.collateral,
+            self.free_stab.take(amount, &mut self.env)?,
+            dec!(1),
+            true,
+            &mut self.env,
+        )?;
+        assert_eq!(
+            leftover_stab.amount(&mut self.env)?,
+            dec!(0),
+            "force liquidation leftover STAB"
+        );
+        let taken: Decimal = collateral.amount(&mut self.env)?;
+        assert_close(taken, amount / self.price, "force liquidated collateral");
+        self.wallet.put(collateral, &mut self.env)?;
+        self.free_stab.put(leftover_stab, &mut self.env)?;
+
+        self.healthy[index].collateral -= taken;
+        self.healthy[index].debt -= amount;
+        Ok(())
+    }
+
+    fn check_invariants(&mut self, step: &str) -> Result<(), RuntimeError> {
+        let open_loans: Vec<Loan> = self
+            .healthy
+            .iter()
+            .cloned()
+            .chain(self.marked.iter().map(|(_, loan)| loan.clone()))
+            .collect();
+
+        // the circulating STAB equals the sum of the debts of all open loans
+        let state = self
+            .stab_comp
+            .get_state(vec![self.collateral], &mut self.env)?;
+        assert_eq!(
+            state.circulating_stab,
+            total_debt(&open_loans),
+            "circulating STAB after {}",
+            step
+        );
+        assert_eq!(
+            state.marked_cdps,
+            self.marked.len() as u64,
+            "marked loans after {}",
+            step
+        );
+
+        // the loans match the model
+        for loan in open_loans.iter() {
+            let quote = self.stab_comp.quote_close(loan.id.clone(), &mut self.env)?;
+            assert_eq!(
+                quote.collateral_returned, loan.collateral,
+                "loan collateral after {}",
+                step
+            );
+            assert_eq!(quote.stab_to_repay, loan.debt, "loan debt after {}", step);
+        }
+
+        // collateral is conserved
+        let (revenue, insurance) = self
+            .stab_comp
+            .get_treasury_balances(self.collateral, &mut self.env)?;
+        let collateral: Decimal = self.wallet.amount(&mut self.env)?
+            + total_collateral(&open_loans)
+            + revenue
+            + insurance;
+        let difference: Decimal = collateral - Decimal::from(INITIAL_SUPPLY);
+        assert!(
+            difference < dec!("0.000001") && difference > dec!("-0.000001"),
+            "collateral not conserved after {}: {}",
+            step,
+            collateral
+        );
+
+        // no healthy loan sits below the liquidation collateral ratio
+        for loan in self.healthy.iter() {
+            assert!(
+                loan.cr() >= self.lcr(),
+                "healthy loan below LCR after {}",
+                step
+            );
+        }
+
+        // the AvlTree holds exactly the healthy loans
+        let histogram = self
+            .stab_comp
+            .get_cr_histogram(self.collateral, vec![], &mut self.env)?;
+        assert!(histogram.complete);
+        assert_eq!(
+            histogram.cdps,
+            vec![self.healthy.len() as u64],
+            "AvlTree loans after {}",
+            step
+        );
+        assert_eq!(
+            histogram.debt,
+            vec![total_debt(&self.healthy)],
+            "AvlTree debt after {}",
+            step
+        );
+
+        Ok(())
+    }
+}
+
+fn random_walk(seed: u64, steps: u64) -> Result<(), RuntimeError> {
+    let mut rng = Rng(seed);
+    let mut harness = Harness::new()?;
+    harness.check_invariants("setup")?;
+
+    for step in 0..steps {
+        let operation = match rng.below(9) {
+            0 => {
+                harness.open(&mut rng)?;
+                "open"
+            }
+            1 => {
+                harness.top_up(&mut rng)?;
+                "top up"
+            }
+            2 => {
+                harness.borrow_more(&mut rng)?;
+                "borrow more"
+            }
+            3 => {
+                harness.partial_close(&mut rng)?;
+                "partial close"
+            }
+            4 => {
+                harness.close(&mut rng)?;
+                "close"
+            }
+            5 => {
+                harness.move_price(&mut rng)?;
+                "price move"
+            }
+            6 => {
+                harness.liquidate(&mut rng)?;
+                "liquidation"
+            }
+            7 => {
+                harness.force_mint(&mut rng)?;
+                "force mint"
+            }
+            _ => {
+                harness.force_liquidate(&mut rng)?;
+                "force liquidation"
+            }
+        };
+        harness.check_invariants(&format!("{} (seed {}, step {})", operation, seed, step))?;
+    }
+
+    Ok(())
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Random walk through all operations, checking the invariants after every step
+#[test]
+fn random_walk_keeps_invariants() -> Result<(), RuntimeError> {
+    let seeds = env_u64("STAB_FUZZ_SEEDS", 3);
+    let steps = env_u64("STAB_FUZZ_STEPS", 60);
+
+    for seed in 1..=seeds {
+        random_walk(seed * 0x9E3779B97F4A7C15, steps)?;
+    }
+
+    Ok(())
+}
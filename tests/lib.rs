@@ -8,7 +8,18 @@
 /// The entire STAB Protocol package has been tested on Stokenet extensively though.
 use dummy_token_pool::dummy_token_pool_test::*;
 use scrypto_test::prelude::*;
+use stab_module::insurance_claims::insurance_claims_test::*;
+use stab_module::inverse_loans::inverse_loans_test::*;
+use stab_module::proxy::proxy_test::*;
+use stab_module::registry::registry_test::*;
+use stab_module::savings::savings_test::*;
+use stab_module::shared_structs::*;
 use stab_module::stabilis_component::stabilis_component_test::*;
+use stab_module::stabilis_component::{
+    DeleverageSettings, EmissionPeriod, ForceMintLimit, ShareEnforcement,
+};
+use stab_module::stabilis_liquidity_pool::stabilis_liquidity_pool_test::*;
+use stab_module::timelock::timelock_test::*;
 // Generic setup
 pub fn publish_and_setup() -> Result<
     (
@@ -29,7 +40,8 @@ pub fn publish_and_setup() -> Result<
     let package =
         PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
 
-    let (mut stab_comp, controller_badge) = Stabilis::instantiate(package, &mut env)?;
+    let (mut stab_comp, controller_badge) =
+        Stabilis::instantiate(GenesisConfig::default(), package, &mut env)?;
 
     let a_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
         .divisibility(18)
@@ -44,7 +56,7 @@ pub fn publish_and_setup() -> Result<
 
     assert_eq!(controller_badge.amount(&mut env)?, dec!(10));
 
-    stab_comp.set_stops(false, false, false, false, false, &mut env)?;
+    stab_comp.set_paused(PausableAction::ForceMint, false, &mut env)?;
 
     Ok((env, stab_comp, a_bucket, controller_badge))
 }
@@ -56,6 +68,319 @@ fn deploys() -> Result<(), RuntimeError> {
     Ok(())
 }
 
+// Only sandbox instantiations are in sandbox mode
+#[test]
+fn deploys_sandbox() -> Result<(), RuntimeError> {
+    let (mut env, stab_comp, _a_bucket, _control_bucket) = publish_and_setup()?;
+    assert!(!stab_comp.is_sandbox(&mut env)?);
+
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+    let (sandbox_comp, _sandbox_badge) =
+        Stabilis::instantiate_sandbox(GenesisConfig::default(), package, &mut env)?;
+    assert!(sandbox_comp.is_sandbox(&mut env)?);
+
+    Ok(())
+}
+
+// Genesis configurations with out of range parameters are rejected
+#[test]
+fn rejects_invalid_genesis_config() -> Result<(), RuntimeError> {
+    let mut env = TestEnvironment::new();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let result = Stabilis::instantiate(
+        GenesisConfig {
+            liquidator_fine: dec!("1.5"),
+            ..GenesisConfig::default()
+        },
+        package,
+        &mut env,
+    );
+    assert!(result.is_err());
+
+    let result = Stabilis::instantiate(
+        GenesisConfig {
+            liquidator_fine: dec!("0.12"),
+            marker_fine: dec!("0.11"),
+            ..GenesisConfig::default()
+        },
+        package,
+        &mut env,
+    );
+    assert!(result.is_err());
+
+    let result = Stabilis::instantiate(
+        GenesisConfig {
+            info_url: "http://ilikeitstable.com".to_string(),
+            ..GenesisConfig::default()
+        },
+        package,
+        &mut env,
+    );
+    assert!(result.is_err());
+
+    let (stab_comp, _controller_badge) = Stabilis::instantiate(
+        GenesisConfig {
+            minimum_mint: dec!(5),
+            ..GenesisConfig::default()
+        },
+        package,
+        &mut env,
+    )?;
+    assert_eq!(stab_comp.get_parameters(&mut env)?.minimum_mint, dec!(5));
+
+    Ok(())
+}
+
+// A multi-week gap between internal price updates is capped at the max drift, and the rest is carried to the next updates
+#[test]
+fn caps_internal_price_drift_over_multi_week_gaps() {
+    let rate = dec!("1.0000007715");
+    let four_weeks: i64 = 4 * 7 * 24 * 60 * 60;
+    let exact = PreciseDecimal::from(rate)
+        .checked_powi(four_weeks / 60)
+        .unwrap();
+
+    let (mut price, mut remainder) =
+        compound_internal_price(dec!(1), rate, four_weeks, PreciseDecimal::ONE, dec!("0.01"));
+    assert_eq!(price, dec!("1.01"));
+    assert!(remainder > PreciseDecimal::ONE);
+
+    for _ in 0..5 {
+        (price, remainder) = compound_internal_price(price, rate, 0, remainder, dec!("0.01"));
+    }
+    assert!(
+        (PreciseDecimal::from(price) - exact).checked_abs().unwrap()
+            < pdec!("0.000000000000000001")
+    );
+
+    let (price, remainder) = compound_internal_price(
+        dec!(1),
+        dec!("0.9999992287"),
+        2 * four_weeks,
+        PreciseDecimal::ONE,
+        dec!("0.01"),
+    );
+    assert!(price < dec!(1) && price > dec!("0.99"));
+    assert!(remainder < PreciseDecimal::ONE);
+}
+
+// Compounding a multi-week gap at once matches compounding it week by week, as the rounding is carried
+#[test]
+fn compounds_internal_price_precisely_over_multi_week_gaps() {
+    let rate = dec!("1.0000007715");
+    let four_weeks: i64 = 4 * 7 * 24 * 60 * 60 + 30;
+
+    let (one_shot, _remainder) =
+        compound_internal_price(dec!(1), rate, four_weeks, PreciseDecimal::ONE, dec!(1));
+
+    let (mut price, mut remainder) = (dec!(1), PreciseDecimal::ONE);
+    for week in 0..4 {
+        let passed_seconds: i64 = match week {
+            3 => four_weeks - 3 * (four_weeks / 4),
+            _ => four_weeks / 4,
+        };
+        (price, remainder) =
+            compound_internal_price(price, rate, passed_seconds, remainder, dec!(1));
+    }
+
+    assert!((price - one_shot).checked_abs().unwrap() <= dec!("0.000000000000000001"));
+}
+
+// A liquidation payout is split according to the fractions, adding up to the total
+#[test]
+fn splits_liquidation_payout() {
+    let amounts = split_payout_amounts(dec!(100), &[dec!("0.5"), dec!("0.3"), dec!("0.2")], 18);
+    assert_eq!(amounts, vec![dec!(50), dec!(30), dec!(20)]);
+
+    let amounts = split_payout_amounts(dec!(100), &[dec!(1)], 18);
+    assert_eq!(amounts, vec![dec!(100)]);
+}
+
+// The rounding dust of a split liquidation payout goes to the last amount
+#[test]
+fn split_liquidation_payout_dust_goes_to_last() {
+    let amounts = split_payout_amounts(
+        dec!(1),
+        &[
+            dec!("0.333333333333333333"),
+            dec!("0.333333333333333333"),
+            dec!("0.333333333333333334"),
+        ],
+        2,
+    );
+    assert_eq!(amounts, vec![dec!("0.33"), dec!("0.33"), dec!("0.34")]);
+}
+
+// An empty liquidation payout split is rejected
+#[test]
+#[should_panic(expected = "Payout split can't be empty.")]
+fn rejects_empty_payout_split() {
+    split_payout_amounts(dec!(100), &[], 18);
+}
+
+// A liquidation payout split with a share that isn't positive is rejected
+#[test]
+#[should_panic(expected = "Payout split shares need to be positive.")]
+fn rejects_non_positive_payout_split() {
+    split_payout_amounts(dec!(100), &[dec!("1.5"), dec!("-0.5")], 18);
+}
+
+// A liquidation payout split not adding up to 1 is rejected
+#[test]
+#[should_panic(expected = "Payout split shares need to add up to 1.")]
+fn rejects_payout_split_not_adding_up() {
+    split_payout_amounts(dec!(100), &[dec!("0.5"), dec!("0.4")], 18);
+}
+
+// The LP token is priced from the pool's invariant, so skewing the reserves doesn't move it
+#[test]
+fn lp_token_price_follows_invariant() {
+    // 1000 STAB and 1000 XRD, or skewed to 4000 STAB and 250 XRD, have the same invariant
+    let balanced: Decimal = lp_token_price(
+        dec!(1000) * dec!(1000),
+        dec!(1000),
+        dec!(1),
+        dec!(1),
+        dec!(0),
+    );
+    let skewed: Decimal = lp_token_price(
+        dec!(4000) * dec!(250),
+        dec!(1000),
+        dec!(1),
+        dec!(1),
+        dec!(0),
+    );
+    assert_eq!(balanced, dec!(2));
+    assert_eq!(skewed, balanced);
+
+    let depegged: Decimal = lp_token_price(
+        dec!(1000) * dec!(1000),
+        dec!(1000),
+        dec!("0.81"),
+        dec!(1),
+        dec!(0),
+    );
+    assert_eq!(depegged, dec!("1.8"));
+
+    let with_haircut: Decimal = lp_token_price(
+        dec!(1000) * dec!(1000),
+        dec!(1000),
+        dec!(1),
+        dec!(1),
+        dec!("0.1"),
+    );
+    assert_eq!(with_haircut, dec!("1.8"));
+}
+
+// Selling STAB into the pool doesn't lower the LP token price pushed by an update, as STAB isn't valued at the pool's spot price
+#[test]
+fn lp_price_cant_be_skewed_by_swapping() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, _a_bucket, control_bucket) = publish_and_setup()?;
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+    let addresses = stab_comp.get_resource_addresses(&mut env)?;
+
+    //the proxy pushes the oracle's XRD price, so XRD needs to be a collateral
+    stab_comp.add_collateral(XRD, dec!("1.5"), dec!("0.015"), &mut env)?;
+
+    let stab_bucket =
+        BucketFactory::create_fungible_bucket(addresses.stab, dec!(1500), Mock, &mut env)?;
+    let xrd_bucket = BucketFactory::create_fungible_bucket(XRD, dec!(100000), Mock, &mut env)?;
+    let reward_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(1, &mut env)?;
+
+    //the morpher oracle is never called, so any component address will do
+    let (mut proxy, _lp_tokens, _leftover) = Proxy::new(
+        xrd_bucket,
+        stab_bucket.take(dec!(1000), &mut env)?,
+        control_bucket,
+        OwnerRole::None,
+        ComponentAddress::new_or_panic(stab_comp.0 .0),
+        addresses.cdp_receipt,
+        addresses.cdp_marker,
+        ComponentAddress::new_or_panic(stab_comp.0 .0),
+        reward_bucket.resource_address(&mut env)?,
+        GenesisConfig::default(),
+        package,
+        &mut env,
+    )?;
+    let _reward = proxy.update(&mut env)?;
+
+    assert!(proxy
+        .add_lp_collateral(dec!("1.9"), dec!("0.1"), &mut env)
+        .is_err());
+    assert!(proxy
+        .add_lp_collateral(dec!(2), dec!("0.11"), &mut env)
+        .is_err());
+    proxy.add_lp_collateral(dec!(2), dec!("0.1"), &mut env)?;
+    let lp_address = proxy.get_lp_collateral(&mut env)?.address;
+    let price = proxy.get_lp_price(&mut env)?;
+    assert_eq!(stab_comp.get_collateral_price(lp_address, &mut env)?, price);
+
+    //sell half the pool's STAB reserve into it and update, within a single transaction
+    let _xrd = proxy.swap_with_best_source(stab_bucket, XRD, dec!(0), &mut env)?;
+    let _reward = proxy.update(&mut env)?;
+
+    //swap fees stay in the pool, so the price can only rise
+    assert!(proxy.get_lp_price(&mut env)? >= price);
+    assert!(stab_comp.get_collateral_price(lp_address, &mut env)? >= price);
+
+    Ok(())
+}
+
+// Liquidity can't be removed from the STAB/XRD pool below its minimum liquidity, and previewing such a removal fails too
+#[test]
+fn cant_remove_liquidity_below_minimum() -> Result<(), RuntimeError> {
+    let dapp_def_address =
+        GlobalAddress::try_from_hex("0d906318c6318c60f716464c6318c6318cf7bfcad6a3152b46318c6318c6")
+            .unwrap();
+    let mut env = TestEnvironmentBuilder::new()
+        .add_global_references(vec![dapp_def_address])
+        .build();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+    let stab_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(1000, &mut env)?;
+    let xrd_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(1000, &mut env)?;
+
+    let mut pool = StabilisPool::new(
+        OwnerRole::None,
+        stab_bucket.resource_address(&mut env)?,
+        xrd_bucket.resource_address(&mut env)?,
+        dec!("0.001"),
+        dec!(100),
+        dapp_def_address,
+        package,
+        &mut env,
+    )?;
+    let (lp_tokens, _leftover) = pool.add_liquidity(stab_bucket, xrd_bucket, &mut env)?;
+    assert_eq!(pool.get_min_liquidity(&mut env)?, dec!(100));
+
+    assert!(pool.preview_remove_liquidity(dec!(901), &mut env).is_err());
+
+    let preview = pool.preview_remove_liquidity(dec!(900), &mut env)?;
+    let (stab, xrd) = pool.remove_liquidity(lp_tokens.take(dec!(900), &mut env)?, &mut env)?;
+    assert_eq!(stab.amount(&mut env)?, dec!(900));
+    assert_eq!(
+        preview.values().cloned().collect::<Vec<Decimal>>(),
+        vec![stab.amount(&mut env)?, xrd.amount(&mut env)?]
+    );
+
+    assert!(pool
+        .remove_liquidity(lp_tokens.take(dec!(1), &mut env)?, &mut env)
+        .is_err());
+
+    Ok(())
+}
+
 // Can open CDP
 #[test]
 fn can_open_cdp() -> Result<(), RuntimeError> {
@@ -69,6 +394,87 @@ fn can_open_cdp() -> Result<(), RuntimeError> {
     Ok(())
 }
 
+// Can open multiple CDPs at once
+#[test]
+fn can_open_multiple_cdps() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdps) = stab_comp.open_cdps(
+        vec![
+            (a_bucket.take(dec!(1000), &mut env)?, dec!(500)),
+            (a_bucket.take(dec!(1000), &mut env)?, dec!(300)),
+        ],
+        &mut env,
+    )?;
+
+    assert_eq!(stab.amount(&mut env)?, dec!(800));
+    assert_eq!(cdps.len(), 2);
+
+    Ok(())
+}
+
+// Fail to open CDP with more debt than the maximum per CDP
+#[test]
+fn cant_open_cdp_above_max_debt() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    stab_comp.set_max_cdp_debt(Some(dec!(1000)), &mut env)?;
+    stab_comp.set_collateral_max_cdp_debt(
+        a_bucket.resource_address(&mut env)?,
+        Some(dec!(400)),
+        &mut env,
+    )?;
+
+    let result = stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env);
+
+    assert!(result.is_err());
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+
+    assert_eq!(stab.amount(&mut env)?, dec!(400));
+
+    Ok(())
+}
+
+// Fail to open CDP when openings are paused for the collateral
+#[test]
+fn cant_open_cdp_paused_collateral() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    stab_comp.set_collateral_paused(
+        a_bucket.resource_address(&mut env)?,
+        PausableAction::OpenCdp,
+        true,
+        &mut env,
+    )?;
+
+    assert!(stab_comp.is_paused(
+        PausableAction::OpenCdp,
+        Some(a_bucket.resource_address(&mut env)?),
+        &mut env
+    )?);
+    assert!(!stab_comp.is_paused(PausableAction::OpenCdp, None, &mut env)?);
+
+    let result = stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env);
+
+    assert!(result.is_err());
+
+    stab_comp.set_collateral_paused(
+        a_bucket.resource_address(&mut env)?,
+        PausableAction::OpenCdp,
+        false,
+        &mut env,
+    )?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    assert_eq!(stab.amount(&mut env)?, dec!(500));
+
+    Ok(())
+}
+
 // Fail to open CDP with insufficient collateral
 #[test]
 fn cant_open_cdp_insufficient_collateral() -> Result<(), RuntimeError> {
@@ -98,131 +504,223 @@ fn can_close_cdp() -> Result<(), RuntimeError> {
     let cdp = cdps.first().unwrap();
 
     // Repay the loan and close the CDP
-    let (collateral, leftover_stab) = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
+    let (collateral, leftover_stab, _claim) = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
     assert_eq!(collateral.amount(&mut env)?, dec!(1000));
     assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
 
     Ok(())
 }
 
-// Can partial close CDP
+// Closing a CDP with a close fee routes the fee to the stability reserve
 #[test]
-fn can_partial_close_cdp() -> Result<(), RuntimeError> {
+fn close_fee_goes_to_stability_reserve() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
+    stab_comp.set_close_fee(dec!(0.01), &mut env)?;
+
     let (stab, cdp) =
         stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (extra_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
 
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
-    // Repay the loan and close the CDP
-    stab_comp.partial_close_cdp(cdp.clone(), stab.take(dec!(100), &mut env)?, &mut env)?;
-
-    assert_eq!(stab.amount(&mut env)?, dec!(400));
+    stab.put(extra_stab, &mut env)?;
+    let (_collateral, leftover_stab, _claim) = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(495));
+    assert_eq!(stab_comp.get_stability_reserve(&mut env)?, dec!(5));
 
     Ok(())
 }
 
-// Cant close CDP with too little repayment
+// A share of the close fee is routed to the stakers of the collateral's pool, and distributed to them
 #[test]
-fn cant_close_cdp_insufficient_repayment() -> Result<(), RuntimeError> {
+fn close_fee_share_is_routed_to_stakers() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
+    stab_comp.set_close_fee(dec!(0.01), &mut env)?;
+
     let (stab, cdp) =
         stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (extra_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (_token_pool, _pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        stab.resource_address(&mut env)?,
+        extra_stab.take(dec!(100), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+
+    stab_comp.set_incentive_route(
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        "protected_deposit".to_string(),
+        dec!("0.5"),
+        dec!("0.4"),
+        &mut env,
+    )?;
 
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
-    // Repay the loan and close the CDP
-    let result = stab_comp.close_cdp(cdp.clone(), stab.take(dec!(400), &mut env)?, &mut env);
+    stab.put(extra_stab, &mut env)?;
+    let (_collateral, leftover_stab, _claim) = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(395));
+    assert_eq!(stab_comp.get_stability_reserve(&mut env)?, dec!(3));
 
-    assert!(result.is_err());
+    let route = stab_comp
+        .get_incentive_route(a_bucket.resource_address(&mut env)?, &mut env)?
+        .unwrap();
+    assert_eq!(
+        route
+            .pending
+            .get(&leftover_stab.resource_address(&mut env)?),
+        Some(&dec!(2))
+    );
+
+    stab_comp.distribute_incentives(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let route = stab_comp
+        .get_incentive_route(a_bucket.resource_address(&mut env)?, &mut env)?
+        .unwrap();
+    assert_eq!(
+        route
+            .pending
+            .get(&leftover_stab.resource_address(&mut env)?),
+        Some(&dec!(0))
+    );
 
     Ok(())
 }
 
-// Cant partial close below minimum mint
+// Closing a CDP above the withdrawal threshold queues the collateral
 #[test]
-fn cant_partial_close_cdp_below_minimum_mint() -> Result<(), RuntimeError> {
+fn close_cdp_queues_withdrawal_above_threshold() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
     let (stab, cdp) =
         stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    stab_comp.set_withdrawal_throttle(true, 60, dec!("0.1"), &mut env)?;
 
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
-    // Repay the loan and close the CDP
-    let result =
-        stab_comp.partial_close_cdp(cdp.clone(), stab.take(dec!("499.5"), &mut env)?, &mut env);
+    let (collateral, _leftover_stab, claim) = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
+    let claim = claim.unwrap();
+
+    assert_eq!(collateral.amount(&mut env)?, dec!(0));
+    assert!(claim.resource_address(&mut env)? != a_bucket.resource_address(&mut env)?);
+    assert_eq!(
+        stab_comp.get_queued_withdrawals(a_bucket.resource_address(&mut env)?, &mut env)?,
+        dec!(1000)
+    );
+
+    let result = stab_comp.claim_withdrawal(claim, &mut env);
 
     assert!(result.is_err());
 
     Ok(())
 }
 
-// Cant close CDP with wrong repayment resource
+// Removing collateral is throttled too, returning the claim next to the (empty) collateral bucket
 #[test]
-fn cant_close_cdp_wrong_resource() -> Result<(), RuntimeError> {
+fn remove_collateral_queues_withdrawal_above_threshold() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
     let (_stab, cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
+
+    stab_comp.set_withdrawal_throttle(true, 60, dec!("0.1"), &mut env)?;
 
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
-    // Repay the loan and close the CDP
-    let result = stab_comp.close_cdp(cdp.clone(), a_bucket.take(dec!(500), &mut env)?, &mut env);
+    let (removed_collateral, claim) =
+        stab_comp.remove_collateral(cdp.clone(), dec!(50), &mut env)?;
+    assert_eq!(removed_collateral.amount(&mut env)?, dec!(50));
+    assert!(claim.is_none());
 
-    assert!(result.is_err());
+    let (removed_collateral, claim) =
+        stab_comp.remove_collateral(cdp.clone(), dec!(100), &mut env)?;
+    assert_eq!(removed_collateral.amount(&mut env)?, dec!(0));
+    assert!(claim.is_some());
+    assert_eq!(
+        stab_comp.get_queued_withdrawals(a_bucket.resource_address(&mut env)?, &mut env)?,
+        dec!(100)
+    );
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(61).unwrap());
+    let claimed = stab_comp.claim_withdrawal(claim.unwrap(), &mut env)?;
+    assert_eq!(claimed.amount(&mut env)?, dec!(100));
 
     Ok(())
 }
 
-// Cant close CDP with wrong repayment resource
+// Can partial close CDP
 #[test]
-fn cant_partial_close_cdp_wrong_resource() -> Result<(), RuntimeError> {
+fn can_partial_close_cdp() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let (_stab, cdp) =
+    let (stab, cdp) =
         stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
 
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
     // Repay the loan and close the CDP
-    let result =
-        stab_comp.partial_close_cdp(cdp.clone(), a_bucket.take(dec!(500), &mut env)?, &mut env);
+    stab_comp.partial_close_cdp(cdp.clone(), stab.take(dec!(100), &mut env)?, None, &mut env)?;
 
-    assert!(result.is_err());
+    assert_eq!(stab.amount(&mut env)?, dec!(400));
 
     Ok(())
 }
 
-// Top up CDP works
+// Can partial close CDP and withdraw collateral in the same call, with one CR check covering both
 #[test]
-fn can_top_up_cdp() -> Result<(), RuntimeError> {
+fn can_partial_close_cdp_with_withdrawal() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let (_stab, cdp) =
+    let (stab, cdp) =
         stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
 
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
-    let _ = stab_comp.top_up_cdp(cdp.clone(), a_bucket.take(dec!(500), &mut env)?, &mut env);
+    // Withdrawing without the repayment would put the CR below MCR
+    let result = stab_comp.remove_collateral(cdp.clone(), dec!(500), &mut env);
+    assert!(result.is_err());
 
-    assert_eq!(a_bucket.amount(&mut env)?, dec!(8500));
+    // Withdrawing too much, even with the repayment, fails
+    let result = stab_comp.partial_close_cdp(
+        cdp.clone(),
+        stab.take(dec!(200), &mut env)?,
+        Some(dec!(600)),
+        &mut env,
+    );
+    assert!(result.is_err());
+
+    let (collateral, leftover_payment, _claim) = stab_comp.partial_close_cdp(
+        cdp.clone(),
+        stab.take(dec!(200), &mut env)?,
+        Some(dec!(500)),
+        &mut env,
+    )?;
+    assert_eq!(collateral.unwrap().amount(&mut env)?, dec!(500));
+    assert!(leftover_payment.is_none());
 
     Ok(())
 }
 
-// Top up CDP doesn't work with wrong resource
+// Cant close CDP with too little repayment
 #[test]
-fn cant_top_up_cdp_wrong_payment() -> Result<(), RuntimeError> {
+fn cant_close_cdp_insufficient_repayment() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
     let (stab, cdp) =
@@ -231,16 +729,17 @@ fn cant_top_up_cdp_wrong_payment() -> Result<(), RuntimeError> {
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
-    let result = stab_comp.top_up_cdp(cdp.clone(), stab.take(dec!(500), &mut env)?, &mut env);
+    // Repay the loan and close the CDP
+    let result = stab_comp.close_cdp(cdp.clone(), stab.take(dec!(400), &mut env)?, &mut env);
 
     assert!(result.is_err());
 
     Ok(())
 }
 
-// Top up CDP works, and removes updated amount of collateral after
+// Cant partial close below minimum mint
 #[test]
-fn can_top_up_cdp_and_remove() -> Result<(), RuntimeError> {
+fn cant_partial_close_cdp_below_minimum_mint() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
     let (stab, cdp) =
@@ -249,9 +748,112 @@ fn can_top_up_cdp_and_remove() -> Result<(), RuntimeError> {
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
-    let _ = stab_comp.top_up_cdp(cdp.clone(), a_bucket.take(dec!(500), &mut env)?, &mut env);
+    // Repay the loan and close the CDP
+    let result = stab_comp.partial_close_cdp(
+        cdp.clone(),
+        stab.take(dec!("499.5"), &mut env)?,
+        None,
+        &mut env,
+    );
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+// Cant close CDP with wrong repayment resource
+#[test]
+fn cant_close_cdp_wrong_resource() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    // Repay the loan and close the CDP
+    let result = stab_comp.close_cdp(cdp.clone(), a_bucket.take(dec!(500), &mut env)?, &mut env);
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+// Cant close CDP with wrong repayment resource
+#[test]
+fn cant_partial_close_cdp_wrong_resource() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    // Repay the loan and close the CDP
+    let result = stab_comp.partial_close_cdp(
+        cdp.clone(),
+        a_bucket.take(dec!(500), &mut env)?,
+        None,
+        &mut env,
+    );
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+// Top up CDP works
+#[test]
+fn can_top_up_cdp() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    let _ = stab_comp.top_up_cdp(cdp.clone(), a_bucket.take(dec!(500), &mut env)?, &mut env);
+
+    assert_eq!(a_bucket.amount(&mut env)?, dec!(8500));
 
-    let (collateral, leftover_stab) = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
+    Ok(())
+}
+
+// Top up CDP doesn't work with wrong resource
+#[test]
+fn cant_top_up_cdp_wrong_payment() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    let result = stab_comp.top_up_cdp(cdp.clone(), stab.take(dec!(500), &mut env)?, &mut env);
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+// Top up CDP works, and removes updated amount of collateral after
+#[test]
+fn can_top_up_cdp_and_remove() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    let _ = stab_comp.top_up_cdp(cdp.clone(), a_bucket.take(dec!(500), &mut env)?, &mut env);
+
+    let (collateral, leftover_stab, _claim) = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
 
     assert_eq!(collateral.amount(&mut env)?, dec!(1500));
     assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
@@ -270,7 +872,8 @@ fn can_remove_collateral() -> Result<(), RuntimeError> {
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp = cdps.first().unwrap();
 
-    let removed_collateral = stab_comp.remove_collateral(cdp.clone(), dec!(100), &mut env)?;
+    let (removed_collateral, _claim) =
+        stab_comp.remove_collateral(cdp.clone(), dec!(100), &mut env)?;
 
     assert_eq!(removed_collateral.amount(&mut env)?, dec!(100));
     assert_eq!(
@@ -317,6 +920,26 @@ fn can_borrow_more() -> Result<(), RuntimeError> {
     Ok(())
 }
 
+// Set a label on a loan, which can't be too long
+#[test]
+fn can_set_cdp_label() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    stab_comp.set_cdp_label(cdp_id.clone(), "long-term".to_string(), &mut env)?;
+
+    let result = stab_comp.set_cdp_label(cdp_id.clone(), "a".repeat(65), &mut env);
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
 // Mark a loan for liquidation
 #[test]
 fn can_mark_for_liquidation() -> Result<(), RuntimeError> {
@@ -338,208 +961,226 @@ fn can_mark_for_liquidation() -> Result<(), RuntimeError> {
     Ok(())
 }
 
-// Mark a loan for liquidation, with pool unit collateral
+// The debt of loans is grouped by collateral ratio
 #[test]
-fn can_mark_for_liquidation_pool_unit() -> Result<(), RuntimeError> {
+fn can_get_cr_histogram() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let pool_package_address =
-        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
-    let (_token_pool, pool_units, pool_address) = TokenPool::instantiate_token_pool(
-        a_bucket.resource_address(&mut env)?,
-        a_bucket.take(dec!(1000), &mut env)?,
-        pool_package_address,
-        &mut env,
-    )?;
-    let _ = stab_comp.add_pool_collateral(
-        pool_units.resource_address(&mut env)?,
-        a_bucket.resource_address(&mut env)?,
-        pool_address,
-        false,
-        true,
-        &mut env,
-    );
     let (_stab, _cdp) =
-        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(250), &mut env)?;
 
-    let _ = stab_comp.change_collateral_price(
+    let histogram = stab_comp.get_cr_histogram(
         a_bucket.resource_address(&mut env)?,
-        dec!(0.5),
+        vec![dec!(3)],
         &mut env,
-    );
-
-    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    )?;
 
-    assert!(marker.amount(&mut env)? > dec!(0));
+    assert_eq!(histogram.debt, vec![dec!(500), dec!(250)]);
+    assert_eq!(histogram.cdps, vec![1, 1]);
+    assert!(histogram.complete);
 
     Ok(())
 }
 
-// Mark a loan for liquidation, but save it, as it's an appreciated pool unit collateral CDP
+// Export the data and collateral ratios of multiple loans in one call
 #[test]
-fn can_save_cdp_with_pool_unit_through_marking() -> Result<(), RuntimeError> {
+fn can_export_cdps() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let pool_package_address =
-        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
-    let (mut pool_comp, pool_units, pool_address) = TokenPool::instantiate_token_pool(
-        a_bucket.resource_address(&mut env)?,
-        a_bucket.take(dec!(1000), &mut env)?,
-        pool_package_address,
+    let (_stab, first_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, second_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(250), &mut env)?;
+    let first_cdps = first_cdp.non_fungible_local_ids(&mut env)?;
+    let second_cdps = second_cdp.non_fungible_local_ids(&mut env)?;
+
+    let exports = stab_comp.export_cdps(
+        vec![
+            first_cdps.first().unwrap().clone(),
+            second_cdps.first().unwrap().clone(),
+        ],
         &mut env,
     )?;
-    let _ = stab_comp.add_pool_collateral(
-        pool_units.resource_address(&mut env)?,
+
+    assert_eq!(exports.len(), 2);
+    assert_eq!(exports[0].cdp.minted_stab, dec!(500));
+    assert_eq!(exports[0].cr, dec!(2));
+    assert_eq!(exports[1].value_cr, dec!(4));
+
+    Ok(())
+}
+
+// The terms a loan was opened under stay on its receipt, even after the parameters change
+#[test]
+fn cdp_receipt_records_origination_terms() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    stab_comp.set_close_fee(dec!(0.01), &mut env)?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+
+    stab_comp.set_close_fee(dec!(0.02), &mut env)?;
+    stab_comp.edit_collateral(
         a_bucket.resource_address(&mut env)?,
-        pool_address,
-        false,
+        dec!(2),
         true,
+        dec!(1),
         &mut env,
-    );
+    )?;
+
+    let exports = stab_comp.export_cdps(vec![cdps.first().unwrap().clone()], &mut env)?;
+    let origination = &exports[0].cdp.origination;
+    assert_eq!(origination.mcr, dec!("1.5"));
+    assert_eq!(origination.collateral_price, dec!(1));
+    assert_eq!(origination.close_fee, dec!(0.01));
+    assert_eq!(origination.opened_at, env.get_current_time());
+
+    Ok(())
+}
+
+// A fast price drop adds the MCR surcharge, making a loan markable earlier
+#[test]
+fn mcr_surcharge_after_fast_price_drop() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
     let (_stab, _cdp) =
-        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
 
-    let _ = stab_comp.change_collateral_price(
+    stab_comp.set_mcr_surcharge(
         a_bucket.resource_address(&mut env)?,
+        dec!(0.1),
         dec!(0.5),
+        60,
+        &mut env,
+    )?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.9),
         &mut env,
     );
 
-    let _ = pool_comp.protected_deposit(a_bucket.take(dec!(1000), &mut env)?, &mut env);
+    assert_eq!(
+        stab_comp.get_mcr_surcharge(a_bucket.resource_address(&mut env)?, &mut env)?,
+        dec!(0.5)
+    );
 
     let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
-
     assert!(marker.amount(&mut env)? > dec!(0));
 
     Ok(())
 }
 
-// Liquidate a marked loan / CDP, using a marker receipt
+// A collateral above its max share gets a growing share surcharge, until its share falls below the release share
 #[test]
-fn can_liquidate_with_marker() -> Result<(), RuntimeError> {
+fn enforces_collateral_share() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let collateral = a_bucket.resource_address(&mut env)?;
 
-    //open loan
-    let (stab, _cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
-
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
+    let b_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    stab_comp.add_collateral(
+        b_bucket.resource_address(&mut env)?,
+        dec!("1.5"),
+        dec!("1"),
         &mut env,
     )?;
 
-    //change col price so liq is possible
-    let _ = stab_comp.change_collateral_price(
-        a_bucket.resource_address(&mut env)?,
-        dec!(0.5),
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_b_stab, _b_cdp) =
+        stab_comp.open_cdp(b_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    stab_comp.edit_collateral(collateral, dec!("1.5"), true, dec!("0.4"), &mut env)?;
+    stab_comp.set_share_enforcement(
+        collateral,
+        Some(ShareEnforcement {
+            surcharge_per_day: dec!("0.1"),
+            max_surcharge: dec!("0.5"),
+            release_share: dec!("0.3"),
+        }),
         &mut env,
-    );
+    )?;
 
-    //mark loan
-    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
-    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
-    let marker_id = marker_ids.first().unwrap();
+    let _ = stab_comp.change_collateral_price(collateral, dec!(1), &mut env);
+    let status = stab_comp.get_share_enforcement(collateral, &mut env)?;
+    assert_eq!(status.share, dec!("0.5"));
+    assert!(status.enforced_since.is_some());
+    assert!(status.stab_to_repay > dec!(0));
 
     let time = env.get_current_time();
-    let new_time = time.add_minutes(5).unwrap();
-    env.set_current_time(new_time);
-
-    //liq with marker
-    let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
-        .liquidate_position_with_marker(
-            marker_id.clone(),
-            free_stab.take(dec!(600), &mut env)?,
-            &mut env,
-        )?;
+    env.set_current_time(time.add_days(1).unwrap());
+    let _ = stab_comp.change_collateral_price(collateral, dec!(1), &mut env);
+    assert_eq!(
+        stab_comp
+            .get_share_enforcement(collateral, &mut env)?
+            .surcharge,
+        dec!("0.1")
+    );
 
-    //check rewards
-    assert_eq!(collateral_reward.unwrap().amount(&mut env)?, dec!(1000));
-    if let Some(stab) = leftover_stab {
-        assert_eq!(stab.amount(&mut env)?, dec!(100));
-    }
+    let (_more_b_stab, _more_b_cdp) =
+        stab_comp.open_cdp(b_bucket.take(dec!(3000), &mut env)?, dec!(1000), &mut env)?;
+    let _ = stab_comp.change_collateral_price(collateral, dec!(1), &mut env);
+    let status = stab_comp.get_share_enforcement(collateral, &mut env)?;
+    assert!(status.enforced_since.is_none());
+    assert_eq!(status.surcharge, dec!(0));
 
     Ok(())
 }
 
-// Liquidate a marked loan / CDP, using a marker receipt before it should be possible
+// The markers placed on a loan can be queried, with their status
 #[test]
-fn cant_liquidate_with_marker_before_time() -> Result<(), RuntimeError> {
+fn can_get_markers_for_cdp() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    //open loan
-    let (stab, _cdp) =
+    let (_stab, cdp) =
         stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
 
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
-        &mut env,
-    )?;
+    assert!(stab_comp
+        .get_markers_for_cdp(cdp.clone(), &mut env)?
+        .is_empty());
 
-    //change col price so liq is possible
     let _ = stab_comp.change_collateral_price(
         a_bucket.resource_address(&mut env)?,
         dec!(0.5),
         &mut env,
     );
-
-    //mark loan
     let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
     let marker_ids = marker.non_fungible_local_ids(&mut env)?;
     let marker_id = marker_ids.first().unwrap();
 
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(4).unwrap();
-    env.set_current_time(new_time);
+    let markers = stab_comp.get_markers_for_cdp(cdp.clone(), &mut env)?;
+    assert_eq!(markers.len(), 1);
+    assert_eq!(markers[0].marker_id, marker_id.clone());
+    assert!(markers[0].mark_type == CdpUpdate::Marked);
+    assert!(!markers[0].used);
 
-    //liq with marker
-    let failure = stab_comp.liquidate_position_with_marker(
-        marker_id.clone(),
-        free_stab.take(dec!(600), &mut env)?,
-        &mut env,
-    );
-
-    assert!(failure.is_err());
+    let info = stab_comp.get_marker(marker_id.clone(), &mut env)?.unwrap();
+    assert_eq!(info.marked_id, cdp.clone());
+    assert!(stab_comp
+        .get_marker(NonFungibleLocalId::integer(2), &mut env)?
+        .is_none());
 
     Ok(())
 }
 
-// Try to liquidate a CDP with a marker receipt that's received for saving a CDP
+// Marks above the per-collateral marks per minute limit are rejected
 #[test]
-fn cant_liquidate_using_marker_save_receipt() -> Result<(), RuntimeError> {
+fn rejects_marks_above_rate_limit() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let pool_package_address =
-        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
-    let (mut pool_comp, pool_units, pool_address) = TokenPool::instantiate_token_pool(
-        a_bucket.resource_address(&mut env)?,
-        a_bucket.take(dec!(1000), &mut env)?,
-        pool_package_address,
-        &mut env,
-    )?;
-    let _ = stab_comp.add_pool_collateral(
-        pool_units.resource_address(&mut env)?,
-        a_bucket.resource_address(&mut env)?,
-        pool_address,
-        false,
-        true,
-        &mut env,
-    );
-    let (stab, _cdp) =
-        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
 
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
-        &mut env,
-    )?;
+    stab_comp.set_max_marks_per_minute(a_bucket.resource_address(&mut env)?, Some(1), &mut env)?;
 
     let _ = stab_comp.change_collateral_price(
         a_bucket.resource_address(&mut env)?,
@@ -547,258 +1188,2179 @@ fn cant_liquidate_using_marker_save_receipt() -> Result<(), RuntimeError> {
         &mut env,
     );
 
-    let _ = pool_comp.protected_deposit(a_bucket.take(dec!(1000), &mut env)?, &mut env);
-
-    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
-    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
-    let marker_id = marker_ids.first().unwrap();
-
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(5).unwrap();
-    env.set_current_time(new_time);
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
 
-    //liq with marker
-    let failure = stab_comp.liquidate_position_with_marker(
-        marker_id.clone(),
-        free_stab.take(dec!(600), &mut env)?,
-        &mut env,
+    assert_eq!(
+        stab_comp.get_mark_rate(a_bucket.resource_address(&mut env)?, &mut env)?,
+        1
     );
-
-    assert!(failure.is_err());
+    assert!(stab_comp
+        .mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)
+        .is_err());
 
     Ok(())
 }
 
-// Try to liquidate an appreciated CDP with pool unit collateral, so that it will instead save it.
+// A marked loan is saved with its auto-top-up buffer, paying the caller a bounty
 #[test]
-fn can_only_save_appreciated_pool_cdp() -> Result<(), RuntimeError> {
+fn can_apply_buffer_to_marked_cdp() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let pool_package_address =
-        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
-    let (mut pool_comp, pool_units, pool_address) = TokenPool::instantiate_token_pool(
-        a_bucket.resource_address(&mut env)?,
-        a_bucket.take(dec!(1000), &mut env)?,
-        pool_package_address,
-        &mut env,
-    )?;
-    let _ = stab_comp.add_pool_collateral(
-        pool_units.resource_address(&mut env)?,
-        a_bucket.resource_address(&mut env)?,
-        pool_address,
-        false,
-        true,
-        &mut env,
-    );
-    let (stab, _cdp) =
-        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
 
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
-        &mut env,
-    )?;
+    stab_comp.deposit_to_buffer(cdp.clone(), a_bucket.take(dec!(1000), &mut env)?, &mut env)?;
 
     let _ = stab_comp.change_collateral_price(
         a_bucket.resource_address(&mut env)?,
         dec!(0.5),
         &mut env,
     );
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
 
-    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
-    let marker_address = marker.resource_address(&mut env)?;
-    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
-    let marker_id = marker_ids.first().unwrap();
+    let bounty = stab_comp.apply_buffer(cdp.clone(), &mut env)?;
+    assert_eq!(bounty.amount(&mut env)?, dec!(10));
 
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(5).unwrap();
-    env.set_current_time(new_time);
+    let quote = stab_comp.quote_close(cdp.clone(), &mut env)?;
+    assert!(matches!(quote.status, CdpStatus::Healthy));
+    assert_eq!(quote.collateral_returned, dec!(1990));
+    assert_eq!(stab_comp.get_buffer(cdp.clone(), &mut env)?, dec!(0));
 
-    let _ = pool_comp.protected_deposit(a_bucket.take(dec!(1000), &mut env)?, &mut env);
+    Ok(())
+}
 
-    //liq with marker
-    let (payment, remainder, receipt) = stab_comp.liquidate_position_with_marker(
-        marker_id.clone(),
-        free_stab.take(dec!(600), &mut env)?,
-        &mut env,
-    )?;
+// A frozen loan can't be borrowed against or closed until the freeze is lifted
+#[test]
+fn frozen_cdp_blocks_actions() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    assert!(payment.is_none());
-    assert!(remainder.unwrap().amount(&mut env)? == dec!(600));
-    assert!(receipt.resource_address(&mut env)? == marker_address);
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    stab_comp.freeze_cdp(cdp.clone(), 60, "Suspected exploit".to_string(), &mut env)?;
+    let freeze = stab_comp.get_cdp_freeze(cdp.clone(), &mut env)?;
+    assert_eq!(freeze.unwrap().reason, "Suspected exploit".to_string());
+
+    let borrow_result = stab_comp.borrow_more(cdp.clone(), dec!(10), &mut env);
+    assert!(borrow_result.is_err());
+
+    stab_comp.unfreeze_cdp(cdp.clone(), &mut env)?;
+    assert!(stab_comp.get_cdp_freeze(cdp.clone(), &mut env)?.is_none());
+
+    let (_collateral, _leftover, _claim) = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
 
     Ok(())
 }
 
-// Liquidate a CDP with pool unit collateral
+// Refreezing a frozen loan can't extend its dispute beyond the max freeze duration
 #[test]
-fn can_liquidate_pool_cdp() -> Result<(), RuntimeError> {
+fn freeze_is_capped_per_dispute() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let pool_package_address =
-        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
-    let (_pool_comp, pool_units, pool_address) = TokenPool::instantiate_token_pool(
-        a_bucket.resource_address(&mut env)?,
-        a_bucket.take(dec!(1000), &mut env)?,
-        pool_package_address,
-        &mut env,
-    )?;
-    let _ = stab_comp.add_pool_collateral(
-        pool_units.resource_address(&mut env)?,
-        a_bucket.resource_address(&mut env)?,
-        pool_address,
-        false,
-        true,
-        &mut env,
-    );
-    let (stab, _cdp) =
-        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
-
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
-        &mut env,
-    )?;
-
-    let _ = stab_comp.change_collateral_price(
-        a_bucket.resource_address(&mut env)?,
-        dec!(0.5),
-        &mut env,
-    );
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
 
-    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
-    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
-    let marker_id = marker_ids.first().unwrap();
+    stab_comp.set_max_freeze_minutes(120, &mut env)?;
+    stab_comp.freeze_cdp(cdp.clone(), 120, "Dispute".to_string(), &mut env)?;
 
     let time = env.get_current_time();
-    let new_time = time.add_minutes(5).unwrap();
-    env.set_current_time(new_time);
+    env.set_current_time(time.add_minutes(60).unwrap());
+    assert!(stab_comp
+        .freeze_cdp(cdp.clone(), 120, "Dispute".to_string(), &mut env)
+        .is_err());
+    stab_comp.freeze_cdp(cdp.clone(), 60, "Dispute".to_string(), &mut env)?;
 
-    //liq with marker
-    let (payment, remainder, _receipt) = stab_comp.liquidate_position_with_marker(
-        marker_id.clone(),
-        free_stab.take(dec!(600), &mut env)?,
-        &mut env,
-    )?;
-
-    //check results
-    assert_eq!(payment.unwrap().amount(&mut env)?, dec!(1000));
-    assert_eq!(remainder.unwrap().amount(&mut env)?, dec!(100));
+    assert!(stab_comp.set_max_freeze_minutes(0, &mut env).is_err());
 
     Ok(())
 }
 
-// Liquidate a marked loan / CDP, without a marker receipt by id
+// A loan that opted in to auto-deleverage is deleveraged back to its target CR once it crosses the trigger CR
 #[test]
-fn can_liquidate_without_marker_by_id() -> Result<(), RuntimeError> {
+fn can_auto_deleverage() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
     let (stab, cdp) =
         stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
-
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
-    let cdp_id = cdps.first().unwrap();
+    let cdp = cdps.first().unwrap();
 
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
+    stab_comp.set_auto_deleverage(
+        cdp.clone(),
+        Some(DeleverageSettings {
+            trigger_cr: dec!("1.8"),
+            target_cr: dec!(3),
+        }),
         &mut env,
     )?;
 
-    //change col price so liq is possible
-    let _ = stab_comp.change_collateral_price(
-        a_bucket.resource_address(&mut env)?,
-        dec!(0.5),
-        &mut env,
-    );
+    let not_crossed_result = stab_comp.quote_auto_deleverage(cdp.clone(), &mut env);
+    assert!(not_crossed_result.is_err());
 
-    //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    stab_comp.set_auto_deleverage(
+        cdp.clone(),
+        Some(DeleverageSettings {
+            trigger_cr: dec!("2.5"),
+            target_cr: dec!(3),
+        }),
+        &mut env,
+    )?;
 
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(10).unwrap();
-    env.set_current_time(new_time);
+    let (stab_to_repay, collateral_to_sell, bounty) =
+        stab_comp.quote_auto_deleverage(cdp.clone(), &mut env)?;
+    let (collateral, bounty_bucket, _leftover) =
+        stab_comp.auto_deleverage(cdp.clone(), stab.take(stab_to_repay, &mut env)?, &mut env)?;
 
-    //liq without marker
-    let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
-        .liquidate_position_without_marker(
-            free_stab.take(dec!(600), &mut env)?,
-            None,
-            cdp_id.clone(),
-            &mut env,
-        )?;
+    assert_eq!(collateral.amount(&mut env)?, collateral_to_sell);
+    assert_eq!(bounty_bucket.amount(&mut env)?, bounty);
 
-    //check results
-    assert_eq!(collateral_reward.unwrap().amount(&mut env)?, dec!(1000));
-    if let Some(stab) = leftover_stab {
-        assert_eq!(stab.amount(&mut env)?, dec!(100));
-    }
+    let exports = stab_comp.export_cdps(vec![cdp.clone()], &mut env)?;
+    assert!(exports[0].value_cr > dec!("2.99") && exports[0].value_cr < dec!("3.01"));
 
     Ok(())
 }
 
-// Liquidate a marked loan / CDP, without a marker receipt by id before it should be possible
+// Every change of a collateral's risk parameters is recorded in the risk parameter history
 #[test]
-fn cant_liquidate_without_marker_by_id_before_time() -> Result<(), RuntimeError> {
+fn records_risk_parameter_changes() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let (stab, cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
-
-    let cdps = cdp.non_fungible_local_ids(&mut env)?;
-    let cdp_id = cdps.first().unwrap();
-
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
+    stab_comp.edit_collateral(
+        a_bucket.resource_address(&mut env)?,
+        dec!(2),
+        false,
+        dec!("0.5"),
         &mut env,
     )?;
 
-    //change col price so liq is possible
-    let _ = stab_comp.change_collateral_price(
-        a_bucket.resource_address(&mut env)?,
-        dec!(0.5),
+    let changes = stab_comp.get_risk_parameter_changes(
+        0,
+        Some(a_bucket.resource_address(&mut env)?),
         &mut env,
-    );
+    )?;
 
-    //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    assert_eq!(changes.len(), 2);
+    assert!(changes[0].old.is_none());
+    assert_eq!(changes[1].changed_by, "edit_collateral".to_string());
+    assert_eq!(changes[1].old.as_ref().unwrap().mcr, Some(dec!("1.5")));
+    assert_eq!(changes[1].new.mcr, Some(dec!(2)));
+    assert!(!changes[1].new.accepted);
 
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(9).unwrap();
-    env.set_current_time(new_time);
+    Ok(())
+}
 
-    //liq without marker
-    let failure = stab_comp.liquidate_position_without_marker(
-        free_stab.take(dec!(600), &mut env)?,
-        None,
-        cdp_id.clone(),
-        &mut env,
-    );
+// A loan with debt below the minimum mint can only be closed as dust
+#[test]
+fn can_close_dust_cdp() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    assert!(failure.is_err());
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    let not_dust_result =
+        stab_comp.close_dust_cdp(cdp.clone(), stab.take(dec!(500), &mut env)?, &mut env);
+    assert!(not_dust_result.is_err());
+
+    stab_comp.set_minimum_mint(dec!(1000), &mut env)?;
+    let (_collateral, _leftover, _claim) = stab_comp.close_dust_cdp(cdp.clone(), stab, &mut env)?;
+
+    let quote = stab_comp.quote_close(cdp.clone(), &mut env)?;
+    assert!(matches!(quote.status, CdpStatus::Closed));
 
     Ok(())
 }
 
-// Liquidate a marked loan / CDP, without a marker receipt automatically
+// Borrowing and repaying are tracked in the lifetime statistics of a loan
 #[test]
-fn can_liquidate_without_marker_automatic() -> Result<(), RuntimeError> {
+fn tracks_cdp_stats() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
     let (stab, cdp) =
         stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
-
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
-    let cdp_id = cdps.first().unwrap();
+    let cdp = cdps.first().unwrap();
+
+    let _extra_stab = stab_comp.borrow_more(cdp.clone(), dec!(50), &mut env)?;
+    stab_comp.partial_close_cdp(cdp.clone(), stab.take(dec!(100), &mut env)?, None, &mut env)?;
+
+    let stats = stab_comp.get_cdp_stats(cdp.clone(), &mut env)?;
+    assert_eq!(stats.total_borrowed, dec!(550));
+    assert_eq!(stats.total_repaid, dec!(100));
+    assert_eq!(stats.times_marked, 0);
+    assert_eq!(stats.times_saved, 0);
+
+    Ok(())
+}
+
+// Opens and closes are counted per collateral per hour
+#[test]
+fn tracks_collateral_activity() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let collateral = a_bucket.resource_address(&mut env)?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let _closed = stab_comp.close_cdp(cdps.first().unwrap().clone(), stab, &mut env)?;
+
+    let activity = stab_comp.get_collateral_activity(collateral, 1, &mut env)?;
+    assert_eq!(activity.len(), 1);
+    assert_eq!(activity[0].opens, 2);
+    assert_eq!(activity[0].closes, 1);
+    assert_eq!(activity[0].liquidations, 0);
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_hours(2).unwrap());
+    assert!(stab_comp
+        .get_collateral_activity(collateral, 1, &mut env)?
+        .is_empty());
+    assert_eq!(
+        stab_comp
+            .get_collateral_activity(collateral, 24, &mut env)?
+            .len(),
+        1
+    );
+
+    Ok(())
+}
+
+// Collateral can be moved between loans of the same collateral, as long as the source loan stays above MCR
+#[test]
+fn can_move_collateral() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, first_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, second_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let first_cdps = first_cdp.non_fungible_local_ids(&mut env)?;
+    let first_cdp = first_cdps.first().unwrap();
+    let second_cdps = second_cdp.non_fungible_local_ids(&mut env)?;
+    let second_cdp = second_cdps.first().unwrap();
+
+    stab_comp.move_collateral(first_cdp.clone(), second_cdp.clone(), dec!(200), &mut env)?;
+
+    let exports = stab_comp.export_cdps(vec![first_cdp.clone(), second_cdp.clone()], &mut env)?;
+    assert_eq!(exports[0].cdp.collateral_amount, dec!(800));
+    assert_eq!(exports[1].cdp.collateral_amount, dec!(1200));
+    assert_eq!(
+        stab_comp.get_global_cr(&mut env)?.collateral_value,
+        dec!(2000)
+    );
+
+    let result =
+        stab_comp.move_collateral(first_cdp.clone(), second_cdp.clone(), dec!(100), &mut env);
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+// Global CR is the collateral value divided by the circulating STAB value
+#[test]
+fn calculates_global_cr() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    assert!(stab_comp.get_global_cr(&mut env)?.cr.is_none());
+
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(250), &mut env)?;
+
+    let global_cr = stab_comp.get_global_cr(&mut env)?;
+    assert_eq!(global_cr.collateral_value, dec!(2000));
+    assert_eq!(global_cr.stab_value, dec!(750));
+    assert_eq!(global_cr.cr, Some(dec!(2000) / dec!(750)));
+    assert_eq!(global_cr.collaterals.len(), 1);
+    assert_eq!(global_cr.collaterals[0].minted_stab, dec!(750));
+
+    Ok(())
+}
+
+// The supply breakdown separates the STAB minted through loans from the total STAB supply
+#[test]
+fn can_get_supply_breakdown() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(250), &mut env)?;
+    let _freed_stab = stab_comp.free_stab(dec!(100), &mut env)?;
+
+    let breakdown = stab_comp.get_supply_breakdown(&mut env)?;
+    assert_eq!(breakdown.total_supply, dec!(850));
+    assert_eq!(breakdown.circulating_stab, dec!(750));
+    assert_eq!(breakdown.collaterals.len(), 1);
+    assert_eq!(breakdown.collaterals[0].minted_stab, dec!(750));
+    assert_eq!(breakdown.collaterals[0].stab_share, dec!(1));
+
+    Ok(())
+}
+
+// Borrow rewards accrue per minute per STAB borrowed, also when the debt changes in between
+#[test]
+fn accrues_borrow_rewards() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let rewards = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(1000, &mut env)?;
+    stab_comp.fund_borrow_rewards(rewards, &mut env)?;
+
+    let now = env.get_current_time();
+    stab_comp.set_borrow_reward_schedule(
+        vec![EmissionPeriod {
+            start: now,
+            end: now.add_minutes(60).unwrap(),
+            rewards_per_minute: dec!("0.01"),
+        }],
+        &mut env,
+    )?;
+    stab_comp.set_borrow_reward_weight(a_bucket.resource_address(&mut env)?, dec!(1), &mut env)?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    env.set_current_time(now.add_minutes(10).unwrap());
+    assert_eq!(
+        stab_comp.get_pending_borrow_rewards(cdp.clone(), &mut env)?,
+        dec!(50)
+    );
+
+    let _extra_stab = stab_comp.borrow_more(cdp.clone(), dec!(50), &mut env)?;
+    env.set_current_time(now.add_minutes(20).unwrap());
+    assert_eq!(
+        stab_comp.get_pending_borrow_rewards(cdp.clone(), &mut env)?,
+        dec!(105)
+    );
+
+    let claimed = stab_comp.claim_borrow_rewards(cdp.clone(), &mut env)?;
+    assert_eq!(claimed.amount(&mut env)?, dec!(105));
+    assert_eq!(
+        stab_comp.get_pending_borrow_rewards(cdp.clone(), &mut env)?,
+        dec!(0)
+    );
+    assert_eq!(stab_comp.get_borrow_rewards(&mut env)?.available, dec!(895));
+
+    Ok(())
+}
+
+// Loans with an equal CR are marked oldest first, even if the oldest was updated last
+#[test]
+fn marks_oldest_cdp_first_within_equal_cr() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, first_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, second_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let first_cdps = first_cdp.non_fungible_local_ids(&mut env)?;
+    let first_cdp = first_cdps.first().unwrap();
+    let second_cdps = second_cdp.non_fungible_local_ids(&mut env)?;
+    let second_cdp = second_cdps.first().unwrap();
+
+    stab_comp.top_up_cdp(
+        first_cdp.clone(),
+        a_bucket.take(dec!(100), &mut env)?,
+        &mut env,
+    )?;
+    let _removed_collateral =
+        stab_comp.remove_collateral(first_cdp.clone(), dec!(100), &mut env)?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let first_quote = stab_comp.quote_close(first_cdp.clone(), &mut env)?;
+    let second_quote = stab_comp.quote_close(second_cdp.clone(), &mut env)?;
+
+    assert!(matches!(first_quote.status, CdpStatus::Marked));
+    assert!(matches!(second_quote.status, CdpStatus::Healthy));
+
+    Ok(())
+}
+
+// Mark a loan for liquidation, with pool unit collateral
+#[test]
+fn can_mark_for_liquidation_pool_unit() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (_token_pool, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    let _ = stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    );
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    assert!(marker.amount(&mut env)? > dec!(0));
+
+    Ok(())
+}
+
+// Migrate a pool unit CDP to its parent collateral and back, keeping the receipt
+#[test]
+fn can_migrate_cdp_between_pool_unit_and_parent() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (token_pool, pool_units, _pool_address) = TokenPool::instantiate_managed_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    stab_comp.add_adapter_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        ComponentAddress::new_or_panic(token_pool.0 .0),
+        true,
+        &mut env,
+    )?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        a_bucket.resource_address(&mut env)?,
+        &mut env,
+    )?;
+    let quote = stab_comp.quote_close(cdp_id.clone(), &mut env)?;
+    assert_eq!(quote.collateral, a_bucket.resource_address(&mut env)?);
+    assert_eq!(quote.collateral_returned, dec!(1000));
+
+    stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        pool_units.resource_address(&mut env)?,
+        &mut env,
+    )?;
+    let quote = stab_comp.quote_close(cdp_id.clone(), &mut env)?;
+    assert_eq!(quote.collateral, pool_units.resource_address(&mut env)?);
+    assert_eq!(quote.stab_to_repay, dec!(500));
+
+    Ok(())
+}
+
+// Migrating a loan clears the opt-ins that don't carry over to the new collateral, and needs an empty auto-top-up buffer
+#[test]
+fn migrate_cdp_clears_collateral_bound_opt_ins() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (token_pool, pool_units, _pool_address) = TokenPool::instantiate_managed_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    stab_comp.add_adapter_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        ComponentAddress::new_or_panic(token_pool.0 .0),
+        true,
+        &mut env,
+    )?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //the harvest rate belongs to the pool unit, so it doesn't survive a migration
+    stab_comp.set_yield_harvest(cdp_id.clone(), true, &mut env)?;
+    stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        a_bucket.resource_address(&mut env)?,
+        &mut env,
+    )?;
+    assert!(stab_comp
+        .get_yield_harvest(cdp_id.clone(), &mut env)?
+        .is_none());
+
+    stab_comp.set_auto_deleverage(
+        cdp_id.clone(),
+        Some(DeleverageSettings {
+            trigger_cr: dec!("1.8"),
+            target_cr: dec!(3),
+        }),
+        &mut env,
+    )?;
+
+    //the buffer holds the parent collateral, so it needs to be withdrawn first
+    stab_comp.deposit_to_buffer(cdp_id.clone(), a_bucket.take(dec!(10), &mut env)?, &mut env)?;
+    let failure = stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        pool_units.resource_address(&mut env)?,
+        &mut env,
+    );
+    assert!(failure.is_err());
+    let _buffer = stab_comp.withdraw_from_buffer(cdp_id.clone(), dec!(10), &mut env)?;
+
+    //auto-deleverage isn't available for pool unit collateral
+    stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        pool_units.resource_address(&mut env)?,
+        &mut env,
+    )?;
+    assert!(stab_comp
+        .get_auto_deleverage(cdp_id.clone(), &mut env)?
+        .is_none());
+
+    Ok(())
+}
+
+// Migrate a CDP between the pool units of two pools only their adapters can contribute to, through the parent collateral
+#[test]
+fn can_migrate_cdp_between_two_pool_units() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (first_pool, first_units, _first_pool_address) = TokenPool::instantiate_managed_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    let (second_pool, second_units, _second_pool_address) =
+        TokenPool::instantiate_managed_token_pool(
+            a_bucket.resource_address(&mut env)?,
+            a_bucket.take(dec!(1000), &mut env)?,
+            pool_package_address,
+            &mut env,
+        )?;
+    stab_comp.add_adapter_pool_collateral(
+        first_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        ComponentAddress::new_or_panic(first_pool.0 .0),
+        true,
+        &mut env,
+    )?;
+    stab_comp.add_adapter_pool_collateral(
+        second_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        ComponentAddress::new_or_panic(second_pool.0 .0),
+        true,
+        &mut env,
+    )?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(first_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //a loan can't move between pool units directly
+    let failure = stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        second_units.resource_address(&mut env)?,
+        &mut env,
+    );
+    assert!(failure.is_err());
+
+    stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        a_bucket.resource_address(&mut env)?,
+        &mut env,
+    )?;
+    stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        second_units.resource_address(&mut env)?,
+        &mut env,
+    )?;
+
+    let quote = stab_comp.quote_close(cdp_id.clone(), &mut env)?;
+    assert_eq!(quote.collateral, second_units.resource_address(&mut env)?);
+    assert_eq!(quote.collateral_returned, dec!(1000));
+    assert_eq!(quote.stab_to_repay, dec!(500));
+
+    Ok(())
+}
+
+// A loan can be migrated out of a pool unit of a pool without adapter, but not into one, as contributing needs the pool manager role
+#[test]
+fn cant_migrate_cdp_to_pool_unit_without_adapter() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (_token_pool, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    )?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        a_bucket.resource_address(&mut env)?,
+        &mut env,
+    )?;
+
+    let failure = stab_comp.migrate_cdp(
+        cdp_id.clone(),
+        pool_units.resource_address(&mut env)?,
+        &mut env,
+    );
+    assert!(failure.is_err());
+
+    Ok(())
+}
+
+// The appreciation of a pool unit loan's collateral can be harvested once, with a bounty for the caller
+#[test]
+fn can_harvest_yield_of_pool_unit_cdp() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (mut token_pool, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    )?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    assert!(stab_comp.harvest_yield(cdp_id.clone(), &mut env).is_err());
+
+    stab_comp.set_yield_harvest(cdp_id.clone(), true, &mut env)?;
+    assert_eq!(
+        stab_comp.get_yield_harvest(cdp_id.clone(), &mut env)?,
+        Some(dec!(1))
+    );
+    assert!(stab_comp.harvest_yield(cdp_id.clone(), &mut env).is_err());
+
+    token_pool.protected_deposit(a_bucket.take(dec!(100), &mut env)?, &mut env)?;
+
+    let (to_sell, bounty, _min_stab, _max_repayment) =
+        stab_comp.quote_yield_harvest(cdp_id.clone(), &mut env)?;
+    let (harvested, bounty_bucket, _min_stab, _max_repayment) =
+        stab_comp.harvest_yield(cdp_id.clone(), &mut env)?;
+    assert_eq!(harvested.amount(&mut env)?, to_sell);
+    assert_eq!(bounty_bucket.amount(&mut env)?, bounty);
+    assert!(
+        (to_sell + bounty - dec!(1000) / dec!(11))
+            .checked_abs()
+            .unwrap()
+            < dec!("0.000001")
+    );
+
+    assert!(stab_comp.harvest_yield(cdp_id.clone(), &mut env).is_err());
+
+    Ok(())
+}
+
+// A harvest worth more than the debt above the minimum mint is capped, instead of closing the loan, and the pool haircut doesn't count as yield
+#[test]
+fn harvest_yield_is_capped_at_minimum_mint() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (mut token_pool, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    )?;
+    stab_comp.set_pool_haircut(
+        pool_units.resource_address(&mut env)?,
+        dec!("0.1"),
+        &mut env,
+    )?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(50), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    stab_comp.set_yield_harvest(cdp_id.clone(), true, &mut env)?;
+    assert_eq!(
+        stab_comp.get_yield_harvest(cdp_id.clone(), &mut env)?,
+        Some(dec!(1))
+    );
+    token_pool.protected_deposit(a_bucket.take(dec!(100), &mut env)?, &mut env)?;
+
+    let (to_sell, _bounty, _min_stab, max_repayment) =
+        stab_comp.quote_yield_harvest(cdp_id.clone(), &mut env)?;
+    assert_eq!(max_repayment, dec!(45));
+    assert!(
+        (to_sell * dec!("1.1") - max_repayment)
+            .checked_abs()
+            .unwrap()
+            < dec!("0.000001")
+    );
+
+    Ok(())
+}
+
+// Can't open a pool unit CDP that's only sufficiently collateralized without the pool haircut
+#[test]
+fn cant_open_pool_cdp_with_haircut() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (_token_pool, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    )?;
+    stab_comp.set_pool_haircut(
+        pool_units.resource_address(&mut env)?,
+        dec!("0.5"),
+        &mut env,
+    )?;
+
+    let result = stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env);
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+// Mark a loan for liquidation, but save it, as it's an appreciated pool unit collateral CDP
+#[test]
+fn can_save_cdp_with_pool_unit_through_marking() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (mut pool_comp, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    let _ = stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    );
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let _ = pool_comp.protected_deposit(a_bucket.take(dec!(1000), &mut env)?, &mut env);
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    assert!(marker.amount(&mut env)? > dec!(0));
+
+    Ok(())
+}
+
+// Liquidate a marked loan / CDP, using a marker receipt
+#[test]
+fn can_liquidate_with_marker() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    //open loan
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    //mark loan
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(5).unwrap();
+    env.set_current_time(new_time);
+
+    //liq with marker
+    let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_with_marker(
+            marker_id.clone(),
+            free_stab.take(dec!(600), &mut env)?,
+            &mut env,
+        )?;
+
+    //check rewards
+    assert_eq!(collateral_reward.unwrap().amount(&mut env)?, dec!(1000));
+    if let Some(stab) = leftover_stab {
+        assert_eq!(stab.amount(&mut env)?, dec!(100));
+    }
+
+    //the repaid debt is counted in the loan's statistics
+    let stats = stab_comp.get_cdp_stats(cdp.clone(), &mut env)?;
+    assert_eq!(stats.total_repaid, dec!(500));
+
+    Ok(())
+}
+
+// The marker fine can't be set below the liquidator fine, so marking always pays at least as much as liquidating without a marker
+#[test]
+fn marker_fine_cant_be_below_liquidator_fine() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, _a_bucket, _control_bucket) = publish_and_setup()?;
+
+    assert!(stab_comp
+        .set_fines(dec!("0.10"), dec!("0.09"), dec!("0.05"), &mut env)
+        .is_err());
+
+    stab_comp.set_fines(dec!("0.10"), dec!("0.10"), dec!("0.05"), &mut env)?;
+    stab_comp.set_fines(dec!("0.10"), dec!("0.12"), dec!("0.05"), &mut env)?;
+    let parameters = stab_comp.get_parameters(&mut env)?;
+    assert_eq!(parameters.liquidation_liquidation_fine, dec!("0.10"));
+    assert_eq!(parameters.marker_liquidation_fine, dec!("0.12"));
+
+    Ok(())
+}
+
+// Liquidation payouts of a low divisibility collateral are always rounded down, in favor of the protocol, without any collateral leaking
+#[test]
+fn liquidation_rounding_favors_protocol() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, _a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let b_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(2)
+        .mint_initial_supply(10000, &mut env)?;
+    let collateral = b_bucket.resource_address(&mut env)?;
+    stab_comp.add_collateral(collateral, dec!("1.5"), dec!("1"), &mut env)?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(b_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //a price at which the unrounded payouts have more decimals than the collateral
+    let _ = stab_comp.change_collateral_price(collateral, dec!("0.66"), &mut env);
+
+    let marker = stab_comp.mark_for_liquidation(collateral, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(5).unwrap());
+
+    let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_with_marker(
+            marker_ids.first().unwrap().clone(),
+            free_stab.take(dec!(600), &mut env)?,
+            &mut env,
+        )?;
+
+    let reward: Decimal = collateral_reward.unwrap().amount(&mut env)?;
+    let exports = stab_comp.export_cdps(vec![cdps.first().unwrap().clone()], &mut env)?;
+    let leftover: Decimal = exports[0].cdp.collateral_amount;
+    let (treasury, insurance) = stab_comp.get_treasury_balances(collateral, &mut env)?;
+
+    //unrounded payout: 1.11 * 1000 / 1.32 = 840.909..., rounded down to the collateral's divisibility
+    assert_eq!(reward, dec!("840.90"));
+    assert!(leftover > dec!(0));
+    assert_eq!(reward + leftover + treasury + insurance, dec!(1000));
+
+    Ok(())
+}
+
+// The protocol's liquidation fine is split between the treasury and the insurance fund by the insurance share
+#[test]
+fn liquidation_fine_is_split_with_insurance() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let collateral = a_bucket.resource_address(&mut env)?;
+    stab_comp.set_insurance_share(dec!("0.2"), &mut env)?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    stab_comp.change_collateral_price(collateral, dec!("0.66"), &mut env)?;
+    let marker = stab_comp.mark_for_liquidation(collateral, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(5).unwrap());
+
+    stab_comp.liquidate_position_with_marker(
+        marker_ids.first().unwrap().clone(),
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    )?;
+
+    //the loan's CR (132%) covers all fines, so the protocol receives its full fine: 0.05 * 1000 / 1.32 = 37.8787...
+    let (treasury, insurance) = stab_comp.get_treasury_balances(collateral, &mut env)?;
+    let fine: Decimal = dec!("0.05") * dec!(1000) / dec!("1.32");
+    assert!((treasury + insurance - fine).checked_abs().unwrap() < dec!("0.000001"));
+    assert!((insurance - fine * dec!("0.2")).checked_abs().unwrap() < dec!("0.000001"));
+    assert!((treasury - fine * dec!("0.8")).checked_abs().unwrap() < dec!("0.000001"));
+
+    Ok(())
+}
+
+// Used markers are burned in bulk, unused markers are returned
+#[test]
+fn can_burn_used_markers() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _second_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let second_marker =
+        stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_id = marker
+        .non_fungible_local_ids(&mut env)?
+        .first()
+        .unwrap()
+        .clone();
+    let second_marker_id = second_marker
+        .non_fungible_local_ids(&mut env)?
+        .first()
+        .unwrap()
+        .clone();
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(5).unwrap());
+
+    let _ = stab_comp.liquidate_position_with_marker(
+        marker_id,
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    )?;
+
+    marker.put(second_marker, &mut env)?;
+    let unused_markers = stab_comp.burn_markers(marker, &mut env)?;
+    assert_eq!(unused_markers.amount(&mut env)?, dec!(1));
+    assert_eq!(
+        unused_markers
+            .non_fungible_local_ids(&mut env)?
+            .first()
+            .unwrap()
+            .clone(),
+        second_marker_id
+    );
+
+    Ok(())
+}
+
+// Liquidation receipts can be retrieved after liquidating
+#[test]
+fn can_get_liquidation_receipts() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    //mark loan
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+
+    //liq without marker
+    let _ = stab_comp.liquidate_position_without_marker(
+        free_stab.take(dec!(600), &mut env)?,
+        Some(0),
+        cdp_id.clone(),
+        &mut env,
+    )?;
+
+    let receipts = stab_comp.get_liquidation_receipts(0, 100, &mut env)?;
+
+    assert_eq!(receipts.len(), 1);
+    assert_eq!(receipts[0].1.stab_paid, dec!(500));
+    assert!(receipts[0].1.cdp_liquidated == cdp_id.clone());
+
+    Ok(())
+}
+
+// Liquidate a marked loan / CDP, using a marker receipt before it should be possible
+#[test]
+fn cant_liquidate_with_marker_before_time() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    //open loan
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    //mark loan
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(4).unwrap();
+    env.set_current_time(new_time);
+
+    //liq with marker
+    let failure = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    );
+
+    assert!(failure.is_err());
+
+    Ok(())
+}
+
+// A marked loan / CDP is saved during the delay window if the collateral price recovers
+#[test]
+fn can_save_marked_cdp_after_price_recovery() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    //open loan
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    //change col price so liq is possible
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    //mark loan
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_address = marker.resource_address(&mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    //price recovers within the delay window
+    let _ =
+        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+
+    let (payment, remainder, receipt) =
+        stab_comp.liquidate_position_with_marker(marker_id.clone(), stab, &mut env)?;
+
+    assert!(payment.is_none());
+    assert_eq!(remainder.unwrap().amount(&mut env)?, dec!(500));
+    assert!(receipt.resource_address(&mut env)? == marker_address);
+
+    Ok(())
+}
+
+// Try to liquidate a CDP with a marker receipt that's received for saving a CDP
+#[test]
+fn cant_liquidate_using_marker_save_receipt() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (mut pool_comp, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    let _ = stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    );
+    let (stab, _cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let _ = pool_comp.protected_deposit(a_bucket.take(dec!(1000), &mut env)?, &mut env);
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(5).unwrap();
+    env.set_current_time(new_time);
+
+    //liq with marker
+    let failure = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    );
+
+    assert!(failure.is_err());
+
+    Ok(())
+}
+
+// Try to liquidate an appreciated CDP with pool unit collateral, so that it will instead save it.
+#[test]
+fn can_only_save_appreciated_pool_cdp() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (mut pool_comp, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    let _ = stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    );
+    let (stab, _cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_address = marker.resource_address(&mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(5).unwrap();
+    env.set_current_time(new_time);
+
+    let _ = pool_comp.protected_deposit(a_bucket.take(dec!(1000), &mut env)?, &mut env);
+
+    //liq with marker
+    let (payment, remainder, receipt) = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    )?;
+
+    assert!(payment.is_none());
+    assert!(remainder.unwrap().amount(&mut env)? == dec!(600));
+    assert!(receipt.resource_address(&mut env)? == marker_address);
+
+    Ok(())
+}
+
+// Liquidate a CDP with pool unit collateral
+#[test]
+fn can_liquidate_pool_cdp() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (_pool_comp, pool_units, pool_address) = TokenPool::instantiate_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
+        &mut env,
+    )?;
+    let _ = stab_comp.add_pool_collateral(
+        pool_units.resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?,
+        pool_address,
+        false,
+        true,
+        &mut env,
+    );
+    let (stab, _cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(5).unwrap();
+    env.set_current_time(new_time);
+
+    //liq with marker
+    let (payment, remainder, _receipt) = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    )?;
+
+    //check results
+    assert_eq!(payment.unwrap().amount(&mut env)?, dec!(1000));
+    assert_eq!(remainder.unwrap().amount(&mut env)?, dec!(100));
+
+    Ok(())
+}
+
+// Redeem the receipt of an unprofitable liquidation for compensation from the keeper rewards pool
+#[test]
+fn can_redeem_unprofitable_liquidation_receipt() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(5).unwrap();
+    env.set_current_time(new_time);
+
+    let (_payment, _remainder, receipt) = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    )?;
+    let receipt_ids = receipt.non_fungible_local_ids(&mut env)?;
+    let receipt_id = receipt_ids.first().unwrap();
+
+    stab_comp.deposit_keeper_rewards(free_stab.take(dec!(10), &mut env)?, &mut env)?;
+
+    let compensation = stab_comp.redeem_liquidation_receipt(receipt_id.clone(), &mut env)?;
+    assert_eq!(compensation.amount(&mut env)?, dec!(10));
+    assert_eq!(stab_comp.get_keeper_rewards(&mut env)?, dec!(0));
+
+    let failure = stab_comp.redeem_liquidation_receipt(receipt_id.clone(), &mut env);
+    assert!(failure.is_err());
+
+    Ok(())
+}
+
+// The stability reserve only covers the liquidator's loss on the paid STAB, not the missed fine
+#[test]
+fn stability_reserve_covers_only_liquidation_loss() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    stab_comp.set_close_fee(dec!(0.01), &mut env)?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_extra_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+    stab.put(free_stab.take(dec!(10), &mut env)?, &mut env)?;
+    let _ = stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
+    assert_eq!(stab_comp.get_stability_reserve(&mut env)?, dec!(5));
+
+    //collateral is worth 99.6% of the debt, so the liquidator loses 0.4% of the 500 STAB paid
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!("0.498"),
+        &mut env,
+    );
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(5).unwrap());
+
+    let (_payment, _remainder, _receipt) = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        free_stab.take(dec!(500), &mut env)?,
+        &mut env,
+    )?;
+
+    assert_eq!(stab_comp.get_stability_reserve(&mut env)?, dec!(3));
+
+    Ok(())
+}
+
+// Sweep unclaimed leftover collateral into the insurance fund, and reimburse it later
+#[test]
+fn can_sweep_and_reimburse_unclaimed_leftover() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible, with collateral left over
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.7),
+        &mut env,
+    );
+
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(10).unwrap());
+
+    let _ = stab_comp.liquidate_position_without_marker(
+        free_stab.take(dec!(600), &mut env)?,
+        None,
+        cdp_id.clone(),
+        &mut env,
+    )?;
+
+    //can't sweep right away
+    let failure = stab_comp.sweep_unclaimed_leftover(cdp_id.clone(), &mut env);
+    assert!(failure.is_err());
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_days(731).unwrap());
+
+    stab_comp.sweep_unclaimed_leftover(cdp_id.clone(), &mut env)?;
+    let swept = stab_comp
+        .get_swept_leftover(cdp_id.clone(), &mut env)?
+        .unwrap();
+    assert!(swept.amount > dec!(0));
+
+    let reimbursement = stab_comp.reimburse_swept_leftover(cdp_id.clone(), &mut env)?;
+    assert_eq!(reimbursement.amount(&mut env)?, swept.amount);
+    assert!(stab_comp
+        .get_swept_leftover(cdp_id.clone(), &mut env)?
+        .is_none());
+
+    Ok(())
+}
+
+// Liquidate a marked loan / CDP, without a marker receipt by id
+#[test]
+fn can_liquidate_without_marker_by_id() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    //mark loan
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+
+    //liq without marker
+    let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_without_marker(
+            free_stab.take(dec!(600), &mut env)?,
+            None,
+            cdp_id.clone(),
+            &mut env,
+        )?;
+
+    //check results
+    assert_eq!(collateral_reward.unwrap().amount(&mut env)?, dec!(1000));
+    if let Some(stab) = leftover_stab {
+        assert_eq!(stab.amount(&mut env)?, dec!(100));
+    }
+
+    Ok(())
+}
+
+// Liquidate a marked loan / CDP, without a marker receipt by id before it should be possible
+#[test]
+fn cant_liquidate_without_marker_by_id_before_time() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    //mark loan
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(9).unwrap();
+    env.set_current_time(new_time);
+
+    //liq without marker
+    let failure = stab_comp.liquidate_position_without_marker(
+        free_stab.take(dec!(600), &mut env)?,
+        None,
+        cdp_id.clone(),
+        &mut env,
+    );
+
+    assert!(failure.is_err());
+
+    Ok(())
+}
+
+// Liquidate a marked loan / CDP, without a marker receipt automatically
+#[test]
+fn can_liquidate_without_marker_automatic() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    //mark loan
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+
+    //liq without marker
+    let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_without_marker(
+            free_stab.take(dec!(600), &mut env)?,
+            Some(0),
+            cdp_id.clone(),
+            &mut env,
+        )?;
+
+    //check results
+    assert_eq!(collateral_reward.unwrap().amount(&mut env)?, dec!(1000));
+    if let Some(stab) = leftover_stab {
+        assert_eq!(stab.amount(&mut env)?, dec!(100));
+    }
+
+    Ok(())
+}
+
+// Liquidate a marked loan / CDP, without a marker receipt automatically
+#[test]
+fn cant_liquidate_without_marker_automatic_before_time() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    //mark loan
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(9).unwrap();
+    env.set_current_time(new_time);
+
+    //liq without marker
+    let failure = stab_comp.liquidate_position_without_marker(
+        free_stab.take(dec!(600), &mut env)?,
+        Some(0),
+        cdp_id.clone(),
+        &mut env,
+    );
+
+    assert!(failure.is_err());
+
+    Ok(())
+}
+
+//return internal price
+#[test]
+fn can_return_internal_price() -> Result<(), RuntimeError> {
+    let (mut env, stab_comp, _a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let price = stab_comp.return_internal_price(&mut env)?;
+
+    assert_eq!(price, dec!(1));
+
+    Ok(())
+}
+
+// Get the resource addresses of the Stabilis component
+#[test]
+fn can_get_resource_addresses() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let addresses = stab_comp.get_resource_addresses(&mut env)?;
+    assert_eq!(addresses.stab, stab.resource_address(&mut env)?);
+    assert_eq!(addresses.cdp_receipt, cdp.resource_address(&mut env)?);
+    assert_eq!(
+        addresses.controller_badge,
+        control_bucket.resource_address(&mut env)?
+    );
+
+    Ok(())
+}
+
+// Donated STAB is burned and credited to the system surplus, without repaying any loan
+#[test]
+fn can_donate_and_burn() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let receipt = stab_comp.donate_and_burn(
+        stab.take(dec!(100), &mut env)?,
+        Some("Goodwill burn".to_string()),
+        &mut env,
+    )?;
+    let _receipt = stab_comp.donate_and_burn(stab.take(dec!(50), &mut env)?, None, &mut env)?;
+
+    let addresses = stab_comp.get_resource_addresses(&mut env)?;
+    assert_eq!(
+        receipt.resource_address(&mut env)?,
+        addresses.donation_receipt
+    );
+    assert_eq!(stab_comp.get_donated_stab(&mut env)?, dec!(150));
+    assert_eq!(
+        stab_comp
+            .get_state(vec![a_bucket.resource_address(&mut env)?], &mut env)?
+            .circulating_stab,
+        dec!(500)
+    );
+
+    //the surplus offsets the circulating STAB
+    let breakdown = stab_comp.get_supply_breakdown(&mut env)?;
+    assert_eq!(breakdown.total_supply, dec!(350));
+    assert_eq!(breakdown.donated_stab, dec!(150));
+    let global_cr = stab_comp.get_global_cr(&mut env)?;
+    assert_eq!(global_cr.stab_value, dec!(500));
+    assert_eq!(global_cr.surplus_value, dec!(150));
+    assert_eq!(global_cr.cr, Some(dec!(1000) / dec!(350)));
+
+    Ok(())
+}
+
+// Quote opening a CDP
+#[test]
+fn can_quote_open_cdp() -> Result<(), RuntimeError> {
+    let (mut env, stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let quote = stab_comp.quote_open(
+        a_bucket.resource_address(&mut env)?,
+        dec!(1000),
+        dec!(500),
+        &mut env,
+    )?;
+
+    assert!(quote.possible);
+    assert_eq!(quote.collateral_ratio, dec!(2));
+    assert_eq!(quote.max_mintable, dec!(1000) / dec!("1.5"));
+
+    let too_large_quote = stab_comp.quote_open(
+        a_bucket.resource_address(&mut env)?,
+        dec!(1000),
+        dec!(700),
+        &mut env,
+    )?;
+
+    assert!(!too_large_quote.possible);
+
+    Ok(())
+}
+
+// Get share warnings for a collateral
+#[test]
+fn can_get_share_warnings() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let warnings =
+        stab_comp.get_share_warnings(vec![a_bucket.resource_address(&mut env)?], &mut env)?;
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].share, dec!(1));
+    assert!(warnings[0].near_cap);
+
+    stab_comp.edit_collateral(
+        a_bucket.resource_address(&mut env)?,
+        dec!("1.5"),
+        true,
+        dec!(2),
+        &mut env,
+    )?;
+
+    let warnings =
+        stab_comp.get_share_warnings(vec![a_bucket.resource_address(&mut env)?], &mut env)?;
+
+    assert!(!warnings[0].near_cap);
+
+    Ok(())
+}
+
+// Check if liquidation fines are calculated correctly if cr > 115%
+#[test]
+fn correct_liquidation_fines_over_115_cr() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+    let _col_price =
+        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+
+    //new cr is 1.25 now, so this means liquidator receives 1.1 / 1.25, stabilis receives 0.05 / 1.25 and 0.1 / 1.25 is left in the cdp
+    //collateral is 1000
+    //liquidator receives 880, stabilis receives 40 and 80 is left in the cdp
+
+    //mark loan
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+
+    //liq without marker
+    let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_without_marker(
+            free_stab.take(dec!(500), &mut env)?,
+            Some(0),
+            cdp_id.clone(),
+            &mut env,
+        )?;
+
+    let (retrieved_collateral, _claim) =
+        stab_comp.retrieve_leftover_collateral(cdp_id.clone(), &mut env)?;
+    assert!(retrieved_collateral.amount(&mut env)? == dec!(80));
+    assert!(
+        retrieved_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
+    );
+
+    let rewarded_collateral = collateral_reward.unwrap();
+    assert!(rewarded_collateral.amount(&mut env)? == dec!(880));
+    assert!(
+        rewarded_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
+    );
+
+    let protocol_collateral = stab_comp.empty_collateral_treasury(
+        dec!(40),
+        a_bucket.resource_address(&mut env)?,
+        false,
+        &mut env,
+    )?;
+    assert!(protocol_collateral.amount(&mut env)? == dec!(40));
+    assert!(
+        protocol_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
+    );
+
+    let impossible_retrieval = stab_comp.empty_collateral_treasury(
+        dec!("0.1"),
+        a_bucket.resource_address(&mut env)?,
+        false,
+        &mut env,
+    );
+    assert!(impossible_retrieval.is_err());
+
+    Ok(())
+}
+
+// Leftover collateral above the leftover buffer is returned to the refund account of a liquidated loan / CDP
+#[test]
+fn leftover_above_buffer_is_returned_to_refund_account() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //only accounts can be refund accounts
+    let not_an_account = stab_comp.set_refund_account(
+        cdp_id.clone(),
+        Some(ComponentAddress::new_or_panic(stab_comp.0 .0)),
+        &mut env,
+    );
+    assert!(not_an_account.is_err());
+
+    let (account, _owner_badge): (ComponentAddress, Bucket) = env.call_function_typed(
+        ACCOUNT_PACKAGE,
+        ACCOUNT_BLUEPRINT,
+        ACCOUNT_CREATE_IDENT,
+        &AccountCreateInput {},
+    )?;
+    stab_comp.set_refund_account(cdp_id.clone(), Some(account), &mut env)?;
+    assert_eq!(
+        stab_comp.get_refund_account(cdp_id.clone(), &mut env)?,
+        Some(account)
+    );
+
+    //same situation as correct_liquidation_fines_over_115_cr: 80 is left after the fines
+    //the debt is worth 800 collateral, so with the default buffer of 5%, 40 stays in the cdp and 40 is returned
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+    let _col_price =
+        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+
+    let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_without_marker(
+            free_stab.take(dec!(500), &mut env)?,
+            Some(0),
+            cdp_id.clone(),
+            &mut env,
+        )?;
+    assert!(collateral_reward.unwrap().amount(&mut env)? == dec!(880));
+
+    let receipts = stab_comp.get_liquidation_receipts(0, 100, &mut env)?;
+    assert_eq!(receipts[0].1.returned_collateral, dec!(40));
+    assert_eq!(receipts[0].1.leftover_collateral, dec!(40));
+
+    let (retrieved_collateral, _claim) =
+        stab_comp.retrieve_leftover_collateral(cdp_id.clone(), &mut env)?;
+    assert!(retrieved_collateral.amount(&mut env)? == dec!(40));
+
+    Ok(())
+}
+
+// Leftover collateral of multiple liquidated loans / CDPs is retrieved in a single bucket per resource
+#[test]
+fn can_retrieve_leftovers_of_multiple_cdps() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let (_stab, second_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+
+    let cdp_id = cdp
+        .non_fungible_local_ids(&mut env)?
+        .first()
+        .unwrap()
+        .clone();
+    let second_cdp_id = second_cdp
+        .non_fungible_local_ids(&mut env)?
+        .first()
+        .unwrap()
+        .clone();
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //cr of both loans is 1.25 now, so 80 collateral is left in each after liquidation
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+    let _col_price =
+        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(10).unwrap());
+
+    for id in [&cdp_id, &second_cdp_id] {
+        let _ = stab_comp.liquidate_position_without_marker(
+            free_stab.take(dec!(500), &mut env)?,
+            Some(0),
+            id.clone(),
+            &mut env,
+        )?;
+    }
+
+    let (leftovers, _claims) =
+        stab_comp.retrieve_leftovers(vec![cdp_id.clone(), second_cdp_id.clone()], &mut env)?;
+    assert_eq!(leftovers.len(), 1);
+    assert_eq!(leftovers[0].amount(&mut env)?, dec!(160));
+    assert_eq!(
+        leftovers[0].resource_address(&mut env)?,
+        a_bucket.resource_address(&mut env)?
+    );
+
+    //loans with nothing left are skipped instead of failing the retrieval
+    let (leftovers, claims) =
+        stab_comp.retrieve_leftovers(vec![cdp_id, second_cdp_id], &mut env)?;
+    assert!(leftovers.is_empty());
+    assert!(claims.is_none());
+
+    Ok(())
+}
+
+// Check if liquidation fines are calculated correctly if 110% < cr < 115%
+#[test]
+fn correct_liquidation_fines_between_110_115_cr() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    //cr is 2.25
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(4500), &mut env)?, dec!("2000"), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    //get some more free stab to test with
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    //change col price so liq is possible
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+    let _col_price =
+        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+
+    //new cr is 1.125 now, so this means liquidator receives 1.1 / 1.125, stabilis receives 0.025 / 1.125 and 0 is left in the cdp
+    //collateral is 4500
+    //liquidator receives 4400, stabilis receives 100 and 0 is left in the cdp
+
+    //mark loan
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+
+    //liq without marker
+    let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_without_marker(
+            free_stab.take(dec!(2000), &mut env)?,
+            Some(0),
+            cdp_id.clone(),
+            &mut env,
+        )?;
+
+    //uncommenting this gives an error? not sure why but for sure means there's no leftover collateral so all's good I guess
+    /*let retrieved_collateral = stab_comp.retrieve_leftover_collateral(cdp_id.clone(), &mut env);
+    assert!(retrieved_collateral.is_err());*/
+
+    let rewarded_collateral = collateral_reward.unwrap();
+    assert!(rewarded_collateral.amount(&mut env)? == dec!(4400));
+    assert!(
+        rewarded_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
+    );
+
+    let protocol_collateral = stab_comp.empty_collateral_treasury(
+        dec!(100),
+        a_bucket.resource_address(&mut env)?,
+        false,
+        &mut env,
+    )?;
+    assert!(protocol_collateral.amount(&mut env)? == dec!(100));
+    assert!(
+        protocol_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
+    );
+
+    let impossible_retrieval = stab_comp.empty_collateral_treasury(
+        dec!("0.1"),
+        a_bucket.resource_address(&mut env)?,
+        false,
+        &mut env,
+    );
+    assert!(impossible_retrieval.is_err());
+
+    Ok(())
+}
+
+// Check if liquidation fines are calculated correctly if cr < 110%
+#[test]
+fn correct_liquidation_fines_below_110_cr() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    //cr is 2.25
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(2100), &mut env)?, dec!("1000"), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
 
     //get some more free stab to test with
     let free_stab = BucketFactory::create_fungible_bucket(
@@ -808,264 +3370,468 @@ fn can_liquidate_without_marker_automatic() -> Result<(), RuntimeError> {
         &mut env,
     )?;
 
-    //change col price so liq is possible
-    let _ = stab_comp.change_collateral_price(
+    //change col price so liq is possible
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+    let _col_price =
+        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+
+    //new cr is 1.05 now, so this means liquidator receives 1, stabilis receives 0 and 0 is left in the cdp
+    //collateral is 2100
+    //liquidator receives 2100, stabilis receives 0 and 0 is left in the cdp
+
+    //mark loan
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+
+    let time = env.get_current_time();
+    let new_time = time.add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+
+    //liq without marker
+    let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_without_marker(
+            free_stab.take(dec!(1000), &mut env)?,
+            Some(0),
+            cdp_id.clone(),
+            &mut env,
+        )?;
+
+    //uncommenting this gives an error? not sure why but for sure means there's no leftover collateral so all's good I guess
+    /*let retrieved_collateral = stab_comp.retrieve_leftover_collateral(cdp_id.clone(), &mut env);
+    assert!(retrieved_collateral.is_err());*/
+
+    let rewarded_collateral = collateral_reward.unwrap();
+    assert!(rewarded_collateral.amount(&mut env)? == dec!(2100));
+    assert!(
+        rewarded_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
+    );
+
+    let impossible_retrieval = stab_comp.empty_collateral_treasury(
+        dec!("0.1"),
+        a_bucket.resource_address(&mut env)?,
+        false,
+        &mut env,
+    );
+    assert!(impossible_retrieval.is_err());
+
+    Ok(())
+}
+
+// Force liquidate completely
+#[test]
+fn force_liquidate_with_sufficient_collateral() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let liquidation_result = stab_comp.force_liquidate(
+        a_bucket.resource_address(&mut env)?,
+        stab.take(dec!(500), &mut env)?,
+        dec!(1),
+        true,
+        &mut env,
+    );
+
+    assert!(liquidation_result.is_ok());
+    let (returned_collateral, leftover_stab) = liquidation_result.unwrap();
+    assert_eq!(returned_collateral.amount(&mut env)?, dec!(500));
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
+
+    Ok(())
+}
+
+// Force liquidate but not fully
+#[test]
+fn force_liquidate_partly() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    let liquidation_result = stab_comp.force_liquidate(
+        a_bucket.resource_address(&mut env)?,
+        stab.take(dec!(10), &mut env)?,
+        dec!(1),
+        true,
+        &mut env,
+    );
+
+    assert!(liquidation_result.is_ok());
+    let (returned_collateral, leftover_stab) = liquidation_result.unwrap();
+    assert_eq!(returned_collateral.amount(&mut env)?, dec!(10));
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
+    assert_eq!(stab.amount(&mut env)?, dec!(490));
+
+    let close_result = stab_comp.close_cdp(cdp.clone(), stab, &mut env);
+
+    assert!(close_result.is_ok());
+    let (collateral_close, leftover_stab_close) = close_result.unwrap();
+    assert_eq!(collateral_close.amount(&mut env)?, dec!(990));
+    assert_eq!(leftover_stab_close.amount(&mut env)?, dec!(0));
+
+    Ok(())
+}
+
+// Force liquidate skips redemption-protected loans
+#[test]
+fn force_liquidate_skips_protected_cdp() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (other_stab, _other_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1200), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp = cdps.first().unwrap();
+
+    let leftover_premium =
+        stab_comp.protect_cdp(cdp.clone(), 1, a_bucket.take(dec!(1), &mut env)?, &mut env)?;
+    assert_eq!(leftover_premium.amount(&mut env)?, dec!("0.9"));
+
+    let (returned_collateral, _leftover_stab) = stab_comp.force_liquidate(
+        a_bucket.resource_address(&mut env)?,
+        other_stab.take(dec!(10), &mut env)?,
+        dec!(1),
+        true,
+        &mut env,
+    )?;
+    assert_eq!(returned_collateral.amount(&mut env)?, dec!(10));
+
+    let (collateral_close, _leftover_stab_close, _claim) =
+        stab_comp.close_cdp(cdp.clone(), stab, &mut env)?;
+    assert_eq!(collateral_close.amount(&mut env)?, dec!(1000));
+
+    Ok(())
+}
+
+// Try to force liquidate a loan that can be marked, and fail
+#[test]
+fn cant_force_liquidate_markable() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    )?;
+
+    let liquidation_result = stab_comp.force_liquidate(
+        a_bucket.resource_address(&mut env)?,
+        stab.take(dec!(500), &mut env)?,
+        dec!(1),
+        true,
+        &mut env,
+    );
+
+    assert!(liquidation_result.is_err());
+
+    Ok(())
+}
+
+// Force mint with valid parameters
+#[test]
+fn force_mint_valid_parameters() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
+
+    let mint_result = stab_comp.force_mint(
         a_bucket.resource_address(&mut env)?,
-        dec!(0.5),
+        a_bucket.take(dec!(100), &mut env)?,
+        dec!(1),
         &mut env,
     );
 
-    //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
-
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(10).unwrap();
-    env.set_current_time(new_time);
-
-    //liq without marker
-    let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
-        .liquidate_position_without_marker(
-            free_stab.take(dec!(600), &mut env)?,
-            Some(0),
-            cdp_id.clone(),
-            &mut env,
-        )?;
-
-    //check results
-    assert_eq!(collateral_reward.unwrap().amount(&mut env)?, dec!(1000));
-    if let Some(stab) = leftover_stab {
-        assert_eq!(stab.amount(&mut env)?, dec!(100));
-    }
+    assert!(mint_result.is_ok());
+    let (minted_stab, leftover_collateral) = mint_result.unwrap();
+    assert_eq!(minted_stab.amount(&mut env)?, dec!(100));
+    assert_eq!(leftover_collateral.is_none(), true);
 
     Ok(())
 }
 
-// Liquidate a marked loan / CDP, without a marker receipt automatically
+// Force mint a pool unit loan by supplying the parent asset, which is contributed to the pool through its adapter
 #[test]
-fn cant_liquidate_without_marker_automatic_before_time() -> Result<(), RuntimeError> {
+fn force_mint_with_parent_asset() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let (stab, cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
-
-    let cdps = cdp.non_fungible_local_ids(&mut env)?;
-    let cdp_id = cdps.first().unwrap();
-
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
+    let pool_package_address =
+        PackageFactory::compile_and_publish("./dummy_token_pool", &mut env, CompileProfile::Fast)?;
+    let (token_pool, pool_units, _pool_address) = TokenPool::instantiate_managed_token_pool(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(1000), &mut env)?,
+        pool_package_address,
         &mut env,
     )?;
-
-    //change col price so liq is possible
-    let _ = stab_comp.change_collateral_price(
+    stab_comp.add_adapter_pool_collateral(
+        pool_units.resource_address(&mut env)?,
         a_bucket.resource_address(&mut env)?,
-        dec!(0.5),
+        ComponentAddress::new_or_panic(token_pool.0 .0),
+        true,
         &mut env,
-    );
-
-    //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
-
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(9).unwrap();
-    env.set_current_time(new_time);
+    )?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(pool_units.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
 
-    //liq without marker
-    let failure = stab_comp.liquidate_position_without_marker(
-        free_stab.take(dec!(600), &mut env)?,
-        Some(0),
-        cdp_id.clone(),
+    let (minted_stab, _leftover_collateral) = stab_comp.force_mint(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(100), &mut env)?,
+        dec!(1),
         &mut env,
-    );
+    )?;
 
-    assert!(failure.is_err());
+    assert!(minted_stab.amount(&mut env)? > dec!(0));
+    let quote = stab_comp.quote_close(cdp_id.clone(), &mut env)?;
+    assert_eq!(quote.collateral, pool_units.resource_address(&mut env)?);
+    assert!(quote.collateral_returned > dec!(1000));
 
     Ok(())
 }
 
-//return internal price
+// Force mint with excessive collateral
 #[test]
-fn can_return_internal_price() -> Result<(), RuntimeError> {
-    let (mut env, stab_comp, _a_bucket, _control_bucket) = publish_and_setup()?;
+fn force_mint_excessive_collateral() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let price = stab_comp.return_internal_price(&mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(360), &mut env)?, dec!(10), &mut env)?;
 
-    assert_eq!(price, dec!(1));
+    let mint_result = stab_comp.force_mint(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(100), &mut env)?,
+        dec!(1),
+        &mut env,
+    );
+
+    assert!(mint_result.is_ok());
+    let (minted_stab, leftover_collateral) = mint_result.unwrap();
+    assert_eq!(minted_stab.amount(&mut env)?, dec!(90));
+    assert!(leftover_collateral.is_some());
+
+    let leftover_bucket = leftover_collateral.unwrap();
+    assert_eq!(leftover_bucket.amount(&mut env)?, dec!(10));
 
     Ok(())
 }
 
-// Check if liquidation fines are calculated correctly if cr > 115%
+// Force minting adds no more debt to a loan than its force mint cap, and skips it once the cap is reached
 #[test]
-fn correct_liquidation_fines_over_115_cr() -> Result<(), RuntimeError> {
+fn force_mint_respects_cap() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let (stab, cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
-
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp_id = cdps.first().unwrap();
 
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
+    assert!(stab_comp
+        .set_force_mint_cap(
+            cdp_id.clone(),
+            Some(ForceMintLimit::Absolute(dec!(0))),
+            &mut env,
+        )
+        .is_err());
+
+    stab_comp.set_force_mint_cap(
+        cdp_id.clone(),
+        Some(ForceMintLimit::Percentage(dec!("0.5"))),
         &mut env,
     )?;
 
-    //change col price so liq is possible
-    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
-    let _col_price =
-        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+    let (minted_stab, leftover_collateral) = stab_comp.force_mint(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(100), &mut env)?,
+        dec!(1),
+        &mut env,
+    )?;
+    assert_eq!(minted_stab.amount(&mut env)?, dec!(50));
+    assert_eq!(leftover_collateral.unwrap().amount(&mut env)?, dec!(50));
 
-    //new cr is 1.25 now, so this means liquidator receives 1.1 / 1.25, stabilis receives 0.05 / 1.25 and 0.1 / 1.25 is left in the cdp
-    //collateral is 1000
-    //liquidator receives 880, stabilis receives 40 and 80 is left in the cdp
+    let cap = stab_comp
+        .get_force_mint_cap(cdp_id.clone(), &mut env)?
+        .unwrap();
+    assert_eq!(cap.allowance, dec!(50));
+    assert_eq!(cap.force_minted, dec!(50));
 
-    //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let mint_result = stab_comp.force_mint(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(100), &mut env)?,
+        dec!(1),
+        &mut env,
+    );
+    assert!(mint_result.is_err());
 
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(10).unwrap();
-    env.set_current_time(new_time);
+    Ok(())
+}
 
-    //liq without marker
-    let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
-        .liquidate_position_without_marker(
-            free_stab.take(dec!(500), &mut env)?,
-            Some(0),
-            cdp_id.clone(),
-            &mut env,
-        )?;
+// Force minting only adds debt up to the max loan debt, and skips loans that reached it
+#[test]
+fn force_mint_respects_max_cdp_debt() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    let retrieved_collateral = stab_comp.retrieve_leftover_collateral(cdp_id.clone(), &mut env)?;
-    assert!(retrieved_collateral.amount(&mut env)? == dec!(80));
-    assert!(
-        retrieved_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
-    );
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
 
-    let rewarded_collateral = collateral_reward.unwrap();
-    assert!(rewarded_collateral.amount(&mut env)? == dec!(880));
-    assert!(
-        rewarded_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
-    );
+    stab_comp.set_collateral_max_cdp_debt(
+        a_bucket.resource_address(&mut env)?,
+        Some(dec!(150)),
+        &mut env,
+    )?;
 
-    let protocol_collateral = stab_comp.empty_collateral_treasury(
-        dec!(40),
+    let (minted_stab, leftover_collateral) = stab_comp.force_mint(
         a_bucket.resource_address(&mut env)?,
-        false,
+        a_bucket.take(dec!(100), &mut env)?,
+        dec!(1),
         &mut env,
     )?;
-    assert!(protocol_collateral.amount(&mut env)? == dec!(40));
-    assert!(
-        protocol_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
-    );
+    assert_eq!(minted_stab.amount(&mut env)?, dec!(50));
+    assert_eq!(leftover_collateral.unwrap().amount(&mut env)?, dec!(50));
 
-    let impossible_retrieval = stab_comp.empty_collateral_treasury(
-        dec!("0.1"),
+    let mint_result = stab_comp.force_mint(
         a_bucket.resource_address(&mut env)?,
-        false,
+        a_bucket.take(dec!(100), &mut env)?,
+        dec!(1),
         &mut env,
     );
-    assert!(impossible_retrieval.is_err());
+    assert!(mint_result.is_err());
 
     Ok(())
 }
 
-// Check if liquidation fines are calculated correctly if 110% < cr < 115%
+// Force mint with invalid collateral
 #[test]
-fn correct_liquidation_fines_between_110_115_cr() -> Result<(), RuntimeError> {
+fn fail_force_mint_invalid_collateral() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
 
-    //cr is 2.25
-    let (stab, cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(4500), &mut env)?, dec!("2000"), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
 
-    let cdps = cdp.non_fungible_local_ids(&mut env)?;
-    let cdp_id = cdps.first().unwrap();
+    let invalid_collateral = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
 
-    //get some more free stab to test with
-    let free_stab = BucketFactory::create_fungible_bucket(
-        stab.resource_address(&mut env)?,
-        dec!(100000),
-        Mock,
+    let mint_result = stab_comp.force_mint(
+        invalid_collateral.resource_address(&mut env)?,
+        invalid_collateral.take(dec!(500), &mut env)?,
+        dec!(1),
         &mut env,
+    );
+
+    assert!(mint_result.is_err());
+
+    Ok(())
+}
+
+// Savings setup: a Savings component for the STAB of the Stabilis component, and some free STAB
+pub fn publish_savings(
+    env: &mut TestEnvironment<InMemorySubstateDatabase>,
+    stab_comp: &mut Stabilis,
+    a_bucket: &Bucket,
+) -> Result<(Savings, Bucket), RuntimeError> {
+    let dapp_def_address =
+        GlobalAddress::try_from_hex("0d906318c6318c60f716464c6318c6318cf7bfcad6a3152b46318c6318c6")
+            .unwrap();
+    let package = PackageFactory::compile_and_publish(this_package!(), env, CompileProfile::Fast)?;
+
+    let (stab, _cdp) = stab_comp.open_cdp(a_bucket.take(dec!(1000), env)?, dec!(500), env)?;
+    let savings = Savings::instantiate(
+        OwnerRole::None,
+        stab.resource_address(env)?,
+        dapp_def_address,
+        package,
+        env,
     )?;
 
-    //change col price so liq is possible
-    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
-    let _col_price =
-        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+    Ok((savings, stab))
+}
 
-    //new cr is 1.125 now, so this means liquidator receives 1.1 / 1.125, stabilis receives 0.025 / 1.125 and 0 is left in the cdp
-    //collateral is 4500
-    //liquidator receives 4400, stabilis receives 100 and 0 is left in the cdp
+// STAB can be deposited into savings and redeemed with the added yield, while part of the first deposit stays locked
+#[test]
+fn savings_deposit_and_redeem() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let (mut savings, stab) = publish_savings(&mut env, &mut stab_comp, &a_bucket)?;
 
-    //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let ststab = savings.deposit(stab.take(dec!(100), &mut env)?, &mut env)?;
+    assert_eq!(ststab.amount(&mut env)?, dec!("99.999"));
 
-    let time = env.get_current_time();
-    let new_time = time.add_minutes(10).unwrap();
-    env.set_current_time(new_time);
+    savings.add_yield(stab.take(dec!(100), &mut env)?, &mut env)?;
+    assert_eq!(savings.get_redemption_value(&mut env)?, dec!(2));
 
-    //liq without marker
-    let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
-        .liquidate_position_without_marker(
-            free_stab.take(dec!(2000), &mut env)?,
-            Some(0),
-            cdp_id.clone(),
-            &mut env,
-        )?;
+    let redeemed = savings.redeem(ststab, &mut env)?;
+    assert_eq!(redeemed.amount(&mut env)?, dec!("199.998"));
 
-    //uncommenting this gives an error? not sure why but for sure means there's no leftover collateral so all's good I guess
-    /*let retrieved_collateral = stab_comp.retrieve_leftover_collateral(cdp_id.clone(), &mut env);
-    assert!(retrieved_collateral.is_err());*/
+    let (pooled, supply, _reserve, _rate) = savings.get_savings_info(&mut env)?;
+    assert_eq!(pooled, dec!("0.002"));
+    assert_eq!(supply, dec!("0.001"));
 
-    let rewarded_collateral = collateral_reward.unwrap();
-    assert!(rewarded_collateral.amount(&mut env)? == dec!(4400));
-    assert!(
-        rewarded_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
-    );
+    Ok(())
+}
+
+// Donating to the pool can't inflate the redemption value enough to steal later deposits, and deposits receiving no stSTAB are rejected
+#[test]
+fn savings_resists_inflation_attack() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let (mut savings, stab) = publish_savings(&mut env, &mut stab_comp, &a_bucket)?;
+
+    assert!(savings
+        .deposit(stab.take(dec!("0.001"), &mut env)?, &mut env)
+        .is_err());
 
-    let protocol_collateral = stab_comp.empty_collateral_treasury(
-        dec!(100),
-        a_bucket.resource_address(&mut env)?,
-        false,
-        &mut env,
-    )?;
-    assert!(protocol_collateral.amount(&mut env)? == dec!(100));
-    assert!(
-        protocol_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
-    );
+    let attacker_ststab = savings.deposit(stab.take(dec!("0.002"), &mut env)?, &mut env)?;
+    let _ = savings.redeem(attacker_ststab, &mut env)?;
+    savings.add_yield(stab.take(dec!(200), &mut env)?, &mut env)?;
 
-    let impossible_retrieval = stab_comp.empty_collateral_treasury(
-        dec!("0.1"),
-        a_bucket.resource_address(&mut env)?,
-        false,
-        &mut env,
-    );
-    assert!(impossible_retrieval.is_err());
+    let victim_ststab = savings.deposit(stab.take(dec!(100), &mut env)?, &mut env)?;
+    assert!(victim_ststab.amount(&mut env)? > dec!(0));
+    let redeemed = savings.redeem(victim_ststab, &mut env)?;
+    assert!(redeemed.amount(&mut env)? > dec!("99.999"));
+
+    assert!(savings
+        .deposit(stab.take(dec!("0.000000000000000001"), &mut env)?, &mut env)
+        .is_err());
 
     Ok(())
 }
 
-// Check if liquidation fines are calculated correctly if cr < 110%
+// Insurance claims can only be filed once the collateral price reverted, and are paid with the insurance badge
 #[test]
-fn correct_liquidation_fines_below_110_cr() -> Result<(), RuntimeError> {
+fn insurance_claim_requires_price_revert() -> Result<(), RuntimeError> {
     let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let dapp_def_address =
+        GlobalAddress::try_from_hex("0d906318c6318c60f716464c6318c6318cf7bfcad6a3152b46318c6318c6")
+            .unwrap();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
 
-    //cr is 2.25
-    let (stab, cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(2100), &mut env)?, dec!("1000"), &mut env)?;
+    let insurance_badge = stab_comp.mint_insurance_badge(&mut env)?;
+    assert!(stab_comp.mint_insurance_badge(&mut env).is_err());
+    let mut insurance = InsuranceClaims::instantiate(
+        OwnerRole::None,
+        ComponentAddress::new_or_panic(stab_comp.0 .0),
+        insurance_badge.resource_address(&mut env)?,
+        dapp_def_address,
+        package,
+        &mut env,
+    )?;
+    insurance.deposit_badge(insurance_badge, &mut env)?;
+    insurance.set_min_claim_value(dec!(0), &mut env)?;
+    stab_comp.set_insurance_share(dec!("0.5"), &mut env)?;
 
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
     let cdps = cdp.non_fungible_local_ids(&mut env)?;
     let cdp_id = cdps.first().unwrap();
 
-    //get some more free stab to test with
     let free_stab = BucketFactory::create_fungible_bucket(
         stab.resource_address(&mut env)?,
         dec!(100000),
@@ -1073,207 +3839,430 @@ fn correct_liquidation_fines_below_110_cr() -> Result<(), RuntimeError> {
         &mut env,
     )?;
 
-    //change col price so liq is possible
-    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
-    let _col_price =
-        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
-
-    //new cr is 1.05 now, so this means liquidator receives 1, stabilis receives 0 and 0 is left in the cdp
-    //collateral is 2100
-    //liquidator receives 2100, stabilis receives 0 and 0 is left in the cdp
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.7),
+        &mut env,
+    );
 
-    //mark loan
     let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
 
     let time = env.get_current_time();
-    let new_time = time.add_minutes(10).unwrap();
-    env.set_current_time(new_time);
+    env.set_current_time(time.add_minutes(10).unwrap());
 
-    //liq without marker
-    let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
-        .liquidate_position_without_marker(
-            free_stab.take(dec!(1000), &mut env)?,
-            Some(0),
-            cdp_id.clone(),
-            &mut env,
-        )?;
+    let _ = stab_comp.liquidate_position_without_marker(
+        free_stab.take(dec!(600), &mut env)?,
+        None,
+        cdp_id.clone(),
+        &mut env,
+    )?;
 
-    //uncommenting this gives an error? not sure why but for sure means there's no leftover collateral so all's good I guess
-    /*let retrieved_collateral = stab_comp.retrieve_leftover_collateral(cdp_id.clone(), &mut env);
-    assert!(retrieved_collateral.is_err());*/
+    let receipts = stab_comp.get_liquidation_receipts(0, 100, &mut env)?;
+    let liquidation_id = receipts[0].0.clone();
+    assert_eq!(receipts[0].1.collateral_price, dec!(0.7));
 
-    let rewarded_collateral = collateral_reward.unwrap();
-    assert!(rewarded_collateral.amount(&mut env)? == dec!(2100));
-    assert!(
-        rewarded_collateral.resource_address(&mut env)? == a_bucket.resource_address(&mut env)?
+    //price hasn't reverted, so no claim can be filed
+    let failure = insurance.file_claim(
+        NonFungibleProof(cdp.create_proof_of_all(&mut env)?),
+        liquidation_id.clone(),
+        dec!(1),
+        &mut env,
     );
+    assert!(failure.is_err());
 
-    let impossible_retrieval = stab_comp.empty_collateral_treasury(
-        dec!("0.1"),
-        a_bucket.resource_address(&mut env)?,
-        false,
+    let _ =
+        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+
+    let claim_id = insurance.file_claim(
+        NonFungibleProof(cdp.create_proof_of_all(&mut env)?),
+        liquidation_id.clone(),
+        dec!(1),
         &mut env,
-    );
-    assert!(impossible_retrieval.is_err());
+    )?;
+    let claim = insurance.get_claim(claim_id, &mut env)?.unwrap();
+    assert_eq!(claim.price_at_liquidation, dec!(0.7));
+    assert_eq!(claim.price_at_filing, dec!(1));
+
+    insurance.approve_claim(claim_id, dec!(1), &mut env)?;
+    let payout = insurance.collect_payout(
+        NonFungibleProof(cdp.create_proof_of_all(&mut env)?),
+        claim_id,
+        &mut env,
+    )?;
+    assert_eq!(payout.amount(&mut env)?, dec!(1));
 
     Ok(())
 }
 
-// Force liquidate completely
+// Can borrow collateral against STAB within the LTV, and repay it with the borrow fee to get the STAB back
 #[test]
-fn force_liquidate_with_sufficient_collateral() -> Result<(), RuntimeError> {
-    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
-
-    let (stab, _cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
-
-    let liquidation_result = stab_comp.force_liquidate(
-        a_bucket.resource_address(&mut env)?,
-        stab.take(dec!(500), &mut env)?,
-        dec!(1),
-        true,
+fn can_borrow_and_repay_inverse_loan() -> Result<(), RuntimeError> {
+    let (mut env, stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let (mut inverse_loans, stab) = setup_inverse_loans(&mut env, &stab_comp, &a_bucket)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    assert!(inverse_loans
+        .borrow(
+            stab.take(dec!(100), &mut env)?,
+            a_address,
+            dec!(51),
+            &mut env
+        )
+        .is_err());
+
+    let (borrowed, receipt) = inverse_loans.borrow(
+        stab.take(dec!(100), &mut env)?,
+        a_address,
+        dec!(50),
         &mut env,
+    )?;
+    assert_eq!(borrowed.amount(&mut env)?, dec!(50));
+    assert_eq!(inverse_loans.get_available(a_address, &mut env)?, dec!(950));
+    let loan_id = receipt
+        .non_fungible_local_ids(&mut env)?
+        .first()
+        .unwrap()
+        .clone();
+    assert_eq!(
+        inverse_loans.get_loan_ltv(loan_id.clone(), &mut env)?,
+        dec!("0.505")
     );
 
-    assert!(liquidation_result.is_ok());
-    let (returned_collateral, leftover_stab) = liquidation_result.unwrap();
-    assert_eq!(returned_collateral.amount(&mut env)?, dec!(500));
-    assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
+    assert!(inverse_loans
+        .liquidate(loan_id, a_bucket.take(dec!("50.5"), &mut env)?, &mut env)
+        .is_err());
+
+    borrowed.put(a_bucket.take(dec!(1), &mut env)?, &mut env)?;
+    let (returned_stab, leftover) = inverse_loans.repay(receipt, borrowed, &mut env)?;
+    assert_eq!(returned_stab.amount(&mut env)?, dec!(100));
+    assert_eq!(leftover.amount(&mut env)?, dec!("0.5"));
+    assert_eq!(
+        inverse_loans.get_available(a_address, &mut env)?,
+        dec!("1000.5")
+    );
 
     Ok(())
 }
 
-// Force liquidate but not fully
+// Can't repay an inverse loan without its borrow fee
 #[test]
-fn force_liquidate_partly() -> Result<(), RuntimeError> {
-    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+fn cant_repay_inverse_loan_without_fee() -> Result<(), RuntimeError> {
+    let (mut env, stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let (mut inverse_loans, stab) = setup_inverse_loans(&mut env, &stab_comp, &a_bucket)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let (borrowed, receipt) = inverse_loans.borrow(
+        stab.take(dec!(100), &mut env)?,
+        a_address,
+        dec!(50),
+        &mut env,
+    )?;
 
-    let (stab, cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    assert!(inverse_loans.repay(receipt, borrowed, &mut env).is_err());
 
-    let cdps = cdp.non_fungible_local_ids(&mut env)?;
-    let cdp = cdps.first().unwrap();
+    Ok(())
+}
 
-    let liquidation_result = stab_comp.force_liquidate(
-        a_bucket.resource_address(&mut env)?,
-        stab.take(dec!(10), &mut env)?,
-        dec!(1),
-        true,
+// An inverse loan above the liquidation LTV can be liquidated with a bonus, after which the borrower retrieves the STAB left
+#[test]
+fn can_liquidate_inverse_loan() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let (mut inverse_loans, stab) = setup_inverse_loans(&mut env, &stab_comp, &a_bucket)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let (_borrowed, receipt) = inverse_loans.borrow(
+        stab.take(dec!(100), &mut env)?,
+        a_address,
+        dec!(50),
         &mut env,
+    )?;
+    let loan_id = receipt
+        .non_fungible_local_ids(&mut env)?
+        .first()
+        .unwrap()
+        .clone();
+
+    stab_comp.change_collateral_price(a_address, dec!("1.7"), &mut env)?;
+    assert_eq!(
+        inverse_loans.get_loan_ltv(loan_id.clone(), &mut env)?,
+        dec!("0.8585")
     );
 
-    assert!(liquidation_result.is_ok());
-    let (returned_collateral, leftover_stab) = liquidation_result.unwrap();
-    assert_eq!(returned_collateral.amount(&mut env)?, dec!(10));
-    assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
-    assert_eq!(stab.amount(&mut env)?, dec!(490));
+    let (liquidator_stab, leftover) = inverse_loans.liquidate(
+        loan_id.clone(),
+        a_bucket.take(dec!(51), &mut env)?,
+        &mut env,
+    )?;
+    // 50.5 repaid at a price of 1.7, plus the 5% bonus
+    assert_eq!(liquidator_stab.amount(&mut env)?, dec!("90.1425"));
+    assert_eq!(leftover.amount(&mut env)?, dec!("0.5"));
 
-    let close_result = stab_comp.close_cdp(cdp.clone(), stab, &mut env);
+    assert!(inverse_loans
+        .liquidate(loan_id, a_bucket.take(dec!(51), &mut env)?, &mut env)
+        .is_err());
 
-    assert!(close_result.is_ok());
-    let (collateral_close, leftover_stab_close) = close_result.unwrap();
-    assert_eq!(collateral_close.amount(&mut env)?, dec!(990));
-    assert_eq!(leftover_stab_close.amount(&mut env)?, dec!(0));
+    let leftover_stab = inverse_loans.retrieve_leftover_stab(receipt, &mut env)?;
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!("9.8575"));
 
     Ok(())
 }
 
-// Try to force liquidate a loan that can be marked, and fail
+// Market LTVs need to be between 0 and 1, with the LTV below the liquidation LTV
 #[test]
-fn cant_force_liquidate_markable() -> Result<(), RuntimeError> {
-    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
-
-    let (stab, _cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+fn cant_set_inverse_market_ltvs_out_of_bounds() -> Result<(), RuntimeError> {
+    let (mut env, stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let (mut inverse_loans, _stab) = setup_inverse_loans(&mut env, &stab_comp, &a_bucket)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    for (ltv, liquidation_ltv) in [
+        (dec!(0), dec!("0.8")),
+        (dec!("-0.5"), dec!("0.8")),
+        (dec!("0.5"), dec!("1.1")),
+        (dec!("0.8"), dec!("0.5")),
+    ] {
+        assert!(inverse_loans
+            .add_market(XRD, ltv, liquidation_ltv, dec!("0.01"), &mut env)
+            .is_err());
+        assert!(inverse_loans
+            .edit_market(
+                a_address,
+                ltv,
+                liquidation_ltv,
+                dec!("0.01"),
+                true,
+                &mut env
+            )
+            .is_err());
+    }
 
-    let _ = stab_comp.change_collateral_price(
-        a_bucket.resource_address(&mut env)?,
-        dec!(0.5),
+    inverse_loans.add_market(XRD, dec!("0.9"), dec!(1), dec!("0.01"), &mut env)?;
+    inverse_loans.edit_market(
+        a_address,
+        dec!("0.6"),
+        dec!("0.9"),
+        dec!("0.01"),
+        true,
         &mut env,
     )?;
 
-    let liquidation_result = stab_comp.force_liquidate(
-        a_bucket.resource_address(&mut env)?,
-        stab.take(dec!(500), &mut env)?,
-        dec!(1),
-        true,
-        &mut env,
-    );
+    Ok(())
+}
 
-    assert!(liquidation_result.is_err());
+// Inverse loan setup: a market for the test collateral (LTV 0.5, liquidation LTV 0.8, 1% borrow fee) with 1000 supplied, and free STAB
+fn setup_inverse_loans(
+    env: &mut TestEnvironment<InMemorySubstateDatabase>,
+    stab_comp: &Stabilis,
+    a_bucket: &Bucket,
+) -> Result<(InverseLoans, Bucket), RuntimeError> {
+    let package = PackageFactory::compile_and_publish(this_package!(), env, CompileProfile::Fast)?;
+    let dapp_def_address =
+        GlobalAddress::try_from_hex("0d906318c6318c60f716464c6318c6318cf7bfcad6a3152b46318c6318c6")
+            .unwrap();
+    let addresses = stab_comp.get_resource_addresses(env)?;
+
+    let mut inverse_loans = InverseLoans::instantiate(
+        OwnerRole::None,
+        ComponentAddress::new_or_panic(stab_comp.0 .0),
+        addresses.stab,
+        dapp_def_address,
+        package,
+        env,
+    )?;
+    let a_address = a_bucket.resource_address(env)?;
+    inverse_loans.add_market(a_address, dec!("0.5"), dec!("0.8"), dec!("0.01"), env)?;
+    inverse_loans.supply(a_bucket.take(dec!(1000), env)?, env)?;
 
-    Ok(())
+    let stab = BucketFactory::create_fungible_bucket(addresses.stab, dec!(1000), Mock, env)?;
+
+    Ok((inverse_loans, stab))
 }
 
-// Force mint with valid parameters
+// Components can be registered, updated to a higher version, resolved and unregistered
 #[test]
-fn force_mint_valid_parameters() -> Result<(), RuntimeError> {
-    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+fn can_register_and_resolve_components() -> Result<(), RuntimeError> {
+    let (mut env, package, dapp_def_address) = publish_for_governance()?;
+    let mut registry = Registry::instantiate(OwnerRole::None, dapp_def_address, package, &mut env)?;
+    let new_address = GlobalAddress::from(package);
 
-    let (_stab, _cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
+    assert_eq!(registry.resolve("stabilis".to_string(), &mut env)?, None);
 
-    let mint_result = stab_comp.force_mint(
-        a_bucket.resource_address(&mut env)?,
-        a_bucket.take(dec!(100), &mut env)?,
-        dec!(1),
-        &mut env,
+    registry.register("stabilis".to_string(), dapp_def_address, 1, &mut env)?;
+    assert_eq!(
+        registry.resolve("stabilis".to_string(), &mut env)?,
+        Some(dapp_def_address)
     );
 
-    assert!(mint_result.is_ok());
-    let (minted_stab, leftover_collateral) = mint_result.unwrap();
-    assert_eq!(minted_stab.amount(&mut env)?, dec!(100));
-    assert_eq!(leftover_collateral.is_none(), true);
+    assert!(registry
+        .register("stabilis".to_string(), new_address, 1, &mut env)
+        .is_err());
+
+    registry.register("stabilis".to_string(), new_address, 2, &mut env)?;
+    assert_eq!(
+        registry.resolve("stabilis".to_string(), &mut env)?,
+        Some(new_address)
+    );
+    let entries = registry.get_entries(&mut env)?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].1.version, 2);
+
+    registry.unregister("stabilis".to_string(), &mut env)?;
+    assert_eq!(registry.resolve("stabilis".to_string(), &mut env)?, None);
+    assert!(registry.get_entries(&mut env)?.is_empty());
+    assert!(registry
+        .unregister("stabilis".to_string(), &mut env)
+        .is_err());
 
     Ok(())
 }
 
-// Force mint with excessive collateral
+// A queued action can only be executed once its timelock has passed, and only once
 #[test]
-fn force_mint_excessive_collateral() -> Result<(), RuntimeError> {
-    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+fn can_execute_timelocked_action() -> Result<(), RuntimeError> {
+    let (mut env, package, dapp_def_address) = publish_for_governance()?;
+    let (mut timelock, registry) = setup_timelock(&mut env, package, dapp_def_address)?;
+
+    let id = timelock.queue(
+        ComponentAddress::new_or_panic(registry.0 .0),
+        "register".to_string(),
+        registry_args(dapp_def_address),
+        &mut env,
+    )?;
+    assert!(timelock.execute(id, &mut env).is_err());
 
-    let (_stab, _cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(360), &mut env)?, dec!(10), &mut env)?;
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(60).unwrap());
 
-    let mint_result = stab_comp.force_mint(
-        a_bucket.resource_address(&mut env)?,
-        a_bucket.take(dec!(100), &mut env)?,
-        dec!(1),
-        &mut env,
+    timelock.execute(id, &mut env)?;
+    assert_eq!(
+        registry.resolve("stabilis".to_string(), &mut env)?,
+        Some(dapp_def_address)
     );
+    assert_eq!(
+        timelock.get_action(id, &mut env)?.unwrap().status,
+        TimelockStatus::Executed
+    );
+    assert!(timelock.execute(id, &mut env).is_err());
 
-    assert!(mint_result.is_ok());
-    let (minted_stab, leftover_collateral) = mint_result.unwrap();
-    assert_eq!(minted_stab.amount(&mut env)?, dec!(90));
-    assert!(leftover_collateral.is_some());
+    Ok(())
+}
 
-    let leftover_bucket = leftover_collateral.unwrap();
-    assert_eq!(leftover_bucket.amount(&mut env)?, dec!(10));
+// Vetoed and cancelled actions can't be executed or closed again
+#[test]
+fn cant_execute_vetoed_or_cancelled_action() -> Result<(), RuntimeError> {
+    let (mut env, package, dapp_def_address) = publish_for_governance()?;
+    let (mut timelock, registry) = setup_timelock(&mut env, package, dapp_def_address)?;
+    let registry_address = ComponentAddress::new_or_panic(registry.0 .0);
+
+    let vetoed = timelock.queue(
+        registry_address,
+        "register".to_string(),
+        registry_args(dapp_def_address),
+        &mut env,
+    )?;
+    let cancelled = timelock.queue(
+        registry_address,
+        "register".to_string(),
+        registry_args(dapp_def_address),
+        &mut env,
+    )?;
+    timelock.veto(vetoed, &mut env)?;
+    timelock.cancel(cancelled, &mut env)?;
+
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(60).unwrap());
+
+    assert!(timelock.execute(vetoed, &mut env).is_err());
+    assert!(timelock.execute(cancelled, &mut env).is_err());
+    assert!(timelock.cancel(vetoed, &mut env).is_err());
+    assert!(timelock.veto(cancelled, &mut env).is_err());
+    assert_eq!(
+        timelock.get_action(vetoed, &mut env)?.unwrap().status,
+        TimelockStatus::Vetoed
+    );
+    assert_eq!(
+        timelock.get_action(cancelled, &mut env)?.unwrap().status,
+        TimelockStatus::Cancelled
+    );
+    assert_eq!(registry.resolve("stabilis".to_string(), &mut env)?, None);
 
     Ok(())
 }
 
-// Force mint with invalid collateral
+// Raising the timelock delay applies immediately, lowering it only after the current delay has passed
 #[test]
-fn fail_force_mint_invalid_collateral() -> Result<(), RuntimeError> {
-    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+fn lowering_timelock_delay_is_delayed() -> Result<(), RuntimeError> {
+    let (mut env, package, dapp_def_address) = publish_for_governance()?;
+    let (mut timelock, _registry) = setup_timelock(&mut env, package, dapp_def_address)?;
 
-    let (_stab, _cdp) =
-        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    timelock.set_delay(120, &mut env)?;
+    assert_eq!(timelock.get_delay(&mut env)?, 120);
 
-    let invalid_collateral = ResourceBuilder::new_fungible(OwnerRole::None)
-        .divisibility(18)
-        .mint_initial_supply(10000, &mut env)?;
+    timelock.set_delay(10, &mut env)?;
+    assert_eq!(timelock.get_delay(&mut env)?, 120);
 
-    let mint_result = stab_comp.force_mint(
-        invalid_collateral.resource_address(&mut env)?,
-        invalid_collateral.take(dec!(500), &mut env)?,
-        dec!(1),
-        &mut env,
-    );
+    let time = env.get_current_time();
+    env.set_current_time(time.add_minutes(120).unwrap());
+    assert_eq!(timelock.get_delay(&mut env)?, 10);
 
-    assert!(mint_result.is_err());
+    assert!(timelock.set_delay(-1, &mut env).is_err());
 
     Ok(())
 }
+
+// Governance setup: a published package and a dapp definition address, without a Stabilis component
+fn publish_for_governance() -> Result<
+    (
+        TestEnvironment<InMemorySubstateDatabase>,
+        PackageAddress,
+        GlobalAddress,
+    ),
+    RuntimeError,
+> {
+    let dapp_def_address =
+        GlobalAddress::try_from_hex("0d906318c6318c60f716464c6318c6318cf7bfcad6a3152b46318c6318c6")
+            .unwrap();
+    let mut env = TestEnvironmentBuilder::new()
+        .add_global_references(vec![dapp_def_address])
+        .build();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    Ok((env, package, dapp_def_address))
+}
+
+// Timelock setup: a timelock with a delay of 60 minutes, holding the owner badge of a registry
+fn setup_timelock(
+    env: &mut TestEnvironment<InMemorySubstateDatabase>,
+    package: PackageAddress,
+    dapp_def_address: GlobalAddress,
+) -> Result<(Timelock, Registry), RuntimeError> {
+    let owner_badge = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(0)
+        .mint_initial_supply(1, env)?;
+    let badge_address = owner_badge.resource_address(env)?;
+
+    let registry = Registry::instantiate(
+        OwnerRole::Fixed(rule!(require(badge_address))),
+        dapp_def_address,
+        package,
+        env,
+    )?;
+    let mut timelock = Timelock::instantiate(
+        OwnerRole::None,
+        rule!(allow_all),
+        badge_address,
+        60,
+        dapp_def_address,
+        package,
+        env,
+    )?;
+    timelock.deposit_badge(owner_badge, env)?;
+
+    Ok((timelock, registry))
+}
+
+// The arguments of registering the Stabilis component at an address, as queued in the timelock
+fn registry_args(address: GlobalAddress) -> ScryptoValue {
+    scrypto_decode(&scrypto_encode(&("stabilis".to_string(), address, 1u64)).unwrap()).unwrap()
+}